@@ -31,11 +31,11 @@ TIP:<br>
 Download the binary for your system from [Releases](https://github.com/ctrlcctrlv/ata2/releases).
 If you're running Arch Linux, then you can use the AUR package: [ata2](https://aur.archlinux.org/packages/ata2)
 
-To specify the API key and some basic model settings, start the application.
-It should give an error and the option to create a configuration file called `ata2.toml` for you.
-Press `y` and `ENTER` to create a `ata2.toml` file.
+To specify the API key and some basic model settings, create a configuration file called `ata2.toml` with `ata2 config init`.
+`ata2 config path` prints where it is.
 
-Next, request an API key via <https://beta.openai.com/account/api-keys> and update the key in the example configuration file.
+Next, request an API key via <https://beta.openai.com/account/api-keys> and update the key in the example configuration file, e.g. with `ata2 config edit`.
+Afterwards, `ata2 config validate` checks it.
 
 For more information, see:
 
@@ -71,6 +71,12 @@ $ cargo build --release
 ```
 After this, your binary should be available at `target/release/ata2` (Unix-based) or `target/release/ata2.exe` (Windows).
 
+Recording prompts from the microphone (`/speak`) and playing answers read aloud by the API (`/tts`, otherwise read by `say` or `espeak`) need the `audio` feature, and on Linux ALSA's headers (e.g. `libasound2-dev`):
+
+```sh
+$ cargo build --release --features audio
+```
+
 You may also:
 
 ```sh
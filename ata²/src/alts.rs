@@ -0,0 +1,394 @@
+//! Regenerating answers (`/retry`) and choosing between the alternatives (`/alts`).
+//!
+//! Every answer generated for the last prompt is kept, and saved with the session. Only the
+//! selected one is in the conversation sent to the model.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::types::{ChatCompletionRequestMessage, ChatCompletionResponseStreamMessage};
+use serde::{Deserialize, Serialize};
+
+use std::sync::Mutex;
+
+use crate::ask;
+use crate::highlight;
+use crate::output::{self, NullSink, OutputSink as _};
+use crate::prompt::{self, finish_prompt, print_error, CONVERSATION};
+use crate::readline::{
+    chat_completion_message_to_string, string_to_chat_completion_assistant_message,
+};
+use crate::sessions;
+use crate::TokioResult;
+
+/// Longest preview of an alternative shown by `/alts`.
+const PREVIEW_LEN: usize = 60;
+
+/// The most answers `/retry n` generates at once.
+const MAX_RETRIES: usize = 9;
+
+/// Narrowest column answers are shown side by side in.
+const MIN_COLUMN_WIDTH: usize = 40;
+
+const COLUMN_GAP: usize = 2;
+
+/// The answers generated for one prompt.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Alternatives {
+    /// Index in the conversation of the user message these answer.
+    pub turn: usize,
+    pub answers: Vec<String>,
+    /// Which of `answers` is in the conversation.
+    pub selected: usize,
+    /// The model that produced each of `answers`, if known.
+    #[serde(default)]
+    pub models: Vec<Option<String>>,
+}
+
+lazy_static! {
+    static ref ALTERNATIVES: Mutex<Vec<Alternatives>> = Mutex::new(vec![]);
+}
+
+/// All alternatives of this session, for saving it.
+pub fn all() -> Vec<Alternatives> {
+    ALTERNATIVES.lock().unwrap().clone()
+}
+
+/// Replaces the alternatives by those of a resumed session.
+pub fn restore(alternatives: Vec<Alternatives>) {
+    *ALTERNATIVES.lock().unwrap() = alternatives;
+}
+
+/// Forgets the alternatives for messages from index `len` on, e.g. when the conversation is cleared.
+pub fn truncate(len: usize) {
+    ALTERNATIVES.lock().unwrap().retain(|a| a.turn + 1 < len);
+}
+
+/// Follows the messages as a message is put at the head of the conversation.
+pub fn shift() {
+    for alts in ALTERNATIVES.lock().unwrap().iter_mut() {
+        alts.turn += 1;
+    }
+}
+
+/// The index of the last user message, if it has been answered.
+fn last_turn(conversation: &[ChatCompletionRequestMessage]) -> Option<usize> {
+    match conversation {
+        [.., ChatCompletionRequestMessage::User(_), ChatCompletionRequestMessage::Assistant(_)] => {
+            Some(conversation.len() - 2)
+        }
+        _ => None,
+    }
+}
+
+/// Records `answer`, produced by `model`, as an alternative for `turn`, returning its index.
+fn record(
+    alternatives: &mut Vec<Alternatives>,
+    turn: usize,
+    answer: String,
+    model: Option<String>,
+) -> usize {
+    let alts = match alternatives.iter().position(|a| a.turn == turn) {
+        Some(i) => &mut alternatives[i],
+        None => {
+            alternatives.push(Alternatives {
+                turn,
+                ..Default::default()
+            });
+            alternatives.last_mut().unwrap()
+        }
+    };
+    let i = match alts.answers.iter().position(|a| *a == answer) {
+        Some(i) => i,
+        None => {
+            alts.answers.push(answer);
+            alts.answers.len() - 1
+        }
+    };
+    alts.models.resize(alts.answers.len(), None);
+    if model.is_some() {
+        alts.models[i] = model;
+    }
+    i
+}
+
+/// Takes the answer to the last prompt out of the conversation, keeping it as an alternative.
+/// Returns the index of the prompt and the answer.
+async fn take_last_answer() -> Option<(usize, ChatCompletionRequestMessage)> {
+    let (turn, previous) = {
+        let mut conversation = CONVERSATION.lock().await;
+        let turn = last_turn(&conversation)?;
+        (turn, conversation.pop().unwrap())
+    };
+    let mut alternatives = ALTERNATIVES.lock().unwrap();
+    let selected = record(
+        &mut alternatives,
+        turn,
+        chat_completion_message_to_string(&previous),
+        sessions::model_at(turn + 1),
+    );
+    alternatives
+        .iter_mut()
+        .find(|a| a.turn == turn)
+        .unwrap()
+        .selected = selected;
+    Some((turn, previous))
+}
+
+/// Handles `/retry [n]`: generates a new answer to the last prompt, keeping the previous one as an
+/// alternative. With `n`, generates that many and shows them side by side to choose from.
+pub async fn retry(args: &str) -> TokioResult<Vec<ChatCompletionResponseStreamMessage>> {
+    let n = match args {
+        "" => None,
+        n => match n.parse::<usize>() {
+            Ok(n) if (1..=MAX_RETRIES).contains(&n) => Some(n),
+            _ => {
+                print_error(&format!("Usage: /retry [1–{MAX_RETRIES}]"));
+                return Ok(vec![]);
+            }
+        },
+    };
+    let (turn, previous) = match take_last_answer().await {
+        Some(taken) => taken,
+        None => {
+            print_error("Nothing to retry: the last prompt has not been answered.");
+            return Ok(vec![]);
+        }
+    };
+    match n {
+        Some(n) => retry_many(turn, previous, n).await,
+        None => retry_once(turn, previous).await,
+    }
+}
+
+async fn retry_once(
+    turn: usize,
+    previous: ChatCompletionRequestMessage,
+) -> TokioResult<Vec<ChatCompletionResponseStreamMessage>> {
+    let result = prompt::request(None, None).await;
+    let mut conversation = CONVERSATION.lock().await;
+    match (&result, conversation.last()) {
+        (Ok(r), Some(answer @ ChatCompletionRequestMessage::Assistant(_))) if !r.is_empty() => {
+            let answer = chat_completion_message_to_string(answer);
+            let mut alternatives = ALTERNATIVES.lock().unwrap();
+            let selected = record(
+                &mut alternatives,
+                turn,
+                answer,
+                sessions::model_at(turn + 1),
+            );
+            let alts = alternatives.iter_mut().find(|a| a.turn == turn).unwrap();
+            alts.selected = selected;
+            info!(
+                "Alternative {} of {}; see /alts",
+                selected + 1,
+                alts.answers.len()
+            );
+        }
+        // Don't lose the previous answer if regenerating it failed.
+        _ => conversation.push(previous),
+    }
+    result
+}
+
+/// Generates `n` answers without showing them as they come, then shows them side by side and asks
+/// which to keep.
+async fn retry_many(
+    turn: usize,
+    previous: ChatCompletionRequestMessage,
+    n: usize,
+) -> TokioResult<Vec<ChatCompletionResponseStreamMessage>> {
+    let mut generated = vec![];
+    for i in 1..=n {
+        info!("Generating alternative {i} of {n}…");
+        // Not shown, so not copied, paged, read aloud, logged or autosaved either.
+        let options = prompt::RequestOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        let result = prompt::request_with(&mut NullSink, None, options).await;
+        let mut conversation = CONVERSATION.lock().await;
+        match (&result, conversation.last()) {
+            (Ok(r), Some(answer @ ChatCompletionRequestMessage::Assistant(_))) if !r.is_empty() => {
+                let answer = chat_completion_message_to_string(answer);
+                let mut alternatives = ALTERNATIVES.lock().unwrap();
+                generated.push(record(
+                    &mut alternatives,
+                    turn,
+                    answer,
+                    sessions::model_at(turn + 1),
+                ));
+                conversation.pop();
+            }
+            _ => {
+                if let Err(ref e) = result {
+                    warn!("Could not generate alternative {i}: {e}");
+                }
+                break;
+            }
+        }
+    }
+    CONVERSATION.lock().await.push(previous);
+    if generated.is_empty() {
+        print_error("No alternative could be generated.");
+        return Ok(vec![]);
+    }
+
+    let answers: Vec<(usize, String)> = {
+        let alternatives = ALTERNATIVES.lock().unwrap();
+        let alts = alternatives.iter().find(|a| a.turn == turn).unwrap();
+        generated
+            .iter()
+            .map(|&i| (i + 1, alts.answers[i].clone()))
+            .collect()
+    };
+    side_by_side(&answers);
+    let (first, last) = (answers[0].0, answers[answers.len() - 1].0);
+    let choice = ask::ask(&format!(
+        "Keep which answer? [{first}–{last}, Enter for {first}, /alts lists all] "
+    ))
+    .await;
+    let choice = match choice.as_deref().map(str::trim) {
+        None | Some("") => first.to_string(),
+        Some(choice) => choice.to_string(),
+    };
+    command(&choice).await;
+    prompt::autosave().await;
+    Ok(vec![])
+}
+
+/// Prints `answers` in columns, headed by their numbers, as many at a time as fit.
+fn side_by_side(answers: &[(usize, String)]) {
+    let width = output::terminal_width();
+    let per_row = (width / MIN_COLUMN_WIDTH).clamp(1, answers.len());
+    let column = (width / per_row).saturating_sub(COLUMN_GAP).max(1);
+    for row in answers.chunks(per_row) {
+        let columns: Vec<Vec<String>> = row
+            .iter()
+            .map(|(n, answer)| {
+                let mut lines = vec![format!("{n}.")];
+                lines.extend(wrap(answer, column));
+                lines
+            })
+            .collect();
+        let height = columns.iter().map(Vec::len).max().unwrap_or(0);
+        for i in 0..height {
+            let line: Vec<String> = columns
+                .iter()
+                .map(|lines| {
+                    let line = lines.get(i).map(String::as_str).unwrap_or_default();
+                    let padding = column.saturating_sub(line.chars().count());
+                    format!("{line}{}", " ".repeat(padding))
+                })
+                .collect();
+            eprintln!("{}", line.join(&" ".repeat(COLUMN_GAP)).trim_end());
+        }
+        eprintln!();
+    }
+}
+
+/// `text` broken into lines of at most `width` characters, at spaces where possible.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split(' ') {
+            let mut word = word.to_string();
+            while word.chars().count() > width {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                let rest = word.chars().skip(width).collect();
+                lines.push(word.chars().take(width).collect());
+                word = rest;
+            }
+            let len = line.chars().count();
+            if len > 0 && len + 1 + word.chars().count() > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+fn preview(answer: &str) -> String {
+    let line = answer.trim().lines().next().unwrap_or_default();
+    if line.chars().count() > PREVIEW_LEN {
+        line.chars().take(PREVIEW_LEN).collect::<String>() + "…"
+    } else {
+        line.to_string()
+    }
+}
+
+/// Handles `/alts [next|prev|<n>]`: without arguments, lists the alternatives for the last answer;
+/// otherwise selects one and puts it in the conversation.
+pub async fn command(args: &str) {
+    let mut conversation = CONVERSATION.lock().await;
+    let mut alternatives = ALTERNATIVES.lock().unwrap();
+    let alts = match last_turn(&conversation)
+        .and_then(|turn| alternatives.iter_mut().find(|a| a.turn == turn))
+    {
+        Some(alts) if alts.answers.len() > 1 => alts,
+        _ => {
+            drop(alternatives);
+            drop(conversation);
+            print_error("There are no alternatives for the last answer. Use /retry to make one.");
+            return;
+        }
+    };
+    // The selected answer may have been changed since, e.g. by /continue.
+    alts.answers[alts.selected] = chat_completion_message_to_string(conversation.last().unwrap());
+    let count = alts.answers.len();
+    let selected = match args {
+        "" => {
+            for (i, answer) in alts.answers.iter().enumerate() {
+                let marker = if i == alts.selected { '*' } else { ' ' };
+                eprintln!("{marker}{:>3}. {}", i + 1, preview(answer));
+            }
+            finish_prompt();
+            return;
+        }
+        "next" => (alts.selected + 1) % count,
+        "prev" => (alts.selected + count - 1) % count,
+        n => match n.parse::<usize>() {
+            Ok(n) if (1..=count).contains(&n) => n - 1,
+            _ => {
+                drop(alternatives);
+                drop(conversation);
+                print_error(&format!("Usage: /alts [next|prev|<1–{count}>]"));
+                return;
+            }
+        },
+    };
+    alts.selected = selected;
+    let answer = alts.answers[selected].clone();
+    *conversation.last_mut().unwrap() = string_to_chat_completion_assistant_message(answer.clone());
+    if let Some(Some(model)) = alts.models.get(selected) {
+        sessions::record_model(conversation.len() - 1, model);
+    }
+    info!("Selected alternative {} of {count}", selected + 1);
+    let mut sink = highlight::terminal_sink();
+    sink.write(&(answer + "\n"));
+    sink.flush();
+    finish_prompt();
+}
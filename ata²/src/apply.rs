@@ -0,0 +1,498 @@
+//! `/apply [path]`: reviewing the changes the last answer proposes to a file hunk by hunk, like
+//! `git add -p`, and writing the accepted ones.
+//!
+//! The answer may propose a unified diff, in a `diff` block, or the new contents of the file, in
+//! any other block, which are then compared with the file. Without `path`, the files are the ones
+//! the diff names, each in turn, or the last one included with `@path`; with it, only that file is
+//! changed. Files the conversation didn't include are only changed if you confirm it, since the
+//! diff can name any path. The file is backed up in the state directory before it is written.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::types::ChatCompletionRequestMessage;
+use chrono::Local;
+use similar::{ChangeTag, TextDiff};
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+
+use crate::ask;
+use crate::attach;
+use crate::extract::{self, CodeBlock};
+use crate::prompt::CONVERSATION;
+use crate::readline::chat_completion_message_to_string;
+use crate::theme::{self, Role};
+use crate::CONFIGURATION;
+
+/// Lines of context around each change of new contents.
+const CONTEXT_LINES: usize = 3;
+
+/// A change to consecutive lines of the file.
+#[derive(Debug)]
+struct Hunk {
+    /// The index of the first line replaced.
+    start: usize,
+    old: Vec<String>,
+    new: Vec<String>,
+}
+
+fn is_diff(block: &CodeBlock) -> bool {
+    matches!(block.language.as_str(), "diff" | "patch")
+        || block.code.starts_with("--- ")
+        || block.code.starts_with("@@")
+}
+
+/// The changes a unified diff makes to one file.
+#[derive(Debug)]
+struct FileDiff {
+    /// The file, from the `+++` line, if there is one.
+    target: Option<String>,
+    /// The old and new lines of each hunk.
+    hunks: Vec<(Vec<String>, Vec<String>)>,
+}
+
+/// The file named on a `+++` line.
+fn target_name(name: &str) -> Option<String> {
+    let name = name.split('\t').next()?.trim();
+    let name = name.strip_prefix("b/").unwrap_or(name);
+    (name != "/dev/null").then(|| name.to_string())
+}
+
+/// The numbers of old and new lines an `@@ -1,3 +1,4 @@` header gives, if it gives them.
+fn hunk_lengths(header: &str) -> Option<(usize, usize)> {
+    let length = |range: &str| -> Option<usize> {
+        let mut parts = range.split(',');
+        parts.next()?.parse::<usize>().ok()?;
+        parts.next().map_or(Some(1), |n| n.parse().ok())
+    };
+    let mut ranges = header.trim_start_matches('@').split_whitespace();
+    let old = length(ranges.next()?.strip_prefix('-')?)?;
+    let new = length(ranges.next()?.strip_prefix('+')?)?;
+    Some((old, new))
+}
+
+/// The files a unified diff changes, in order. `---`/`+++` headers are only taken as such between
+/// hunks, so that removing a line starting with `-- ` doesn't start another file; hunks are
+/// known to have ended when their headers' line counts are used up, or otherwise by a header.
+fn parse_diff(diff: &str) -> Vec<FileDiff> {
+    let mut files: Vec<FileDiff> = vec![];
+    let mut in_hunk = false;
+    // Old and new lines still to come in the current hunk, if its header says.
+    let mut left: Option<(usize, usize)> = None;
+    let mut lines = diff.lines().peekable();
+    while let Some(line) = lines.next() {
+        if left == Some((0, 0)) {
+            in_hunk = false;
+        }
+        let header =
+            line.starts_with("--- ") && lines.peek().map_or(false, |next| next.starts_with("+++ "));
+        if header && (!in_hunk || left.is_none()) {
+            let target = lines
+                .next()
+                .and_then(|line| line.strip_prefix("+++ "))
+                .and_then(target_name);
+            files.push(FileDiff {
+                target,
+                hunks: vec![],
+            });
+            in_hunk = false;
+            continue;
+        }
+        if line.starts_with("@@") {
+            if files.is_empty() {
+                files.push(FileDiff {
+                    target: None,
+                    hunks: vec![],
+                });
+            }
+            files.last_mut().unwrap().hunks.push((vec![], vec![]));
+            left = hunk_lengths(line);
+            in_hunk = true;
+            continue;
+        }
+        if !in_hunk || line.starts_with('\\') {
+            continue;
+        }
+        let (old, new) = files.last_mut().unwrap().hunks.last_mut().unwrap();
+        let used = match line.chars().next() {
+            Some('-') => {
+                old.push(line[1..].to_string());
+                (1, 0)
+            }
+            Some('+') => {
+                new.push(line[1..].to_string());
+                (0, 1)
+            }
+            Some(' ') => {
+                old.push(line[1..].to_string());
+                new.push(line[1..].to_string());
+                (1, 1)
+            }
+            // Blank context lines often lose their space.
+            None => {
+                old.push(String::new());
+                new.push(String::new());
+                (1, 1)
+            }
+            _ => {
+                in_hunk = false;
+                continue;
+            }
+        };
+        if let Some((old_left, new_left)) = left.as_mut() {
+            *old_left = old_left.saturating_sub(used.0);
+            *new_left = new_left.saturating_sub(used.1);
+        }
+    }
+    files
+}
+
+/// Where `old` occurs in `lines`, preferably from `from` on. Trailing whitespace is ignored.
+fn find(lines: &[String], old: &[String], from: usize) -> Option<usize> {
+    let matches_at = |i: usize| {
+        lines.len() >= i + old.len()
+            && lines[i..i + old.len()]
+                .iter()
+                .zip(old)
+                .all(|(a, b)| a.trim_end() == b.trim_end())
+    };
+    (from..=lines.len())
+        .chain(0..from.min(lines.len() + 1))
+        .find(|&i| matches_at(i))
+}
+
+/// The hunks of a file's diff, located in `lines`. Hunks that can't be found are reported and
+/// left out.
+fn diff_hunks(parsed: Vec<(Vec<String>, Vec<String>)>, lines: &[String]) -> Vec<Hunk> {
+    let mut hunks = vec![];
+    let mut from = 0;
+    for (i, (old, new)) in parsed.into_iter().enumerate() {
+        match find(lines, &old, from) {
+            Some(start) => {
+                from = start + old.len();
+                hunks.push(Hunk { start, old, new });
+            }
+            None => warn!("Hunk {} doesn't match the file, leaving it out", i + 1),
+        }
+    }
+    hunks.sort_by_key(|hunk| hunk.start);
+    let mut end = 0;
+    hunks.retain(|hunk| {
+        let keep = hunk.start >= end;
+        if keep {
+            end = hunk.start + hunk.old.len();
+        } else {
+            warn!(
+                "A hunk at line {} overlaps another, leaving it out",
+                hunk.start + 1
+            );
+        }
+        keep
+    });
+    hunks
+}
+
+/// The hunks turning `lines` into `new`.
+fn content_hunks(lines: &[String], new: &str) -> Vec<Hunk> {
+    let old: Vec<&str> = lines.iter().map(String::as_str).collect();
+    let new: Vec<&str> = new.lines().collect();
+    let diff = TextDiff::from_slices(&old, &new);
+    diff.grouped_ops(CONTEXT_LINES)
+        .into_iter()
+        .filter_map(|group| {
+            let old_range = group.first()?.old_range().start..group.last()?.old_range().end;
+            let new_range = group.first()?.new_range().start..group.last()?.new_range().end;
+            Some(Hunk {
+                start: old_range.start,
+                old: lines[old_range].to_vec(),
+                new: new[new_range].iter().map(|line| line.to_string()).collect(),
+            })
+        })
+        .collect()
+}
+
+fn print_hunk(number: usize, total: usize, hunk: &Hunk) {
+    let color = atty::is(atty::Stream::Stderr);
+    eprintln!("\n@@ {number}/{total}, line {} @@", hunk.start + 1);
+    let old: Vec<&str> = hunk.old.iter().map(String::as_str).collect();
+    let new: Vec<&str> = hunk.new.iter().map(String::as_str).collect();
+    for change in TextDiff::from_slices(&old, &new).iter_all_changes() {
+        let (sign, role) = match change.tag() {
+            ChangeTag::Delete => ('-', Some(Role::Removed)),
+            ChangeTag::Insert => ('+', Some(Role::Added)),
+            ChangeTag::Equal => (' ', None),
+        };
+        let line = format!("{sign}{}", change.value());
+        match role {
+            Some(role) if color => eprintln!("{}", theme::paint(role, &line)),
+            _ => eprintln!("{line}"),
+        }
+    }
+}
+
+/// The last answer, and the names of the files included in the conversation, last first.
+async fn last_answer() -> Option<(String, Vec<String>)> {
+    let conversation = CONVERSATION.lock().await;
+    let answer = conversation
+        .iter()
+        .rev()
+        .find_map(|message| match message {
+            ChatCompletionRequestMessage::Assistant(_) => {
+                Some(chat_completion_message_to_string(message))
+            }
+            _ => None,
+        })?;
+    let included = conversation
+        .iter()
+        .rev()
+        .filter(|message| matches!(message, ChatCompletionRequestMessage::User(_)))
+        .flat_map(|message| {
+            let mut names = attach::included(&chat_completion_message_to_string(message));
+            names.reverse();
+            names
+        })
+        .collect();
+    Some((answer, included))
+}
+
+/// Copies `path` into the backups directory, under the time and the path with `%` for `/`, e.g.
+/// `20240101-120000-src%main.rs`. An existing backup is never overwritten: a counter is added to
+/// the name instead.
+fn backup(path: &Path) -> Result<PathBuf, String> {
+    let dir = CONFIGURATION.load().paths.backups();
+    fs::create_dir_all(&dir).map_err(|e| format!("Could not create {}: {e}", dir.display()))?;
+    let name = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("%");
+    let time = Local::now().format("%Y%m%d-%H%M%S");
+    let failed = |e: io::Error| format!("Could not back up {}: {e}", path.display());
+    let mut source = fs::File::open(path).map_err(failed)?;
+    for n in 0.. {
+        let backup = match n {
+            0 => dir.join(format!("{time}-{name}")),
+            n => dir.join(format!("{time}-{n}-{name}")),
+        };
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&backup)
+        {
+            Ok(mut file) => {
+                io::copy(&mut source, &mut file).map_err(failed)?;
+                return Ok(backup);
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(failed(e)),
+        }
+    }
+    unreachable!()
+}
+
+/// What was answered for the hunks still to come.
+#[derive(Clone, Copy, PartialEq)]
+enum Rest {
+    Ask,
+    /// `a`ll the rest.
+    Apply,
+    /// `q`uit.
+    Skip,
+}
+
+/// Asks about each of the hunks `located` in `target` and applies those accepted. A target not
+/// among the `known` files is only changed if that is confirmed first.
+async fn apply_file(
+    target: &str,
+    known: &[String],
+    located: impl FnOnce(&[String]) -> Vec<Hunk>,
+    rest: &mut Rest,
+) -> Result<String, String> {
+    if !known.iter().any(|name| name == target)
+        && !ask::confirm(&format!(
+            "The answer changes {target}, which isn't in the conversation. Review the changes?"
+        ))
+        .await
+    {
+        return Err(format!("Left {target} unchanged"));
+    }
+    let path = PathBuf::from(target);
+    let contents =
+        fs::read_to_string(&path).map_err(|e| format!("Could not read {target}: {e}"))?;
+    let lines: Vec<String> = contents.lines().map(String::from).collect();
+    let hunks = located(&lines);
+    if hunks.is_empty() {
+        return Err(format!("No changes to apply to {target}"));
+    }
+    let heading = format!("Changes to {target}:");
+    if atty::is(atty::Stream::Stderr) {
+        eprintln!("\n{}", theme::paint(Role::Banner, &heading));
+    } else {
+        eprintln!("\n{heading}");
+    }
+
+    let mut accepted = vec![];
+    'hunks: for (i, hunk) in hunks.iter().enumerate() {
+        if *rest == Rest::Skip {
+            break;
+        }
+        if *rest == Rest::Ask {
+            print_hunk(i + 1, hunks.len(), hunk);
+            loop {
+                let answer =
+                    ask::ask("Apply this hunk? [y]es, [n]o, [a]ll the rest, [q]uit: ").await;
+                match answer.as_deref().map(str::trim) {
+                    Some("y") => break,
+                    Some("n") => continue 'hunks,
+                    Some("a") => {
+                        *rest = Rest::Apply;
+                        break;
+                    }
+                    Some("q") | None => {
+                        *rest = Rest::Skip;
+                        break 'hunks;
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        accepted.push(hunk);
+    }
+    if accepted.is_empty() {
+        return Ok(format!("Left {target} unchanged"));
+    }
+
+    let backup = backup(&path)?;
+    let mut lines = lines;
+    for hunk in accepted.iter().rev() {
+        lines.splice(
+            hunk.start..hunk.start + hunk.old.len(),
+            hunk.new.iter().cloned(),
+        );
+    }
+    let mut new = lines.join("\n");
+    if contents.ends_with('\n') || contents.is_empty() {
+        new.push('\n');
+    }
+    fs::write(&path, new).map_err(|e| format!("Could not write {target}: {e}"))?;
+    Ok(format!(
+        "Applied {} of {} hunks to {target} (backup: {})",
+        accepted.len(),
+        hunks.len(),
+        backup.display()
+    ))
+}
+
+/// `/apply [path]`.
+pub async fn command(args: &str) -> Result<String, String> {
+    let (answer, included) = last_answer()
+        .await
+        .ok_or_else(|| String::from("There is no answer to apply yet"))?;
+    let blocks = extract::code_blocks(&answer);
+    let block = blocks
+        .iter()
+        .rev()
+        .find(|block| is_diff(block))
+        .or_else(|| blocks.last())
+        .ok_or_else(|| String::from("The last answer has no code block to apply"))?;
+    let path = match args.trim() {
+        "" => None,
+        path => Some(path.to_string()),
+    };
+    let usage = || String::from("Which file? Usage: /apply [path]");
+    // The files you named, by including them or asking for them.
+    let known: Vec<String> = included.iter().cloned().chain(path.clone()).collect();
+    let mut rest = Rest::Ask;
+    if !is_diff(block) {
+        let target = path
+            .or_else(|| included.first().cloned())
+            .ok_or_else(usage)?;
+        return apply_file(
+            &target,
+            &known,
+            |lines| content_hunks(lines, &block.code),
+            &mut rest,
+        )
+        .await;
+    }
+
+    // Each file of the diff in turn, or just the one asked for.
+    let mut files = parse_diff(&block.code);
+    if let Some(path) = path {
+        if files.len() > 1 {
+            files.retain(|file| file.target.as_deref() == Some(path.as_str()));
+        }
+        if files.is_empty() {
+            return Err(format!("The diff doesn't change {path}"));
+        }
+        for file in &mut files {
+            file.target = Some(path.clone());
+        }
+    }
+    if files.len() == 1 {
+        let file = files.remove(0);
+        let target = file
+            .target
+            .or_else(|| included.first().cloned())
+            .ok_or_else(usage)?;
+        return apply_file(
+            &target,
+            &known,
+            |lines| diff_hunks(file.hunks, lines),
+            &mut rest,
+        )
+        .await;
+    }
+    let mut applied = vec![];
+    let mut failed = vec![];
+    for file in files {
+        if rest == Rest::Skip {
+            break;
+        }
+        let target = match file.target {
+            Some(target) => target,
+            None => {
+                failed.push(String::from(
+                    "Left out a part of the diff that names no file",
+                ));
+                continue;
+            }
+        };
+        match apply_file(
+            &target,
+            &known,
+            |lines| diff_hunks(file.hunks, lines),
+            &mut rest,
+        )
+        .await
+        {
+            Ok(done) => applied.push(done),
+            Err(e) => failed.push(e),
+        }
+    }
+    if applied.is_empty() {
+        return Err(failed.join("\n"));
+    }
+    applied.extend(failed);
+    Ok(applied.join("\n"))
+}
@@ -19,9 +19,14 @@
 //!  limitations under the License.
 
 use crate::config::ConfigLocation;
+use crate::extract::Extract;
+use crate::import;
 
-use clap::Parser;
 use clap::{crate_authors, crate_version};
+use clap::{Parser, Subcommand};
+use clap_complete::Shell;
+
+use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(author = crate_authors!(), version = crate_version!(),
@@ -35,18 +40,330 @@ use clap::{crate_authors, crate_version};
     {all-args}{after-help}")]
 pub struct Ata2 {
     /// Path to the configuration TOML file.
-    #[arg(short = 'c', long = "config", default_value = "")]
+    #[arg(short = 'c', long = "config", default_value = "", global = true)]
     pub config: ConfigLocation,
 
-    /// Avoid printing the configuration to stdout.
-    #[arg(long)]
+    /// Use the settings of `[profiles.<NAME>]` in the configuration file.
+    #[arg(short = 'p', long, value_name = "NAME", global = true)]
+    pub profile: Option<String>,
+
+    /// Avoid printing the configuration to stderr.
+    #[arg(long, global = true)]
     pub hide_config: bool,
 
     /// Print the keyboard shortcuts.
     #[arg(long)]
     pub print_shortcuts: bool,
 
-    /// Conversation file to load.
+    /// System prompt, steering the assistant's behavior. Overrides `system_prompt` in the
+    /// configuration.
+    #[arg(short = 's', long = "system", global = true)]
+    pub system: Option<String>,
+
+    /// Keep the conversation in this file, in the format read by `--load`. It is written after
+    /// each answer.
+    #[arg(long = "save", value_name = "PATH")]
+    pub save: Option<PathBuf>,
+
+    /// Conversation file (saved with F2) or session to load.
     #[arg(short = 'l', long = "load")]
     pub load: Option<String>,
+
+    /// Continue a saved session, given its ID or the start of it (see --list-sessions).
+    #[arg(short = 'r', long, value_name = "ID", conflicts_with = "load")]
+    pub resume: Option<String>,
+
+    /// List the saved sessions, like `sessions list`, and exit.
+    #[arg(long)]
+    pub list_sessions: bool,
+
+    /// After answering the prompt piped to stdin, continue in the REPL on the terminal, so you
+    /// can ask follow-up questions about it.
+    #[arg(long)]
+    pub interactive_after_pipe: bool,
+
+    /// Start every prompt by recording it from the microphone, as with `/speak`.
+    #[arg(long)]
+    pub dictate: bool,
+
+    /// Edit prompts with vi's keys, as with `ui.edit_mode = "vi"`.
+    #[arg(long)]
+    pub vi: bool,
+
+    /// Use a full-screen interface, with the conversation in a pane that can be scrolled (also
+    /// with the mouse wheel), an input box and a status bar, instead of the line-by-line REPL.
+    #[arg(long)]
+    pub tui: bool,
+
+    /// Print the request each prompt would make (URL, headers without the key, and JSON body)
+    /// instead of sending it, as with `/dry`. Passages of the `/rag` index and summaries aren't
+    /// asked for either. Only for the REPL, `ask` and --batch.
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Save each answer, as it is streamed, in DIR: the request as `<n>.request.json` and the
+    /// answer as `<n>.sse`, which `provider = "mock"` with `api_base = "DIR"` replays.
+    #[arg(long, value_name = "DIR", global = true)]
+    pub record: Option<PathBuf>,
+
+    /// Print only part of the answer: `code` (all code blocks), `first-code`, `json`, or
+    /// `regex:<pattern>`. Meant for one-shot mode (piping the prompt in).
+    #[arg(long, value_name = "WHAT", global = true)]
+    pub extract: Option<Extract>,
+
+    /// Create a named pipe at PATH, through which other programs can control the REPL by writing
+    /// lines to it: `prompt: <text>`, `abort` or `save`.
+    #[arg(long, value_name = "PATH")]
+    pub control_fifo: Option<PathBuf>,
+
+    /// Ask the prompts in this file one after the other, and write the answers as JSON lines.
+    /// The file holds a prompt per line, or prompts with metadata if it is YAML.
+    #[arg(long, value_name = "PATH", conflicts_with = "prompt")]
+    pub batch: Option<PathBuf>,
+
+    /// With --batch, write the answers to this file instead of stdout.
+    #[arg(long, value_name = "PATH", requires = "batch")]
+    pub batch_output: Option<PathBuf>,
+
+    /// With --batch, ask each prompt in a new conversation, rather than following up on the ones
+    /// before.
+    #[arg(long, requires = "batch")]
+    pub fresh: bool,
+
+    /// With --batch and --fresh, how many prompts to ask at once. The answers are still written
+    /// in the order of the prompts.
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "batch")]
+    pub jobs: usize,
+
+    /// With --batch, go ahead without asking however much the forecast says the prompts cost.
+    #[arg(long, requires = "batch")]
+    pub yes: bool,
+
+    /// Keep running, answering the JSON requests of other programs, e.g. editors, on a Unix
+    /// socket (by default ata2.sock in the state directory) or a localhost TCP address such as
+    /// 127.0.0.1:7878. They share one conversation and configuration.
+    #[arg(
+        long,
+        value_name = "ADDR",
+        num_args = 0..=1,
+        default_missing_value = "",
+        conflicts_with_all = ["prompt", "batch"]
+    )]
+    pub serve: Option<String>,
+
+    /// Send prompts to an `ata2 --serve` process at ADDR (by default its socket in the state
+    /// directory), rather than to the API, sharing its conversation and configuration.
+    #[arg(
+        long,
+        value_name = "ADDR",
+        num_args = 0..=1,
+        default_missing_value = "",
+        conflicts_with_all = ["serve", "batch", "tui"]
+    )]
+    pub connect: Option<String>,
+
+    /// A prompt to ask once, e.g. `ata2 "what is wrong here?" < error.log`, as with `ata2 ask`.
+    #[arg(value_name = "PROMPT")]
+    pub prompt: Vec<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Ata2 {
+    /// The prompt to ask once, given with `ask` or, as before there were subcommands, on its own.
+    pub fn prompt(&self) -> &[String] {
+        match self.command {
+            Some(Command::Ask { ref prompt }) => prompt,
+            _ => &self.prompt,
+        }
+    }
+
+    /// Whether `--dry-run` can be honoured: the other subcommands, --serve and --connect don't
+    /// print the requests they would make.
+    pub fn honours_dry_run(&self) -> bool {
+        matches!(
+            self.command,
+            None | Some(Command::Repl) | Some(Command::Ask { .. })
+        ) && self.serve.is_none()
+            && self.connect.is_none()
+    }
+}
+
+/// Subcommands. Without one, ata² starts the REPL, or asks the prompt it is given, so that
+/// `ata2` and `ata2 "prompt"` work as they always have.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Start the REPL (the default).
+    Repl,
+    /// Ask a prompt once, print the answer and exit. What is piped to stdin is included after
+    /// it, as a code block; either may be left out.
+    Ask {
+        #[arg(value_name = "PROMPT")]
+        prompt: Vec<String>,
+    },
+    /// Serve msgpack-RPC on stdin/stdout for the Neovim plugin.
+    NvimRpc,
+    /// Host a conversation that other ata² clients can join (experimental).
+    ServeSession {
+        /// Address to listen on.
+        #[arg(long, default_value = "127.0.0.1:7777")]
+        listen: String,
+    },
+    /// Join a conversation hosted with `serve-session` (experimental).
+    JoinSession {
+        /// Address of the host, e.g. `example.com:7777`.
+        addr: String,
+        /// Name shown to the other participants. Defaults to $USER.
+        #[arg(long)]
+        name: Option<String>,
+        /// The token the host printed when it started.
+        #[arg(long)]
+        token: String,
+    },
+    /// Manage saved sessions.
+    Sessions {
+        #[command(subcommand)]
+        action: SessionsCommand,
+    },
+    /// Play scripted conversations.
+    Script {
+        #[command(subcommand)]
+        action: ScriptCommand,
+    },
+    /// Show or update the per-model pricing table.
+    Pricing {
+        #[command(subcommand)]
+        action: PricingCommand,
+    },
+    /// Inspect, create or change the configuration file.
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Index files for `/rag`: split them into passages and ask for their embeddings. Files
+    /// indexed before are only indexed again if they changed.
+    Index {
+        /// Files and directories to index.
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Sync the configuration directory with `[sync] remote`, a git repository or rsync target.
+    /// Secrets and the history stay on this machine.
+    Sync {
+        #[command(subcommand)]
+        action: SyncCommand,
+    },
+    /// Print the completion script for a shell, e.g. `ata2 completions bash >
+    /// ~/.local/share/bash-completion/completions/ata2`.
+    Completions { shell: Shell },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the configuration in effect as TOML, noting where each value came from: the
+    /// configuration file, an environment variable, or the default.
+    Show,
+    /// Print the path of the configuration file.
+    Path,
+    /// Write an example configuration file to edit, or with a profile name, add a profile to
+    /// the existing one.
+    Init {
+        /// Add `[profiles.<PROFILE>]` instead.
+        #[arg(value_name = "PROFILE")]
+        profile: Option<String>,
+        /// The model to use.
+        #[arg(long)]
+        model: Option<String>,
+        /// The API key to use, rather than a placeholder to replace.
+        #[arg(long)]
+        api_key: Option<String>,
+        /// Replace an existing configuration file.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Open the configuration file in $VISUAL or $EDITOR, then check it.
+    Edit,
+    /// Check the configuration file, as when starting, and report where errors are.
+    Validate,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SyncCommand {
+    /// Show what changed here and on the remote since the last sync.
+    Status,
+    /// Take the changes made on the remote. Files changed on both sides are left alone.
+    Pull {
+        /// Take the remote's version of files changed on both sides too.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Send the changes made here. Refused if the remote has changes not pulled yet.
+    Push {
+        /// Replace the remote's versions anyway.
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SessionsCommand {
+    /// Archive old sessions and remove old history entries, as configured.
+    Gc {
+        /// Archive sessions older than this many days, instead of the configured value.
+        #[arg(long, value_name = "DAYS")]
+        archive_after: Option<u64>,
+        /// Remove history entries older than this many days, instead of the configured value.
+        #[arg(long, value_name = "DAYS")]
+        history_retention: Option<u64>,
+    },
+    /// List the saved sessions, oldest first: ID, last update, model, tags and title.
+    List {
+        /// Only list sessions with this tag. Can be repeated.
+        #[arg(long)]
+        tag: Vec<String>,
+    },
+    /// Delete a saved session, given its ID or the start of it.
+    Delete { id: String },
+    /// Change the tags of a saved session.
+    Tag {
+        id: String,
+        /// e.g. `rust,work`. Tags starting with `-` are removed instead.
+        #[arg(allow_hyphen_values = true)]
+        tags: String,
+    },
+    /// Save conversations from another tool as sessions: a chat file of sgpt, the output of
+    /// `llm logs --json`, or a session file of aichat.
+    Import {
+        /// `sgpt`, `llm` or `aichat`.
+        #[arg(long)]
+        format: import::Format,
+        path: PathBuf,
+    },
+    /// Ask the prompts of a saved session again, in order, e.g. with another model. The cost is
+    /// estimated first, and has to be confirmed if above `confirm_cost_above`.
+    Replay {
+        id: String,
+        /// Don't ask for confirmation.
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ScriptCommand {
+    /// Play a scenario: a YAML file of prompts, with checks on the answers and values captured
+    /// from them for later prompts. Fails if a check does.
+    Play { path: PathBuf },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PricingCommand {
+    /// Print the pricing table in effect.
+    Show,
+    /// Replace the pricing table with the one maintained by the project, or with a local file.
+    Update {
+        /// URL or path of the table. Defaults to the project's.
+        source: Option<String>,
+    },
 }
@@ -0,0 +1,84 @@
+//! Asking the user something while an answer is being generated, e.g. whether to run a command
+//! the model asked for.
+//!
+//! The terminal is being read by the REPL all along, so the question is printed, and the next line
+//! typed at the prompt is taken as the answer instead of a new prompt. Meanwhile, Enter accepts
+//! the line even in multiline mode.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use tokio::sync::oneshot;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::output::eprint_and_flush;
+
+lazy_static! {
+    static ref PENDING: Mutex<Option<oneshot::Sender<String>>> = Mutex::new(None);
+}
+
+/// Set when no REPL reads the answers, e.g. in batch mode.
+static UNATTENDED: AtomicBool = AtomicBool::new(false);
+
+/// Declines every question from now on, without asking it.
+pub fn set_unattended() {
+    UNATTENDED.store(true, Ordering::Relaxed);
+}
+
+/// Whether questions are declined without being asked.
+pub fn is_unattended() -> bool {
+    UNATTENDED.load(Ordering::Relaxed)
+}
+
+/// Prints `question` and waits for the answer typed at the prompt. Returns `None` if there is no
+/// terminal to ask at, nobody to answer (see [`set_unattended`]), or the REPL is gone.
+pub async fn ask(question: &str) -> Option<String> {
+    if !atty::is(atty::Stream::Stdin) || is_unattended() {
+        return None;
+    }
+    let (tx, rx) = oneshot::channel();
+    *PENDING.lock().unwrap() = Some(tx);
+    eprint_and_flush(question);
+    rx.await.ok()
+}
+
+/// Asks a yes/no `question`, which defaults to no.
+pub async fn confirm(question: &str) -> bool {
+    match ask(&format!("{question} [y/N] ")).await {
+        Some(answer) => answer.trim().eq_ignore_ascii_case("y"),
+        None => false,
+    }
+}
+
+/// Whether a question is waiting for an answer.
+pub fn is_pending() -> bool {
+    PENDING.lock().unwrap().is_some()
+}
+
+/// Gives `line` to the question waiting for an answer, if any. Returns whether there was one.
+pub fn answer(line: &str) -> bool {
+    match PENDING.lock().unwrap().take() {
+        Some(tx) => {
+            let _ = tx.send(line.to_string());
+            true
+        }
+        None => false,
+    }
+}
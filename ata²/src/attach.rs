@@ -0,0 +1,133 @@
+//! Including files in prompts with `@path`, e.g. `explain @src/main.rs`.
+//!
+//! Each `@path` naming a file is replaced by the path, and the file is added after the prompt in a
+//! fenced code block. Words starting with `@` that aren't files, like `@someone`, are left alone.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Punctuation that may follow `@path` in a sentence without being part of it.
+const TRAILING: &[char] = &['.', ',', ';', ':', '!', '?', ')', ']', '\'', '"'];
+
+/// How much of a file is looked at to tell whether it is binary.
+const SNIFF_LEN: usize = 8192;
+
+/// The file `word` (without the `@`) refers to, if any, and how much of `word` it takes up.
+fn file_of(word: &str) -> Option<(PathBuf, usize)> {
+    let mut word = word;
+    loop {
+        let path = match word.strip_prefix("~/") {
+            Some(rest) => Path::new(&env::var("HOME").ok()?).join(rest),
+            None => PathBuf::from(word),
+        };
+        if path.is_file() {
+            return Some((path, word.len()));
+        }
+        word = word.strip_suffix(TRAILING)?;
+    }
+}
+
+/// The fenced code block for `path`, made long enough not to be closed by the contents.
+fn code_block(name: &str, path: &Path, max_bytes: u64) -> Result<String, String> {
+    let size = fs::metadata(path)
+        .map_err(|e| format!("{name}: {e}"))?
+        .len();
+    if size > max_bytes {
+        return Err(format!(
+            "{name} is {size} bytes, more than attach_max_bytes ({max_bytes})"
+        ));
+    }
+    let bytes = fs::read(path).map_err(|e| format!("{name}: {e}"))?;
+    if bytes[..bytes.len().min(SNIFF_LEN)].contains(&0) {
+        return Err(format!("{name} looks like a binary file"));
+    }
+    let text = String::from_utf8(bytes).map_err(|_| format!("{name} is not UTF-8 text"))?;
+    let language = path
+        .extension()
+        .map(|e| e.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Ok(format!("{name}:\n{}", fenced(&text, &language)))
+}
+
+/// `text` in a fenced code block, with a fence longer than any in it.
+pub fn fenced(text: &str, language: &str) -> String {
+    let longest_run = text
+        .lines()
+        .map(|line| line.trim_start().chars().take_while(|&c| c == '`').count())
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    let newline = if text.ends_with('\n') { "" } else { "\n" };
+    format!("{fence}{language}\n{text}{newline}{fence}")
+}
+
+/// The names of the files included in `prompt` by [`expand`], in order.
+pub fn included(prompt: &str) -> Vec<String> {
+    let lines: Vec<&str> = prompt.lines().collect();
+    lines
+        .windows(2)
+        .filter_map(|pair| {
+            let name = pair[0].strip_suffix(':')?;
+            (pair[1].starts_with("```") && file_of(name).is_some()).then(|| name.to_string())
+        })
+        .collect()
+}
+
+/// `prompt` with the files it mentions included. Fails if one is too large or not text.
+pub fn expand(prompt: &str, max_bytes: u64) -> Result<String, String> {
+    let mut text = String::with_capacity(prompt.len());
+    let mut blocks: Vec<String> = vec![];
+    let mut rest = prompt;
+    while let Some(at) = rest.find('@') {
+        let starts_word = at == 0 || rest[..at].ends_with(char::is_whitespace);
+        let word_end = rest[at + 1..]
+            .find(char::is_whitespace)
+            .map_or(rest.len(), |end| at + 1 + end);
+        let file = if starts_word {
+            file_of(&rest[at + 1..word_end])
+        } else {
+            None
+        };
+        match file {
+            Some((path, len)) => {
+                let name = &rest[at + 1..at + 1 + len];
+                text.push_str(&rest[..at]);
+                text.push_str(&format!("`{name}`"));
+                let block = code_block(name, &path, max_bytes)?;
+                if !blocks.contains(&block) {
+                    blocks.push(block);
+                }
+                rest = &rest[at + 1 + len..];
+            }
+            None => {
+                text.push_str(&rest[..at + 1]);
+                rest = &rest[at + 1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    for block in blocks {
+        text.push_str("\n\n");
+        text.push_str(&block);
+    }
+    Ok(text)
+}
@@ -0,0 +1,373 @@
+//! Dictating prompts (`/speak`, `--dictate`): recording from the default microphone until Enter,
+//! then transcribing the recording with `transcription_model` to edit and send as the prompt.
+//!
+//! Reading answers aloud (`ui.tts`, `/tts`): once finished, an answer is spoken by `speech_model`
+//! or by `say`/`espeak`, leaving out its code blocks.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::error::OpenAIError;
+use async_openai::types::{AudioInput, CreateSpeechRequest, CreateTranscriptionRequestArgs};
+use async_openai::Client;
+#[cfg(feature = "audio")]
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+#[cfg(feature = "audio")]
+use cpal::{SampleFormat, SizedSample, Stream, StreamConfig};
+use serde_json::json;
+
+#[cfg(feature = "audio")]
+use std::io::{self, Cursor, Write as _};
+use std::process::{Command, Stdio};
+#[cfg(feature = "audio")]
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use crate::config::{ApiConfig, Tts};
+use crate::params;
+use crate::Config;
+use crate::CONFIGURATION;
+
+/// Whether `line` asks to dictate the prompt.
+pub fn speak_requested(line: &str) -> bool {
+    line.trim() == "/speak"
+}
+
+/// Opens a stream adding what `device` records to `samples`, as 16-bit samples.
+#[cfg(feature = "audio")]
+fn open<T: SizedSample>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    samples: Arc<Mutex<Vec<i16>>>,
+    convert: fn(T) -> i16,
+) -> Result<Stream, String> {
+    device
+        .build_input_stream(
+            config,
+            move |data: &[T], _: &cpal::InputCallbackInfo| {
+                samples
+                    .lock()
+                    .unwrap()
+                    .extend(data.iter().map(|&s| convert(s)))
+            },
+            |e| warn!("Recording failed: {e}"),
+            None,
+        )
+        .map_err(|e| format!("Could not record: {e}"))
+}
+
+/// Records from the default microphone until Enter is pressed. Returns the samples, mixed down to
+/// mono, and the sample rate.
+#[cfg(feature = "audio")]
+fn record() -> Result<(Vec<i16>, u32), String> {
+    let device = cpal::default_host()
+        .default_input_device()
+        .ok_or_else(|| String::from("No microphone found"))?;
+    let supported = device
+        .default_input_config()
+        .map_err(|e| format!("Could not record: {e}"))?;
+    let config: StreamConfig = supported.config();
+    let samples = Arc::new(Mutex::new(vec![]));
+    let stream = match supported.sample_format() {
+        SampleFormat::I16 => open::<i16>(&device, &config, samples.clone(), |s| s),
+        SampleFormat::U16 => open::<u16>(&device, &config, samples.clone(), |s| {
+            (s as i32 - 0x8000) as i16
+        }),
+        SampleFormat::F32 => open::<f32>(&device, &config, samples.clone(), |s| {
+            (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+        }),
+        format => return Err(format!("Unsupported sample format {format}")),
+    }?;
+    stream
+        .play()
+        .map_err(|e| format!("Could not record: {e}"))?;
+    eprint!("Recording… press Enter to stop.");
+    io::stderr().flush().ok();
+    io::stdin()
+        .read_line(&mut String::new())
+        .map_err(|e| e.to_string())?;
+    drop(stream);
+
+    let channels = config.channels.max(1) as usize;
+    let samples = samples.lock().unwrap();
+    let mono = samples
+        .chunks(channels)
+        .map(|frame| (frame.iter().map(|&s| s as i32).sum::<i32>() / frame.len() as i32) as i16)
+        .collect();
+    Ok((mono, config.sample_rate.0))
+}
+
+#[cfg(not(feature = "audio"))]
+fn record() -> Result<(Vec<i16>, u32), String> {
+    Err(String::from(
+        "ata² was built without the audio feature, so it can't record (cargo build --features audio)",
+    ))
+}
+
+/// `samples` as a mono 16-bit WAV file.
+fn wav(samples: &[i16], sample_rate: u32) -> Vec<u8> {
+    let data_len = samples.len() as u32 * 2;
+    let mut out = Vec::with_capacity(44 + data_len as usize);
+    out.extend(b"RIFF");
+    out.extend((36 + data_len).to_le_bytes());
+    out.extend(b"WAVEfmt ");
+    out.extend(16u32.to_le_bytes());
+    out.extend(1u16.to_le_bytes()); // PCM
+    out.extend(1u16.to_le_bytes()); // channels
+    out.extend(sample_rate.to_le_bytes());
+    out.extend((sample_rate * 2).to_le_bytes()); // bytes per second
+    out.extend(2u16.to_le_bytes()); // bytes per frame
+    out.extend(16u16.to_le_bytes()); // bits per sample
+    out.extend(b"data");
+    out.extend(data_len.to_le_bytes());
+    for sample in samples {
+        out.extend(sample.to_le_bytes());
+    }
+    out
+}
+
+async fn transcribe(config: &Config, wav: Vec<u8>) -> Result<String, OpenAIError> {
+    let request = CreateTranscriptionRequestArgs::default()
+        .file(AudioInput::from_vec_u8(String::from("prompt.wav"), wav))
+        .model(&config.transcription_model)
+        .build()?;
+    let http = config.http_client()?;
+    let response = match config.api_config() {
+        ApiConfig::OpenAI(c) => {
+            Client::with_config(c)
+                .with_http_client(http)
+                .audio()
+                .transcribe(request)
+                .await?
+        }
+        ApiConfig::Azure(c) => {
+            Client::with_config(c)
+                .with_http_client(http)
+                .audio()
+                .transcribe(request)
+                .await?
+        }
+    };
+    Ok(response.text.trim().to_string())
+}
+
+/// Records a prompt and returns its transcript.
+pub async fn dictate() -> Result<String, String> {
+    let config = params::effective_config(&CONFIGURATION.load(), &Default::default())?;
+    let (samples, sample_rate) = record()?;
+    if samples.is_empty() {
+        return Ok(String::new());
+    }
+    eprintln!("Transcribing…");
+    transcribe(&config, wav(&samples, sample_rate))
+        .await
+        .map_err(|e| format!("Could not transcribe: {e}"))
+}
+
+/// The most text the speech endpoint takes at once, in characters.
+const MAX_SPEECH_INPUT: usize = 4000;
+
+/// Programs reading text aloud, tried in order.
+const SPEAKERS: &[&str] = &["say", "espeak-ng", "espeak"];
+
+lazy_static! {
+    /// How answers are read aloud now, after `/tts`.
+    static ref TTS: Mutex<Tts> = Mutex::new(CONFIGURATION.load().ui.tts);
+    /// Held while reading an answer aloud, so that answers don't talk over each other.
+    static ref READING: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+/// Takes up `config`, e.g. once it is reloaded.
+pub fn apply(config: &Config) {
+    *TTS.lock().unwrap() = config.ui.tts;
+}
+
+/// `/tts [on|off]`.
+pub fn tts_command(args: &str) -> Result<String, String> {
+    let mut tts = TTS.lock().unwrap();
+    match args.trim() {
+        "" => {}
+        "on" if *tts == Tts::Off => {
+            *tts = match CONFIGURATION.load().ui.tts {
+                Tts::Off => Tts::Api,
+                configured => configured,
+            }
+        }
+        "on" => {}
+        "off" => *tts = Tts::Off,
+        _ => return Err(String::from("Usage: /tts [on|off]")),
+    }
+    Ok(match *tts {
+        Tts::Off => String::from("Answers aren't read aloud"),
+        Tts::Api => format!(
+            "Answers are read aloud by {}",
+            CONFIGURATION.load().speech_model
+        ),
+        Tts::Local => String::from("Answers are read aloud by say or espeak"),
+    })
+}
+
+/// `answer` without its code blocks, which don't make sense read aloud.
+fn speakable(answer: &str) -> String {
+    let mut out = String::new();
+    let mut in_block = false;
+    for line in answer.lines() {
+        let fence = line.trim_start().starts_with("```") || line.trim_start().starts_with("~~~");
+        if fence && !in_block {
+            out.push_str("(code)\n");
+        }
+        if fence {
+            in_block = !in_block;
+        } else if !in_block {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Splits `text` into pieces the speech endpoint takes, between words.
+fn pieces(text: &str) -> Vec<String> {
+    let mut pieces = vec![String::new()];
+    for word in text.split_inclusive(char::is_whitespace) {
+        let last = pieces.last_mut().unwrap();
+        if !last.is_empty() && last.len() + word.len() > MAX_SPEECH_INPUT {
+            pieces.push(String::new());
+        }
+        pieces.last_mut().unwrap().push_str(word);
+    }
+    pieces
+}
+
+async fn speech(config: &Config, text: &str) -> Result<Vec<u8>, OpenAIError> {
+    // Built from JSON, so that any model and voice the endpoint knows can be configured.
+    let request: CreateSpeechRequest = serde_json::from_value(json!({
+        "model": config.speech_model,
+        "voice": config.ui.tts_voice,
+        "input": text,
+    }))
+    .map_err(|e| OpenAIError::InvalidArgument(format!("Invalid speech_model or tts_voice: {e}")))?;
+    let http = config.http_client()?;
+    let response = match config.api_config() {
+        ApiConfig::OpenAI(c) => {
+            Client::with_config(c)
+                .with_http_client(http)
+                .audio()
+                .speech(request)
+                .await?
+        }
+        ApiConfig::Azure(c) => {
+            Client::with_config(c)
+                .with_http_client(http)
+                .audio()
+                .speech(request)
+                .await?
+        }
+    };
+    Ok(response.bytes.to_vec())
+}
+
+/// Plays MP3 audio on the default output device, until it ends.
+#[cfg(feature = "audio")]
+fn play(mp3: Vec<u8>) -> Result<(), String> {
+    let (_stream, handle) = rodio::OutputStream::try_default().map_err(|e| e.to_string())?;
+    let sink = rodio::Sink::try_new(&handle).map_err(|e| e.to_string())?;
+    let source = rodio::Decoder::new(Cursor::new(mp3)).map_err(|e| e.to_string())?;
+    sink.append(source);
+    sink.sleep_until_end();
+    Ok(())
+}
+
+#[cfg(not(feature = "audio"))]
+fn play(_: Vec<u8>) -> Result<(), String> {
+    Err(String::from(
+        "ata² was built without the audio feature, so it can't play audio (cargo build --features audio)",
+    ))
+}
+
+fn speak_locally(text: &str) -> Result<(), String> {
+    for program in SPEAKERS {
+        // After `--`, text starting with `-` is read rather than taken for options.
+        match Command::new(program)
+            .arg("--")
+            .arg(text)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+        {
+            Ok(status) if status.success() => return Ok(()),
+            Ok(status) => return Err(format!("{program} failed: {status}")),
+            Err(_) => continue,
+        }
+    }
+    Err(format!("None of {} is installed", SPEAKERS.join(", ")))
+}
+
+async fn read_aloud_now(config: &Config, text: &str) -> Result<(), String> {
+    let mut mode = *TTS.lock().unwrap();
+    if mode == Tts::Api && !cfg!(feature = "audio") {
+        debug!("Built without the audio feature, so answers are read aloud locally");
+        mode = Tts::Local;
+    }
+    for piece in pieces(text) {
+        if piece.trim().is_empty() {
+            continue;
+        }
+        if mode == Tts::Api {
+            match speech(config, &piece).await {
+                Ok(mp3) => {
+                    tokio::task::spawn_blocking(move || play(mp3))
+                        .await
+                        .map_err(|e| e.to_string())??;
+                }
+                Err(e) => {
+                    warn!(
+                        "Could not read the answer aloud with {}: {e}",
+                        config.speech_model
+                    );
+                    mode = Tts::Local;
+                }
+            }
+        }
+        if mode == Tts::Local {
+            tokio::task::spawn_blocking(move || speak_locally(&piece))
+                .await
+                .map_err(|e| e.to_string())??;
+        }
+        // Stopped with /tts off.
+        if *TTS.lock().unwrap() == Tts::Off {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Reads `answer` aloud in the background, if `/tts` is on.
+pub fn read_aloud(config: &Config, answer: &str) {
+    if *TTS.lock().unwrap() == Tts::Off {
+        return;
+    }
+    let config = config.clone();
+    let text = speakable(answer);
+    tokio::spawn(async move {
+        let _reading = READING.lock().await;
+        if let Err(e) = read_aloud_now(&config, &text).await {
+            warn!("Could not read the answer aloud: {e}");
+        }
+    });
+}
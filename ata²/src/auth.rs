@@ -0,0 +1,208 @@
+//! Recovering from a rejected API key without editing the configuration and restarting.
+//!
+//! If the first request is refused with 401 or 403, the user is offered to enter another key,
+//! which is checked right away, used for the rest of the session, and, if they want, written to the
+//! configuration file. The prompt is then sent again.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::error::OpenAIError;
+use async_openai::types::CreateChatCompletionRequestArgs;
+use tokio::sync::mpsc::Sender;
+use tokio_stream::StreamExt as _;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::ask;
+use crate::backend;
+use crate::output::eprint_and_flush;
+use crate::prompt::finish_prompt;
+use crate::readline::string_to_chat_completion_request_user_message;
+use crate::Config;
+use crate::CONFIGURATION;
+use crate::FLAGS;
+
+/// Whether a request has been answered, i.e. the key works.
+static ANSWERED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    /// Set when the key was rejected: the prompt to send again once there is a new key.
+    static ref PENDING: Mutex<Option<Option<String>>> = Mutex::new(None);
+    /// The key entered to replace the configured one.
+    static ref KEY: Mutex<Option<String>> = Mutex::new(None);
+}
+
+/// Notes that a request was answered. From then on, rejections are reported like other errors.
+pub fn answered() {
+    ANSWERED.store(true, Ordering::Relaxed);
+}
+
+/// Whether `e` means the API didn't accept the key.
+pub fn rejected(e: &OpenAIError) -> bool {
+    match e {
+        OpenAIError::ApiError(e) => {
+            e.message.contains("API key") || e.r#type.as_deref() == Some("authentication_error")
+        }
+        e => {
+            let e = e.to_string();
+            e.contains("401 Unauthorized") || e.contains("403 Forbidden")
+        }
+    }
+}
+
+/// Offers to enter another key, if no request has been answered yet and there is a terminal to
+/// enter it on. `prompt` is sent again once the key works. Returns whether it was offered.
+pub fn offer(e: &OpenAIError, prompt: Option<String>) -> bool {
+    if ANSWERED.load(Ordering::Relaxed) || !atty::is(atty::Stream::Stdin) || ask::is_unattended() {
+        return false;
+    }
+    error!("The API rejected the key: {e}");
+    eprint_and_flush("Press Enter to enter another key.");
+    *PENDING.lock().unwrap() = Some(prompt);
+    true
+}
+
+/// Applies the key entered during this session, if any, to `config`.
+pub fn apply(config: &mut Config) {
+    if let Some(ref key) = *KEY.lock().unwrap() {
+        config.api_key = Some(key.clone());
+    }
+}
+
+/// Reads a line from the terminal without showing it.
+#[cfg(unix)]
+fn read_hidden(prompt: &str) -> io::Result<String> {
+    use std::os::unix::io::AsRawFd as _;
+
+    eprint_and_flush(prompt);
+    let fd = io::stdin().as_raw_fd();
+    let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+    if unsafe { libc::tcgetattr(fd, &mut termios) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let echoing = termios;
+    termios.c_lflag &= !libc::ECHO;
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) };
+    let mut line = String::new();
+    let read = io::stdin().read_line(&mut line);
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &echoing) };
+    eprintln!();
+    read.map(|_| line)
+}
+
+#[cfg(not(unix))]
+fn read_hidden(prompt: &str) -> io::Result<String> {
+    warn!("The key will be shown as you type it");
+    eprint_and_flush(prompt);
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    Ok(line)
+}
+
+/// Asks the model for a single token with `key`.
+async fn check(key: &str) -> Result<(), OpenAIError> {
+    let mut config = (**CONFIGURATION.load()).clone();
+    config.api_key = Some(key.to_string());
+    let mut request: CreateChatCompletionRequestArgs = (&config).into();
+    let request = request
+        .messages(vec![string_to_chat_completion_request_user_message(
+            String::from("Hi"),
+        )])
+        .max_tokens(1u16)
+        .build()?;
+    let mut stream = backend::primary(&config)?.stream_chat(request).await?;
+    match stream.next().await {
+        Some(Err(e)) => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Sets `api_key` in the configuration file at `path`, keeping the rest of it as it is. The file is
+/// made readable by the user only; `sync` already leaves the key out of what it copies.
+fn save(path: &Path, key: &str) -> io::Result<()> {
+    let contents = fs::read_to_string(path)?;
+    let setting = format!("api_key = {}", toml::Value::String(key.to_string()));
+    let mut lines: Vec<String> = contents.lines().map(String::from).collect();
+    // Top-level keys have to come before the first table.
+    let tables = lines
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .unwrap_or(lines.len());
+    let existing = lines[..tables]
+        .iter()
+        .position(|line| line.split('=').next().map(str::trim) == Some("api_key"));
+    match existing {
+        Some(i) => lines[i] = setting,
+        None => lines.insert(0, setting),
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    fs::write(path, lines.join("\n") + "\n")
+}
+
+/// If the key was rejected, asks for another one until it works or the user gives up, then sends
+/// the rejected prompt again through `tx`. Called by the REPL between lines.
+pub async fn troubleshoot(tx: &Sender<Option<String>>) {
+    let prompt = match PENDING.lock().unwrap().take() {
+        Some(prompt) => prompt,
+        None => return,
+    };
+    let key = loop {
+        let key = match read_hidden("API key (leave empty to give up): ") {
+            Ok(key) => key.trim().to_string(),
+            Err(e) => {
+                error!("Could not read the key: {e}");
+                return finish_prompt();
+            }
+        };
+        if key.is_empty() {
+            return finish_prompt();
+        }
+        match check(&key).await {
+            Ok(()) => break key,
+            Err(e) => error!("That key doesn't work either: {e}"),
+        }
+    };
+    info!("The key works, and will be used for the rest of the session");
+    *KEY.lock().unwrap() = Some(key.clone());
+    answered();
+
+    let path = FLAGS.config.location();
+    eprint_and_flush(&format!("Save it to {}? [y/N] ", path.display()));
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y") {
+        match save(&path, &key) {
+            Ok(()) => info!("Saved the key to {}", path.display()),
+            Err(e) => error!("Could not save the key: {e}"),
+        }
+    }
+    match prompt {
+        Some(prompt) => {
+            let _ = tx.send(Some(prompt)).await;
+        }
+        None => finish_prompt(),
+    }
+}
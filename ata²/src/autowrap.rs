@@ -0,0 +1,179 @@
+//! Asking about bare pastes of code or logs (`ui.auto_wrap_code`).
+//!
+//! A prompt of several lines that looks like code or a log, with no question in it, is put in a
+//! fenced code block tagged with its language, followed by `ui.auto_wrap_question`.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use regex::Regex;
+
+/// The fewest lines a prompt needs to be taken for a paste.
+const MIN_LINES: usize = 3;
+
+/// How many of the non-blank lines must look like code or a log.
+const MIN_CODE_SHARE: f64 = 0.6;
+
+/// Telltale signs of each language. The language with the most wins.
+const LANGUAGES: &[(&str, &[&str])] = &[
+    (
+        "rust",
+        &[
+            "fn ", "let mut ", "impl ", "pub ", "use ", "::", "&mut ", "-> ", "match ",
+        ],
+    ),
+    (
+        "python",
+        &[
+            "def ",
+            "import ",
+            "from ",
+            "self.",
+            "elif ",
+            "print(",
+            "None",
+            "    return ",
+        ],
+    ),
+    (
+        "javascript",
+        &[
+            "const ",
+            "function ",
+            "=> ",
+            "console.",
+            "require(",
+            "let ",
+            "===",
+            "export ",
+        ],
+    ),
+    (
+        "go",
+        &[
+            "func ",
+            "package ",
+            ":= ",
+            "fmt.",
+            "go ",
+            "defer ",
+            "err != nil",
+        ],
+    ),
+    (
+        "c",
+        &[
+            "#include", "int main", "printf(", "->", "NULL", "malloc(", "void ",
+        ],
+    ),
+    (
+        "cpp",
+        &["std::", "#include <", "template<", "nullptr", "cout"],
+    ),
+    (
+        "java",
+        &[
+            "public class",
+            "System.out",
+            "private ",
+            "public static",
+            "@Override",
+        ],
+    ),
+    (
+        "sh",
+        &["#!/bin/", "echo ", "fi", "done", "then", "export ", "$("],
+    ),
+    (
+        "sql",
+        &[
+            "SELECT ",
+            "FROM ",
+            "WHERE ",
+            "INSERT ",
+            "CREATE TABLE",
+            "JOIN ",
+        ],
+    ),
+    (
+        "html",
+        &["<div", "</", "<html", "<body", "<span", "class=\""],
+    ),
+];
+
+lazy_static! {
+    /// A line of code: ending like a statement or block, or starting with a keyword or comment.
+    static ref CODE_LINE: Regex = Regex::new(
+        r#"[;{}(),\[\]:]\s*$|^\s*(//|#|/\*|\*|--|<)|^\s*(fn|def|class|import|from|use|let|const|var|func|package|return|if|for|while|public|private|SELECT|FROM)\b|^\s{2,}\S"#
+    ).unwrap();
+    /// A line of a log or a stack trace: starting with a date or time, a log level, or `at`.
+    static ref LOG_LINE: Regex = Regex::new(
+        r#"^\s*(\[?\d{4}-\d{2}-\d{2}|\[?\d{2}:\d{2}:\d{2}|\[?(TRACE|DEBUG|INFO|WARN|WARNING|ERROR|FATAL)\b|at |Traceback|File "|thread '|Caused by)"#
+    ).unwrap();
+    /// A question to the model, e.g. `why does this fail?` or `explain this`.
+    static ref QUESTION: Regex = Regex::new(
+        r"(?i)\?\s*$|^\s*(why|what|how|explain|fix|can|could|please|write|rewrite|refactor|review)\b"
+    ).unwrap();
+}
+
+/// The language `code` seems to be written in, or `log` or `text` if none.
+fn language(code: &str, logs: usize, lines: usize) -> &'static str {
+    let (language, hits) = LANGUAGES
+        .iter()
+        .map(|(language, signs)| {
+            let hits = signs.iter().filter(|sign| code.contains(*sign)).count();
+            (*language, hits)
+        })
+        .max_by_key(|(_, hits)| *hits)
+        .unwrap();
+    if logs * 2 >= lines {
+        "log"
+    } else if hits >= 2 {
+        language
+    } else if serde_json::from_str::<serde_json::Value>(code).is_ok() {
+        "json"
+    } else {
+        "text"
+    }
+}
+
+/// `prompt` wrapped and followed by `question`, if it is a bare paste of code or a log.
+pub fn wrap(prompt: &str, question: &str) -> Option<String> {
+    let lines: Vec<&str> = prompt.lines().filter(|l| !l.trim().is_empty()).collect();
+    if lines.len() < MIN_LINES || prompt.contains("```") {
+        return None;
+    }
+    let is_question = |line: &&str| {
+        QUESTION.is_match(line) && !CODE_LINE.is_match(line) && !LOG_LINE.is_match(line)
+    };
+    if lines.iter().any(is_question) {
+        return None;
+    }
+    let logs = lines.iter().filter(|line| LOG_LINE.is_match(line)).count();
+    let code = lines
+        .iter()
+        .filter(|line| CODE_LINE.is_match(line) || LOG_LINE.is_match(line))
+        .count();
+    if (code as f64) < MIN_CODE_SHARE * lines.len() as f64 {
+        return None;
+    }
+    let code = prompt.trim_matches('\n');
+    let language = language(code, logs, lines.len());
+    info!("Taking the prompt for {language}, and asking: {question}");
+    Some(format!("```{language}\n{code}\n```\n\n{question}"))
+}
@@ -0,0 +1,441 @@
+//! The APIs chat requests are sent to (`provider`).
+//!
+//! Requests are built, and answers streamed, in OpenAI's shape whichever API answers them. A
+//! [`Backend`] translates to and from the API it speaks to: OpenAI's or a compatible one (including
+//! Mistral's, Groq's and Azure OpenAI), or Anthropic's Messages API. Answers can also be recorded
+//! and replayed (see [`crate::mock`]).
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::config::Config as ClientConfig;
+use async_openai::error::{ApiError, OpenAIError};
+use async_openai::types::{
+    ChatCompletionResponseStream, CreateChatCompletionRequest, CreateChatCompletionStreamResponse,
+};
+use async_openai::Client;
+use futures_util::future::{self, BoxFuture, FutureExt as _};
+use futures_util::stream::StreamExt as _;
+use serde_json::{json, Value};
+
+use crate::config::{ApiConfig, ApiProvider, RequestConfig};
+use crate::mock::{Mock, Recording};
+use crate::reasoning;
+use crate::request_body;
+use crate::sse;
+use crate::Config;
+use crate::FLAGS;
+
+/// The version of the Messages API the requests are written for.
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// The Messages API requires `max_tokens`; this is used if the request has none.
+const ANTHROPIC_MAX_TOKENS: u16 = 4096;
+
+/// An API that answers chat requests.
+pub trait Backend: Send + Sync {
+    /// Starts streaming the answer to `request`.
+    fn stream_chat(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> BoxFuture<'_, Result<ChatCompletionResponseStream, OpenAIError>>;
+
+    /// The IDs of the models the API offers.
+    fn models(&self) -> BoxFuture<'_, Result<Vec<String>, OpenAIError>>;
+
+    /// What [`Backend::stream_chat`] would send for `request`: its URL, headers, with the key left
+    /// out, and JSON body. For `--dry-run` and `/dry`.
+    fn payload(&self, request: &CreateChatCompletionRequest) -> Value;
+}
+
+/// Headers carrying the API key.
+const KEY_HEADERS: [&str; 3] = ["authorization", "api-key", "x-api-key"];
+
+/// `headers` as a JSON object, with the API key replaced.
+fn headers_json<'a>(headers: impl IntoIterator<Item = (&'a str, &'a str)>) -> Value {
+    let headers = headers
+        .into_iter()
+        .map(|(name, value)| {
+            let value = if KEY_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+                "[redacted]"
+            } else {
+                value
+            };
+            (name.to_string(), Value::from(value))
+        })
+        .collect::<serde_json::Map<_, _>>();
+    Value::Object(headers)
+}
+
+/// `backend`, recording its answers if asked to with `--record`.
+fn recorded(backend: Box<dyn Backend>) -> Box<dyn Backend> {
+    match FLAGS.record {
+        Some(ref dir) => Box::new(Recording {
+            inner: backend,
+            dir: dir.clone(),
+        }),
+        None => backend,
+    }
+}
+
+/// The backend of the primary provider.
+pub fn primary(config: &Config) -> Result<Box<dyn Backend>, OpenAIError> {
+    let http = config.http_client()?;
+    let body = config.request.clone();
+    Ok(recorded(match config.provider {
+        ApiProvider::Anthropic => Box::new(Anthropic {
+            api_key: config.api_key.clone().unwrap_or_default(),
+            api_base: config.api_base().unwrap_or_default(),
+            http,
+            body,
+        }),
+        ApiProvider::Mock => Box::new(Mock {
+            dir: config.api_base().unwrap_or_default().into(),
+        }),
+        _ => Box::new(OpenAI {
+            api: config.api_config(),
+            http,
+            body,
+        }),
+    }))
+}
+
+/// The backend of the `[fallback]` provider, which is always OpenAI or compatible.
+pub fn fallback(config: &Config) -> Result<Box<dyn Backend>, OpenAIError> {
+    Ok(recorded(Box::new(OpenAI {
+        api: ApiConfig::OpenAI(config.fallback_openai_config()),
+        http: config.fallback_http_client()?,
+        body: config.request.clone(),
+    })))
+}
+
+/// OpenAI's API, or a compatible one.
+struct OpenAI {
+    api: ApiConfig,
+    http: reqwest::Client,
+    body: RequestConfig,
+}
+
+/// Sends `request` with `http` to the provider configured by `client_config`, changing its body if
+/// configured to.
+async fn open_with<C: ClientConfig>(
+    client_config: C,
+    http: reqwest::Client,
+    request: CreateChatCompletionRequest,
+    body: &RequestConfig,
+) -> Result<ChatCompletionResponseStream, OpenAIError> {
+    if request.stream == Some(false) {
+        return request_body::create(&http, &client_config, request, body).await;
+    }
+    if request_body::is_needed(&request, body) {
+        return request_body::create_stream(&http, &client_config, request, body).await;
+    }
+    Client::with_config(client_config)
+        .with_http_client(http)
+        .chat()
+        .create_stream(request)
+        .await
+}
+
+fn payload_with<C: ClientConfig>(
+    client_config: C,
+    request: &CreateChatCompletionRequest,
+    body: &RequestConfig,
+) -> Value {
+    let mut url = client_config.url("/chat/completions");
+    let query = client_config.query();
+    if !query.is_empty() {
+        let query = query
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>();
+        url = format!("{url}?{}", query.join("&"));
+    }
+    let headers = client_config.headers();
+    let headers = headers_json(
+        headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.to_str().unwrap_or_default())),
+    );
+    let mut request = request.clone();
+    // As `async_openai` sends it.
+    if request.stream != Some(false) {
+        request.stream = Some(true);
+    }
+    json!({"url": url, "headers": headers, "body": request_body::body(&request, body)})
+}
+
+async fn list_with<C: ClientConfig>(
+    client_config: C,
+    http: reqwest::Client,
+) -> Result<Vec<String>, OpenAIError> {
+    let models = Client::with_config(client_config)
+        .with_http_client(http)
+        .models()
+        .list()
+        .await?;
+    Ok(models.data.into_iter().map(|m| m.id).collect())
+}
+
+impl Backend for OpenAI {
+    fn stream_chat(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> BoxFuture<'_, Result<ChatCompletionResponseStream, OpenAIError>> {
+        async move {
+            let http = self.http.clone();
+            match self.api.clone() {
+                ApiConfig::OpenAI(c) => open_with(c, http, request, &self.body).await,
+                ApiConfig::Azure(c) => open_with(c, http, request, &self.body).await,
+            }
+        }
+        .boxed()
+    }
+
+    fn models(&self) -> BoxFuture<'_, Result<Vec<String>, OpenAIError>> {
+        async move {
+            let http = self.http.clone();
+            match self.api.clone() {
+                ApiConfig::OpenAI(c) => list_with(c, http).await,
+                ApiConfig::Azure(c) => list_with(c, http).await,
+            }
+        }
+        .boxed()
+    }
+
+    fn payload(&self, request: &CreateChatCompletionRequest) -> Value {
+        match self.api.clone() {
+            ApiConfig::OpenAI(c) => payload_with(c, request, &self.body),
+            ApiConfig::Azure(c) => payload_with(c, request, &self.body),
+        }
+    }
+}
+
+/// Anthropic's Messages API. Tools aren't offered to its models.
+struct Anthropic {
+    api_key: String,
+    api_base: String,
+    http: reqwest::Client,
+    body: RequestConfig,
+}
+
+impl Anthropic {
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.api_base.trim_end_matches('/'))
+    }
+
+    fn with_headers(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        request
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+    }
+}
+
+/// A content part of an OpenAI message as a Messages API content block. Text parts are the same.
+fn content_block(part: &Value) -> Value {
+    let url = match part.pointer("/image_url/url").and_then(Value::as_str) {
+        Some(url) => url,
+        None => return part.clone(),
+    };
+    let source = match url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"))
+    {
+        Some((media_type, data)) => {
+            json!({"type": "base64", "media_type": media_type, "data": data})
+        }
+        None => json!({"type": "url", "url": url}),
+    };
+    json!({"type": "image", "source": source})
+}
+
+/// `request` as a Messages API request. System messages become its `system` prompt.
+fn messages_request(request: &CreateChatCompletionRequest) -> Value {
+    let openai = serde_json::to_value(request).expect("requests are always serializable");
+    let mut system = vec![];
+    let mut messages = vec![];
+    for message in openai["messages"].as_array().into_iter().flatten() {
+        let content = &message["content"];
+        match (message["role"].as_str(), content) {
+            (Some("system"), Value::String(text)) => system.push(text.clone()),
+            (Some(role @ ("user" | "assistant")), Value::String(_)) => {
+                messages.push(json!({"role": role, "content": content}))
+            }
+            (Some(role @ ("user" | "assistant")), Value::Array(parts)) => {
+                let blocks: Vec<Value> = parts.iter().map(content_block).collect();
+                messages.push(json!({"role": role, "content": blocks}))
+            }
+            // Tool calls and their results, which can't have been asked for.
+            _ => {}
+        }
+    }
+
+    let mut body = json!({
+        "model": request.model,
+        "max_tokens": request.max_tokens.unwrap_or(ANTHROPIC_MAX_TOKENS),
+        "messages": messages,
+        "stream": true,
+    });
+    if !system.is_empty() {
+        body["system"] = Value::String(system.join("\n\n"));
+    }
+    for param in ["temperature", "top_p"] {
+        if let Some(value) = openai.get(param).filter(|value| !value.is_null()) {
+            body[param] = value.clone();
+        }
+    }
+    match openai.get("stop") {
+        Some(stop @ Value::String(_)) => body["stop_sequences"] = json!([stop]),
+        Some(stop @ Value::Array(_)) => body["stop_sequences"] = stop.clone(),
+        _ => {}
+    }
+    if let Some(user) = openai.get("user").filter(|user| user.is_string()) {
+        body["metadata"] = json!({ "user_id": user });
+    }
+    body
+}
+
+/// An OpenAI chunk of the answer to message `id`.
+fn chunk(
+    id: &str,
+    model: &str,
+    delta: Value,
+    finish_reason: Option<&str>,
+) -> Result<CreateChatCompletionStreamResponse, OpenAIError> {
+    serde_json::from_value(json!({
+        "id": id,
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": model,
+        "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}],
+    }))
+    .map_err(OpenAIError::JSONDeserialize)
+}
+
+/// Translates the `data` of a Messages API event into a chunk, if it has one. `None` ends the
+/// stream.
+#[allow(clippy::type_complexity)]
+fn translate(
+    (id, model): &mut (String, String),
+    data: Result<String, OpenAIError>,
+) -> Option<Option<Result<CreateChatCompletionStreamResponse, OpenAIError>>> {
+    let event: Value = match data
+        .and_then(|data| serde_json::from_str(&data).map_err(OpenAIError::JSONDeserialize))
+    {
+        Ok(event) => event,
+        Err(e) => return Some(Some(Err(e))),
+    };
+    let chunk = match event["type"].as_str() {
+        Some("message_start") => {
+            if let Some(started) = event.pointer("/message/id").and_then(Value::as_str) {
+                *id = started.to_string();
+            }
+            if let Some(answering) = event.pointer("/message/model").and_then(Value::as_str) {
+                *model = answering.to_string();
+            }
+            chunk(id, model, json!({"role": "assistant", "content": ""}), None)
+        }
+        Some("content_block_delta") => {
+            if let Some(thinking) = event.pointer("/delta/thinking").and_then(Value::as_str) {
+                reasoning::show(thinking);
+                return Some(None);
+            }
+            match event.pointer("/delta/text") {
+                Some(text) => {
+                    reasoning::end();
+                    chunk(id, model, json!({ "content": text }), None)
+                }
+                None => return Some(None),
+            }
+        }
+        Some("message_delta") => {
+            let finish_reason = match event.pointer("/delta/stop_reason").and_then(Value::as_str) {
+                Some("max_tokens") => "length",
+                Some("tool_use") => "tool_calls",
+                Some(_) => "stop",
+                None => return Some(None),
+            };
+            chunk(id, model, json!({}), Some(finish_reason))
+        }
+        Some("message_stop") => return None,
+        Some("error") => Err(
+            match serde_json::from_value::<ApiError>(event["error"].clone()) {
+                Ok(e) => OpenAIError::ApiError(e),
+                Err(_) => OpenAIError::StreamError(event.to_string()),
+            },
+        ),
+        // `ping`, and the start and end of content blocks.
+        _ => return Some(None),
+    };
+    Some(Some(chunk))
+}
+
+impl Backend for Anthropic {
+    fn stream_chat(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> BoxFuture<'_, Result<ChatCompletionResponseStream, OpenAIError>> {
+        async move {
+            let mut body = messages_request(&request);
+            request_body::customize(&mut body, &self.body);
+            let response = self
+                .with_headers(self.http.post(self.url("/messages")))
+                .json(&body)
+                .send()
+                .await?;
+            let events = sse::events(sse::check(response).await?);
+            let stream: ChatCompletionResponseStream = Box::pin(
+                events
+                    .scan((String::new(), request.model), |state, data| {
+                        future::ready(translate(state, data))
+                    })
+                    .filter_map(future::ready),
+            );
+            Ok(stream)
+        }
+        .boxed()
+    }
+
+    fn models(&self) -> BoxFuture<'_, Result<Vec<String>, OpenAIError>> {
+        async move {
+            let response = self
+                .with_headers(self.http.get(self.url("/models")))
+                .query(&[("limit", "1000")])
+                .send()
+                .await?;
+            let listed: Value = sse::check(response).await?.json().await?;
+            Ok(listed["data"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|model| model["id"].as_str().map(String::from))
+                .collect())
+        }
+        .boxed()
+    }
+
+    fn payload(&self, request: &CreateChatCompletionRequest) -> Value {
+        let mut body = messages_request(request);
+        request_body::customize(&mut body, &self.body);
+        let headers = headers_json([
+            ("x-api-key", self.api_key.as_str()),
+            ("anthropic-version", ANTHROPIC_VERSION),
+        ]);
+        json!({"url": self.url("/messages"), "headers": headers, "body": body})
+    }
+}
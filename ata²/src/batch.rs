@@ -0,0 +1,310 @@
+//! `ata2 --batch <path>`: asks a file of prompts, one after the other, and writes the answers as
+//! JSON lines, e.g. for evaluation runs or processing documents in bulk.
+//!
+//! The file holds one prompt per line (empty lines and lines starting with `#` are skipped), or,
+//! if it ends in `.yaml` or `.yml`, prompts with metadata:
+//!
+//! ```yaml
+//! model: gpt-4            # optional, like temperature
+//! fresh: true             # ask each prompt in a new conversation, like --fresh
+//! prompts:
+//!   - Summarize RFC 2324 in one sentence.
+//!   - prompt: Translate "teapot" into French.
+//!     id: teapot          # optional, copied to the output
+//!     model: gpt-4o       # optional, for this prompt only
+//!     metadata: {lang: fr} # optional, copied to the output
+//! ```
+//!
+//! With `--jobs N` and `--fresh`, N prompts are asked at once, each in a conversation of its own.
+//! Prompts are asked as in the REPL, except that the answers aren't copied, paged, read aloud or
+//! saved, and questions, e.g. whether to run a command, are declined. If they are estimated to
+//! cost more than `confirm_cost_above`, the batch has to be confirmed first.
+//!
+//! Each line of the output has the `index` of the prompt (from 1), its `id` and `metadata` if
+//! given, the `prompt`, the `answer`, the `model` that answered and how long it took in
+//! `elapsed_ms`, or the `error` if there was no answer.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use futures_util::stream::{self, StreamExt as _};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::alts;
+use crate::ask;
+use crate::forecast::Forecast;
+use crate::output::NullSink;
+use crate::params::{self, Overrides};
+use crate::prompt::{self, Detached, RequestOptions, CONVERSATION};
+use crate::readline::string_to_chat_completion_request_user_message;
+use crate::sessions;
+use crate::Config;
+use crate::TokioResult;
+use crate::CONFIGURATION;
+use crate::FLAGS;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Batch {
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    temperature: Option<f64>,
+    #[serde(default)]
+    fresh: bool,
+    prompts: Vec<Item>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Item {
+    Plain(String),
+    Detailed(Detailed),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Detailed {
+    prompt: String,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    metadata: Option<Value>,
+}
+
+impl From<Item> for Detailed {
+    fn from(item: Item) -> Self {
+        match item {
+            Item::Plain(prompt) => Detailed {
+                prompt,
+                id: None,
+                model: None,
+                metadata: None,
+            },
+            Item::Detailed(detailed) => detailed,
+        }
+    }
+}
+
+/// A line of the output.
+#[derive(Serialize)]
+struct Answer<'a> {
+    index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<&'a Value>,
+    prompt: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    answer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    elapsed_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn read(path: &Path) -> TokioResult<Batch> {
+    let contents = fs::read_to_string(path)?;
+    let is_yaml = path
+        .extension()
+        .map_or(false, |e| e == "yaml" || e == "yml");
+    if is_yaml {
+        return Ok(serde_yaml::from_str(&contents)?);
+    }
+    Ok(Batch {
+        model: None,
+        temperature: None,
+        fresh: false,
+        prompts: contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| Item::Plain(line.to_string()))
+            .collect(),
+    })
+}
+
+/// Starts a new conversation, like `/clear`.
+async fn clear() {
+    CONVERSATION.lock().await.clear();
+    alts::truncate(0);
+    sessions::truncate(0);
+}
+
+/// Asks `item` in a conversation of its own, outside of the REPL's, so that several can be asked
+/// at once. Returns the answer and the model that gave it.
+async fn ask_alone(
+    item: &Detailed,
+    mut options: RequestOptions,
+) -> Result<(String, String), String> {
+    let conversation = Arc::new(Detached::default());
+    options.conversation = Some(conversation.clone());
+    let deltas = prompt::request_with(&mut NullSink, Some(item.prompt.clone()), options)
+        .await
+        .map_err(|e| e.to_string())?;
+    let answer = prompt::response_text(deltas);
+    let model = conversation.model.lock().unwrap().take();
+    Ok((answer, model.unwrap_or_default()))
+}
+
+/// Asks `item` in the REPL's conversation, after the prompts before it.
+async fn ask_in_conversation(
+    item: &Detailed,
+    options: RequestOptions,
+) -> Result<(String, String), String> {
+    let deltas = prompt::request_with(&mut NullSink, Some(item.prompt.clone()), options)
+        .await
+        .map_err(|e| e.to_string())?;
+    let answer = prompt::response_text(deltas);
+    let model = sessions::model_at(CONVERSATION.lock().await.len().saturating_sub(1));
+    Ok((answer, model.unwrap_or_default()))
+}
+
+/// What asking `items` is estimated to cost, each in a new conversation if `fresh`, otherwise one
+/// after the other in the REPL's.
+fn forecast(config: &Config, items: &[Detailed], fresh: bool) -> Forecast {
+    let prompts: Vec<_> = items
+        .iter()
+        .map(|item| string_to_chat_completion_request_user_message(item.prompt.clone()))
+        .collect();
+    if !fresh {
+        return Forecast::replay(config, &prompts);
+    }
+    let mut forecast = Forecast {
+        model: config.model.clone(),
+        ..Default::default()
+    };
+    for prompt in prompts {
+        let alone = Forecast::replay(config, &[prompt]);
+        forecast.add(alone.input_tokens, alone.output_tokens);
+    }
+    forecast
+}
+
+/// The options to ask `item` with.
+fn options(item: &Detailed, overrides: &Overrides) -> RequestOptions {
+    let mut options = RequestOptions {
+        overrides: overrides.clone(),
+        quiet: true,
+        ..Default::default()
+    };
+    if let Some(ref model) = item.model {
+        options.overrides.set_model(model);
+    }
+    options
+}
+
+/// `ata2 --batch`: asks the prompts in the file at `path`, writing the answers to `output`, or to
+/// stdout. With `fresh`, each prompt is asked in a new conversation; otherwise each follows up on
+/// the ones before. With `jobs` above 1, that many fresh prompts are asked at once (within
+/// `max_concurrent_requests` and `[rate_limit]`), and the answers still written in order.
+pub async fn run(path: &Path, output: Option<&Path>, fresh: bool, jobs: usize) -> TokioResult<()> {
+    let batch = read(path)?;
+    let mut overrides = Overrides::default();
+    if let Some(ref model) = batch.model {
+        overrides.set_model(model);
+    }
+    if let Some(temperature) = batch.temperature {
+        overrides.set("temperature", &temperature.to_string())?;
+    }
+    let fresh = fresh || batch.fresh;
+    if jobs > 1 && !fresh {
+        return Err(
+            "--jobs needs --fresh (or `fresh: true`): prompts following up on each \
+            other can't be asked at once"
+                .into(),
+        );
+    }
+    let count = batch.prompts.len();
+    let items: Vec<Detailed> = batch.prompts.into_iter().map(Detailed::from).collect();
+    let config = params::effective_config(&CONFIGURATION.load(), &overrides)?;
+    if !FLAGS.dry_run && !forecast(&config, &items, fresh).confirm(&config, FLAGS.yes) {
+        return Err("Not asking the prompts".into());
+    }
+    // Nobody is at the REPL to answer questions.
+    ask::set_unattended();
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+
+    let overrides = &overrides;
+    let asked = stream::iter(items.iter().enumerate()).map(|(i, item)| async move {
+        eprintln!(
+            "[{}/{count}] {}",
+            i + 1,
+            item.id.as_deref().unwrap_or(&item.prompt)
+        );
+        let options = options(item, overrides);
+        let started = Instant::now();
+        let result = if jobs > 1 {
+            ask_alone(item, options).await
+        } else {
+            if fresh {
+                clear().await;
+            }
+            ask_in_conversation(item, options).await
+        };
+        (result, started.elapsed().as_millis())
+    });
+    // Answers are taken in order, however many are being asked.
+    let mut asked = asked.buffered(jobs.max(1));
+
+    let mut failed = 0;
+    let mut index = 0;
+    while let Some((result, elapsed_ms)) = asked.next().await {
+        let item = &items[index];
+        index += 1;
+        let (answer, model, error) = match result {
+            Ok((answer, model)) if !answer.is_empty() => (Some(answer), Some(model), None),
+            Ok(_) => (None, None, Some(String::from("there is no answer"))),
+            Err(e) => (None, None, Some(e)),
+        };
+        if let Some(ref error) = error {
+            error!("Prompt {index}: {error}");
+            failed += 1;
+        }
+        let line = Answer {
+            index,
+            id: item.id.as_deref(),
+            metadata: item.metadata.as_ref(),
+            prompt: &item.prompt,
+            answer,
+            model: model.filter(|m| !m.is_empty()),
+            elapsed_ms,
+            error,
+        };
+        writeln!(out, "{}", serde_json::to_string(&line)?)?;
+        out.flush()?;
+    }
+    if failed > 0 {
+        return Err(format!("{failed} of {count} prompts got no answer").into());
+    }
+    info!("Answered all {count} prompts");
+    Ok(())
+}
@@ -0,0 +1,148 @@
+//! What models can do: context window, longest answer, and support for tools and reasoning.
+//! Consulted when validating the configuration, building requests and deciding how much of the
+//! conversation fits.
+//!
+//! A registry is built into the binary (`capabilities.json`). Entries of `capabilities.json` in the
+//! cache directory, e.g. for a local model, take precedence. Dated snapshots such as
+//! `gpt-4o-2024-08-06` fall back to the model they are a snapshot of, but other names are only
+//! looked up as they are, so that e.g. `gpt-4.1` isn't taken for `gpt-4`.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use crate::config;
+
+const BUILTIN_CAPABILITIES: &str = include_str!("capabilities.json");
+
+/// What a model can do. Unset values are unknown, and not held against the model.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct Capabilities {
+    /// Tokens taken in, prompt and answer together.
+    pub context_window: Option<u64>,
+    /// The most tokens an answer may have.
+    pub max_output_tokens: Option<u64>,
+    pub tools: Option<bool>,
+    /// Thinks before answering, like o1, taking `max_completion_tokens` rather than `max_tokens`
+    /// and no sampling parameters.
+    pub reasoning: Option<bool>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Registry {
+    models: BTreeMap<String, Capabilities>,
+}
+
+/// The user's additions and corrections.
+pub fn override_path() -> PathBuf {
+    config::get_cache_dir().join("capabilities.json")
+}
+
+impl Registry {
+    fn load() -> Self {
+        let mut registry: Self = serde_json::from_str(BUILTIN_CAPABILITIES)
+            .expect("built-in capability registry is invalid");
+        let path = override_path();
+        if let Ok(contents) = fs::read_to_string(&path) {
+            match serde_json::from_str::<Self>(&contents) {
+                Ok(overrides) => registry.models.extend(overrides.models),
+                Err(e) => warn!(
+                    "Ignoring invalid capability registry {}: {e}",
+                    path.to_string_lossy()
+                ),
+            }
+        }
+        registry
+    }
+}
+
+lazy_static! {
+    static ref REGISTRY: RwLock<Registry> = RwLock::new(Registry::load());
+}
+
+/// Reads the user's additions and corrections again, e.g. once the configuration is reloaded.
+pub fn reload() {
+    *REGISTRY.write().unwrap() = Registry::load();
+}
+
+/// Whether `model` is a dated snapshot of `name`, e.g. `gpt-4-0613` of `gpt-4`.
+fn is_snapshot_of(model: &str, name: &str) -> bool {
+    model
+        .strip_prefix(name)
+        .and_then(|rest| rest.strip_prefix('-'))
+        .map_or(false, |date| date.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+/// What `model` can do, if it or the model it is a snapshot of is in the registry.
+pub fn lookup(model: &str) -> Option<Capabilities> {
+    let registry = REGISTRY.read().unwrap();
+    if let Some(capabilities) = registry.models.get(model) {
+        return Some(*capabilities);
+    }
+    registry
+        .models
+        .iter()
+        .filter(|(name, _)| is_snapshot_of(model, name))
+        .max_by_key(|(name, _)| name.len())
+        .map(|(_, capabilities)| *capabilities)
+}
+
+/// The models in the registry.
+pub fn models() -> impl Iterator<Item = String> {
+    let names: Vec<String> = REGISTRY.read().unwrap().models.keys().cloned().collect();
+    names.into_iter()
+}
+
+/// Whether `model` is known to be a reasoning model.
+pub fn is_reasoning(model: &str) -> bool {
+    lookup(model).and_then(|c| c.reasoning) == Some(true)
+}
+
+/// Whether `model` is known not to take tools.
+pub fn lacks_tools(model: &str) -> bool {
+    lookup(model).and_then(|c| c.tools) == Some(false)
+}
+
+impl Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![];
+        if let Some(window) = self.context_window {
+            parts.push(format!("{window} tokens of context"));
+        }
+        if let Some(max) = self.max_output_tokens {
+            parts.push(format!("answers up to {max} tokens"));
+        }
+        let features: Vec<_> = [(self.tools, "tools"), (self.reasoning, "reasoning")]
+            .into_iter()
+            .filter(|(supported, _)| *supported == Some(true))
+            .map(|(_, name)| name)
+            .collect();
+        if !features.is_empty() {
+            parts.push(features.join(", "));
+        }
+        write!(f, "{}", parts.join("; "))
+    }
+}
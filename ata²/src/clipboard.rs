@@ -0,0 +1,180 @@
+//! The system clipboard: copying answers (`ui.copy_response`, F3, `/copy`) and pasting into
+//! prompts (`/paste`).
+//!
+//! The clipboard is reached with arboard, or failing that (e.g. over SSH), through the usual
+//! programs (`pbcopy`, `wl-copy`, `xclip`…), and for copying, through the terminal with OSC 52.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use base64::Engine as _;
+
+use std::io::{self, Write as _};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+use crate::config::CopyResponse;
+use crate::extract;
+use crate::output::eprint_and_flush;
+use crate::params;
+
+/// Programs that put their stdin on the clipboard, in the order they are tried.
+const COPY_PROGRAMS: &[(&str, &[&str])] = &[
+    ("pbcopy", &[]),
+    ("wl-copy", &[]),
+    ("xclip", &["-selection", "clipboard"]),
+    ("xsel", &["--clipboard", "--input"]),
+    ("clip.exe", &[]),
+];
+
+/// Programs that print the clipboard, in the order they are tried.
+const PASTE_PROGRAMS: &[(&str, &[&str])] = &[
+    ("pbpaste", &[]),
+    ("wl-paste", &["--no-newline"]),
+    ("xclip", &["-selection", "clipboard", "-o"]),
+    ("xsel", &["--clipboard", "--output"]),
+    (
+        "powershell.exe",
+        &["-NoProfile", "-Command", "Get-Clipboard"],
+    ),
+];
+
+lazy_static! {
+    /// Kept for as long as the process runs: on X11 and Wayland, what was copied is served by it.
+    static ref CLIPBOARD: Mutex<Option<arboard::Clipboard>> =
+        Mutex::new(arboard::Clipboard::new().ok());
+    /// The last answer, complete.
+    static ref LAST: Mutex<Option<String>> = Mutex::new(None);
+}
+
+/// Puts `text` on the clipboard, returning how.
+pub fn copy(text: &str) -> io::Result<&'static str> {
+    if let Some(clipboard) = CLIPBOARD.lock().unwrap().as_mut() {
+        if clipboard.set_text(text.to_string()).is_ok() {
+            return Ok("arboard");
+        }
+    }
+    for &(program, args) in COPY_PROGRAMS {
+        let mut child = match Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => child,
+            // Not installed.
+            Err(_) => continue,
+        };
+        child.stdin.take().unwrap().write_all(text.as_bytes())?;
+        // e.g. `wl-copy` outside of Wayland.
+        if child.wait()?.success() {
+            return Ok(program);
+        }
+    }
+    if atty::is(atty::Stream::Stderr) {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(text);
+        eprint_and_flush(&format!("\x1b]52;c;{encoded}\x07"));
+        return Ok("OSC 52");
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "found neither a clipboard program nor a terminal",
+    ))
+}
+
+/// If the accepted `line` is `/paste`, the text typed after it.
+pub fn paste_requested(line: &str) -> Option<&str> {
+    let rest = line.trim_start().strip_prefix("/paste")?;
+    (rest.is_empty() || rest.starts_with(char::is_whitespace)).then(|| rest.trim())
+}
+
+/// The text on the clipboard.
+pub fn paste() -> io::Result<String> {
+    if let Some(clipboard) = CLIPBOARD.lock().unwrap().as_mut() {
+        if let Ok(text) = clipboard.get_text() {
+            return Ok(text);
+        }
+    }
+    for &(program, args) in PASTE_PROGRAMS {
+        match Command::new(program)
+            .args(args)
+            .stderr(Stdio::null())
+            .output()
+        {
+            Ok(output) if output.status.success() => {
+                return Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+            }
+            _ => continue,
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "found no clipboard program to paste with",
+    ))
+}
+
+/// Copies the last answer, or with `code`, its last code block. Returns what happened.
+pub fn copy_last(code: bool) -> Result<String, String> {
+    let answer = LAST
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or_else(|| String::from("There is no answer to copy yet"))?;
+    let (text, what) = if code {
+        let block = extract::code_blocks(&answer)
+            .pop()
+            .ok_or_else(|| String::from("The last answer has no code block"))?;
+        (block.code, "code block")
+    } else {
+        (answer, "answer")
+    };
+    match copy(&text) {
+        Ok(how) => Ok(format!("Copied the {what} to the clipboard ({how})")),
+        Err(e) => Err(format!("Could not copy the {what}: {e}")),
+    }
+}
+
+/// F3: copies the last answer, or its last code block if it has one and `ui.copy_code_block` is
+/// set.
+pub fn copy_preferred() -> Result<String, String> {
+    let has_code = LAST
+        .lock()
+        .unwrap()
+        .as_deref()
+        .map_or(false, |answer| !extract::code_blocks(answer).is_empty());
+    copy_last(params::current_config().ui.copy_code_block && has_code)
+}
+
+/// The last finished answer, if any.
+pub fn last() -> Option<String> {
+    LAST.lock().unwrap().clone()
+}
+
+/// Notes the finished `answer` for F3 and `/copy`, and copies it or offers to, as configured.
+pub fn answered(answer: &str) {
+    *LAST.lock().unwrap() = Some(answer.to_string());
+    match params::current_config().ui.copy_response {
+        CopyResponse::Never => {}
+        CopyResponse::Ask => info!("Press F3 to copy the answer"),
+        CopyResponse::Always => match copy_preferred() {
+            Ok(msg) => info!("{msg}"),
+            Err(e) => warn!("{e}"),
+        },
+    }
+}
@@ -0,0 +1,699 @@
+//! Slash commands: lines starting with `/` that are handled by ata² instead of being sent to the
+//! API.
+//!
+//! Commands implement [`Command`] and are looked up by name in a [`Registry`]. The built-in ones
+//! are in [`COMMANDS`].
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::types::ChatCompletionResponseStreamMessage;
+use futures_util::future::{BoxFuture, FutureExt as _};
+
+use std::path::PathBuf;
+
+use crate::alts;
+use crate::apply;
+use crate::audio;
+use crate::capabilities;
+use crate::clipboard;
+use crate::context;
+use crate::duplicates;
+use crate::export;
+use crate::git;
+use crate::index;
+use crate::memory;
+use crate::models;
+use crate::params;
+use crate::prompt::{self, finish_prompt, print_error, CONVERSATION};
+use crate::reasoning;
+use crate::reload;
+use crate::search;
+use crate::sessions;
+use crate::title;
+use crate::undo;
+use crate::web;
+use crate::TokioResult;
+use crate::CONFIGURATION;
+
+/// What a command returns: the answer, if it asked the model something.
+pub type CommandResult = TokioResult<Vec<ChatCompletionResponseStreamMessage>>;
+
+pub trait Command: Send + Sync {
+    /// The name, including the slash.
+    fn name(&self) -> &'static str;
+
+    /// How to call it, e.g. `/export [--redact] <format> [path]`.
+    fn usage(&self) -> &'static str;
+
+    /// One line, for `/help`.
+    fn description(&self) -> &'static str;
+
+    fn run<'a>(&'a self, args: &'a str) -> BoxFuture<'a, CommandResult>;
+}
+
+/// A command implemented by a function.
+struct Builtin {
+    name: &'static str,
+    usage: &'static str,
+    description: &'static str,
+    run: for<'a> fn(&'a str) -> BoxFuture<'a, CommandResult>,
+}
+
+impl Command for Builtin {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn usage(&self) -> &'static str {
+        self.usage
+    }
+
+    fn description(&self) -> &'static str {
+        self.description
+    }
+
+    fn run<'a>(&'a self, args: &'a str) -> BoxFuture<'a, CommandResult> {
+        (self.run)(args)
+    }
+}
+
+#[derive(Default)]
+pub struct Registry {
+    commands: Vec<Box<dyn Command>>,
+}
+
+impl Registry {
+    /// Adds `command`, replacing any command of the same name.
+    pub fn register(&mut self, command: Box<dyn Command>) {
+        self.commands.retain(|c| c.name() != command.name());
+        self.commands.push(command);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Command> {
+        self.commands
+            .iter()
+            .find(|c| c.name() == name)
+            .map(|c| c.as_ref())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Command> {
+        self.commands.iter().map(|c| c.as_ref())
+    }
+}
+
+lazy_static! {
+    pub static ref COMMANDS: Registry = {
+        let mut registry = Registry::default();
+        for builtin in builtins() {
+            registry.register(Box::new(builtin));
+        }
+        registry
+    };
+}
+
+/// Whether `word`, the first word of a line, is meant as a command rather than e.g. a path.
+pub fn looks_like_command(word: &str) -> bool {
+    word.len() > 1 && word.starts_with('/') && !word[1..].contains('/')
+}
+
+/// Prints the outcome of a command that doesn't ask the model anything.
+fn report(result: Result<String, String>) -> CommandResult {
+    match result {
+        Ok(msg) => {
+            info!("{msg}");
+            finish_prompt();
+        }
+        Err(e) => print_error(&e),
+    }
+    Ok(vec![])
+}
+
+fn help(_args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        let width = COMMANDS.iter().map(|c| c.usage().len()).max().unwrap_or(0);
+        for command in COMMANDS.iter() {
+            eprintln!("{:<width$}  {}", command.usage(), command.description());
+        }
+        finish_prompt();
+        Ok(vec![])
+    }
+    .boxed()
+}
+
+fn clear(_args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        CONVERSATION.lock().await.clear();
+        alts::truncate(0);
+        sessions::truncate(0);
+        report(Ok(String::from("Cleared the conversation")))
+    }
+    .boxed()
+}
+
+fn save(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        let path = Some(args).filter(|a| !a.is_empty()).map(PathBuf::from);
+        let conversation = CONVERSATION.lock().await.clone();
+        report(
+            prompt::save_conversation(&conversation, path)
+                .map(|path| format!("Saved conversation to {}", path.display()))
+                .map_err(|e| format!("Could not save the conversation: {e}")),
+        )
+    }
+    .boxed()
+}
+
+fn model(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        if args.is_empty() {
+            let model = params::current_model();
+            return report(Ok(match capabilities::lookup(&model) {
+                Some(capabilities) => format!("Model: {model} ({capabilities})"),
+                None => format!("Model: {model}"),
+            }));
+        }
+        let result = params::set_command(&format!("model {args}"));
+        title::idle();
+        report(result)
+    }
+    .boxed()
+}
+
+fn models(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        models::command(args).await;
+        Ok(vec![])
+    }
+    .boxed()
+}
+
+fn continue_(_args: &str) -> BoxFuture<'_, CommandResult> {
+    prompt::continue_last().boxed()
+}
+
+fn dry(args: &str) -> BoxFuture<'_, CommandResult> {
+    prompt::dry_run(args).boxed()
+}
+
+fn retry(args: &str) -> BoxFuture<'_, CommandResult> {
+    alts::retry(args).boxed()
+}
+
+fn alts(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        alts::command(args).await;
+        Ok(vec![])
+    }
+    .boxed()
+}
+
+fn previous(_args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        duplicates::previous().await;
+        Ok(vec![])
+    }
+    .boxed()
+}
+
+fn web(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        let config = CONFIGURATION.load_full();
+        match web::prompt(args, &config.tools.web).await {
+            Ok(prompt) => prompt::request(Some(prompt), None).await,
+            Err(e) => report(Err(e)),
+        }
+    }
+    .boxed()
+}
+
+fn rag(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move { report(index::command(args)) }.boxed()
+}
+
+fn remember(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move { report(memory::remember(args)) }.boxed()
+}
+
+fn memories(_args: &str) -> BoxFuture<'_, CommandResult> {
+    async move { report(memory::list()) }.boxed()
+}
+
+fn forget(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move { report(memory::forget(args)) }.boxed()
+}
+
+fn undo(_args: &str) -> BoxFuture<'_, CommandResult> {
+    async move { report(undo::undo().await) }.boxed()
+}
+
+fn pop(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move { report(undo::pop(args).await) }.boxed()
+}
+
+fn edit_last(_args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        // Like /edit, it needs the terminal, so it is handled before getting here.
+        report(Err(String::from(
+            "/edit-last only works when typed at the prompt",
+        )))
+    }
+    .boxed()
+}
+
+fn edit(_args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        // Typed at the prompt, it is handled before getting here, while the terminal is free.
+        report(Err(String::from(
+            "/edit only works when typed at the prompt",
+        )))
+    }
+    .boxed()
+}
+
+fn copy(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        match args {
+            "" => report(clipboard::copy_last(false)),
+            "code" => report(clipboard::copy_last(true)),
+            _ => report(Err(String::from("Usage: /copy [code]"))),
+        }
+    }
+    .boxed()
+}
+
+fn page(_args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        // Typed at the prompt, it is handled before getting here, while the terminal is free.
+        report(Err(String::from(
+            "/page only works when typed at the prompt",
+        )))
+    }
+    .boxed()
+}
+
+fn paste(_args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        // Like /edit, it needs the terminal, so it is handled before getting here.
+        report(Err(String::from(
+            "/paste only works when typed at the prompt",
+        )))
+    }
+    .boxed()
+}
+
+fn speak(_args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        // Like /paste, it needs the terminal, so it is handled before getting here.
+        report(Err(String::from(
+            "/speak only works when typed at the prompt",
+        )))
+    }
+    .boxed()
+}
+
+fn apply(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move { report(apply::command(args).await) }.boxed()
+}
+
+fn commitmsg(args: &str) -> BoxFuture<'_, CommandResult> {
+    git::commit_message(args).boxed()
+}
+
+fn diff(args: &str) -> BoxFuture<'_, CommandResult> {
+    git::review(args).boxed()
+}
+
+fn tts(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move { report(audio::tts_command(args)) }.boxed()
+}
+
+fn reload(_args: &str) -> BoxFuture<'_, CommandResult> {
+    async move { report(reload::reload()) }.boxed()
+}
+
+fn reasoning(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move { report(reasoning::command(args)) }.boxed()
+}
+
+fn sessions_(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        match args.split_once(char::is_whitespace) {
+            _ if args.is_empty() => {
+                sessions::print_list();
+                finish_prompt();
+                Ok(vec![])
+            }
+            Some(("delete", id)) => report(sessions::delete(id.trim())),
+            _ => report(Err(String::from("Usage: /sessions [delete ID]"))),
+        }
+    }
+    .boxed()
+}
+
+fn resume(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        let result = sessions::switch(args).await;
+        title::idle();
+        report(result)
+    }
+    .boxed()
+}
+
+fn search_(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        let result = search::command(args).await;
+        title::idle();
+        report(result)
+    }
+    .boxed()
+}
+
+fn tag(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move { report(Ok(sessions::tag_current(args))) }.boxed()
+}
+
+fn checkpoint(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move { report(Ok(sessions::checkpoint(args).await)) }.boxed()
+}
+
+fn rollback(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move { report(sessions::rollback(args).await) }.boxed()
+}
+
+fn context(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        context::command(args).await;
+        Ok(vec![])
+    }
+    .boxed()
+}
+
+fn export(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        report(
+            export::command(args)
+                .await
+                .map(|destination| format!("Exported conversation to {destination}"))
+                .map_err(|e| format!("Export failed: {e}")),
+        )
+    }
+    .boxed()
+}
+
+fn profile(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        let result = params::profile_command(args);
+        title::idle();
+        report(result)
+    }
+    .boxed()
+}
+
+fn set(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move { report(params::set_command(args)) }.boxed()
+}
+
+fn show(_args: &str) -> BoxFuture<'_, CommandResult> {
+    async move {
+        match params::effective_config(&CONFIGURATION.load(), &Default::default()) {
+            Ok(config) => {
+                eprint!("{config}");
+                finish_prompt();
+            }
+            Err(e) => print_error(&format!("Invalid parameters: {e}")),
+        }
+        Ok(vec![])
+    }
+    .boxed()
+}
+
+fn unset(args: &str) -> BoxFuture<'_, CommandResult> {
+    async move { report(params::unset_command(args)) }.boxed()
+}
+
+fn builtins() -> Vec<Builtin> {
+    vec![
+        Builtin {
+            name: "/help",
+            usage: "/help",
+            description: "List the commands.",
+            run: help,
+        },
+        Builtin {
+            name: "/clear",
+            usage: "/clear",
+            description: "Start a new conversation.",
+            run: clear,
+        },
+        Builtin {
+            name: "/save",
+            usage: "/save [path]",
+            description: "Save the conversation as JSON (like F2), to be loaded with --load.",
+            run: save,
+        },
+        Builtin {
+            name: "/edit",
+            usage: "/edit [text]",
+            description: "Compose the prompt in $EDITOR, starting from text (also Ctrl-X Ctrl-E).",
+            run: edit,
+        },
+        Builtin {
+            name: "/copy",
+            usage: "/copy [code]",
+            description: "Copy the last answer, or its last code block, to the clipboard.",
+            run: copy,
+        },
+        Builtin {
+            name: "/page",
+            usage: "/page",
+            description: "Show the last answer in $PAGER (less -R by default).",
+            run: page,
+        },
+        Builtin {
+            name: "/paste",
+            usage: "/paste [text]",
+            description: "Start the next prompt with text and what is on the clipboard.",
+            run: paste,
+        },
+        Builtin {
+            name: "/speak",
+            usage: "/speak",
+            description: "Record the next prompt from the microphone, to be edited before sending.",
+            run: speak,
+        },
+        Builtin {
+            name: "/apply",
+            usage: "/apply [path]",
+            description: "Review the changes the last answer proposes to a file, hunk by hunk.",
+            run: apply,
+        },
+        Builtin {
+            name: "/commitmsg",
+            usage: "/commitmsg [range]",
+            description: "Write a commit message for what is staged, or for a range of commits.",
+            run: commitmsg,
+        },
+        Builtin {
+            name: "/diff",
+            usage: "/diff [range]",
+            description: "Review what is staged, or a range of commits.",
+            run: diff,
+        },
+        Builtin {
+            name: "/tts",
+            usage: "/tts [on|off]",
+            description: "Show whether answers are read aloud, or turn it on or off.",
+            run: tts,
+        },
+        Builtin {
+            name: "/reasoning",
+            usage: "/reasoning [on|off]",
+            description: "Show whether the reasoning models send is shown, or turn it on or off.",
+            run: reasoning,
+        },
+        Builtin {
+            name: "/model",
+            usage: "/model [name]",
+            description: "Show the model, or switch to another one for the rest of the session.",
+            run: model,
+        },
+        Builtin {
+            name: "/models",
+            usage: "/models [filter]",
+            description: "List the models the API offers, marking the one in use.",
+            run: models,
+        },
+        Builtin {
+            name: "/continue",
+            usage: "/continue",
+            description: "Resume the last answer exactly where it stopped.",
+            run: continue_,
+        },
+        Builtin {
+            name: "/dry",
+            usage: "/dry [prompt]",
+            description: "Print the request prompt would make, without sending it.",
+            run: dry,
+        },
+        Builtin {
+            name: "/retry",
+            usage: "/retry [n]",
+            description: "Generate a new answer (or n to choose from) to the last prompt.",
+            run: retry,
+        },
+        Builtin {
+            name: "/undo",
+            usage: "/undo",
+            description: "Remove the last prompt and its answer.",
+            run: undo,
+        },
+        Builtin {
+            name: "/pop",
+            usage: "/pop [n]",
+            description: "Remove the last n messages (by default one).",
+            run: pop,
+        },
+        Builtin {
+            name: "/edit-last",
+            usage: "/edit-last",
+            description: "Edit the last prompt in $EDITOR and send it again instead.",
+            run: edit_last,
+        },
+        Builtin {
+            name: "/alts",
+            usage: "/alts [next|prev|n]",
+            description: "List the alternative answers to the last prompt, or choose one.",
+            run: alts,
+        },
+        Builtin {
+            name: "/previous",
+            usage: "/previous",
+            description: "Show the earlier answer to a prompt held back as a duplicate.",
+            run: previous,
+        },
+        Builtin {
+            name: "/sessions",
+            usage: "/sessions [delete ID]",
+            description: "List the saved sessions, marking the current one, or delete one.",
+            run: sessions_,
+        },
+        Builtin {
+            name: "/resume",
+            usage: "/resume [ID]",
+            description: "Save this session and continue another (by default the last updated).",
+            run: resume,
+        },
+        Builtin {
+            name: "/search",
+            usage: "/search <text|/regex/>",
+            description: "Find text in the history and saved sessions, and open a session found.",
+            run: search_,
+        },
+        Builtin {
+            name: "/tag",
+            usage: "/tag [tags]",
+            description: "Show or change the session's tags, e.g. rust,work (-work removes it).",
+            run: tag,
+        },
+        Builtin {
+            name: "/checkpoint",
+            usage: "/checkpoint [name]",
+            description: "Save the conversation as it is, to come back to with /rollback.",
+            run: checkpoint,
+        },
+        Builtin {
+            name: "/rollback",
+            usage: "/rollback [name]",
+            description: "Bring the conversation back to a checkpoint (by default the last one).",
+            run: rollback,
+        },
+        Builtin {
+            name: "/web",
+            usage: "/web <query>",
+            description: "Search the web and answer from the pages found ([tools.web]).",
+            run: web,
+        },
+        Builtin {
+            name: "/rag",
+            usage: "/rag [on|off]",
+            description: "Send the passages of the index (ata2 index) most like each prompt.",
+            run: rag,
+        },
+        Builtin {
+            name: "/remember",
+            usage: "/remember <text>",
+            description: "Tell the model text at the start of every new conversation.",
+            run: remember,
+        },
+        Builtin {
+            name: "/memories",
+            usage: "/memories",
+            description: "List the facts saved with /remember.",
+            run: memories,
+        },
+        Builtin {
+            name: "/forget",
+            usage: "/forget <id>",
+            description: "Remove a fact saved with /remember.",
+            run: forget,
+        },
+        Builtin {
+            name: "/context",
+            usage: "/context [prompt]",
+            description: "Show what the next request is made of, in tokens.",
+            run: context,
+        },
+        Builtin {
+            name: "/export",
+            usage: "/export [--redact] md|org|html|gist [path]",
+            description: "Export the conversation as a Markdown, Org or HTML document, or a gist.",
+            run: export,
+        },
+        Builtin {
+            name: "/profile",
+            usage: "/profile [name|-]",
+            description: "Show the profile, or switch to another one (- for none).",
+            run: profile,
+        },
+        Builtin {
+            name: "/reload",
+            usage: "/reload",
+            description: "Read the configuration file again.",
+            run: reload,
+        },
+        Builtin {
+            name: "/set",
+            usage: "/set [key value]",
+            description: "Override a parameter for the rest of the session, or show overrides.",
+            run: set,
+        },
+        Builtin {
+            name: "/show",
+            usage: "/show",
+            description: "Show the parameters requests are made with, and where they come from.",
+            run: show,
+        },
+        Builtin {
+            name: "/unset",
+            usage: "/unset key",
+            description: "Remove a session override.",
+            run: unset,
+        },
+    ]
+}
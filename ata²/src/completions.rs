@@ -0,0 +1,61 @@
+//! `ata2 completions <shell>`: shell completion scripts, generated with [`clap_complete`].
+//!
+//! The scripts are static, so values that depend on the configuration, the names of the
+//! `[profiles]` for `--profile`, are those configured when the script is generated. Generate it
+//! again after adding a profile.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use clap::builder::PossibleValuesParser;
+use clap::CommandFactory as _;
+use clap_complete::Shell;
+
+use std::fs;
+use std::io;
+
+use crate::args::Ata2;
+use crate::config::ConfigLocation;
+
+/// The names of the profiles in the configuration file at `location`, if it can be read. The
+/// configuration isn't otherwise validated: completions work even if it is invalid.
+fn profile_names(location: &ConfigLocation) -> Vec<String> {
+    let contents = match fs::read_to_string(location.location()) {
+        Ok(contents) => contents,
+        Err(_) => return vec![],
+    };
+    contents
+        .parse::<toml::Value>()
+        .ok()
+        .as_ref()
+        .and_then(|config| config.get("profiles")?.as_table())
+        .map(|profiles| profiles.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Prints the completion script for `shell`.
+pub fn print(shell: Shell, location: &ConfigLocation) {
+    let mut command = Ata2::command();
+    let profiles = profile_names(location);
+    if !profiles.is_empty() {
+        command = command.mut_arg("profile", |arg| {
+            arg.value_parser(PossibleValuesParser::new(profiles))
+        });
+    }
+    clap_complete::generate(shell, &mut command, "ata2", &mut io::stdout());
+}
@@ -18,26 +18,41 @@
 //!  See the License for the specific language governing permissions and
 //!  limitations under the License.
 
+use std::collections::BTreeMap;
 use std::collections::HashMap as StdHashMap;
+use std::collections::HashSet;
 use std::convert::Infallible;
 use std::env;
 use std::ffi::OsString;
 use std::fmt::{self, Display};
+use std::fs;
 
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
-use ansi_colors::ColouredStr;
-use async_openai::{config::OpenAIConfig, types::CreateChatCompletionRequestArgs};
-use bevy_reflect::{Reflect, Struct};
+use async_openai::config::{AzureConfig, OpenAIConfig};
+use async_openai::error::OpenAIError;
+use async_openai::types::CreateChatCompletionRequestArgs;
+use bevy_reflect::{Reflect, ReflectRef, Struct};
 use bevy_utils::HashMap;
 use directories::ProjectDirs;
 use os_str_bytes::OsStrBytes as _;
 use os_str_bytes::OsStringBytes as _;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::{Number, Value};
 use toml::de::Error as TomlError;
 
+use crate::capabilities;
+use crate::highlight;
+use crate::postprocess::PostProcessor;
+use crate::pricing;
+use crate::readline;
+use crate::sessions;
+use crate::status;
+use crate::theme::{self, Role};
+use crate::tls;
+
 lazy_static! {
     pub(crate) static ref DEFAULT_CONFIG_FILENAME: PathBuf = "ata2.toml".into();
     pub(crate) static ref DEFAULT_CONFIG_FILENAME_V1: PathBuf = "ata.toml".into();
@@ -46,7 +61,7 @@ lazy_static! {
 /// UI config
 #[repr(C)]
 #[derive(Clone, Deserialize, Debug, Serialize, Reflect)]
-#[serde(default)]
+#[serde(default, deny_unknown_fields)]
 pub struct UiConfig {
     /// Require user to press ^C twice?
     pub double_ctrlc: bool,
@@ -58,48 +73,1067 @@ pub struct UiConfig {
     pub multiline_insertions: bool,
     /// Save history?
     pub save_history: bool,
-    /// History file
+    /// History file. Default: `history` in the state directory.
     pub history_file: PathBuf,
+    /// Archive (compress and move) saved sessions older than this many days. 0 means never.
+    pub archive_sessions_after_days: u64,
+    /// Remove history entries older than this many days. 0 means keep them forever.
+    pub history_retention_days: u64,
+    /// Show the session and model in the terminal's title?
+    pub set_terminal_title: bool,
+    /// Save each conversation as it grows, to a file named after its session in the data
+    /// directory's `conversations` folder?
+    pub autosave_conversations: bool,
+    /// Hold back a prompt that was asked before (`session` or `all`), offering the earlier answer.
+    pub duplicate_prompts: DuplicatePrompts,
+    /// Highlight the code blocks in answers, when printing them on a terminal?
+    pub highlight_code: bool,
+    /// Theme for `highlight_code`, e.g. `base16-ocean.dark`, `InspiredGitHub` or `Solarized (light)`.
+    pub code_theme: String,
+    /// Tidy up pasted terminal transcripts (colors, `user@host:~$` prompts) before sending them?
+    pub clean_pastes: bool,
+    /// Put each finished answer on the clipboard: `always`, `ask` (offer to, with F3) or `never`.
+    pub copy_response: CopyResponse,
+    /// Copy the answer's last code block rather than the whole answer, if it has one?
+    pub copy_code_block: bool,
+    /// Replace `$VAR`, `${VAR}` and (once confirmed) `$(command)` in prompts?
+    pub expand_env_vars: bool,
+    /// Ask about prompts that are just pasted code or logs, with `auto_wrap_question`?
+    pub auto_wrap_code: bool,
+    /// What `auto_wrap_code` asks about the paste.
+    pub auto_wrap_question: String,
+    /// Read finished answers aloud: `api` (with `speech_model`), `local` (`say` or `espeak`) or
+    /// `off`. Toggled with `/tts`.
+    pub tts: Tts,
+    /// The voice of `api`, e.g. `alloy`, `nova` or `onyx`.
+    pub tts_voice: String,
+    /// Show, dimmed, the reasoning of models that send it before their answers. Toggled with
+    /// `/reasoning`.
+    pub show_reasoning: bool,
+    /// Reload the configuration whenever its file changes, as `/reload` does.
+    pub watch_config: bool,
+    /// After the first answer, ask the model for a short title for the session, in the
+    /// background. Without it, titles are made up from the first prompt.
+    pub generate_titles: bool,
+    /// The model `generate_titles` asks, e.g. a cheaper one. Default: the conversation's.
+    pub title_model: Option<String>,
+    /// The line editor's keys: `emacs` or `vi` (also `--vi`).
+    pub edit_mode: EditMode,
+    /// The prompt string, with segments such as `{model}`, e.g. `"{model}|{tokens_used} ❯ "`.
+    /// Empty for none, so that the next prompt can be typed while an answer is printed.
+    pub prompt: String,
+    /// Wrap answers between words at the terminal's width (`auto`), at a number of columns (e.g.
+    /// `100`), or not at all (`off`). Code blocks and tables are never wrapped.
+    #[serde(deserialize_with = "string_or_number")]
+    pub wrap: String,
+    /// Open answers longer than the terminal in `$PAGER` once they are finished?
+    pub auto_page: bool,
+    /// Append every prompt and answer, with the time and model, to a plain-text log in this
+    /// directory, one file a day. Unset for none.
+    pub session_log_dir: Option<PathBuf>,
+}
+
+/// A string, or a number taken as one, e.g. both `wrap = 100` and `wrap = "auto"`.
+fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrNumber {
+        String(String),
+        Number(u64),
+    }
+    Ok(match StringOrNumber::deserialize(deserializer)? {
+        StringOrNumber::String(s) => s,
+        StringOrNumber::Number(n) => n.to_string(),
+    })
+}
+
+/// Which keys the line editor has, as in readline.
+#[derive(Clone, Copy, Deserialize, Debug, Default, Serialize, Reflect, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EditMode {
+    #[default]
+    Emacs,
+    Vi,
+}
+
+impl FromStr for EditMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "emacs" => Ok(Self::Emacs),
+            "vi" => Ok(Self::Vi),
+            _ => Err(format!("Unknown edit mode {s}")),
+        }
+    }
+}
+
+/// How to read answers aloud.
+#[derive(Clone, Copy, Deserialize, Debug, Default, Serialize, Reflect, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Tts {
+    #[default]
+    Off,
+    /// The provider's text-to-speech endpoint, falling back to `local` if it fails.
+    Api,
+    /// `say` on macOS, `espeak` elsewhere.
+    Local,
+}
+
+impl FromStr for Tts {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "api" => Ok(Self::Api),
+            "local" => Ok(Self::Local),
+            _ => Err(format!("Unknown tts value {s}")),
+        }
+    }
+}
+
+/// Which API the primary provider speaks.
+#[derive(Clone, Copy, Deserialize, Debug, Default, Serialize, Reflect, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiProvider {
+    /// OpenAI's API or a compatible one, e.g. a local server, LiteLLM, or Azure OpenAI.
+    #[default]
+    OpenAI,
+    /// Anthropic's Messages API.
+    Anthropic,
+    /// Mistral's API, which is OpenAI compatible.
+    Mistral,
+    /// Groq's API, which is OpenAI compatible.
+    Groq,
+    /// No API: answers recorded with `--record` are replayed from `api_base`, their directory, for
+    /// tests and offline demos.
+    Mock,
+}
+
+impl ApiProvider {
+    /// The URL of the API when `api_base` isn't set, if not OpenAI's.
+    pub fn default_api_base(self) -> Option<&'static str> {
+        match self {
+            Self::OpenAI => None,
+            Self::Anthropic => Some("https://api.anthropic.com/v1"),
+            Self::Mistral => Some("https://api.mistral.ai/v1"),
+            Self::Groq => Some("https://api.groq.com/openai/v1"),
+            Self::Mock => None,
+        }
+    }
+
+    /// The environment variable the key is read from when `api_key` isn't set.
+    pub fn key_var(self) -> &'static str {
+        match self {
+            Self::OpenAI => "OPENAI_API_KEY",
+            Self::Anthropic => "ANTHROPIC_API_KEY",
+            Self::Mistral => "MISTRAL_API_KEY",
+            Self::Groq => "GROQ_API_KEY",
+            Self::Mock => "ATA2_MOCK_API_KEY",
+        }
+    }
+}
+
+impl FromStr for ApiProvider {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "openai" => Ok(Self::OpenAI),
+            "anthropic" => Ok(Self::Anthropic),
+            "mistral" => Ok(Self::Mistral),
+            "groq" => Ok(Self::Groq),
+            "mock" => Ok(Self::Mock),
+            _ => Err(format!("Unknown provider {s}")),
+        }
+    }
+}
+
+/// What to do when a request doesn't fit in the model's context window.
+#[derive(Clone, Copy, Deserialize, Debug, Default, Serialize, Reflect, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextOverflow {
+    /// Send it anyway, and let the API refuse it.
+    #[default]
+    Off,
+    /// Leave the oldest messages out.
+    Trim,
+    /// Replace the oldest messages by a summary of them.
+    Summarize,
+}
+
+impl FromStr for ContextOverflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "trim" => Ok(Self::Trim),
+            "summarize" => Ok(Self::Summarize),
+            _ => Err(format!("Unknown context_overflow value {s}")),
+        }
+    }
+}
+
+/// Where to look for an earlier, near-identical prompt before sending one.
+#[derive(Clone, Copy, Deserialize, Debug, Default, Serialize, Reflect, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicatePrompts {
+    #[default]
+    Off,
+    /// The current conversation.
+    Session,
+    /// The current conversation and the saved sessions.
+    All,
+}
+
+impl FromStr for DuplicatePrompts {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "session" => Ok(Self::Session),
+            "all" => Ok(Self::All),
+            _ => Err(format!("Unknown duplicate_prompts value {s}")),
+        }
+    }
+}
+
+/// When to put a finished answer on the clipboard.
+#[derive(Clone, Copy, Deserialize, Debug, Default, Serialize, Reflect, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CopyResponse {
+    #[default]
+    Never,
+    /// Offer to, with F3.
+    Ask,
+    Always,
+}
+
+impl FromStr for CopyResponse {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(Self::Never),
+            "ask" => Ok(Self::Ask),
+            "always" => Ok(Self::Always),
+            _ => Err(format!("Unknown copy_response value {s}")),
+        }
+    }
+}
+
+/// What to do when an outgoing prompt contains personal information.
+#[derive(Clone, Copy, Deserialize, Debug, Default, Serialize, Reflect, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PiiAction {
+    #[default]
+    Off,
+    /// Print a warning, but send the prompt as is.
+    Warn,
+    /// Replace it by a placeholder, which is restored in the answer.
+    Mask,
+}
+
+/// PII filter config, per class of personal information. All are off by default.
+#[repr(C)]
+#[derive(Clone, Deserialize, Debug, Default, Serialize, Reflect)]
+#[serde(default, deny_unknown_fields)]
+pub struct PiiConfig {
+    /// API keys, tokens and private keys.
+    pub secret: PiiAction,
+    pub email: PiiAction,
+    pub phone: PiiAction,
+    pub ip: PiiAction,
+    /// Names are detected heuristically (capitalized words), so expect false positives.
+    pub name: PiiAction,
+}
+
+/// What to do when an answer gets stuck repeating itself.
+#[derive(Clone, Copy, Deserialize, Debug, Default, Serialize, Reflect, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RepetitionGuard {
+    #[default]
+    Off,
+    /// Print a warning, but let the answer go on.
+    Warn,
+    /// Stop the answer, keeping what came before.
+    Stop,
+}
+
+/// How answers are generated, `[generation]`.
+#[repr(C)]
+#[derive(Clone, Deserialize, Debug, Serialize, Reflect)]
+#[serde(default, deny_unknown_fields)]
+pub struct GenerationConfig {
+    /// Notice answers stuck in a loop, as local models sometimes get: `off`, `warn` or `stop`.
+    pub repetition_guard: RepetitionGuard,
+    /// How many times in a row a passage has to come up to be taken for a loop.
+    pub repetition_threshold: u64,
+}
+
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        Self {
+            repetition_guard: RepetitionGuard::default(),
+            repetition_threshold: 4,
+        }
+    }
+}
+
+/// The `run_shell_command` tool, `[tools.shell]`. Each command the model asks to run is shown,
+/// and only run once confirmed.
+#[repr(C)]
+#[derive(Clone, Deserialize, Debug, Serialize, Reflect)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShellToolConfig {
+    /// Offer the tool to the model?
+    pub enabled: bool,
+    /// If not empty, only commands starting with one of these may be run, e.g. `["git log", "ls"]`.
+    /// Each part of a pipeline or list of commands is checked.
+    pub allow: Vec<String>,
+    /// Commands starting with one of these are refused without asking, e.g. `["rm", "sudo"]`.
+    pub deny: Vec<String>,
+    /// Commands still running after this many seconds are killed.
+    pub timeout_secs: u64,
+    /// How much of a command's output is sent to the model, in bytes.
+    pub max_output_bytes: u64,
+}
+
+impl Default for ShellToolConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            allow: vec![],
+            deny: [
+                "sudo", "su", "doas", "rm", "dd", "mkfs", "shutdown", "reboot",
+            ]
+            .map(String::from)
+            .to_vec(),
+            timeout_secs: 30,
+            max_output_bytes: 20000,
+        }
+    }
+}
+
+/// The search API `[tools.web]` asks.
+#[derive(Clone, Copy, Deserialize, Debug, Default, Serialize, Reflect, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchApi {
+    /// A SearXNG instance at `url`, with its JSON format enabled.
+    #[default]
+    Searxng,
+    /// The Brave Search API, with `api_key`.
+    Brave,
+}
+
+/// Searching the web, with `/web` or by the model (the `web_search` tool), `[tools.web]`. Off by
+/// default, as the queries go to the search API, and the pages found to the model's provider.
+#[repr(C)]
+#[derive(Clone, Deserialize, Debug, Serialize, Reflect)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebToolConfig {
+    /// Offer the tool to the model, and allow `/web`?
+    pub enabled: bool,
+    /// `searxng` or `brave`.
+    pub api: SearchApi,
+    /// The SearXNG instance, e.g. `http://localhost:8888`.
+    pub url: Option<String>,
+    pub api_key: Option<String>,
+    /// How many of the pages found are read.
+    pub max_results: u64,
+    /// How much of the text of each page is sent to the model, in bytes.
+    pub max_page_bytes: u64,
+    /// Searches and pages taking longer than this many seconds are given up on.
+    pub timeout_secs: u64,
+}
+
+impl Default for WebToolConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            api: SearchApi::default(),
+            url: None,
+            api_key: None,
+            max_results: 3,
+            max_page_bytes: 8000,
+            timeout_secs: 15,
+        }
+    }
+}
+
+/// Tools the model may call, `[tools]`.
+#[repr(C)]
+#[derive(Clone, Deserialize, Debug, Default, Serialize, Reflect)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToolsConfig {
+    pub shell: ShellToolConfig,
+    pub web: WebToolConfig,
+}
+
+/// A second provider to race against the primary one (see `hedge_after_ms`). Unset values are the
+/// same as the primary provider's.
+#[repr(C)]
+#[derive(Clone, Deserialize, Debug, Default, Serialize, Reflect)]
+#[serde(default, deny_unknown_fields)]
+pub struct FallbackConfig {
+    /// Default: the primary provider's if the fallback asks the same API, `$OPENAI_API_KEY` if it
+    /// asks OpenAI's.
+    pub api_key: Option<String>,
+    /// e.g. `https://api.example.com/v1`. Default: OpenAI's.
+    pub api_base: Option<String>,
+    pub model: Option<String>,
+    /// Unset, the primary provider's `[tls]`.
+    pub tls: TlsConfig,
+    /// Unset, the primary provider's `[rate_limit]`. The limits are counted apart.
+    pub rate_limit: RateLimitConfig,
+}
+
+impl FallbackConfig {
+    pub fn is_configured(&self) -> bool {
+        self.api_key.is_some() || self.api_base.is_some() || self.model.is_some()
+    }
+}
+
+/// How to connect to a provider over TLS, e.g. a self-hosted server behind an internal CA or a
+/// gateway requiring client certificates.
+#[repr(C)]
+#[derive(Clone, Deserialize, Debug, Default, Serialize, Reflect, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM file of CA certificates to trust, besides the system's.
+    pub ca_bundle: Option<String>,
+    /// PEM file of the certificate (chain) to present to the server, for mutual TLS.
+    pub client_cert: Option<String>,
+    /// PEM file of the PKCS#8 private key of `client_cert`. Default: `client_cert`, for a file
+    /// holding both.
+    pub client_key: Option<String>,
+    /// Accept any certificate, e.g. a self-signed one. Anyone on the way can read the requests!
+    pub insecure_skip_verify: bool,
+}
+
+/// Timeouts of requests to providers, `[network]`. 0 means no timeout.
+#[repr(C)]
+#[derive(Clone, Deserialize, Debug, Serialize, Reflect, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// How long connecting to a provider may take, in seconds.
+    pub connect_timeout_secs: u64,
+    /// How long to wait for the answer to start, and then for each next piece of it, in seconds.
+    /// A request stalled for longer is given up on, keeping what was answered so far.
+    pub request_timeout_secs: u64,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: 10,
+            // Reasoning models may think for minutes before answering.
+            request_timeout_secs: 300,
+        }
+    }
+}
+
+impl NetworkConfig {
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        (self.connect_timeout_secs > 0).then(|| Duration::from_secs(self.connect_timeout_secs))
+    }
+
+    pub fn request_timeout(&self) -> Option<Duration> {
+        (self.request_timeout_secs > 0).then(|| Duration::from_secs(self.request_timeout_secs))
+    }
+}
+
+/// How `ata2 sync` reaches the copy of the configuration directory on other machines.
+#[derive(Clone, Copy, Deserialize, Debug, Default, Serialize, Reflect, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncMethod {
+    /// `remote` is a git repository, e.g. `git@example.com:me/ata2-config.git`.
+    #[default]
+    Git,
+    /// `remote` is an rsync target, e.g. `example.com:ata2-config`.
+    Rsync,
+}
+
+/// Where `ata2 sync` pulls the configuration directory from and pushes it to, `[sync]`.
+#[repr(C)]
+#[derive(Clone, Deserialize, Debug, Default, Serialize, Reflect)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyncConfig {
+    pub remote: Option<String>,
+    pub method: SyncMethod,
+    /// Paths in the configuration directory not to sync, besides the history and secrets.
+    pub exclude: Vec<String>,
+}
+
+/// Where runtime files are kept, `[paths]`. Unset paths are in the platform's data or state
+/// directory, e.g. `~/.local/share/ata2` and `~/.local/state/ata2` on Linux.
+#[repr(C)]
+#[derive(Clone, Deserialize, Debug, Default, Serialize, Reflect)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    /// Saved sessions. Default: `sessions` in the data directory.
+    pub sessions: Option<PathBuf>,
+    /// Conversations saved with `ui.autosave_conversations`. Default: `conversations` in the data
+    /// directory.
+    pub conversations: Option<PathBuf>,
+    /// Copies of files taken before `/apply` changes them. Default: `backups` in the state
+    /// directory.
+    pub backups: Option<PathBuf>,
+    /// The copies `ata2 sync` compares the configuration directory with. Default: `sync` in the
+    /// state directory.
+    pub sync: Option<PathBuf>,
+    /// The full-text index of saved sessions `/search` uses. Default: `search.sqlite` in the state
+    /// directory.
+    pub search_index: Option<PathBuf>,
+}
+
+impl PathsConfig {
+    pub fn sessions(&self) -> PathBuf {
+        self.sessions
+            .clone()
+            .unwrap_or_else(|| get_data_dir().join("sessions"))
+    }
+
+    pub fn conversations(&self) -> PathBuf {
+        self.conversations
+            .clone()
+            .unwrap_or_else(|| get_data_dir().join("conversations"))
+    }
+
+    pub fn backups(&self) -> PathBuf {
+        self.backups
+            .clone()
+            .unwrap_or_else(|| get_state_dir().join("backups"))
+    }
+
+    pub fn sync(&self) -> PathBuf {
+        self.sync
+            .clone()
+            .unwrap_or_else(|| get_state_dir().join("sync"))
+    }
+
+    pub fn search_index(&self) -> PathBuf {
+        self.search_index
+            .clone()
+            .unwrap_or_else(|| get_state_dir().join("search.sqlite"))
+    }
+}
+
+/// Facts saved with `/remember`, told to the model at the start of every session, `[memory]`.
+#[repr(C)]
+#[derive(Clone, Deserialize, Debug, Default, Serialize, Reflect)]
+#[serde(default, deny_unknown_fields)]
+pub struct MemoryConfig {
+    pub enabled: bool,
+    /// Default: `memory.jsonl` in the data directory.
+    pub file: Option<PathBuf>,
+}
+
+impl MemoryConfig {
+    pub fn file(&self) -> PathBuf {
+        self.file
+            .clone()
+            .unwrap_or_else(|| get_data_dir().join("memory.jsonl"))
+    }
+}
+
+/// Answering from local files indexed with `ata2 index`, `[rag]`.
+#[repr(C)]
+#[derive(Clone, Deserialize, Debug, Serialize, Reflect)]
+#[serde(default, deny_unknown_fields)]
+pub struct RagConfig {
+    /// Add the passages of the index most like each prompt to it? Toggled with `/rag on|off`.
+    pub enabled: bool,
+    pub embedding_model: String,
+    /// How many passages to add to each prompt.
+    pub top_k: u64,
+    /// The longest passage files are split into, in characters.
+    pub chunk_chars: u64,
+    /// Default: `index.json` in the data directory.
+    pub file: Option<PathBuf>,
+    /// The OpenAI-compatible API to ask for embeddings, e.g. when the primary provider has none.
+    /// Default: the primary provider's.
+    pub api_base: Option<String>,
+    /// Default: the primary provider's, if `api_base` is the primary provider's too.
+    pub api_key: Option<String>,
+}
+
+impl Default for RagConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            embedding_model: String::from("text-embedding-3-small"),
+            top_k: 4,
+            chunk_chars: 2000,
+            file: None,
+            api_base: None,
+            api_key: None,
+        }
+    }
+}
+
+impl RagConfig {
+    pub fn file(&self) -> PathBuf {
+        self.file
+            .clone()
+            .unwrap_or_else(|| get_data_dir().join("index.json"))
+    }
+}
+
+/// How much may be asked of a provider, so that requests wait for their turn rather than being
+/// refused, `[rate_limit]`. 0 means no limit.
+#[repr(C)]
+#[derive(Clone, Deserialize, Debug, Default, Serialize, Reflect, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub requests_per_minute: u64,
+    /// Counting the prompt and `max_tokens` of each request, as providers do.
+    pub tokens_per_minute: u64,
+}
+
+impl RateLimitConfig {
+    pub fn is_configured(&self) -> bool {
+        *self != Self::default()
+    }
+}
+
+impl TlsConfig {
+    pub fn is_configured(&self) -> bool {
+        *self != Self::default()
+    }
+}
+
+impl Display for FallbackConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        fmt_reflectable(f, self)
+    }
+}
+
+/// Where a configuration value came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    /// The environment variable of that name.
+    Env(&'static str),
+    /// The command-line flag of that name.
+    Flag(&'static str),
+    /// The configuration file at that path.
+    File(PathBuf),
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::Env(var) => write!(f, "${var}"),
+            Source::Flag(flag) => write!(f, "{flag}"),
+            Source::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// The environment variable a value defaults to (see `impl Default for Config`), by dotted key.
+fn env_var(key: &str) -> Option<&'static str> {
+    Some(match key {
+        "provider" => "ATA2_PROVIDER",
+        "api_key" => "OPENAI_API_KEY",
+        "api_base" => "ATA2_API_BASE",
+        "api_version" => "ATA2_API_VERSION",
+        "deployment_id" => "ATA2_DEPLOYMENT_ID",
+        "model" => "ATA2_MODEL",
+        "max_tokens" => "ATA2_MAX_TOKENS",
+        "temperature" => "ATA2_TEMPERATURE",
+        "suffix" => "ATA2_SUFFIX",
+        "top_p" => "ATA2_TOP_P",
+        "n" => "ATA2_N",
+        "stop" => "ATA2_STOP",
+        "presence_penalty" => "ATA2_PRESENCE_PENALTY",
+        "frequency_penalty" => "ATA2_FREQUENCY_PENALTY",
+        "logit_bias" => "ATA2_LOGIT_BIAS",
+        "user_id" => "ATA2_USER_ID",
+        "system_prompt" => "ATA2_SYSTEM_PROMPT",
+        "reply_language" => "ATA2_REPLY_LANGUAGE",
+        "hedge_after_ms" => "ATA2_HEDGE_AFTER_MS",
+        "max_concurrent_requests" => "ATA2_MAX_CONCURRENT_REQUESTS",
+        "confirm_cost_above" => "ATA2_CONFIRM_COST_ABOVE",
+        "context_window" => "ATA2_CONTEXT_WINDOW",
+        "context_overflow" => "ATA2_CONTEXT_OVERFLOW",
+        "attach_max_bytes" => "ATA2_ATTACH_MAX_BYTES",
+        "transcription_model" => "ATA2_TRANSCRIPTION_MODEL",
+        "speech_model" => "ATA2_SPEECH_MODEL",
+        "ui.double_ctrlc" => "ATA2_DOUBLE_CTRLC",
+        "ui.hide_config" => "ATA2_HIDE_CONFIG",
+        "ui.redact_api_key" => "ATA2_REDACT_API_KEY",
+        "ui.multiline_insertions" => "ATA2_MULTILINE_INSERTIONS",
+        "ui.save_history" => "ATA2_SAVE_HISTORY",
+        "ui.history_file" => "ATA2_HISTORY_FILE",
+        "ui.archive_sessions_after_days" => "ATA2_ARCHIVE_SESSIONS_AFTER_DAYS",
+        "ui.history_retention_days" => "ATA2_HISTORY_RETENTION_DAYS",
+        "ui.set_terminal_title" => "ATA2_SET_TERMINAL_TITLE",
+        "ui.autosave_conversations" => "ATA2_AUTOSAVE_CONVERSATIONS",
+        "ui.duplicate_prompts" => "ATA2_DUPLICATE_PROMPTS",
+        "ui.highlight_code" => "ATA2_HIGHLIGHT_CODE",
+        "ui.code_theme" => "ATA2_CODE_THEME",
+        "ui.clean_pastes" => "ATA2_CLEAN_PASTES",
+        "ui.copy_response" => "ATA2_COPY_RESPONSE",
+        "ui.copy_code_block" => "ATA2_COPY_CODE_BLOCK",
+        "ui.expand_env_vars" => "ATA2_EXPAND_ENV_VARS",
+        "ui.auto_wrap_code" => "ATA2_AUTO_WRAP_CODE",
+        "ui.auto_wrap_question" => "ATA2_AUTO_WRAP_QUESTION",
+        "ui.tts" => "ATA2_TTS",
+        "ui.tts_voice" => "ATA2_TTS_VOICE",
+        "ui.show_reasoning" => "ATA2_SHOW_REASONING",
+        "ui.watch_config" => "ATA2_WATCH_CONFIG",
+        "ui.generate_titles" => "ATA2_GENERATE_TITLES",
+        "ui.title_model" => "ATA2_TITLE_MODEL",
+        "ui.edit_mode" => "ATA2_EDIT_MODE",
+        "ui.prompt" => "ATA2_PROMPT",
+        "ui.wrap" => "ATA2_WRAP",
+        "ui.auto_page" => "ATA2_AUTO_PAGE",
+        "ui.session_log_dir" => "ATA2_SESSION_LOG_DIR",
+        _ => return None,
+    })
+}
+
+/// Which values were set on the command line or in the configuration file; the others come from
+/// the environment or are defaults.
+#[derive(Clone, Debug, Default)]
+pub struct Sources {
+    file: Option<PathBuf>,
+    /// Dotted keys, e.g. `ui.history_file`, as well as the tables themselves, e.g. `ui`.
+    keys: HashSet<String>,
+    /// Dotted keys, and the flags that set them.
+    flags: StdHashMap<String, &'static str>,
+}
+
+impl Sources {
+    pub fn new(path: &Path, contents: &str) -> Self {
+        let mut keys = HashSet::new();
+        if let Ok(toml::Value::Table(table)) = contents.parse::<toml::Value>() {
+            for (key, value) in table {
+                if let toml::Value::Table(inner) = value {
+                    keys.extend(inner.keys().map(|k| format!("{key}.{k}")));
+                }
+                keys.insert(key);
+            }
+        }
+        Self {
+            file: Some(path.to_path_buf()),
+            keys,
+            flags: StdHashMap::new(),
+        }
+    }
+
+    /// Records that `key` was set by `flag`, overriding the configuration file.
+    pub fn set_by_flag(&mut self, key: &str, flag: &'static str) {
+        self.flags.insert(key.to_string(), flag);
+    }
+
+    /// Where the value of `key` (dotted, e.g. `ui.history_file`) came from.
+    pub fn of(&self, key: &str) -> Source {
+        if let Some(flag) = self.flags.get(key) {
+            return Source::Flag(flag);
+        }
+        match &self.file {
+            Some(path) if self.keys.contains(key) => Source::File(path.clone()),
+            _ => match env_var(key).filter(|var| env::var_os(var).is_some()) {
+                Some(var) => Source::Env(var),
+                None => Source::Default,
+            },
+        }
+    }
+
+    /// Where the fields of a table came from, e.g. `default, $ATA2_HISTORY_FILE`.
+    fn of_table(&self, key: &str, table: &dyn Struct) -> String {
+        let mut sources: Vec<Source> = vec![];
+        for i in 0..table.field_len() {
+            let source = self.of(&format!("{key}.{}", table.name_at(i).unwrap()));
+            if !sources.contains(&source) {
+                sources.push(source);
+            }
+        }
+        sources
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// A named set of settings, `[profiles.<name>]`, used instead of the top-level ones when selected
+/// with `--profile` or `/profile`.
+#[derive(Clone, Deserialize, Debug, Default, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub model: Option<String>,
+    pub temperature: Option<f64>,
+    pub system_prompt: Option<String>,
+    pub api_key: Option<String>,
+}
+
+impl Profile {
+    fn apply(&self, config: &mut Config) {
+        if let Some(ref model) = self.model {
+            config.model = model.clone();
+        }
+        if let Some(temperature) = self.temperature {
+            config.temperature = temperature;
+        }
+        if let Some(ref system_prompt) = self.system_prompt {
+            config.system_prompt = Some(system_prompt.clone());
+        }
+        if let Some(ref api_key) = self.api_key {
+            config.api_key = Some(api_key.clone());
+        }
+    }
+}
+
+/// Which colours `[theme]` starts from.
+#[derive(Clone, Copy, Deserialize, Debug, Default, Serialize, Reflect, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemePreset {
+    /// For dark terminal backgrounds.
+    #[default]
+    Dark,
+    /// For light ones, with darker colours and the `InspiredGitHub` code theme.
+    Light,
+    /// No colours or styles at all, as with `NO_COLOR`.
+    None,
+}
+
+/// The colours of ata²'s own output, `[theme]`. Styles are words among `bold`, `dim`, `italic`,
+/// `underline`, the colours `black`, `red`, `green`, `yellow`, `blue`, `magenta`, `cyan` and
+/// `white`, and their `bright-` variants, e.g. `"bold cyan"`. Unset ones are the preset's.
+#[repr(C)]
+#[derive(Clone, Deserialize, Debug, Default, Serialize, Reflect)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    pub preset: ThemePreset,
+    /// "Ask the Terminal Anything²" and "Configuration:".
+    pub header: Option<String>,
+    /// "Prompt:", "Response:" and the like.
+    pub banner: Option<String>,
+    pub error: Option<String>,
+    pub warning: Option<String>,
+    /// `[redacted]`, in place of API keys.
+    pub redacted: Option<String>,
+    /// The segments of the prompt string, `ui.prompt`: `{model}`, `{profile}`, `{title}`, and
+    /// `{tokens_used}` and `{cost}`.
+    pub model: Option<String>,
+    pub profile: Option<String>,
+    pub title: Option<String>,
+    pub usage: Option<String>,
+    /// What was searched for, in `/search` results, and the entry selected in the history search.
+    pub emphasis: Option<String>,
+    /// Command hints, and the reasoning shown before answers.
+    pub muted: Option<String>,
+    /// The syntax highlighting theme of code blocks. Default: `ui.code_theme`, or
+    /// `InspiredGitHub` with the `light` preset.
+    pub code: Option<String>,
+}
+
+/// Keys for the line editor's actions, `[keybindings]`, e.g. `newline = ["alt-enter"]`. Each is
+/// one key or several separated by spaces, e.g. `"ctrl-x ctrl-e"`, with any of the modifiers
+/// `ctrl-`, `alt-` and `shift-`. They take precedence over ata²'s own keys, e.g. Enter and Ctrl-D
+/// in multiline mode.
+#[derive(Clone, Deserialize, Debug, Default, Serialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct KeybindingsConfig {
+    pub accept_line: Vec<String>,
+    pub newline: Vec<String>,
+    pub clear_screen: Vec<String>,
+    /// Stops the answer being printed, like Ctrl-C. Otherwise, the key does what it would have.
+    pub abort_request: Vec<String>,
+    /// Continues the prompt in `$VISUAL` or `$EDITOR`, like Ctrl-X Ctrl-E.
+    pub edit_in_editor: Vec<String>,
+}
+
+impl KeybindingsConfig {
+    /// The actions and their keys.
+    pub fn actions(&self) -> [(readline::Action, &[String]); 5] {
+        use readline::Action::*;
+        [
+            (AcceptLine, &self.accept_line),
+            (Newline, &self.newline),
+            (ClearScreen, &self.clear_screen),
+            (AbortRequest, &self.abort_request),
+            (EditInEditor, &self.edit_in_editor),
+        ]
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (_, keys) in self.actions() {
+            for key in keys {
+                readline::parse_keys(key).map_err(|e| format!("In [keybindings]: {e}"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Changes to the JSON body of chat requests, for endpoints with nonstandard parameters.
+#[derive(Clone, Deserialize, Debug, Default, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RequestConfig {
+    /// Merged into the body, e.g. `min_p = 0.05`.
+    pub extra_body: serde_json::Map<String, Value>,
+    /// Removed from the body, e.g. `["presence_penalty"]`.
+    pub drop_params: Vec<String>,
+}
+
+impl Display for RequestConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "{{extra_body: {}, drop_params: {:?}}}",
+            Value::Object(self.extra_body.clone()),
+            self.drop_params
+        )
+    }
 }
 
 /// For definitions, see <https://platform.openai.com/docs/api-reference/completions/create>.
 #[repr(C)]
 #[derive(Clone, Deserialize, Debug, Serialize, Reflect)]
-#[serde(default)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
+    /// The API to send requests to: `openai` (or compatible), `anthropic`, `mistral`, or `groq`; or
+    /// `mock`, to replay recorded answers.
+    pub provider: ApiProvider,
     pub api_key: Option<String>,
+    /// e.g. `https://litellm.example.com/v1`, or `https://<resource>.openai.azure.com` for Azure
+    /// OpenAI. Default: the provider's.
+    pub api_base: Option<String>,
+    /// Azure OpenAI's API version, e.g. `2023-05-15`. Setting it selects Azure OpenAI.
+    pub api_version: Option<String>,
+    /// Azure OpenAI's deployment of the model.
+    pub deployment_id: Option<String>,
     pub model: String,
+    /// The most tokens an answer may have, up to the model's maximum. With 0, none is sent, and
+    /// the server decides.
     pub max_tokens: i64,
     pub temperature: f64,
     pub suffix: Option<String>,
     pub top_p: f64,
     pub n: u64,
+    /// Stream answers as they are written? Turn it off for OpenAI-compatible servers that can't;
+    /// answers are then printed once complete. Anthropic's API always streams.
     pub stream: bool,
     pub stop: Vec<String>,
     pub presence_penalty: f64,
     pub frequency_penalty: f64,
     pub logit_bias: HashMap<String, f64>,
     pub user_id: Option<String>,
+    /// Sent as the first message of each conversation, to steer the assistant's behavior.
+    pub system_prompt: Option<String>,
+    /// Language to answer in: `"auto"` (the language of the prompt) or a language such as `"de"`.
+    pub reply_language: Option<String>,
+    pub pii: PiiConfig,
+    pub generation: GenerationConfig,
+    pub tools: ToolsConfig,
+    /// If the first token hasn't arrived after this many milliseconds, send the same request to
+    /// the `[fallback]` provider too, and keep whichever answers first. 0 means never.
+    pub hedge_after_ms: u64,
+    pub fallback: FallbackConfig,
+    pub tls: TlsConfig,
+    pub network: NetworkConfig,
+    pub sync: SyncConfig,
+    pub paths: PathsConfig,
+    pub memory: MemoryConfig,
+    pub rag: RagConfig,
+    /// How many requests may be answered at the same time. 0 means no limit.
+    pub max_concurrent_requests: u64,
+    pub rate_limit: RateLimitConfig,
+    /// Ask before running a batch of requests (`sessions replay` or --batch) estimated to cost
+    /// more than this many USD.
+    pub confirm_cost_above: f64,
+    /// How many tokens the model takes in, prompt and answer together. 0 means what is known of
+    /// the model (unlimited for unknown ones).
+    pub context_window: u64,
+    /// What to do with requests that don't fit in the context window: `trim` the oldest messages,
+    /// `summarize` them, or `off` (the default) to send them anyway.
+    pub context_overflow: ContextOverflow,
+    /// The largest file that `@path` in a prompt may include, in bytes.
+    pub attach_max_bytes: u64,
+    /// The model turning `/speak` recordings into prompts.
+    pub transcription_model: String,
+    /// The model reading answers aloud, with `ui.tts = "api"`.
+    pub speech_model: String,
+    /// Not reflected, as it holds arbitrary JSON.
+    #[reflect(ignore)]
+    pub request: RequestConfig,
+    /// Steps to change complete answers with, in order, e.g. `["format_code"]`. Not reflected, as
+    /// the steps may hold commands.
+    #[reflect(ignore)]
+    pub postprocess: Vec<PostProcessor>,
+    /// Not reflected, as it is a map of tables.
+    #[reflect(ignore)]
+    pub profiles: BTreeMap<String, Profile>,
     pub ui: UiConfig,
+    pub theme: ThemeConfig,
+    /// Not reflected, as keys are bound once, when ata² starts.
+    #[reflect(ignore)]
+    pub keybindings: KeybindingsConfig,
+    /// Where each value came from.
+    #[serde(skip)]
+    #[reflect(ignore)]
+    pub sources: Sources,
 }
 
 impl Config {
     pub fn validate(&self) -> Result<(), String> {
         match self.api_key.as_ref().map(|s| s.as_str()) {
-            Some("") | None => return Err(String::from("API key is missing")),
+            Some("") | None if self.provider != ApiProvider::Mock => {
+                return Err(String::from("API key is missing"))
+            }
             _ => {}
         }
 
+        if self.provider == ApiProvider::Mock && self.api_base.is_none() {
+            return Err(String::from(
+                "api_base must be set to the directory of the recorded answers for provider = \"mock\"",
+            ));
+        }
+
+        if self.api_version.is_some() != self.deployment_id.is_some() {
+            return Err(String::from(
+                "api_version and deployment_id must both be set for Azure OpenAI",
+            ));
+        }
+
+        if self.api_version.is_some() && self.provider != ApiProvider::OpenAI {
+            return Err(String::from(
+                "api_version and deployment_id are only for Azure OpenAI, with provider = \"openai\"",
+            ));
+        }
+
+        if self.api_version.is_some() && self.api_base.is_none() {
+            return Err(String::from(
+                "api_base must be set for Azure OpenAI, e.g. https://<resource>.openai.azure.com",
+            ));
+        }
+
         if self.model.is_empty() {
             return Err(String::from("Model ID is missing"));
         }
 
-        if self.max_tokens < 1 || self.max_tokens > 2048 {
-            return Err(String::from("Max tokens must be between 1 and 2048"));
+        let max_output_tokens = capabilities::lookup(&self.model)
+            .and_then(|c| c.max_output_tokens)
+            .unwrap_or(u64::MAX)
+            .min(u16::MAX as u64) as i64;
+        if self.max_tokens < 0 || self.max_tokens > max_output_tokens {
+            return Err(format!(
+                "Max tokens must be between 1 and {max_output_tokens} for {}, or 0 to let the \
+                server decide",
+                self.model
+            ));
         }
 
-        if self.temperature < 0.0 || self.temperature > 1.0 {
-            return Err(String::from("Temperature must be between 0.0 and 1.0"));
+        if self.temperature < 0.0 || self.temperature > 2.0 {
+            return Err(String::from("Temperature must be between 0.0 and 2.0"));
         }
 
         if let Some(suffix) = &self.suffix {
@@ -120,13 +1154,15 @@ impl Config {
             return Err(String::from("Stop phrases cannot contain empties"));
         }
 
-        if self.presence_penalty < 0.0 || self.presence_penalty > 1.0 {
-            return Err(String::from("Presence penalty must be between 0.0 and 1.0"));
+        if self.presence_penalty < -2.0 || self.presence_penalty > 2.0 {
+            return Err(String::from(
+                "Presence penalty must be between -2.0 and 2.0",
+            ));
         }
 
-        if self.frequency_penalty < 0.0 || self.frequency_penalty > 1.0 {
+        if self.frequency_penalty < -2.0 || self.frequency_penalty > 2.0 {
             return Err(String::from(
-                "Frequency penalty must be between 0.0 and 1.0",
+                "Frequency penalty must be between -2.0 and 2.0",
             ));
         }
 
@@ -135,6 +1171,36 @@ impl Config {
             _ => {}
         }
 
+        match self.system_prompt.as_ref().map(|s| s.trim()) {
+            Some("") => return Err(String::from("System prompt cannot be an empty string")),
+            _ => {}
+        }
+
+        match self.reply_language.as_ref().map(|s| s.trim()) {
+            Some("") => return Err(String::from("Reply language cannot be an empty string")),
+            _ => {}
+        }
+
+        if self.confirm_cost_above < 0.0 {
+            return Err(String::from("confirm_cost_above cannot be negative"));
+        }
+
+        if self.generation.repetition_threshold < 2 {
+            return Err(String::from(
+                "generation.repetition_threshold must be at least 2",
+            ));
+        }
+
+        if self.hedge_after_ms > 0 && !self.fallback.is_configured() {
+            return Err(String::from(
+                "hedge_after_ms is set, but there is no [fallback] provider",
+            ));
+        }
+
+        tls::http_client(&self.tls, &self.network).map_err(|e| format!("In [tls]: {e}"))?;
+        tls::http_client(self.fallback_tls(), &self.network)
+            .map_err(|e| format!("In [fallback.tls]: {e}"))?;
+
         for (key, value) in &self.logit_bias {
             if value < &-2.0 || value > &2.0 {
                 return Err(format!(
@@ -144,14 +1210,77 @@ impl Config {
             }
         }
 
+        for (name, profile) in &self.profiles {
+            let mut config = self.clone();
+            config.profiles.clear();
+            profile.apply(&mut config);
+            config
+                .validate()
+                .map_err(|e| format!("In profile {name}: {e}"))?;
+        }
+
+        self.keybindings.validate()?;
+        theme::validate(self)?;
+
         Ok(self.ui.validate()?)
     }
+
+    /// Values the API accepts, but which are unlikely to give good answers.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = vec![];
+        if self.temperature > 1.5 {
+            warnings.push(format!(
+                "temperature = {} is high: answers may ramble or be gibberish",
+                self.temperature
+            ));
+        }
+        for (name, value) in [
+            ("presence_penalty", self.presence_penalty),
+            ("frequency_penalty", self.frequency_penalty),
+        ] {
+            if value < 0.0 {
+                warnings.push(format!(
+                    "{name} = {value} is negative: answers are likely to repeat themselves"
+                ));
+            } else if value > 1.0 {
+                warnings.push(format!(
+                    "{name} = {value} is high: answers may avoid words they need"
+                ));
+            }
+        }
+        warnings
+    }
+
+    /// This configuration with the settings of profile `name` applied.
+    pub fn with_profile(&self, name: &str) -> Result<Config, String> {
+        let profile = self.profiles.get(name).ok_or_else(|| {
+            let names = self.profiles.keys().cloned().collect::<Vec<_>>();
+            match names.len() {
+                0 => {
+                    format!("Unknown profile {name}: there are no [profiles] in the configuration")
+                }
+                _ => format!("Unknown profile {name}. Profiles: {}", names.join(", ")),
+            }
+        })?;
+        let mut config = self.clone();
+        profile.apply(&mut config);
+        Ok(config)
+    }
+
+    /// The names of the profiles.
+    pub fn profile_names(&self) -> Vec<&str> {
+        self.profiles.keys().map(String::as_str).collect()
+    }
 }
 
 /// Note: the result is heavily based on the environment variables.
 ///
+/// * `ATA2_PROVIDER` sets the API to use. Default: `openai`.
+/// * `OPENAI_API_KEY` sets the API key, or `ANTHROPIC_API_KEY`, `MISTRAL_API_KEY` or `GROQ_API_KEY` for the other providers. Default: `None`.
+/// * `ATA2_API_BASE` sets the URL of the API. Default: `None` (the provider's).
+/// * `ATA2_API_VERSION` and `ATA2_DEPLOYMENT_ID` select Azure OpenAI. Default: `None`.
 /// * `ATA2_MODEL` sets the model ID. Default: `gpt-3.5-turbo`.
-/// * `ATA2_MAX_TOKENS` sets the maximum amount of tokens that the server can answer with. Longer answers will be truncated. Default: `2048`.
+/// * `ATA2_MAX_TOKENS` sets the maximum amount of tokens that the server can answer with. Longer answers will be truncated. `0` leaves it to the server. Default: `2048`.
 /// * `ATA2_TEMPERATURE`. Default: `0.8`.
 /// * `ATA2_SUFFIX` sets the suffix. Default: `None`.
 /// * `ATA2_TOP_P`. Default: `1.0`.
@@ -160,9 +1289,24 @@ impl Config {
 /// * `ATA2_PRESENCE_PENALTY`. Default: `0.0`.
 /// * `ATA2_FREQUENCY_PENALTY`. Default: `0.0`.
 /// * `ATA2_LOGIT_BIAS` sets the logit bias. Default: `{}`.
+/// * `ATA2_SYSTEM_PROMPT` sets the system prompt. Default: `None`.
+/// * `ATA2_REPLY_LANGUAGE` sets the language to answer in. Default: `None`.
+/// * `ATA2_HEDGE_AFTER_MS` sets when to also ask the fallback provider. Default: `0` (never).
+/// * `ATA2_MAX_CONCURRENT_REQUESTS` sets how many requests may be answered at once. Default: `4`.
+/// * `ATA2_CONFIRM_COST_ABOVE` sets the estimated cost (USD) above which to ask before batches. Default: `1.0`.
+/// * `ATA2_CONTEXT_WINDOW` sets the size of the model's context window. Default: `0` (known).
+/// * `ATA2_CONTEXT_OVERFLOW` sets what to do with requests that don't fit in it. Default: `off`.
+/// * `ATA2_ATTACH_MAX_BYTES` sets the size limit of files included with `@path`. Default: `100000`.
+/// * `ATA2_TRANSCRIPTION_MODEL` sets the model transcribing `/speak`. Default: `whisper-1`.
+/// * `ATA2_SPEECH_MODEL` sets the model reading answers aloud. Default: `tts-1`.
 impl Default for Config {
     fn default() -> Self {
+        let provider: ApiProvider = env::var("ATA2_PROVIDER")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_default();
         Self {
+            provider,
             model: env::var("ATA2_MODEL")
                 .ok()
                 .unwrap_or_else(|| "gpt-3.5-turbo".to_string()),
@@ -200,9 +1344,61 @@ impl Default for Config {
                 .ok()
                 .map(|s| serde_json::from_str(&s).unwrap())
                 .unwrap_or_else(|| HashMap::default()),
-            api_key: env::var("OPENAI_API_KEY").ok(),
+            api_key: env::var(provider.key_var()).ok(),
+            api_base: env::var("ATA2_API_BASE").ok(),
+            api_version: env::var("ATA2_API_VERSION").ok(),
+            deployment_id: env::var("ATA2_DEPLOYMENT_ID").ok(),
             user_id: env::var("ATA2_USER_ID").ok(),
+            system_prompt: env::var("ATA2_SYSTEM_PROMPT").ok(),
+            reply_language: env::var("ATA2_REPLY_LANGUAGE").ok(),
+            pii: PiiConfig::default(),
+            generation: GenerationConfig::default(),
+            tools: ToolsConfig::default(),
+            hedge_after_ms: env::var("ATA2_HEDGE_AFTER_MS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            fallback: FallbackConfig::default(),
+            tls: TlsConfig::default(),
+            network: NetworkConfig::default(),
+            sync: SyncConfig::default(),
+            paths: PathsConfig::default(),
+            memory: MemoryConfig::default(),
+            rag: RagConfig::default(),
+            max_concurrent_requests: env::var("ATA2_MAX_CONCURRENT_REQUESTS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(4),
+            rate_limit: RateLimitConfig::default(),
+            confirm_cost_above: env::var("ATA2_CONFIRM_COST_ABOVE")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(1.0),
+            context_window: env::var("ATA2_CONTEXT_WINDOW")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            context_overflow: env::var("ATA2_CONTEXT_OVERFLOW")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_default(),
+            attach_max_bytes: env::var("ATA2_ATTACH_MAX_BYTES")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(100_000),
+            transcription_model: env::var("ATA2_TRANSCRIPTION_MODEL")
+                .ok()
+                .unwrap_or_else(|| "whisper-1".to_string()),
+            speech_model: env::var("ATA2_SPEECH_MODEL")
+                .ok()
+                .unwrap_or_else(|| "tts-1".to_string()),
+            request: RequestConfig::default(),
+            postprocess: vec![],
+            profiles: BTreeMap::new(),
             ui: UiConfig::default(),
+            theme: ThemeConfig::default(),
+            keybindings: KeybindingsConfig::default(),
+            sources: Sources::default(),
         }
     }
 }
@@ -214,7 +1410,31 @@ impl Default for Config {
 /// * `ATA2_REDACT_API_KEY` sets whether to redact API key. Default: `true`.
 /// * `ATA2_MULTILINE_INSERTIONS` sets whether to allow multiline insertions. Default: `true`.
 /// * `ATA2_SAVE_HISTORY` sets whether to save history. Default: `true`.
-/// * `ATA2_HISTORY_FILE` sets the history file. Default: `~/.config/ata2/history`.
+/// * `ATA2_HISTORY_FILE` sets the history file. Default: `history` in the state directory.
+/// * `ATA2_ARCHIVE_SESSIONS_AFTER_DAYS` sets when to archive saved sessions. Default: `0` (never).
+/// * `ATA2_HISTORY_RETENTION_DAYS` sets how long to keep history entries. Default: `0` (forever).
+/// * `ATA2_SET_TERMINAL_TITLE` sets whether to show the session in the terminal's title. Default: `false`.
+/// * `ATA2_AUTOSAVE_CONVERSATIONS` sets whether to save each conversation as it grows. Default: `false`.
+/// * `ATA2_DUPLICATE_PROMPTS` sets where to look for earlier identical prompts. Default: `off`.
+/// * `ATA2_HIGHLIGHT_CODE` sets whether to highlight code blocks. Default: `false`.
+/// * `ATA2_CODE_THEME` sets the theme to highlight code blocks with. Default: `base16-ocean.dark`.
+/// * `ATA2_CLEAN_PASTES` sets whether to tidy up pasted terminal transcripts. Default: `false`.
+/// * `ATA2_COPY_RESPONSE` sets when to copy answers to the clipboard. Default: `never`.
+/// * `ATA2_COPY_CODE_BLOCK` sets whether to copy only the last code block. Default: `false`.
+/// * `ATA2_EXPAND_ENV_VARS` sets whether to substitute variables and commands in prompts. Default: `false`.
+/// * `ATA2_AUTO_WRAP_CODE` sets whether to ask about bare pastes of code or logs. Default: `false`.
+/// * `ATA2_AUTO_WRAP_QUESTION` sets what to ask about them. Default: `Explain this and point out problems.`
+/// * `ATA2_TTS` sets how to read answers aloud (`api`, `local` or `off`). Default: `off`.
+/// * `ATA2_TTS_VOICE` sets the voice of `api`. Default: `alloy`.
+/// * `ATA2_SHOW_REASONING` shows the reasoning models send. Default: `false`.
+/// * `ATA2_WATCH_CONFIG` reloads the configuration when its file changes. Default: `false`.
+/// * `ATA2_GENERATE_TITLES` asks the model for session titles. Default: `false`.
+/// * `ATA2_TITLE_MODEL` sets the model asked for titles. Default: `None` (the conversation's).
+/// * `ATA2_EDIT_MODE` sets the line editor's keys (`emacs` or `vi`). Default: `emacs`.
+/// * `ATA2_PROMPT` sets the prompt string. Default: none.
+/// * `ATA2_WRAP` sets where answers are wrapped (`auto`, `off` or columns). Default: `off`.
+/// * `ATA2_AUTO_PAGE` sets whether to page answers longer than the terminal. Default: `false`.
+/// * `ATA2_SESSION_LOG_DIR` sets where prompts and answers are logged. Default: `None`.
 impl Default for UiConfig {
     fn default() -> Self {
         Self {
@@ -241,19 +1461,118 @@ impl Default for UiConfig {
             history_file: env::var("ATA2_HISTORY_FILE")
                 .ok()
                 .map(|s| PathBuf::from(s))
-                .unwrap_or_else(|| {
-                    get_config_dir::<2>()
-                        .join("history")
-                        .to_string_lossy()
-                        .to_string()
-                        .into()
-                }),
+                .unwrap_or_else(default_history_file),
+            archive_sessions_after_days: env::var("ATA2_ARCHIVE_SESSIONS_AFTER_DAYS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            history_retention_days: env::var("ATA2_HISTORY_RETENTION_DAYS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            set_terminal_title: env::var("ATA2_SET_TERMINAL_TITLE")
+                .ok()
+                .map(|s| s.len() > 0)
+                .unwrap_or(false),
+            autosave_conversations: env::var("ATA2_AUTOSAVE_CONVERSATIONS")
+                .ok()
+                .map(|s| s.len() > 0)
+                .unwrap_or(false),
+            duplicate_prompts: env::var("ATA2_DUPLICATE_PROMPTS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_default(),
+            highlight_code: env::var("ATA2_HIGHLIGHT_CODE")
+                .ok()
+                .map(|s| !matches!(s.as_str(), "" | "0" | "false"))
+                .unwrap_or(false),
+            code_theme: env::var("ATA2_CODE_THEME")
+                .ok()
+                .unwrap_or_else(|| "base16-ocean.dark".to_string()),
+            clean_pastes: env::var("ATA2_CLEAN_PASTES")
+                .ok()
+                .map(|s| s.len() > 0)
+                .unwrap_or(false),
+            copy_response: env::var("ATA2_COPY_RESPONSE")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_default(),
+            copy_code_block: env::var("ATA2_COPY_CODE_BLOCK")
+                .ok()
+                .map(|s| s.len() > 0)
+                .unwrap_or(false),
+            expand_env_vars: env::var("ATA2_EXPAND_ENV_VARS")
+                .ok()
+                .map(|s| s.len() > 0)
+                .unwrap_or(false),
+            auto_wrap_code: env::var("ATA2_AUTO_WRAP_CODE")
+                .ok()
+                .map(|s| s.len() > 0)
+                .unwrap_or(false),
+            auto_wrap_question: env::var("ATA2_AUTO_WRAP_QUESTION")
+                .ok()
+                .unwrap_or_else(|| "Explain this and point out problems.".to_string()),
+            tts: env::var("ATA2_TTS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_default(),
+            tts_voice: env::var("ATA2_TTS_VOICE")
+                .ok()
+                .unwrap_or_else(|| "alloy".to_string()),
+            show_reasoning: env::var("ATA2_SHOW_REASONING")
+                .ok()
+                .map(|s| s.len() > 0)
+                .unwrap_or(false),
+            watch_config: env::var("ATA2_WATCH_CONFIG")
+                .ok()
+                .map(|s| s.len() > 0)
+                .unwrap_or(false),
+            generate_titles: env::var("ATA2_GENERATE_TITLES")
+                .ok()
+                .map(|s| s.len() > 0)
+                .unwrap_or(false),
+            title_model: env::var("ATA2_TITLE_MODEL").ok(),
+            edit_mode: env::var("ATA2_EDIT_MODE")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_default(),
+            prompt: env::var("ATA2_PROMPT").unwrap_or_default(),
+            wrap: env::var("ATA2_WRAP").unwrap_or_else(|_| String::from("off")),
+            auto_page: env::var("ATA2_AUTO_PAGE")
+                .ok()
+                .map(|s| s.len() > 0)
+                .unwrap_or(false),
+            session_log_dir: env::var("ATA2_SESSION_LOG_DIR").ok().map(PathBuf::from),
         }
     }
 }
 
 impl UiConfig {
+    /// Whether the line editor waits for each answer before reading the next line, rather than
+    /// reading while it is printed.
+    pub fn waits_for_answers(&self) -> bool {
+        !self.prompt.is_empty() || self.auto_page
+    }
+
     pub fn validate(&self) -> Result<(), String> {
+        if self.highlight_code && !highlight::has_theme(&self.code_theme) {
+            return Err(format!("Unknown code theme {}", self.code_theme));
+        }
+
+        status::validate(&self.prompt)?;
+
+        if !matches!(self.wrap.as_str(), "auto" | "off")
+            && self
+                .wrap
+                .parse::<usize>()
+                .map_or(true, |columns| columns == 0)
+        {
+            return Err(format!(
+                "Wrap must be auto, off, or a number of columns, not {}",
+                self.wrap
+            ));
+        }
+
         let history_dir = match self.history_file.parent() {
             Some(dir) => dir,
             None => return Err(String::from("History file has no parent")),
@@ -278,33 +1597,158 @@ impl<'a> Into<OpenAIConfig> for &'a Config {
         if let Some(api_key) = &self.api_key {
             ret = ret.with_api_key(api_key.to_owned());
         }
+        if let Some(api_base) = self.api_base() {
+            ret = ret.with_api_base(api_base);
+        }
+        ret
+    }
+}
+
+/// How to reach a provider: OpenAI's API or a compatible one, or Azure OpenAI.
+#[derive(Clone)]
+pub enum ApiConfig {
+    OpenAI(OpenAIConfig),
+    Azure(AzureConfig),
+}
+
+impl Config {
+    /// The URL of the primary provider's API, if not OpenAI's.
+    pub fn api_base(&self) -> Option<String> {
+        self.api_base
+            .clone()
+            .or_else(|| self.provider.default_api_base().map(String::from))
+    }
+
+    /// The client configuration of the primary provider.
+    pub fn api_config(&self) -> ApiConfig {
+        let (api_version, deployment_id) = match (&self.api_version, &self.deployment_id) {
+            (Some(api_version), Some(deployment_id)) => (api_version, deployment_id),
+            _ => return ApiConfig::OpenAI(self.into()),
+        };
+        let mut ret = AzureConfig::new()
+            .with_api_version(api_version.to_owned())
+            .with_deployment_id(deployment_id.to_owned());
+        if let Some(api_key) = &self.api_key {
+            ret = ret.with_api_key(api_key.to_owned());
+        }
+        if let Some(api_base) = &self.api_base {
+            ret = ret.with_api_base(api_base.to_owned());
+        }
+        ApiConfig::Azure(ret)
+    }
+
+    /// The client configuration of the API to ask for embeddings: `[rag]`'s, or the primary
+    /// provider's.
+    pub fn embeddings_api_config(&self) -> Result<ApiConfig, String> {
+        if self.rag.api_base.is_none() && self.rag.api_key.is_none() {
+            if self.provider == ApiProvider::Anthropic {
+                return Err(String::from(
+                    "Anthropic has no embeddings API; set api_base in [rag] to one that has",
+                ));
+            }
+            return Ok(self.api_config());
+        }
+        let mut ret = OpenAIConfig::new();
+        let api_base = self.rag.api_base.clone().or_else(|| self.api_base());
+        // The primary key is only sent to the primary provider.
+        let primary_key = self
+            .api_key
+            .as_ref()
+            .filter(|_| api_base == self.api_base());
+        if let Some(api_key) = self.rag.api_key.as_ref().or(primary_key) {
+            ret = ret.with_api_key(api_key.to_owned());
+        }
+        if let Some(api_base) = api_base {
+            ret = ret.with_api_base(api_base);
+        }
+        Ok(ApiConfig::OpenAI(ret))
+    }
+
+    /// The client configuration of the `[fallback]` provider.
+    /// It is always OpenAI or compatible; an Azure OpenAI or Anthropic `api_base` isn't inherited,
+    /// and the primary key is only used if the fallback asks the same API.
+    pub fn fallback_openai_config(&self) -> OpenAIConfig {
+        let mut ret = OpenAIConfig::new();
+        let compatible = self.api_version.is_none()
+            && matches!(
+                self.provider,
+                ApiProvider::OpenAI | ApiProvider::Mistral | ApiProvider::Groq
+            );
+        let primary_base = self.api_base().filter(|_| compatible);
+        let api_base = self.fallback.api_base.clone().or(primary_base.clone());
+        let api_key = match self.fallback.api_key {
+            Some(ref api_key) => Some(api_key.clone()),
+            None if compatible && api_base == primary_base => self.api_key.clone(),
+            None if api_base.is_none() => env::var("OPENAI_API_KEY").ok(),
+            None => None,
+        };
+        if let Some(api_key) = api_key {
+            ret = ret.with_api_key(api_key);
+        }
+        if let Some(api_base) = api_base {
+            ret = ret.with_api_base(api_base);
+        }
         ret
     }
+
+    /// The rate limits of the `[fallback]` provider.
+    pub fn fallback_rate_limit(&self) -> &RateLimitConfig {
+        if self.fallback.rate_limit.is_configured() {
+            &self.fallback.rate_limit
+        } else {
+            &self.rate_limit
+        }
+    }
+
+    /// The TLS settings of the `[fallback]` provider.
+    pub fn fallback_tls(&self) -> &TlsConfig {
+        if self.fallback.tls.is_configured() {
+            &self.fallback.tls
+        } else {
+            &self.tls
+        }
+    }
+
+    /// The HTTP client for the primary provider.
+    pub fn http_client(&self) -> Result<reqwest::Client, OpenAIError> {
+        tls::http_client(&self.tls, &self.network).map_err(OpenAIError::InvalidArgument)
+    }
+
+    /// The HTTP client for the `[fallback]` provider.
+    pub fn fallback_http_client(&self) -> Result<reqwest::Client, OpenAIError> {
+        tls::http_client(self.fallback_tls(), &self.network).map_err(OpenAIError::InvalidArgument)
+    }
 }
 
 impl<'a> Into<CreateChatCompletionRequestArgs> for &'a Config {
     fn into(self) -> CreateChatCompletionRequestArgs {
-        if !self.stream {
-            warn!("Stream is disabled. This is not supported anymore and will be ignored.");
-        }
         let mut args = CreateChatCompletionRequestArgs::default()
             .n(self.n as u8)
             .model(&self.model)
-            .max_tokens(self.max_tokens as u16)
-            .temperature(self.temperature as f32)
-            .frequency_penalty(self.frequency_penalty as f32)
-            .presence_penalty(self.presence_penalty as f32)
-            .logit_bias(
-                self.logit_bias
-                    .clone()
-                    .into_iter()
-                    .map(|(k, v)| (k, serde_json::Value::Number(Number::from_f64(v).unwrap())))
-                    .collect::<StdHashMap<String, Value>>(),
-            )
-            .top_p(self.top_p as f32)
             .stop(self.stop.clone())
-            .stream(true)
+            .stream(self.stream)
             .to_owned();
+        if self.max_tokens > 0 {
+            args.max_tokens(self.max_tokens as u16);
+        }
+
+        // Reasoning models refuse sampling parameters. `max_tokens` is renamed when the request is
+        // sent (see `request_body`).
+        if !capabilities::is_reasoning(&self.model) {
+            args = args
+                .temperature(self.temperature as f32)
+                .frequency_penalty(self.frequency_penalty as f32)
+                .presence_penalty(self.presence_penalty as f32)
+                .logit_bias(
+                    self.logit_bias
+                        .clone()
+                        .into_iter()
+                        .map(|(k, v)| (k, serde_json::Value::Number(Number::from_f64(v).unwrap())))
+                        .collect::<StdHashMap<String, Value>>(),
+                )
+                .top_p(self.top_p as f32)
+                .to_owned();
+        }
 
         if let Some(user_id) = &self.user_id {
             args = args.user(user_id).to_owned();
@@ -314,6 +1758,46 @@ impl<'a> Into<CreateChatCompletionRequestArgs> for &'a Config {
     }
 }
 
+impl Config {
+    /// The configuration as TOML, each value followed by a comment saying where it came from.
+    pub fn annotated_toml(&self) -> String {
+        let mut config = self.clone();
+        if config.ui.redact_api_key {
+            let redacted = || Some(String::from("[redacted]"));
+            config.api_key = config.api_key.and(redacted());
+            config.fallback.api_key = config.fallback.api_key.and(redacted());
+            for profile in config.profiles.values_mut() {
+                profile.api_key = profile.api_key.take().and(redacted());
+            }
+        }
+        let table = match toml::Value::try_from(&config) {
+            Ok(toml::Value::Table(table)) => table,
+            _ => return String::new(),
+        };
+        let line = |key: &str, name: &str, value: &toml::Value| {
+            format!("{name} = {value} # {}\n", self.sources.of(key))
+        };
+        let mut ret = String::new();
+        let mut tables = String::new();
+        for (key, value) in &table {
+            match value {
+                toml::Value::Table(inner)
+                    if self
+                        .field(key)
+                        .map_or(false, |v| matches!(v.reflect_ref(), ReflectRef::Struct(_))) =>
+                {
+                    tables += &format!("\n[{key}]\n");
+                    for (name, value) in inner {
+                        tables += &line(&format!("{key}.{name}"), name, value);
+                    }
+                }
+                _ => ret += &line(key, key, value),
+            }
+        }
+        ret + &tables
+    }
+}
+
 fn fmt_reflectable(f: &mut fmt::Formatter<'_>, value: &dyn Struct) -> Result<(), fmt::Error> {
     write!(f, "{{")?;
     let num_fields = value.iter_fields().count();
@@ -367,7 +1851,7 @@ where
     }
 }
 
-fn get_config_dir<const V: usize>() -> PathBuf {
+pub(crate) fn get_config_dir<const V: usize>() -> PathBuf {
     ProjectDirs::from(
         if V == 1 {
             "ata"
@@ -390,6 +1874,95 @@ fn get_config_dir<const V: usize>() -> PathBuf {
     .into()
 }
 
+/// Where mutable data such as saved sessions lives.
+pub(crate) fn get_data_dir() -> PathBuf {
+    ProjectDirs::from(
+        "ata2",
+        "Ask the Terminal Anything (ATA) Project Authors",
+        "ata2",
+    )
+    .unwrap()
+    .data_dir()
+    .into()
+}
+
+/// Where runtime state such as the prompt history lives. Platforms without a state directory use
+/// the local data directory.
+pub(crate) fn get_state_dir() -> PathBuf {
+    let dirs = ProjectDirs::from(
+        "ata2",
+        "Ask the Terminal Anything (ATA) Project Authors",
+        "ata2",
+    )
+    .unwrap();
+    dirs.state_dir()
+        .unwrap_or_else(|| dirs.data_local_dir())
+        .into()
+}
+
+/// Where files that can be fetched or built again, such as the pricing table, are kept.
+pub(crate) fn get_cache_dir() -> PathBuf {
+    ProjectDirs::from(
+        "ata2",
+        "Ask the Terminal Anything (ATA) Project Authors",
+        "ata2",
+    )
+    .unwrap()
+    .cache_dir()
+    .into()
+}
+
+fn default_history_file() -> PathBuf {
+    get_state_dir().join("history")
+}
+
+/// Moves the runtime files earlier versions kept in the configuration and data directories to
+/// where they are kept now, if they aren't there yet. Paths set in `config` are left alone.
+pub(crate) fn migrate_runtime_files(config: &Config) {
+    let mut moves = vec![];
+    if config.ui.history_file == default_history_file() {
+        let old = get_config_dir::<2>().join("history");
+        moves.push((
+            sessions::history_times_path(&old),
+            sessions::history_times_path(&config.ui.history_file),
+        ));
+        moves.push((old, config.ui.history_file.clone()));
+    }
+    if config.paths.backups.is_none() {
+        moves.push((get_data_dir().join("backups"), config.paths.backups()));
+    }
+    if config.paths.sync.is_none() {
+        moves.push((get_data_dir().join("sync"), config.paths.sync()));
+    }
+    moves.push((
+        get_config_dir::<2>().join("pricing.json"),
+        pricing::override_path(),
+    ));
+    moves.push((
+        get_config_dir::<2>().join("capabilities.json"),
+        capabilities::override_path(),
+    ));
+    for (old, new) in moves {
+        if !old.exists() || new.exists() || old == new {
+            continue;
+        }
+        let moved = new
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::rename(&old, &new));
+        match moved {
+            Ok(()) => info!("Moved {} to {}", old.display(), new.display()),
+            Err(e) => warn!("Could not move {} to {}: {e}", old.display(), new.display()),
+        }
+    }
+    // The history file can only be created in an existing directory.
+    if let Some(dir) = config.ui.history_file.parent() {
+        if let Err(e) = fs::create_dir_all(dir) {
+            warn!("Could not create {}: {e}", dir.display());
+        }
+    }
+}
+
 pub fn default_path<const V: usize>(name: Option<&Path>) -> PathBuf {
     let mut config_file = get_config_dir::<V>().to_path_buf();
     let file: Vec<_> = if let Some(name) = name {
@@ -427,10 +2000,29 @@ impl ConfigLocation {
                 default_path::<2>(None)
             }
             ConfigLocation::Path(pb) => pb.clone(),
-            ConfigLocation::Named(name) => default_path::<2>(Some(name)),
+            ConfigLocation::Named(name) => match default_path::<2>(Some(name)) {
+                path if !path.exists() && self.profile().is_some() => {
+                    ConfigLocation::Auto.location()
+                }
+                path => path,
+            },
         }
     }
 
+    /// For `-c <name>` where there is no configuration file of that name, but the default one has
+    /// a profile of that name: the profile.
+    pub fn profile(&self) -> Option<String> {
+        let name = match self {
+            ConfigLocation::Named(name) if !default_path::<2>(Some(name)).exists() => name,
+            _ => return None,
+        };
+        let contents = fs::read_to_string(ConfigLocation::Auto.location()).ok()?;
+        let config: toml::Value = contents.parse().ok()?;
+        let name = name.to_string_lossy();
+        config.get("profiles")?.get(name.as_ref())?;
+        Some(name.into_owned())
+    }
+
     pub fn location_v1(&self) -> PathBuf {
         default_path::<1>(Some(&Path::new("ata.toml")))
     }
@@ -440,24 +2032,81 @@ impl FromStr for Config {
     type Err = TomlError;
 
     fn from_str(contents: &str) -> Result<Self, Self::Err> {
-        toml::from_str(&contents)
+        let mut config: Config = toml::from_str(&contents)?;
+        // The default key is that of `$ATA2_PROVIDER`, which a provider set here may not be.
+        let has_key = matches!(
+            contents.parse::<toml::Value>(),
+            Ok(toml::Value::Table(ref table)) if table.contains_key("api_key")
+        );
+        if !has_key {
+            config.api_key = env::var(config.provider.key_var()).ok();
+        }
+        Ok(config)
     }
 }
 
-impl<S> From<S> for Config
-where
-    S: AsRef<str>,
-{
-    fn from(s: S) -> Self {
-        Self::from_str(s.as_ref()).unwrap_or_else(|e| panic!("Config parsing failure!: {:?}", e))
+impl Config {
+    /// The value at dotted `key`, e.g. `temperature` or `ui.highlight_code`, if it is neither a
+    /// table nor a list.
+    fn scalar(&self, key: &str) -> Option<&dyn Reflect> {
+        let (parent, name): (&dyn Struct, &str) = match key.split_once('.') {
+            Some((table, name)) => match self.field(table)?.reflect_ref() {
+                ReflectRef::Struct(table) => (table, name),
+                _ => return None,
+            },
+            None => (self, key),
+        };
+        let value = parent.field(name)?;
+        match value.reflect_ref() {
+            ReflectRef::Value(_) | ReflectRef::Enum(_) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Sets the value at dotted `key` to `value`, read as the value's type: e.g. `0.2`, `true`,
+    /// `none` for unset optional values, or a string.
+    pub fn set_field(&mut self, key: &str, value: &str) -> Result<(), String> {
+        self.set_fields([(key, value)])
+    }
+
+    /// Sets several values as [`Config::set_field`] does, going through JSON once for all of them.
+    pub fn set_fields<'a>(
+        &mut self,
+        fields: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<(), String> {
+        let mut json = serde_json::to_value(&*self).map_err(|e| e.to_string())?;
+        let mut set = vec![];
+        for (key, value) in fields {
+            if self.scalar(key).is_none() {
+                return Err(format!(
+                    "Unknown parameter `{key}`. /show lists the parameters"
+                ));
+            }
+            let (table, name) = match key.split_once('.') {
+                Some((table, name)) => (&mut json[table], name),
+                None => (&mut json, key),
+            };
+            let new = match (&table[name], value) {
+                (Value::String(_), value) => Value::String(value.to_string()),
+                (_, "none") => Value::Null,
+                (_, value) => {
+                    serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()))
+                }
+            };
+            table[name] = new;
+            set.push(format!("{key}: `{value}`"));
+        }
+        let mut config: Config = serde_json::from_value(json)
+            .map_err(|e| format!("Invalid value for {} ({e})", set.join(", ")))?;
+        config.sources = std::mem::take(&mut self.sources);
+        *self = config;
+        Ok(())
     }
 }
 
 impl Display for Config {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
-        let mut header = ColouredStr::new("Configuration:");
-        header.underline();
-        let mut ok = writeln!(f, "{}", header);
+        let mut ok = writeln!(f, "{}", theme::paint(Role::Header, "Configuration:"));
         for (i, value) in self.iter_fields().enumerate() {
             if !ok.is_ok() {
                 break;
@@ -474,18 +2123,36 @@ impl Display for Config {
                     None => None,
                 },
             };
+            if let Some(fallback) = value.downcast_ref::<FallbackConfig>() {
+                let mut fallback = fallback.clone();
+                if self.ui.redact_api_key && fallback.api_key.is_some() {
+                    fallback.api_key = Some(String::from("[redacted]"));
+                }
+                value2 = Some(fallback.to_string());
+            }
             if self.ui.redact_api_key && key == "api_key" {
-                let mut redacted = ColouredStr::new("[redacted]");
-                redacted.red();
-                value2 = Some(redacted.to_string());
+                value2 = Some(theme::paint(Role::Redacted, "[redacted]"));
             }
 
+            let source = match value.reflect_ref() {
+                ReflectRef::Struct(table) => self.sources.of_table(key, table),
+                _ => self.sources.of(key).to_string(),
+            };
             if let Some(v) = value2 {
-                ok = writeln!(f, "{key}: {value}", key = key, value = v);
+                ok = writeln!(f, "{key}: {value} ({source})", key = key, value = v);
             } else {
-                ok = writeln!(f, "{key}: {value:#?}", key = key, value = value);
+                ok = writeln!(f, "{key}: {value:#?} ({source})", key = key, value = value);
             }
         }
+        if ok.is_ok() {
+            let source = self.sources.of("request");
+            ok = writeln!(f, "request: {} ({source})", self.request);
+        }
+        if ok.is_ok() {
+            let source = self.sources.of("postprocess");
+            let steps = self.postprocess.iter().map(ToString::to_string);
+            ok = writeln!(f, "postprocess: {:?} ({source})", steps.collect::<Vec<_>>());
+        }
         ok
     }
 }
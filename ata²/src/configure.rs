@@ -0,0 +1,301 @@
+//! `ata2 config`: printing the path of the configuration file, writing a new one or a profile,
+//! opening it in the editor, and checking it.
+//!
+//! Errors in the file are reported with the line and setting they are about, when starting too.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use regex::Regex;
+use toml::de::Error as TomlError;
+
+use std::fs;
+use std::path::Path;
+
+use crate::config::ConfigLocation;
+use crate::edit;
+use crate::help::EXAMPLE_TOML;
+use crate::state;
+
+lazy_static! {
+    /// Where TOML's errors say they are.
+    static ref LINE: Regex = Regex::new(r"at line (\d+)").unwrap();
+    static ref UNKNOWN: Regex = Regex::new(r"unknown field `([^`]*)`, expected (.*)").unwrap();
+    static ref FIELD: Regex = Regex::new(r"`([^`]*)`").unwrap();
+}
+
+/// `value` as a TOML string.
+fn quoted(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+/// `name` as a TOML key, quoted unless it is a bare key.
+fn key(name: &str) -> String {
+    if !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        name.to_string()
+    } else {
+        quoted(name)
+    }
+}
+
+/// `config path`
+pub fn path(location: &ConfigLocation) {
+    println!("{}", location.location().display());
+}
+
+/// `config init`: writes the example configuration, or with `profile`, adds a profile to the
+/// existing one.
+pub fn init(
+    location: &ConfigLocation,
+    profile: Option<&str>,
+    model: Option<&str>,
+    api_key: Option<&str>,
+    force: bool,
+) -> Result<String, String> {
+    let path = location.location();
+    if let Some(profile) = profile {
+        return add_profile(&path, profile, model, api_key);
+    }
+    if path.exists() && !force {
+        return Err(format!(
+            "{} exists already. Use --force to replace it, or give a profile name to add one",
+            path.display()
+        ));
+    }
+    let mut contents = String::new();
+    for line in EXAMPLE_TOML.lines() {
+        match line.split_once(" = ") {
+            Some(("api_key", _)) if api_key.is_some() => {
+                contents += &format!("api_key = {}", quoted(api_key.unwrap()))
+            }
+            Some(("model", _)) if model.is_some() => {
+                contents += &format!("model = {}", quoted(model.unwrap()))
+            }
+            _ => contents += line,
+        }
+        contents.push('\n');
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Could not make configuration directory: {e}"))?;
+    }
+    fs::write(&path, contents).map_err(|e| format!("Could not write {}: {e}", path.display()))?;
+    Ok(match api_key {
+        Some(_) => format!("Wrote {}", path.display()),
+        None => format!(
+            "Wrote {}. Replace <YOUR SECRET API KEY> in it with your API key",
+            path.display()
+        ),
+    })
+}
+
+fn add_profile(
+    path: &Path,
+    name: &str,
+    model: Option<&str>,
+    api_key: Option<&str>,
+) -> Result<String, String> {
+    let contents = fs::read_to_string(path).map_err(|e| {
+        format!(
+            "Could not read {}: {e}. Run `ata2 config init` first",
+            path.display()
+        )
+    })?;
+    let config: toml::Value = contents
+        .parse()
+        .map_err(|e| format!("{} is not valid TOML: {e}", path.display()))?;
+    if config
+        .get("profiles")
+        .and_then(|profiles| profiles.get(name))
+        .is_some()
+    {
+        return Err(format!("There is a profile {name} already"));
+    }
+    let mut profile = format!("\n[profiles.{}]\n", key(name));
+    match model {
+        Some(model) => profile += &format!("model = {}\n", quoted(model)),
+        None => profile += "# model = \"gpt-4o\"\n",
+    }
+    profile += "# temperature = 0.8\n# system_prompt = \"\"\n";
+    if let Some(api_key) = api_key {
+        profile += &format!("api_key = {}\n", quoted(api_key));
+    }
+    let separator = if contents.ends_with('\n') || contents.is_empty() {
+        ""
+    } else {
+        "\n"
+    };
+    fs::write(path, format!("{contents}{separator}{profile}"))
+        .map_err(|e| format!("Could not write {}: {e}", path.display()))?;
+    Ok(format!(
+        "Added profile {name} to {}; use it with --profile {name}",
+        path.display()
+    ))
+}
+
+/// `config edit`: opens the configuration file in the editor, then checks it.
+pub fn edit(location: &ConfigLocation) -> Result<String, String> {
+    let path = location.location();
+    if !path.exists() {
+        return Err(format!(
+            "{} doesn't exist. Run `ata2 config init` to write one",
+            path.display()
+        ));
+    }
+    edit::open(&path).map_err(|e| format!("Could not edit {}: {e}", path.display()))?;
+    validate(location).map_err(|e| format!("{e}\nRun `ata2 config edit` again to fix it."))
+}
+
+/// The tables and settings in `contents`: their line numbers and dotted keys, split.
+fn settings(contents: &str) -> Vec<(usize, Vec<String>)> {
+    let mut table: Vec<String> = vec![];
+    let mut settings = vec![];
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        let key = if let Some(header) = line.strip_prefix('[') {
+            let header = header.trim_matches(|c| c == '[' || c == ']');
+            table = header
+                .split('.')
+                .map(|part| part.trim().trim_matches('"').to_string())
+                .collect();
+            table.clone()
+        } else {
+            match line.split_once('=') {
+                Some((name, _)) if !line.starts_with('#') => {
+                    let mut key = table.clone();
+                    key.push(name.trim().trim_matches('"').to_string());
+                    key
+                }
+                _ => continue,
+            }
+        };
+        settings.push((i + 1, key));
+    }
+    settings
+}
+
+/// The line of the setting in `contents` that `error` is about, and its dotted key, when it can
+/// be told from the error's wording, e.g. `Temperature must be…` is about `temperature`.
+fn locate(contents: &str, error: &str) -> Option<(usize, String)> {
+    let words = |s: &str| {
+        s.to_lowercase()
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+            .collect::<String>()
+    };
+    let error = format!(" {} ", words(error));
+    let mentions = |name: &str| error.contains(&format!(" {} ", words(name).trim()));
+    let mut best: Option<(usize, usize, String)> = None;
+    for (line, key) in settings(contents) {
+        let (name, tables) = match key.split_last() {
+            Some((name, tables)) if mentions(name) => (name, tables),
+            _ => continue,
+        };
+        // The more of its tables the error names too, the likelier it is meant.
+        let score = 1 + tables
+            .iter()
+            .filter(|part| mentions(part) || mentions(part.trim_end_matches('s')))
+            .count();
+        if best.as_ref().map_or(true, |(best, ..)| score > *best) {
+            best = Some((score, line, key.join(".")));
+        }
+    }
+    best.map(|(_, line, key)| (line, key))
+}
+
+/// How many single character edits turn `a` into `b`.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (diagonal + (ca != *cb) as usize)
+                .min(above + 1)
+                .min(row[j] + 1);
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// For an unknown key, the known one it is most likely a misspelling of.
+fn suggestion(message: &str) -> Option<String> {
+    let captures = UNKNOWN.captures(message)?;
+    let unknown = &captures[1];
+    FIELD
+        .captures_iter(&captures[2])
+        .map(|field| field[1].to_string())
+        .map(|field| (distance(unknown, &field), field))
+        .filter(|(distance, _)| *distance <= (unknown.chars().count() / 3).max(2))
+        .min()
+        .map(|(_, field)| field)
+}
+
+/// A report of the failure to parse the configuration file at `path`: where the error is, as
+/// TOML's error shows it, the setting it is in, and for unknown keys, what may have been meant.
+pub fn parse_error(path: &Path, contents: &str, error: &TomlError) -> String {
+    let text = error.to_string();
+    let mut report = format!(
+        "Could not read the configuration in {}: {text}",
+        path.display()
+    );
+    let line = LINE
+        .captures(&text)
+        .and_then(|captures| captures[1].parse::<usize>().ok());
+    // The setting on that line, or the table it is in.
+    let key = line.and_then(|line| {
+        settings(contents)
+            .into_iter()
+            .take_while(|(at, _)| *at <= line)
+            .last()
+            .filter(|(_, key)| !key.is_empty())
+            .map(|(_, key)| key.join("."))
+    });
+    if let Some(key) = key {
+        report += &format!("\nIn `{key}`.");
+    }
+    if let Some(field) = suggestion(&text) {
+        report += &format!("\nDid you mean `{field}`?");
+    }
+    report
+}
+
+/// `config validate`: reads and checks the configuration file, as when starting, reporting where
+/// in the file an error is.
+pub fn validate(location: &ConfigLocation) -> Result<String, String> {
+    let path = location.location();
+    let contents =
+        fs::read_to_string(&path).map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+    let config = state::read_configuration(&path)?;
+    config.validate().map_err(|e| match locate(&contents, &e) {
+        Some((line, key)) => format!("{}:{line}: {e} (`{key}`)", path.display()),
+        None => format!("{}: {e}", path.display()),
+    })?;
+    let mut report = format!("{} is valid", path.display());
+    for warning in config.warnings() {
+        report += &format!("\nWarning: {warning}");
+    }
+    Ok(report)
+}
@@ -0,0 +1,106 @@
+//! `/context`: what the next request would be made of, in tokens.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::types::ChatCompletionRequestMessage;
+
+use crate::params;
+use crate::prompt::{self, finish_prompt, print_error, CONVERSATION};
+use crate::readline::chat_completion_message_to_string;
+use crate::tokens;
+use crate::CONFIGURATION;
+
+/// Width of the longest bar.
+const BAR_WIDTH: usize = 30;
+
+/// Prints the composition of the next request, with `pending` as its prompt.
+pub async fn command(pending: &str) {
+    let config = match params::effective_config(&CONFIGURATION.load(), &Default::default()) {
+        Ok(config) => config,
+        Err(e) => return print_error(&format!("Invalid parameters: {e}")),
+    };
+    let conversation = CONVERSATION.lock().await.clone();
+    let (system, history) = match conversation.split_first() {
+        Some((first @ ChatCompletionRequestMessage::System(_), rest)) => {
+            (tokens::count_message(&config.model, first), rest)
+        }
+        _ => (
+            config
+                .system_prompt
+                .as_deref()
+                .filter(|_| conversation.is_empty())
+                .map(|s| tokens::count_as_message(&config.model, s))
+                .unwrap_or(0),
+            &conversation[..],
+        ),
+    };
+    let last_prompt = match pending {
+        "" => history
+            .iter()
+            .rev()
+            .find(|m| matches!(m, ChatCompletionRequestMessage::User(_)))
+            .map(chat_completion_message_to_string)
+            .unwrap_or_default(),
+        pending => pending.to_string(),
+    };
+    let instructions = prompt::reply_language_instruction(&config, &last_prompt)
+        .map(|instruction| tokens::count_as_message(&config.model, &instruction))
+        .unwrap_or(0);
+    let parts = [
+        ("system prompt", system),
+        ("instructions", instructions),
+        (
+            "history",
+            history
+                .iter()
+                .map(|m| tokens::count_message(&config.model, m))
+                .sum(),
+        ),
+        (
+            "pending prompt",
+            match pending {
+                "" => 0,
+                pending => tokens::count_as_message(&config.model, pending),
+            },
+        ),
+    ];
+
+    let total: usize = parts.iter().map(|(_, n)| n).sum();
+    let window = match tokens::context_window(&config) {
+        usize::MAX => "an unknown number of".to_string(),
+        n => n.to_string(),
+    };
+    eprintln!(
+        "Context of the next request: {total} of {window} tokens, {} of which are kept for the \
+         answer:",
+        tokens::answer_tokens(&config)
+    );
+    let width = parts.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    let largest = parts.iter().map(|(_, n)| *n).max().unwrap_or(0).max(1);
+    for (name, n) in parts {
+        let filled = n * BAR_WIDTH / largest;
+        let percent = if total == 0 { 0 } else { n * 100 / total };
+        eprintln!(
+            "{name:<width$}  {}{}  {n:>6} ({percent}%)",
+            "█".repeat(filled),
+            "░".repeat(BAR_WIDTH - filled),
+        );
+    }
+    finish_prompt();
+}
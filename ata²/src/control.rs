@@ -0,0 +1,133 @@
+//! A control channel on a named pipe (`--control-fifo`), for window managers, cron jobs and
+//! editors to drive the running REPL without speaking a protocol.
+//!
+//! Each line written to the pipe is a command:
+//!
+//! * `prompt: <text>` asks `text`, as if it had been typed;
+//! * `abort` stops the answer being printed;
+//! * `save` saves the conversation, like F2.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use once_cell::sync::OnceCell;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::Sender;
+
+use std::fs::{self, File};
+use std::io::{self, BufRead as _, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::thread;
+
+use crate::output::eprint_bold;
+use crate::prompt::{self, CONVERSATION};
+use crate::STOP_ANSWER;
+
+/// The pipe, if it was created by us and so is to be removed on exit.
+static CREATED: OnceCell<PathBuf> = OnceCell::new();
+
+#[cfg(unix)]
+fn make_fifo(path: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt as _;
+    use std::os::unix::fs::FileTypeExt as _;
+
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.file_type().is_fifo() => return Ok(()),
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a named pipe", path.display()),
+            ))
+        }
+        Err(_) => {}
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let _ = CREATED.set(path.to_path_buf());
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn make_fifo(_path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "named pipes are only supported on Unix",
+    ))
+}
+
+/// Carries out one line written to the pipe.
+async fn run(line: &str, tx: &Sender<Option<String>>) {
+    let line = line.trim();
+    if let Some(text) = line.strip_prefix("prompt:") {
+        let text = text.trim();
+        if !text.is_empty() {
+            eprint_bold("\nPrompt (control pipe):\n");
+            eprintln!("{text}");
+            let _ = tx.send(Some(text.to_string())).await;
+        }
+        return;
+    }
+    match line {
+        "" => {}
+        "abort" => STOP_ANSWER.store(true, Ordering::Relaxed),
+        "save" => {
+            let conversation = CONVERSATION.lock().await.clone();
+            match prompt::save_conversation(&conversation, None) {
+                Ok(path) => info!("Saved conversation to {}", path.display()),
+                Err(e) => error!("Could not save the conversation: {e}"),
+            }
+        }
+        _ => warn!("Unknown command on the control pipe: {line}"),
+    }
+}
+
+/// Creates the pipe at `path`, unless there already is one, and carries out the commands written
+/// to it, sending prompts to `tx`.
+pub fn listen(path: &Path, tx: Sender<Option<String>>) -> io::Result<()> {
+    make_fifo(path)?;
+    let path = path.to_path_buf();
+    let runtime = Handle::current();
+    // Opening a pipe blocks until there is a writer, so this gets its own thread rather than a
+    // task, which would keep the runtime from shutting down.
+    thread::spawn(move || loop {
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("Could not open the control pipe: {e}");
+                return;
+            }
+        };
+        // Once all writers have closed it, the pipe is opened again for the next one.
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            runtime.block_on(run(&line, &tx));
+        }
+    });
+    Ok(())
+}
+
+/// Removes the pipe, if we created it.
+pub fn remove() {
+    if let Some(path) = CREATED.get() {
+        let _ = fs::remove_file(path);
+    }
+}
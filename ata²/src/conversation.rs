@@ -0,0 +1,226 @@
+//! Keeping requests within the model's context window (`context_overflow`).
+//!
+//! Before each request, its messages are counted. If they don't fit in the context window, less
+//! the `max_tokens` kept for the answer, the oldest ones are left out of the request, or replaced
+//! by a summary of them. The conversation itself is kept whole, e.g. for saving or exporting it.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::types::{ChatCompletionRequestMessage, CreateChatCompletionRequestArgs};
+use tokio_stream::StreamExt as _;
+
+use std::sync::Mutex;
+
+use crate::config::ContextOverflow;
+use crate::limits;
+use crate::readline::{
+    chat_completion_message_role, chat_completion_message_to_string,
+    string_to_chat_completion_request_user_message, string_to_chat_completion_system_message,
+};
+use crate::tokens;
+use crate::Config;
+
+/// Longest summary of the messages left out.
+const SUMMARY_MAX_TOKENS: u16 = 512;
+
+const SUMMARY_INSTRUCTION: &str = "Summarize the following conversation in a few sentences, \
+    keeping the facts, decisions and code that later messages may refer to.";
+
+/// A summary of the first `covers` messages after the system prompt.
+struct Summary {
+    covers: usize,
+    /// The text of the last message covered, to notice when the conversation was replaced.
+    last: String,
+    text: String,
+}
+
+lazy_static! {
+    static ref SUMMARY: Mutex<Option<Summary>> = Mutex::new(None);
+}
+
+/// The answer to a one-off request, outside of the conversation: `text` following `instruction`.
+pub async fn complete(
+    config: &Config,
+    instruction: &str,
+    text: String,
+    max_tokens: u16,
+) -> Result<String, String> {
+    let mut args: CreateChatCompletionRequestArgs = config.into();
+    let request = args
+        .max_tokens(max_tokens)
+        .messages(vec![
+            string_to_chat_completion_system_message(instruction.to_string()),
+            string_to_chat_completion_request_user_message(text),
+        ])
+        .build()
+        .map_err(|e| e.to_string())?;
+    let (mut stream, _) = limits::create_stream(config, request)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut answer = String::new();
+    while let Some(response) = stream.next().await {
+        let response = response.map_err(|e| e.to_string())?;
+        for choice in response.choices {
+            answer.extend(choice.delta.content);
+        }
+    }
+    Ok(answer)
+}
+
+pub struct ConversationManager<'a> {
+    config: &'a Config,
+    /// Only say where the summary would be, rather than ask for it.
+    dry_run: bool,
+}
+
+impl<'a> ConversationManager<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        Self {
+            config,
+            dry_run: false,
+        }
+    }
+
+    /// For a request that is only printed, not sent.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    fn count(&self, messages: &[ChatCompletionRequestMessage]) -> usize {
+        messages
+            .iter()
+            .map(|m| tokens::count_message(&self.config.model, m))
+            .sum()
+    }
+
+    /// Tokens available to the request, once room has been kept for the answer.
+    fn budget(&self) -> usize {
+        tokens::context_window(self.config).saturating_sub(tokens::answer_tokens(self.config))
+    }
+
+    /// Fits `messages`, a request about to be sent, in the context window. The system prompt and
+    /// the last prompt (with anything after it) are always sent.
+    pub async fn fit(
+        &self,
+        messages: Vec<ChatCompletionRequestMessage>,
+    ) -> Result<Vec<ChatCompletionRequestMessage>, String> {
+        let budget = self.budget();
+        let mut total = self.count(&messages);
+        if self.config.context_overflow == ContextOverflow::Off || total <= budget {
+            return Ok(messages);
+        }
+
+        let head = match messages.first() {
+            Some(ChatCompletionRequestMessage::System(_)) => 1,
+            _ => 0,
+        };
+        let last_prompt = messages
+            .iter()
+            .rposition(|m| matches!(m, ChatCompletionRequestMessage::User(_)))
+            .unwrap_or(messages.len())
+            .max(head);
+        let summarize = self.config.context_overflow == ContextOverflow::Summarize;
+        if summarize {
+            total += tokens::count_as_message(&self.config.model, "") + SUMMARY_MAX_TOKENS as usize;
+        }
+        let required = total - self.count(&messages[head..last_prompt]);
+        if required > budget {
+            return Err(format!(
+                "The prompt doesn't fit in the context window: it takes {required} of the {budget} \
+                 tokens left after max_tokens"
+            ));
+        }
+
+        // Don't start on an answer whose prompt was left out.
+        let mut cut = head;
+        while cut < last_prompt
+            && (total > budget
+                || matches!(messages[cut], ChatCompletionRequestMessage::Assistant(_)))
+        {
+            total -= tokens::count_message(&self.config.model, &messages[cut]);
+            cut += 1;
+        }
+        info!(
+            "Leaving the {} oldest messages out of the request to fit in the context window",
+            cut - head
+        );
+        let mut fitted = messages[..head].to_vec();
+        if summarize && self.dry_run {
+            fitted.push(string_to_chat_completion_system_message(String::from(
+                "Summary of the earlier conversation: (not asked for in a dry run)",
+            )));
+        } else if summarize {
+            match self.summarize(&messages[head..cut]).await {
+                Ok(summary) => fitted.push(string_to_chat_completion_system_message(format!(
+                    "Summary of the earlier conversation: {summary}"
+                ))),
+                Err(e) => warn!("Could not summarize the messages left out: {e}"),
+            }
+        }
+        fitted.extend_from_slice(&messages[cut..]);
+        Ok(fitted)
+    }
+
+    /// A summary of `left_out`, reusing the previous one if it covers some of them.
+    async fn summarize(&self, left_out: &[ChatCompletionRequestMessage]) -> Result<String, String> {
+        let last = left_out
+            .last()
+            .map(chat_completion_message_to_string)
+            .unwrap_or_default();
+        let mut transcript = String::new();
+        let mut from = 0;
+        if let Some(ref previous) = *SUMMARY.lock().unwrap() {
+            let still_valid = left_out
+                .get(previous.covers.wrapping_sub(1))
+                .map_or(false, |m| {
+                    chat_completion_message_to_string(m) == previous.last
+                });
+            if still_valid && previous.covers == left_out.len() {
+                return Ok(previous.text.clone());
+            }
+            if still_valid {
+                transcript = format!("(Summary of what came before: {})\n\n", previous.text);
+                from = previous.covers;
+            }
+        }
+        for message in &left_out[from..] {
+            transcript += &format!(
+                "{}: {}\n\n",
+                chat_completion_message_role(message),
+                chat_completion_message_to_string(message)
+            );
+        }
+
+        let text = complete(
+            self.config,
+            SUMMARY_INSTRUCTION,
+            transcript,
+            SUMMARY_MAX_TOKENS,
+        )
+        .await?;
+
+        *SUMMARY.lock().unwrap() = Some(Summary {
+            covers: left_out.len(),
+            last,
+            text: text.clone(),
+        });
+        Ok(text)
+    }
+}
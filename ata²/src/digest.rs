@@ -0,0 +1,233 @@
+//! Reading what is piped to stdin progressively, so that inputs of any size can be asked about.
+//!
+//! Input is read in pieces, with its progress shown on the terminal. Once it is more than fits in
+//! the context window, it is summarized part by part as it is read (map), and the summaries are
+//! combined until they fit (reduce). Only the part being read and the summaries are kept in memory.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use tokio::io::AsyncReadExt as _;
+
+use std::io::{self, Write as _};
+
+use crate::conversation;
+use crate::forecast::Forecast;
+use crate::params;
+use crate::tokens;
+use crate::Config;
+use crate::CONFIGURATION;
+use crate::FLAGS;
+
+/// How much is read from stdin at once.
+const READ_BYTES: usize = 64 * 1024;
+
+/// A cautious estimate, for logs and code, of how many bytes make a token.
+const BYTES_PER_TOKEN: usize = 3;
+
+/// Tokens kept free besides the input: the prompt around it, the instruction, message framing.
+const RESERVED_TOKENS: usize = 1000;
+
+/// Most bytes of a part, whatever the context window, so that an unknown window doesn't mean
+/// buffering all of stdin.
+const MAX_PART_BYTES: usize = 4 << 20;
+
+/// Show the progress every this many bytes.
+const PROGRESS_BYTES: usize = 1 << 20;
+
+/// Longest summary of a part.
+const PART_MAX_TOKENS: u16 = 512;
+
+const PART_INSTRUCTION: &str = "The following is one part of a long input. Summarize it, keeping \
+    what someone asking about the whole input would need: errors, names, numbers, and anything \
+    unusual.";
+
+const COMBINE_INSTRUCTION: &str = "The following are summaries of consecutive parts of a long \
+    input. Combine them into one summary, keeping errors, names, numbers, and anything unusual.";
+
+/// Where `buffer` can be cut to keep at most `max` bytes: after a line if possible, never inside
+/// a character.
+fn cut_point(buffer: &[u8], max: usize) -> usize {
+    if buffer.len() <= max {
+        return buffer.len();
+    }
+    if let Some(newline) = buffer[..max].iter().rposition(|&b| b == b'\n') {
+        return newline + 1;
+    }
+    let mut cut = max;
+    while cut > 0 && buffer[cut] & 0xC0 == 0x80 {
+        cut -= 1;
+    }
+    cut
+}
+
+fn progress(message: &str) {
+    if atty::is(atty::Stream::Stderr) {
+        eprint!("\r\x1b[K{message}");
+        io::stderr().flush().ok();
+    }
+}
+
+struct Digest<'a> {
+    config: &'a Config,
+    /// Bytes of input that fit in a request.
+    part_bytes: usize,
+    summaries: Vec<String>,
+    /// The requests made so far, and the one about to be.
+    forecast: Forecast,
+    /// Whether the user agreed to go on although it costs more than `confirm_cost_above`.
+    confirmed: bool,
+}
+
+impl Digest<'_> {
+    /// Counts a request about `text`, asking the user whether to go on once the requests cost more
+    /// than `confirm_cost_above`.
+    fn count_request(&mut self, text: &str) -> io::Result<()> {
+        let input_tokens = text.len() / BYTES_PER_TOKEN + RESERVED_TOKENS;
+        self.forecast.add(input_tokens, PART_MAX_TOKENS as usize);
+        if self.confirmed || self.forecast.is_cheap(self.config) {
+            return Ok(());
+        }
+        progress("");
+        eprintln!("The input is too long to send whole, and summarizing it takes at least:");
+        if !self.forecast.confirm(self.config, false) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Not summarizing the input without confirmation",
+            ));
+        }
+        self.confirmed = true;
+        Ok(())
+    }
+
+    async fn summarize(&mut self, part: &[u8]) -> io::Result<()> {
+        if FLAGS.dry_run {
+            let n = self.summaries.len() + 1;
+            let summary = format!("(Summary of part {n}, not asked for in a dry run.)");
+            self.summaries.push(summary);
+            return Ok(());
+        }
+        let text = String::from_utf8_lossy(part).into_owned();
+        self.count_request(&text)?;
+        progress(&format!(
+            "Summarizing part {} of the input…",
+            self.summaries.len() + 1
+        ));
+        let summary = conversation::complete(self.config, PART_INSTRUCTION, text, PART_MAX_TOKENS)
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        self.summaries.push(summary);
+        Ok(())
+    }
+
+    /// Combines the summaries until they fit in a request, or can't be combined any further.
+    async fn reduce(mut self) -> io::Result<String> {
+        let separator = "\n\n";
+        while !FLAGS.dry_run
+            && self.summaries.len() > 1
+            && self.summaries.join(separator).len() > self.part_bytes
+        {
+            let mut combined = vec![];
+            let mut group = String::new();
+            for summary in &self.summaries {
+                if !group.is_empty() && group.len() + summary.len() > self.part_bytes {
+                    combined.push(std::mem::take(&mut group));
+                }
+                group += summary;
+                group += separator;
+            }
+            combined.push(group);
+            if combined.len() >= self.summaries.len() {
+                // Each summary is a group of its own: combining them wouldn't make fewer.
+                break;
+            }
+            progress(&format!("Combining {} summaries…", self.summaries.len()));
+            self.summaries.clear();
+            for group in combined {
+                self.count_request(&group)?;
+                let summary = conversation::complete(
+                    self.config,
+                    COMBINE_INSTRUCTION,
+                    group,
+                    PART_MAX_TOKENS,
+                )
+                .await
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                self.summaries.push(summary);
+            }
+        }
+        Ok(format!(
+            "(The input was too long to include whole; this is a summary of it.)\n\n{}",
+            self.summaries.join(separator)
+        ))
+    }
+}
+
+/// Reads all of stdin. Input too long for the context window is summarized.
+pub async fn read() -> io::Result<String> {
+    let config = params::effective_config(&CONFIGURATION.load(), &Default::default())
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let budget = tokens::context_window(&config)
+        .saturating_sub(tokens::answer_tokens(&config) + RESERVED_TOKENS)
+        .max(RESERVED_TOKENS);
+    let part_bytes = budget.saturating_mul(BYTES_PER_TOKEN).min(MAX_PART_BYTES);
+    let mut digest = Digest {
+        config: &config,
+        part_bytes,
+        summaries: vec![],
+        forecast: Forecast {
+            model: config.model.clone(),
+            ..Default::default()
+        },
+        confirmed: false,
+    };
+
+    let mut stdin = tokio::io::stdin();
+    let mut buffer: Vec<u8> = vec![];
+    let mut chunk = vec![0; READ_BYTES];
+    let mut total = 0;
+    loop {
+        let n = stdin.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+        if total / PROGRESS_BYTES != (total + n) / PROGRESS_BYTES {
+            progress(&format!("Read {} MiB…", (total + n) / PROGRESS_BYTES));
+        }
+        total += n;
+        // Once the input can't be sent whole, each full part is summarized as soon as it is read.
+        while buffer.len() > part_bytes {
+            let cut = cut_point(&buffer, part_bytes).max(1);
+            let part: Vec<u8> = buffer.drain(..cut).collect();
+            digest.summarize(&part).await?;
+        }
+    }
+
+    if digest.summaries.is_empty() {
+        progress("");
+        return Ok(String::from_utf8_lossy(&buffer).into_owned());
+    }
+    if !buffer.is_empty() {
+        digest.summarize(&buffer).await?;
+    }
+    let summary = digest.reduce().await?;
+    progress("");
+    info!("The input ({total} bytes) was too long for the context window, so it was summarized");
+    Ok(summary)
+}
@@ -0,0 +1,140 @@
+//! Noticing that a prompt was asked before (`ui.duplicate_prompts`).
+//!
+//! A prompt that is nearly identical to an earlier one, in this conversation or (with `all`) in a
+//! saved session, is held back. `/previous` then shows the earlier answer without asking the model
+//! again; sending the same prompt once more asks it anyway.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::types::ChatCompletionRequestMessage;
+
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+use crate::config::DuplicatePrompts;
+use crate::highlight;
+use crate::output::OutputSink as _;
+use crate::prompt::{finish_prompt, print_error, CONVERSATION};
+use crate::readline::{
+    chat_completion_message_to_string, string_to_chat_completion_assistant_message,
+    string_to_chat_completion_request_user_message,
+};
+use crate::sessions;
+use crate::CONFIGURATION;
+
+/// How much of the words two prompts must share to be considered the same question.
+const MIN_SIMILARITY: f64 = 0.9;
+
+/// A prompt that was held back, and the earlier answer to it.
+struct Held {
+    prompt: String,
+    answer: String,
+}
+
+lazy_static! {
+    static ref HELD: Mutex<Option<Held>> = Mutex::new(None);
+}
+
+/// The lowercased words of `text`, ignoring punctuation.
+fn words(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn similar(a: &BTreeSet<String>, b: &BTreeSet<String>) -> bool {
+    let union = a.union(b).count();
+    union > 0 && a.intersection(b).count() as f64 / union as f64 >= MIN_SIMILARITY
+}
+
+/// The answer to the last prompt in `messages` similar to `prompt`, if any.
+fn find_in(messages: &[ChatCompletionRequestMessage], prompt: &BTreeSet<String>) -> Option<String> {
+    messages.windows(2).rev().find_map(|pair| match pair {
+        [ChatCompletionRequestMessage::User(_), ChatCompletionRequestMessage::Assistant(_)]
+            if similar(&words(&chat_completion_message_to_string(&pair[0])), prompt) =>
+        {
+            Some(chat_completion_message_to_string(&pair[1]))
+        }
+        _ => None,
+    })
+}
+
+/// Whether `prompt` should be held back because it was asked before. Only interactive prompts are
+/// checked.
+pub async fn hold_back(prompt: &str) -> bool {
+    let held = HELD.lock().unwrap().take();
+    if held.map_or(false, |held| held.prompt == prompt) {
+        // Asked again: the user wants a new answer.
+        return false;
+    }
+    let scope = CONFIGURATION.load().ui.duplicate_prompts;
+    if scope == DuplicatePrompts::Off || !atty::is(atty::Stream::Stdin) {
+        return false;
+    }
+
+    let wanted = words(prompt);
+    let mut found = find_in(&CONVERSATION.lock().await, &wanted).map(|a| (a, None));
+    if found.is_none() && scope == DuplicatePrompts::All {
+        let current = sessions::current_id();
+        found = sessions::load_saved()
+            .into_iter()
+            .filter(|session| session.id != current)
+            .find_map(|session| find_in(&session.messages, &wanted).map(|a| (a, Some(session.id))));
+    }
+    let (answer, session) = match found {
+        Some(found) => found,
+        None => return false,
+    };
+    let place = match session {
+        Some(id) => format!("in session {id}"),
+        None => String::from("earlier in this conversation"),
+    };
+    warn!(
+        "You asked this {place}. /previous shows that answer; send the prompt again to ask anyway."
+    );
+    finish_prompt();
+    *HELD.lock().unwrap() = Some(Held {
+        prompt: prompt.to_string(),
+        answer,
+    });
+    true
+}
+
+/// `/previous`: answers the held back prompt with the earlier answer.
+pub async fn previous() {
+    let held = match HELD.lock().unwrap().take() {
+        Some(held) => held,
+        None => {
+            print_error("No prompt was held back as a duplicate.");
+            return;
+        }
+    };
+    {
+        let mut conversation = CONVERSATION.lock().await;
+        conversation.push(string_to_chat_completion_request_user_message(held.prompt));
+        conversation.push(string_to_chat_completion_assistant_message(
+            held.answer.clone(),
+        ));
+    }
+    let mut sink = highlight::terminal_sink();
+    sink.write(&(held.answer + "\n"));
+    sink.flush();
+    finish_prompt();
+}
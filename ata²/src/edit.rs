@@ -0,0 +1,80 @@
+//! Composing a prompt in `$VISUAL` or `$EDITOR`, with `/edit [text]` or Ctrl-X Ctrl-E.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+
+lazy_static! {
+    /// Set by Ctrl-X Ctrl-E: the line being edited, to start from.
+    static ref REQUESTED: Mutex<Option<String>> = Mutex::new(None);
+}
+
+/// Asks for the editor to be opened once the current line is accepted, starting from `line`.
+pub fn request(line: &str) {
+    *REQUESTED.lock().unwrap() = Some(line.to_string());
+}
+
+/// If the editor should be opened for the accepted `line`, what to start from: the line itself
+/// after Ctrl-X Ctrl-E, or the text after `/edit`.
+pub fn requested(line: &str) -> Option<String> {
+    if let Some(initial) = REQUESTED.lock().unwrap().take() {
+        return Some(initial);
+    }
+    let line = line.trim_start();
+    let rest = line.strip_prefix("/edit")?;
+    (rest.is_empty() || rest.starts_with(char::is_whitespace)).then(|| rest.trim().to_string())
+}
+
+/// Opens `$VISUAL` or `$EDITOR` on `path`, returning once it exits.
+pub fn open(path: &Path) -> io::Result<()> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from("vi"));
+    // e.g. `code --wait`
+    let mut words = editor.split_whitespace();
+    let status = Command::new(words.next().unwrap_or("vi"))
+        .args(words)
+        .arg(path)
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("{editor} exited with an error"),
+        ));
+    }
+    Ok(())
+}
+
+/// Opens the editor on a temporary file holding `initial`, and returns what it holds afterwards.
+pub fn compose(initial: &str) -> io::Result<String> {
+    // Created with a name no one else can guess and readable by the user only; removed when
+    // dropped.
+    let file = tempfile::Builder::new()
+        .prefix("ata2-prompt-")
+        .suffix(".md")
+        .tempfile()?;
+    fs::write(file.path(), initial)?;
+    open(file.path())?;
+    Ok(fs::read_to_string(file.path())?.trim_end().to_string())
+}
@@ -0,0 +1,373 @@
+//! Exporting the conversation to other document formats (`/export`).
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use chrono::Local;
+use serde_json::json;
+
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::ask;
+use crate::params;
+use crate::pii::Redactor;
+use crate::prompt::CONVERSATION;
+use crate::readline::{chat_completion_message_role, chat_completion_message_to_string};
+use crate::sessions;
+use crate::Config;
+use crate::TokioResult;
+use crate::CONFIGURATION;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Markdown,
+    /// Emacs Org mode.
+    Org,
+    /// A standalone page.
+    Html,
+    /// Markdown, uploaded as a secret GitHub gist.
+    Gist,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown | ExportFormat::Gist => "md",
+            ExportFormat::Org => "org",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "md" | "markdown" => Ok(ExportFormat::Markdown),
+            "org" => Ok(ExportFormat::Org),
+            "html" => Ok(ExportFormat::Html),
+            "gist" => Ok(ExportFormat::Gist),
+            _ => Err(format!(
+                "Unknown export format `{s}`. Available formats: md, org, html, gist"
+            )),
+        }
+    }
+}
+
+/// The role and text of each message.
+type Transcript = Vec<(&'static str, String)>;
+
+/// Handles `/export [--redact] <format> [path]`, returning the path written to, or the gist's URL
+/// (`path` being the name of its file). With `--redact`, personal information and secrets are
+/// replaced by placeholders.
+pub async fn command(args: &str) -> TokioResult<String> {
+    let (redact, args): (Vec<_>, Vec<_>) =
+        args.split_whitespace().partition(|arg| *arg == "--redact");
+    let redact = !redact.is_empty();
+    if args.is_empty() {
+        return Err("Usage: /export [--redact] <format> [path]".into());
+    }
+    let mut args = args.into_iter();
+    let format: ExportFormat = args.next().unwrap_or("").parse()?;
+    let path = args.next().map(PathBuf::from).unwrap_or_else(|| {
+        format!(
+            "conversation-{}.{}",
+            Local::now().format("%Y%m%d-%H%M%S"),
+            format.extension()
+        )
+        .into()
+    });
+    let mut transcript = CONVERSATION
+        .lock()
+        .await
+        .iter()
+        .map(|message| {
+            (
+                chat_completion_message_role(message),
+                chat_completion_message_to_string(message),
+            )
+        })
+        .collect::<Transcript>();
+    let mut title = sessions::current_title();
+    if redact {
+        let mut redactor = Redactor::default();
+        for text in transcript
+            .iter_mut()
+            .map(|(_, text)| text)
+            .chain(&mut title)
+        {
+            *text = redactor.redact(text);
+        }
+    }
+    let config = CONFIGURATION.load_full();
+    let document = match format {
+        ExportFormat::Markdown | ExportFormat::Gist => {
+            to_markdown(&transcript, title.as_deref(), &config)
+        }
+        ExportFormat::Org => to_org(&transcript, title.as_deref(), &config),
+        ExportFormat::Html => to_html(&transcript, title.as_deref(), &config),
+    };
+    if format == ExportFormat::Gist {
+        let name = path.to_string_lossy();
+        return upload_gist(&name, title.as_deref(), &document, redact).await;
+    }
+    fs::write(&path, document)?;
+    Ok(path.display().to_string())
+}
+
+/// The token to create gists with: `$GITHUB_TOKEN`, or `$GH_TOKEN` as the GitHub CLI reads it.
+fn github_token() -> Option<String> {
+    ["GITHUB_TOKEN", "GH_TOKEN"]
+        .iter()
+        .find_map(|var| env::var(var).ok().filter(|token| !token.is_empty()))
+}
+
+/// Uploads `document` as a secret gist with one file, `name`, once confirmed. Returns its URL.
+async fn upload_gist(
+    name: &str,
+    title: Option<&str>,
+    document: &str,
+    redacted: bool,
+) -> TokioResult<String> {
+    let token = github_token()
+        .ok_or("Set GITHUB_TOKEN to a token allowed to create gists to upload one")?;
+    let question = if redacted {
+        "Upload the redacted conversation to GitHub as a secret gist?"
+    } else {
+        "Upload the conversation to GitHub as a secret gist, without --redact?"
+    };
+    if !ask::confirm(question).await {
+        return Err("Not uploaded".into());
+    }
+    let response = params::current_config()
+        .http_client()?
+        .post("https://api.github.com/gists")
+        .bearer_auth(token)
+        .header("Accept", "application/vnd.github+json")
+        .header("User-Agent", "ata2")
+        .json(&json!({
+            "description": title.unwrap_or("ata² conversation"),
+            "public": false,
+            "files": { name: { "content": document } },
+        }))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("GitHub refused the gist ({status}): {}", body.trim()).into());
+    }
+    let gist: serde_json::Value = response.json().await?;
+    gist["html_url"]
+        .as_str()
+        .map(String::from)
+        .ok_or_else(|| "GitHub didn't say where the gist is".into())
+}
+
+/// Lines starting with these would be parsed as Org syntax inside a block, so they must be escaped
+/// with a comma.
+fn needs_org_escape(line: &str) -> bool {
+    line.starts_with('*') || line.starts_with("#+")
+}
+
+/// Converts Markdown-ish model output to Org: fenced code blocks become `#+begin_src` blocks, and
+/// `*` bullets (which would be headings in Org) become `-` bullets.
+fn markdown_to_org(text: &str) -> String {
+    let mut ret = String::with_capacity(text.len());
+    let mut in_code = false;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if let Some(lang) = trimmed.strip_prefix("```") {
+            if in_code {
+                ret.push_str("#+end_src\n");
+            } else {
+                let lang = lang.trim();
+                if lang.is_empty() {
+                    ret.push_str("#+begin_src\n");
+                } else {
+                    let _ = writeln!(ret, "#+begin_src {lang}");
+                }
+            }
+            in_code = !in_code;
+        } else if in_code && needs_org_escape(line) {
+            let _ = writeln!(ret, ",{line}");
+        } else if !in_code && line.starts_with("* ") {
+            let _ = writeln!(ret, "- {}", &line[2..]);
+        } else if !in_code && needs_org_escape(line) {
+            let _ = writeln!(ret, " {line}");
+        } else {
+            ret.push_str(line);
+            ret.push('\n');
+        }
+    }
+    if in_code {
+        // The model's answer was cut off in the middle of a code block.
+        ret.push_str("#+end_src\n");
+    }
+    ret
+}
+
+fn heading(role: &str) -> &'static str {
+    match role {
+        "system" => "System",
+        "user" => "Prompt",
+        "assistant" => "Response",
+        _ => "Tool",
+    }
+}
+
+/// Renders the conversation as a Markdown document, with a heading per message.
+pub fn to_markdown(transcript: &[(&str, String)], title: Option<&str>, config: &Config) -> String {
+    let mut ret = String::new();
+    let _ = writeln!(ret, "# {}", title.unwrap_or("ata² conversation"));
+    ret.push('\n');
+    let _ = writeln!(
+        ret,
+        "{}, {}",
+        Local::now().format("%Y-%m-%d %H:%M"),
+        config.model
+    );
+    for (role, text) in transcript {
+        let _ = writeln!(ret, "\n## {}\n", heading(role));
+        let _ = writeln!(ret, "{}", text.trim_end());
+    }
+    ret
+}
+
+/// `text` with the characters that are markup in HTML escaped.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Converts Markdown-ish model output to HTML: fenced code blocks become `<pre><code>` blocks, and
+/// the text between them paragraphs, keeping its line breaks. Nothing else is interpreted, so the
+/// text reads as it was written.
+fn markdown_to_html(text: &str) -> String {
+    fn flush(ret: &mut String, paragraph: &mut Vec<&str>) {
+        if !paragraph.is_empty() {
+            let lines: Vec<String> = paragraph.iter().map(|line| escape_html(line)).collect();
+            let _ = writeln!(ret, "<p>{}</p>", lines.join("<br>\n"));
+            paragraph.clear();
+        }
+    }
+
+    let mut ret = String::with_capacity(text.len());
+    let mut paragraph: Vec<&str> = vec![];
+    let mut code: Option<Vec<&str>> = None;
+    for line in text.lines() {
+        let fence = line.trim_start().strip_prefix("```");
+        if let Some(lines) = code.as_mut() {
+            if fence.is_some() {
+                let _ = writeln!(ret, "{}</code></pre>", escape_html(&lines.join("\n")));
+                code = None;
+            } else {
+                lines.push(line);
+            }
+            continue;
+        }
+        match fence {
+            Some(lang) => {
+                flush(&mut ret, &mut paragraph);
+                match lang.trim() {
+                    "" => ret.push_str("<pre><code>"),
+                    lang => {
+                        let _ = write!(ret, "<pre><code class=\"language-{}\">", escape_html(lang));
+                    }
+                }
+                code = Some(vec![]);
+            }
+            None if line.trim().is_empty() => flush(&mut ret, &mut paragraph),
+            None => paragraph.push(line),
+        }
+    }
+    // The model's answer was cut off in the middle of a code block.
+    if let Some(lines) = code {
+        let _ = writeln!(ret, "{}</code></pre>", escape_html(&lines.join("\n")));
+    }
+    flush(&mut ret, &mut paragraph);
+    ret
+}
+
+/// Renders the conversation as a standalone HTML page, with a section per message.
+pub fn to_html(transcript: &[(&str, String)], title: Option<&str>, config: &Config) -> String {
+    let title = escape_html(title.unwrap_or("ata² conversation"));
+    let mut ret = String::new();
+    let _ = writeln!(ret, "<!DOCTYPE html>");
+    let _ = writeln!(ret, "<html>\n<head>\n<meta charset=\"utf-8\">");
+    let _ = writeln!(ret, "<title>{title}</title>");
+    let _ = writeln!(
+        ret,
+        "<style>body {{ max-width: 50em; margin: auto; font-family: sans-serif; }} \
+         pre {{ background: #f4f4f4; padding: 1em; overflow-x: auto; }} \
+         section.user {{ border-left: 3px solid #888; padding-left: 1em; }}</style>"
+    );
+    let _ = writeln!(ret, "</head>\n<body>\n<h1>{title}</h1>");
+    let _ = writeln!(
+        ret,
+        "<p>{}, {}</p>",
+        Local::now().format("%Y-%m-%d %H:%M"),
+        escape_html(&config.model)
+    );
+    for (role, text) in transcript {
+        let _ = writeln!(ret, "<section class=\"{role}\">");
+        let _ = writeln!(ret, "<h2>{}</h2>", heading(role));
+        ret.push_str(&markdown_to_html(text));
+        let _ = writeln!(ret, "</section>");
+    }
+    let _ = writeln!(ret, "</body>\n</html>");
+    ret
+}
+
+/// Renders the conversation as an Org document. Each message is a heading with a properties drawer
+/// carrying its role and, for responses, the model and parameters used.
+pub fn to_org(transcript: &[(&str, String)], title: Option<&str>, config: &Config) -> String {
+    let mut ret = String::new();
+    let _ = writeln!(ret, "#+TITLE: {}", title.unwrap_or("ata² conversation"));
+    let _ = writeln!(
+        ret,
+        "#+DATE: {}",
+        Local::now().format("[%Y-%m-%d %a %H:%M]")
+    );
+    let _ = writeln!(ret, "#+PROPERTY: MODEL {}", config.model);
+    ret.push('\n');
+    for (role, text) in transcript {
+        let _ = writeln!(ret, "* {}", heading(role));
+        let _ = writeln!(ret, ":PROPERTIES:");
+        let _ = writeln!(ret, ":ROLE: {role}");
+        if *role == "assistant" {
+            let _ = writeln!(ret, ":MODEL: {}", config.model);
+            let _ = writeln!(ret, ":TEMPERATURE: {}", config.temperature);
+            let _ = writeln!(ret, ":TOP_P: {}", config.top_p);
+            let _ = writeln!(ret, ":MAX_TOKENS: {}", config.max_tokens);
+            let _ = writeln!(ret, ":PRESENCE_PENALTY: {}", config.presence_penalty);
+            let _ = writeln!(ret, ":FREQUENCY_PENALTY: {}", config.frequency_penalty);
+        }
+        let _ = writeln!(ret, ":END:");
+        ret.push_str(&markdown_to_org(text));
+        ret.push('\n');
+    }
+    ret
+}
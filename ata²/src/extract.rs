@@ -0,0 +1,146 @@
+//! Extracting part of an answer for scripts (`--extract`).
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use regex::Regex;
+use serde_json::Value;
+
+use std::str::FromStr;
+
+/// A fenced code block in Markdown.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CodeBlock {
+    /// The first word of the info string, e.g. `rust`. May be empty.
+    pub language: String,
+    pub code: String,
+}
+
+/// Finds the fenced (```` ``` ````) code blocks in `text`. An unterminated block at the end is
+/// included, as the answer may have been cut off.
+pub fn code_blocks(text: &str) -> Vec<CodeBlock> {
+    let mut ret = vec![];
+    let mut current: Option<CodeBlock> = None;
+    for line in text.lines() {
+        let fence = line.trim_start().strip_prefix("```");
+        match (current.take(), fence) {
+            (None, Some(info)) => {
+                current = Some(CodeBlock {
+                    language: info.split_whitespace().next().unwrap_or("").to_string(),
+                    code: String::new(),
+                })
+            }
+            (Some(block), Some(_)) => ret.push(block),
+            (Some(mut block), None) => {
+                block.code.push_str(line);
+                block.code.push('\n');
+                current = Some(block);
+            }
+            (None, None) => {}
+        }
+    }
+    ret.extend(current);
+    ret
+}
+
+#[derive(Clone, Debug)]
+pub enum Extract {
+    /// The contents of all code blocks.
+    Code,
+    /// The contents of the first code block.
+    FirstCode,
+    /// The first JSON object or array.
+    Json,
+    /// Every match of the regex (or of its first capture group, if it has one), one per line.
+    Regex(Regex),
+}
+
+impl FromStr for Extract {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "code" => Ok(Extract::Code),
+            "first-code" => Ok(Extract::FirstCode),
+            "json" => Ok(Extract::Json),
+            _ => match s.strip_prefix("regex:") {
+                Some(pattern) => Regex::new(pattern)
+                    .map(Extract::Regex)
+                    .map_err(|e| e.to_string()),
+                None => Err(format!(
+                    "`{s}` is not one of code, first-code, json or regex:<pattern>"
+                )),
+            },
+        }
+    }
+}
+
+/// The first JSON value in `text` that is an object or an array.
+fn first_json(text: &str) -> Option<Value> {
+    // Prefer what the model marked as JSON.
+    for block in code_blocks(text) {
+        if let Ok(value) = serde_json::from_str::<Value>(&block.code) {
+            if value.is_object() || value.is_array() {
+                return Some(value);
+            }
+        }
+    }
+    text.char_indices()
+        .filter(|(_, c)| *c == '{' || *c == '[')
+        .find_map(|(i, _)| {
+            serde_json::Deserializer::from_str(&text[i..])
+                .into_iter::<Value>()
+                .next()
+                .and_then(Result::ok)
+        })
+}
+
+impl Extract {
+    /// The extracted part of `answer`, or `None` if there is nothing to extract.
+    pub fn apply(&self, answer: &str) -> Option<String> {
+        match self {
+            Extract::Code => {
+                let blocks = code_blocks(answer);
+                if blocks.is_empty() {
+                    return None;
+                }
+                Some(
+                    blocks
+                        .into_iter()
+                        .map(|b| b.code)
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                )
+            }
+            Extract::FirstCode => code_blocks(answer).into_iter().next().map(|b| b.code),
+            Extract::Json => {
+                first_json(answer).map(|v| serde_json::to_string_pretty(&v).unwrap() + "\n")
+            }
+            Extract::Regex(regex) => {
+                let matches = regex
+                    .captures_iter(answer)
+                    .map(|caps| caps.get(1).or_else(|| caps.get(0)).unwrap().as_str())
+                    .collect::<Vec<_>>();
+                if matches.is_empty() {
+                    return None;
+                }
+                Some(matches.join("\n") + "\n")
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Estimating what running many requests at once will cost, before sending them, e.g. for
+//! `ata2 sessions replay`. Above `confirm_cost_above`, the user has to confirm.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::types::ChatCompletionRequestMessage;
+
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+use crate::output::eprint_and_flush;
+use crate::pricing::Pricing;
+use crate::tokens;
+use crate::Config;
+
+#[derive(Debug, Default)]
+pub struct Forecast {
+    pub model: String,
+    pub requests: usize,
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+    /// In USD, if the model's price is known.
+    pub cost: Option<f64>,
+}
+
+impl Forecast {
+    /// Asking the prompts of `messages` again, in order, in one conversation. Answers are taken to
+    /// be as long as the ones in `messages`, or `max_tokens` where there are none.
+    pub fn replay(config: &Config, messages: &[ChatCompletionRequestMessage]) -> Self {
+        Self::replay_with(config, messages, |message| {
+            tokens::count_message(&config.model, message)
+        })
+    }
+
+    /// Like [`Forecast::replay`], with the tokens of each message counted by `count`, e.g. from a
+    /// cache.
+    pub fn replay_with(
+        config: &Config,
+        messages: &[ChatCompletionRequestMessage],
+        mut count: impl FnMut(&ChatCompletionRequestMessage) -> usize,
+    ) -> Self {
+        let model = &config.model;
+        let mut forecast = Forecast {
+            model: model.clone(),
+            ..Default::default()
+        };
+        let mut history = config
+            .system_prompt
+            .as_ref()
+            .map_or(0, |system| tokens::count_as_message(model, system));
+        for (i, message) in messages.iter().enumerate() {
+            if !matches!(message, ChatCompletionRequestMessage::User(_)) {
+                continue;
+            }
+            history += count(message);
+            let answer = match messages.get(i + 1) {
+                Some(answer @ ChatCompletionRequestMessage::Assistant(_)) => count(answer),
+                _ => tokens::answer_tokens(config),
+            };
+            forecast.requests += 1;
+            forecast.input_tokens += history;
+            forecast.output_tokens += answer;
+            history += answer;
+        }
+        forecast.price();
+        forecast
+    }
+
+    /// Adds a request of `input_tokens`, answered with `output_tokens`.
+    pub fn add(&mut self, input_tokens: usize, output_tokens: usize) {
+        self.requests += 1;
+        self.input_tokens += input_tokens;
+        self.output_tokens += output_tokens;
+        self.price();
+    }
+
+    fn price(&mut self) {
+        self.cost = Pricing::load().lookup(&self.model).map(|price| {
+            (self.input_tokens as f64 * price.input + self.output_tokens as f64 * price.output)
+                / 1_000_000.0
+        });
+    }
+
+    /// Whether the forecast costs at most `confirm_cost_above`, so that it needn't be confirmed.
+    pub fn is_cheap(&self, config: &Config) -> bool {
+        self.cost
+            .map_or(false, |cost| cost <= config.confirm_cost_above)
+    }
+
+    /// Prints the forecast, and if it costs more than `confirm_cost_above` (or its cost is
+    /// unknown), asks whether to go ahead, on the terminal even if stdin is piped. `yes` answers
+    /// for the user.
+    pub fn confirm(&self, config: &Config, yes: bool) -> bool {
+        eprintln!("{self}");
+        if self.is_cheap(config) || yes {
+            return true;
+        }
+        let mut terminal: Box<dyn BufRead> = if atty::is(atty::Stream::Stdin) {
+            Box::new(io::stdin().lock())
+        } else {
+            match File::open("/dev/tty") {
+                Ok(tty) => Box::new(BufReader::new(tty)),
+                Err(_) => {
+                    error!("Not going ahead without confirmation; pass --yes to confirm");
+                    return false;
+                }
+            }
+        };
+        eprint_and_flush("Go ahead? [y/N] ");
+        let mut answer = String::new();
+        terminal.read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y")
+    }
+}
+
+impl Display for Forecast {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "{} requests to {}: about {} input and {} output tokens, ",
+            self.requests, self.model, self.input_tokens, self.output_tokens
+        )?;
+        match self.cost {
+            Some(cost) => write!(f, "costing about ${cost:.2}"),
+            None => write!(f, "at an unknown price (see ata2 pricing show)"),
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! Asking about changes tracked by git: `/commitmsg` has the model write a commit message for
+//! them, `/diff` has it review them. Both take what is staged, or a range like `HEAD~3..`.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use std::process::Command;
+
+use crate::attach;
+use crate::commands::CommandResult;
+use crate::prompt::{self, print_error};
+use crate::CONFIGURATION;
+
+const COMMIT_MESSAGE_INSTRUCTION: &str = "Write a commit message for the following change: a \
+    summary line in the imperative mood of at most 50 characters, a blank line, then a short body \
+    saying what changed and why, wrapped at 72 columns. Reply with the message only.";
+
+const REVIEW_INSTRUCTION: &str = "Review the following change. Point out bugs, risky or unclear \
+    code, and missing tests, quoting the lines concerned. Be brief about what is fine.";
+
+/// How many recent commit subjects to show the model, for their style.
+const RECENT_SUBJECTS: &str = "10";
+
+fn git(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Could not run git: {e}"))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The diff of `range`, or of what is staged, as plain text whatever the user's git config says.
+fn diff(range: &str) -> Result<String, String> {
+    let mut args = vec!["diff", "--no-color", "--no-ext-diff"];
+    match range {
+        "" => args.push("--staged"),
+        range => args.extend(range.split_whitespace()),
+    }
+    let diff = git(&args)?;
+    if diff.trim().is_empty() {
+        return Err(match range {
+            "" => String::from("Nothing is staged"),
+            range => format!("No changes in {range}"),
+        });
+    }
+    let max_bytes = CONFIGURATION.load().attach_max_bytes;
+    if diff.len() as u64 > max_bytes {
+        return Err(format!(
+            "The diff is {} bytes, more than attach_max_bytes ({max_bytes})",
+            diff.len()
+        ));
+    }
+    Ok(diff)
+}
+
+async fn ask(instruction: String, range: &str) -> CommandResult {
+    let diff = match diff(range.trim()) {
+        Ok(diff) => diff,
+        Err(e) => {
+            print_error(&e);
+            return Ok(vec![]);
+        }
+    };
+    let prompt = format!("{instruction}\n\n{}", attach::fenced(&diff, "diff"));
+    prompt::request(Some(prompt), None).await
+}
+
+/// `/commitmsg [range]`.
+pub async fn commit_message(range: &str) -> CommandResult {
+    let mut instruction = String::from(COMMIT_MESSAGE_INSTRUCTION);
+    if let Ok(subjects) = git(&["log", "-n", RECENT_SUBJECTS, "--format=%s"]) {
+        if !subjects.trim().is_empty() {
+            instruction += &format!(
+                " Follow the style of the repository's recent subjects:\n{}",
+                subjects.trim_end()
+            );
+        }
+    }
+    ask(instruction, range).await
+}
+
+/// `/diff [range]`.
+pub async fn review(range: &str) -> CommandResult {
+    ask(String::from(REVIEW_INSTRUCTION), range).await
+}
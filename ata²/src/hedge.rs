@@ -0,0 +1,174 @@
+//! Racing a slow provider against the fallback one (`hedge_after_ms`).
+//!
+//! The request is sent to the primary provider. If its first token hasn't arrived within the
+//! budget, the same request is sent to the `[fallback]` provider as well, and whichever stream
+//! answers first is kept; the other is cancelled.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::error::OpenAIError;
+use async_openai::types::{
+    ChatCompletionResponseStream, CreateChatCompletionRequest, CreateChatCompletionStreamResponse,
+};
+use futures_util::stream;
+use tokio_stream::StreamExt as _;
+
+use std::time::Duration;
+
+use crate::backend::{self, Backend};
+use crate::config::{NetworkConfig, RateLimitConfig};
+use crate::ratelimit::{self, Provider};
+use crate::Config;
+
+/// A stream whose first item has already been received.
+type Started = (
+    Option<Result<CreateChatCompletionStreamResponse, OpenAIError>>,
+    ChatCompletionResponseStream,
+);
+
+fn stalled(timeout: Duration) -> OpenAIError {
+    OpenAIError::StreamError(format!(
+        "nothing came from the API for {}s (request_timeout_secs in [network])",
+        timeout.as_secs()
+    ))
+}
+
+/// `stream`, ending with an error if the next item takes longer than `timeout` to come.
+fn guard(stream: ChatCompletionResponseStream, timeout: Duration) -> ChatCompletionResponseStream {
+    Box::pin(stream::unfold(Some(stream), move |stream| async move {
+        let mut stream = stream?;
+        match tokio::time::timeout(timeout, stream.next()).await {
+            Ok(item) => item.map(|item| (item, Some(stream))),
+            Err(_) => Some((Err(stalled(timeout)), None)),
+        }
+    }))
+}
+
+/// Sends `request` to `provider` through `backend` once its rate limit allows. Waiting for the
+/// rate limit doesn't count towards `[network] request_timeout_secs`.
+async fn open(
+    provider: Provider,
+    limit: &RateLimitConfig,
+    network: &NetworkConfig,
+    backend: Result<Box<dyn Backend>, OpenAIError>,
+    request: CreateChatCompletionRequest,
+) -> Result<ChatCompletionResponseStream, OpenAIError> {
+    ratelimit::acquire(provider, limit, ratelimit::estimate(&request)).await;
+    let backend = backend?;
+    let timeout = match network.request_timeout() {
+        Some(timeout) => timeout,
+        None => return backend.stream_chat(request).await,
+    };
+    match tokio::time::timeout(timeout, backend.stream_chat(request)).await {
+        Ok(stream) => Ok(guard(stream?, timeout)),
+        Err(_) => Err(stalled(timeout)),
+    }
+}
+
+async fn start(
+    provider: Provider,
+    limit: &RateLimitConfig,
+    network: &NetworkConfig,
+    backend: Result<Box<dyn Backend>, OpenAIError>,
+    request: CreateChatCompletionRequest,
+) -> Result<Started, OpenAIError> {
+    let mut stream = open(provider, limit, network, backend, request).await?;
+    let first = stream.next().await;
+    Ok((first, stream))
+}
+
+fn answered(started: &Result<Started, OpenAIError>) -> bool {
+    matches!(started, Ok((Some(Ok(_)), _)))
+}
+
+/// Puts the first item back in front of the stream.
+fn resume((first, rest): Started) -> ChatCompletionResponseStream {
+    Box::pin(stream::iter(first).chain(rest))
+}
+
+/// Starts streaming the answer to `request`, hedging if configured to. Returns the stream and the
+/// model that is answering.
+pub async fn create_stream(
+    config: &Config,
+    request: CreateChatCompletionRequest,
+) -> Result<(ChatCompletionResponseStream, String), OpenAIError> {
+    let model = request.model.clone();
+    if config.hedge_after_ms == 0 {
+        let stream = open(
+            Provider::Primary,
+            &config.rate_limit,
+            &config.network,
+            backend::primary(config),
+            request,
+        )
+        .await?;
+        return Ok((stream, model));
+    }
+
+    let mut fallback_request = request.clone();
+    if let Some(ref fallback_model) = config.fallback.model {
+        fallback_request.model = fallback_model.clone();
+    }
+    let fallback_model = fallback_request.model.clone();
+
+    let primary = start(
+        Provider::Primary,
+        &config.rate_limit,
+        &config.network,
+        backend::primary(config),
+        request,
+    );
+    tokio::pin!(primary);
+    let budget = Duration::from_millis(config.hedge_after_ms);
+    if let Ok(started) = tokio::time::timeout(budget, &mut primary).await {
+        return started.map(|started| (resume(started), model));
+    }
+    info!(
+        "No answer after {}ms, also asking the fallback provider",
+        config.hedge_after_ms
+    );
+    let fallback = start(
+        Provider::Fallback,
+        config.fallback_rate_limit(),
+        &config.network,
+        backend::fallback(config),
+        fallback_request,
+    );
+    tokio::pin!(fallback);
+
+    // Whichever isn't returned is cancelled by dropping it. If the first to finish failed, wait for
+    // the other one instead.
+    tokio::select! {
+        started = &mut primary => {
+            if answered(&started) {
+                return started.map(|started| (resume(started), model));
+            }
+            warn!("The primary provider failed, waiting for the fallback provider");
+            fallback.await.map(|started| (resume(started), fallback_model))
+        }
+        started = &mut fallback => {
+            if answered(&started) {
+                info!("Using the fallback provider's answer");
+                return started.map(|started| (resume(started), fallback_model));
+            }
+            warn!("The fallback provider failed, waiting for the primary provider");
+            primary.await.map(|started| (resume(started), model))
+        }
+    }
+}
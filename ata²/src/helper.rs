@@ -0,0 +1,122 @@
+//! Completion and hints while typing a prompt.
+//!
+//! Tab completes the name of a `/command` at the start of the line, the model after `/model `,
+//! the profile after `/profile ` and the path of an `@file`. As a command's name is typed, the
+//! rest of it is hinted dimmed when only one command fits, and Right accepts the hint.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper, Result};
+
+use std::borrow::Cow;
+
+use crate::commands::COMMANDS;
+use crate::models;
+use crate::status;
+use crate::theme::{self, Role};
+use crate::CONFIGURATION;
+
+#[derive(Default)]
+pub struct AtaHelper {
+    files: FilenameCompleter,
+}
+
+/// Candidates among `names` starting with `typed`.
+fn candidates(names: impl IntoIterator<Item = String>, typed: &str) -> Vec<Pair> {
+    names
+        .into_iter()
+        .filter(|name| name.starts_with(typed))
+        .map(|name| Pair {
+            display: name.clone(),
+            replacement: name,
+        })
+        .collect()
+}
+
+impl Completer for AtaHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Result<(usize, Vec<Pair>)> {
+        let before = &line[..pos];
+        let start = before
+            .rfind(char::is_whitespace)
+            .map_or(0, |i| i + before[i..].chars().next().unwrap().len_utf8());
+        let word = &before[start..];
+        if word.starts_with('@') {
+            return self.files.complete(line, pos, ctx);
+        }
+        if start == 0 && word.starts_with('/') {
+            let names = COMMANDS.iter().map(|c| c.name().to_string());
+            return Ok((0, candidates(names, word)));
+        }
+        let names = match before[..start].trim_end() {
+            "/model" => models::known(),
+            "/profile" => {
+                let config = CONFIGURATION.load();
+                let mut names: Vec<String> = config
+                    .profile_names()
+                    .into_iter()
+                    .map(String::from)
+                    .collect();
+                names.push(String::from("-"));
+                names
+            }
+            _ => return Ok((pos, vec![])),
+        };
+        Ok((start, candidates(names, word)))
+    }
+}
+
+impl Hinter for AtaHelper {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, _: &Context<'_>) -> Option<String> {
+        if pos < line.len() || !line.starts_with('/') || line.contains(char::is_whitespace) {
+            return None;
+        }
+        let mut fitting = COMMANDS.iter().filter(|c| c.name().starts_with(line));
+        match (fitting.next(), fitting.next()) {
+            (Some(command), None) if command.name() != line => {
+                Some(command.name()[line.len()..].to_string())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Highlighter for AtaHelper {
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(&'s self, prompt: &'p str, _: bool) -> Cow<'b, str> {
+        match status::coloured(prompt) {
+            Some(coloured) => Cow::Owned(coloured),
+            None => Cow::Borrowed(prompt),
+        }
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(theme::paint(Role::Muted, hint))
+    }
+}
+
+impl Validator for AtaHelper {}
+
+impl Helper for AtaHelper {}
@@ -0,0 +1,178 @@
+//! Syntax highlighting of the code blocks in answers (`ui.highlight_code`).
+//!
+//! Text outside code blocks is printed as it streams in. Inside a fenced block, each line is
+//! highlighted according to the block's language tag once it is complete; a block cut short is
+//! printed as plain text.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use syntect::easy::HighlightLines;
+use syntect::highlighting::ThemeSet;
+use syntect::parsing::SyntaxSet;
+use syntect::util::as_24_bit_terminal_escaped;
+
+use std::mem;
+
+use crate::output::{OutputSink, StdoutSink};
+use crate::params;
+use crate::theme;
+use crate::wrap::WrappingSink;
+
+const RESET: &str = "\x1b[0m";
+
+lazy_static! {
+    static ref SYNTAXES: SyntaxSet = SyntaxSet::load_defaults_newlines();
+    static ref THEMES: ThemeSet = ThemeSet::load_defaults();
+}
+
+/// Whether `name` is one of the themes `ui.code_theme` can be set to.
+pub fn has_theme(name: &str) -> bool {
+    THEMES.themes.contains_key(name)
+}
+
+/// The sink for answers printed on the terminal: highlighting and wrapping as configured if
+/// stdout is a terminal, plain otherwise.
+pub fn terminal_sink() -> Box<dyn OutputSink> {
+    if !atty::is(atty::Stream::Stdout) {
+        return Box::new(StdoutSink);
+    }
+    let config = params::current_config();
+    let ui = &config.ui;
+    let sink: Box<dyn OutputSink> = if ui.highlight_code && theme::code_theme().is_some() {
+        Box::new(HighlightingSink::new(StdoutSink))
+    } else {
+        Box::new(StdoutSink)
+    };
+    if ui.wrap == "off" {
+        return sink;
+    }
+    // `auto` follows the terminal's width.
+    Box::new(WrappingSink::new(sink, ui.wrap.parse().ok()))
+}
+
+/// If `line` opens a fenced code block, its fence (e.g. ```` ```` ````) and language tag.
+fn fence_start(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    ['`', '~'].into_iter().find_map(|c| {
+        let tag = line.trim_start_matches(c);
+        let fence = &line[..line.len() - tag.len()];
+        (fence.len() >= 3).then(|| (fence, tag.split_whitespace().next().unwrap_or("")))
+    })
+}
+
+/// A code block being printed.
+struct Block {
+    /// The fence it was opened with; it is closed by a line of at least as many of its character.
+    fence: String,
+    highlighter: HighlightLines<'static>,
+}
+
+impl Block {
+    fn closed_by(&self, line: &str) -> bool {
+        let line = line.trim();
+        let c = self.fence.chars().next().unwrap_or('`');
+        line.len() >= self.fence.len() && line.chars().all(|l| l == c)
+    }
+}
+
+pub struct HighlightingSink<S: OutputSink> {
+    inner: S,
+    /// The current line, of which the first `written` bytes have been printed.
+    line: String,
+    written: usize,
+    block: Option<Block>,
+}
+
+impl<S: OutputSink> HighlightingSink<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            line: String::new(),
+            written: 0,
+            block: None,
+        }
+    }
+
+    /// Whether the current line, outside of a block, could still turn out to be a fence.
+    fn may_be_fence(&self) -> bool {
+        let line = self.line.trim_start();
+        ["```", "~~~"]
+            .into_iter()
+            .any(|fence| fence.starts_with(line) || line.starts_with(fence))
+    }
+
+    fn finish_line(&mut self) {
+        let line = mem::take(&mut self.line);
+        let unwritten = &line[mem::take(&mut self.written)..];
+        let closes = self.block.as_ref().map_or(false, |b| b.closed_by(&line));
+        match self.block {
+            _ if closes => {
+                self.block = None;
+                self.inner.write(&line);
+            }
+            None => {
+                if let Some((fence, tag)) = fence_start(&line) {
+                    let syntax = SYNTAXES
+                        .find_syntax_by_token(tag)
+                        .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text());
+                    let name = theme::code_theme().unwrap_or_default();
+                    let theme = THEMES
+                        .themes
+                        .get(&name)
+                        .unwrap_or_else(|| &THEMES.themes["base16-ocean.dark"]);
+                    self.block = Some(Block {
+                        fence: fence.to_string(),
+                        highlighter: HighlightLines::new(syntax, theme),
+                    });
+                }
+                self.inner.write(unwritten);
+            }
+            Some(ref mut block) => match block.highlighter.highlight_line(&line, &SYNTAXES) {
+                Ok(ranges) => {
+                    let highlighted = as_24_bit_terminal_escaped(&ranges, false);
+                    let highlighted = highlighted.trim_end_matches('\n');
+                    self.inner.write(&format!("{highlighted}{RESET}\n"));
+                }
+                Err(_) => self.inner.write(&line),
+            },
+        }
+    }
+}
+
+impl<S: OutputSink> OutputSink for HighlightingSink<S> {
+    fn write(&mut self, text: &str) {
+        for piece in text.split_inclusive('\n') {
+            self.line.push_str(piece);
+            if piece.ends_with('\n') {
+                self.finish_line();
+            }
+        }
+        if self.block.is_none() && !self.may_be_fence() {
+            self.inner.write(&self.line[self.written..]);
+            self.written = self.line.len();
+        }
+    }
+
+    fn flush(&mut self) {
+        let line = mem::take(&mut self.line);
+        self.inner.write(&line[mem::take(&mut self.written)..]);
+        self.block = None;
+        self.inner.flush();
+    }
+}
@@ -0,0 +1,284 @@
+//! Ctrl-R: fuzzy search of the prompt history, in the manner of fzf, rather than rustyline's
+//! search for exact substrings.
+//!
+//! The characters typed need only appear in an entry in order, not next to each other: matches
+//! that are consecutive or start words rank higher, and ties go to the most recent entry. The
+//! whole of a multi-line entry is previewed under the list. Only on Unix terminals; elsewhere
+//! Ctrl-R is rustyline's own search.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::output::{eprint_and_flush, terminal_width};
+use crate::theme::{self, Role};
+
+/// The most entries listed, and lines of the selected entry previewed.
+const MAX_SHOWN: usize = 10;
+const PREVIEW_LINES: usize = 6;
+
+/// Scores of a match: per character, and bonuses for following the last one or starting a word.
+const MATCH: i64 = 16;
+const CONSECUTIVE: i64 = 16;
+const WORD_START: i64 = 8;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Asks for the search to be opened once the current line is accepted.
+pub fn request() {
+    REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Whether the line just accepted was accepted to open the search.
+pub fn requested() -> bool {
+    REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+/// How well `entry` matches `query`, if it does: each character of the query must appear in the
+/// entry, in order, ignoring case.
+fn score(query: &str, entry: &str) -> Option<i64> {
+    let entry: Vec<char> = entry.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut last: Option<usize> = None;
+    for wanted in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = next + entry[next..].iter().position(|&c| c == wanted)?;
+        score += MATCH;
+        if last.map_or(false, |last| last + 1 == found) {
+            score += CONSECUTIVE;
+        } else if let Some(last) = last {
+            // The further apart, the less likely it is meant.
+            score -= (found - last) as i64;
+        }
+        if found == 0 || !entry[found - 1].is_alphanumeric() {
+            score += WORD_START;
+        }
+        last = Some(found);
+        next = found + 1;
+    }
+    Some(score)
+}
+
+/// The entries of `history` (oldest first) matching `query`, best first.
+fn matches<'a>(history: &'a [String], query: &str) -> Vec<&'a str> {
+    let mut seen = std::collections::HashSet::new();
+    let mut scored: Vec<(i64, &str)> = history
+        .iter()
+        .rev()
+        .filter(|entry| seen.insert(entry.as_str()))
+        .filter_map(|entry| Some((score(query, entry)?, entry.as_str())))
+        .collect();
+    // Stable, so that the most recent comes first among equals.
+    scored.sort_by_key(|(score, _)| -score);
+    scored.into_iter().map(|(_, entry)| entry).collect()
+}
+
+/// `text` cut to `width` characters.
+fn fit(text: &str, width: usize) -> String {
+    match text.char_indices().nth(width.saturating_sub(1)) {
+        Some((end, _)) if text.chars().count() > width => format!("{}…", &text[..end]),
+        _ => text.to_string(),
+    }
+}
+
+/// Draws the list, the preview and the query, over what was drawn before (`drawn` lines above
+/// the cursor).
+fn draw(query: &str, shown: &[&str], selected: usize, drawn: &mut usize) {
+    let width = terminal_width().saturating_sub(4).max(10);
+    let mut lines = vec![];
+    for (i, entry) in shown.iter().enumerate() {
+        let first = entry.lines().next().unwrap_or("");
+        let more = if entry.lines().nth(1).is_some() {
+            " ↵"
+        } else {
+            ""
+        };
+        let line = fit(&format!("{first}{more}"), width);
+        lines.push(match i == selected {
+            true => theme::paint(Role::Emphasis, &format!("> {line}")),
+            false => format!("  {line}"),
+        });
+    }
+    if let Some(entry) = shown.get(selected).filter(|e| e.lines().nth(1).is_some()) {
+        for line in entry.lines().take(PREVIEW_LINES) {
+            lines.push(format!("  │ {}", fit(line, width.saturating_sub(2))));
+        }
+    }
+    lines.push(format!(
+        "{} {query}",
+        theme::paint(Role::Emphasis, &format!("history ({} found):", shown.len()))
+    ));
+    let up = match *drawn {
+        0 => String::new(),
+        n => format!("\x1b[{n}A"),
+    };
+    eprint_and_flush(&format!("{up}\r\x1b[J{}", lines.join("\n")));
+    *drawn = lines.len() - 1;
+}
+
+/// Clears what was drawn.
+fn clear(drawn: usize) {
+    let up = match drawn {
+        0 => String::new(),
+        n => format!("\x1b[{n}A"),
+    };
+    eprint_and_flush(&format!("{up}\r\x1b[J"));
+}
+
+enum Key {
+    Char(char),
+    Backspace,
+    Up,
+    Down,
+    Enter,
+    Cancel,
+    Other,
+}
+
+mod terminal {
+    use super::Key;
+    use std::io;
+
+    /// The terminal, reading keys as they are pressed, until dropped.
+    pub struct Raw {
+        original: libc::termios,
+    }
+
+    impl Raw {
+        pub fn enter() -> io::Result<Self> {
+            let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+            if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let original = termios;
+            termios.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+            termios.c_cc[libc::VMIN] = 1;
+            termios.c_cc[libc::VTIME] = 0;
+            if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self { original })
+        }
+
+        /// The next byte typed, or with `timeout_ms`, none if it takes longer.
+        fn byte(&self, timeout_ms: Option<i32>) -> io::Result<Option<u8>> {
+            if let Some(timeout_ms) = timeout_ms {
+                let mut poll = libc::pollfd {
+                    fd: libc::STDIN_FILENO,
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                if unsafe { libc::poll(&mut poll, 1, timeout_ms) } <= 0 {
+                    return Ok(None);
+                }
+            }
+            let mut byte = 0u8;
+            match unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) } {
+                1 => Ok(Some(byte)),
+                0 => Ok(None),
+                _ => Err(io::Error::last_os_error()),
+            }
+        }
+
+        pub fn key(&self) -> io::Result<Key> {
+            let byte = match self.byte(None)? {
+                Some(byte) => byte,
+                None => return Ok(Key::Cancel),
+            };
+            Ok(match byte {
+                b'\r' | b'\n' => Key::Enter,
+                0x7f | 0x08 => Key::Backspace,
+                // Ctrl-C, Ctrl-G
+                0x03 | 0x07 => Key::Cancel,
+                // Ctrl-R, Ctrl-N
+                0x12 | 0x0e => Key::Down,
+                // Ctrl-P
+                0x10 => Key::Up,
+                0x1b => match self.byte(Some(50))? {
+                    None => Key::Cancel,
+                    Some(b'[') | Some(b'O') => match self.byte(Some(50))? {
+                        Some(b'A') => Key::Up,
+                        Some(b'B') => Key::Down,
+                        _ => Key::Other,
+                    },
+                    Some(_) => Key::Other,
+                },
+                0x20..=0x7e => Key::Char(byte as char),
+                0xc0..=0xf7 => {
+                    let len = byte.leading_ones() as usize;
+                    let mut bytes = vec![byte];
+                    for _ in 1..len {
+                        bytes.extend(self.byte(Some(50))?);
+                    }
+                    match std::str::from_utf8(&bytes)
+                        .ok()
+                        .and_then(|s| s.chars().next())
+                    {
+                        Some(c) => Key::Char(c),
+                        None => Key::Other,
+                    }
+                }
+                _ => Key::Other,
+            })
+        }
+    }
+
+    impl Drop for Raw {
+        fn drop(&mut self) {
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+        }
+    }
+}
+
+/// Lets the user pick an entry of `history` (oldest first), starting the query with `typed`.
+/// Returns the entry picked, or none if the search was cancelled.
+pub fn pick(history: &[String], typed: &str) -> io::Result<Option<String>> {
+    let terminal = terminal::Raw::enter()?;
+    let mut query = typed.to_string();
+    let mut selected = 0;
+    let mut drawn = 0;
+    loop {
+        let found = matches(history, &query);
+        let shown = &found[..found.len().min(MAX_SHOWN)];
+        selected = selected.min(shown.len().saturating_sub(1));
+        draw(&query, shown, selected, &mut drawn);
+        match terminal.key()? {
+            Key::Char(c) => {
+                query.push(c);
+                selected = 0;
+            }
+            Key::Backspace => {
+                query.pop();
+                selected = 0;
+            }
+            Key::Down => selected += 1,
+            Key::Up => selected = selected.saturating_sub(1),
+            Key::Enter => {
+                clear(drawn);
+                return Ok(shown.get(selected).map(|entry| entry.to_string()));
+            }
+            Key::Cancel => {
+                clear(drawn);
+                return Ok(None);
+            }
+            Key::Other => {}
+        }
+    }
+}
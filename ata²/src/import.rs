@@ -0,0 +1,254 @@
+//! `ata2 sessions import`: bringing conversations over from other command-line tools.
+//!
+//! * `sgpt`: a chat file of shell_gpt (in its `chat_cache` directory), a JSON list of messages.
+//! * `llm`: the output of `llm logs --json` (Simon Willison's `llm`), one session per
+//!   conversation.
+//! * `aichat`: a session file of aichat (in its `sessions` directory), in YAML.
+//!
+//! Imported sessions are tagged `imported` and with the format, e.g. `sessions list --tag llm`.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::types::ChatCompletionRequestMessage;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::readline::{
+    string_to_chat_completion_assistant_message, string_to_chat_completion_request_user_message,
+    string_to_chat_completion_system_message,
+};
+use crate::sessions::{self, Session};
+use crate::TokioResult;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Format {
+    Sgpt,
+    Llm,
+    Aichat,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sgpt" => Ok(Self::Sgpt),
+            "llm" => Ok(Self::Llm),
+            "aichat" => Ok(Self::Aichat),
+            _ => Err(format!("`{s}` is not one of sgpt, llm or aichat")),
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        f.write_str(match self {
+            Self::Sgpt => "sgpt",
+            Self::Llm => "llm",
+            Self::Aichat => "aichat",
+        })
+    }
+}
+
+/// A message as stored by sgpt and aichat.
+#[derive(Deserialize)]
+struct RoleMessage {
+    role: String,
+    /// A string, or for aichat, a list of parts of which the text ones are kept.
+    content: Value,
+}
+
+impl RoleMessage {
+    fn text(&self) -> String {
+        match &self.content {
+            Value::String(text) => text.clone(),
+            Value::Array(parts) => parts
+                .iter()
+                .filter_map(|part| part.get("text").and_then(Value::as_str))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => String::new(),
+        }
+    }
+
+    /// As ata² stores it. Tool calls and results have no equivalent and are left out.
+    fn convert(&self) -> Option<ChatCompletionRequestMessage> {
+        let text = self.text();
+        match self.role.as_str() {
+            "system" => Some(string_to_chat_completion_system_message(text)),
+            "user" => Some(string_to_chat_completion_request_user_message(text)),
+            "assistant" => Some(string_to_chat_completion_assistant_message(text)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AichatSession {
+    /// e.g. `openai:gpt-4o`.
+    model: Option<String>,
+    #[serde(default)]
+    messages: Vec<RoleMessage>,
+}
+
+/// A prompt and its response, as logged by llm.
+#[derive(Deserialize)]
+struct LlmResponse {
+    model: Option<String>,
+    prompt: Option<String>,
+    system: Option<String>,
+    response: Option<String>,
+    conversation_id: Option<String>,
+    datetime_utc: Option<String>,
+}
+
+/// A conversation read from another tool, before it becomes a [`Session`].
+struct Imported {
+    name: String,
+    created: DateTime<Utc>,
+    model: Option<String>,
+    messages: Vec<ChatCompletionRequestMessage>,
+}
+
+/// When `path` was last changed, for formats that don't record it.
+fn modified(path: &Path) -> DateTime<Utc> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map(DateTime::from)
+        .unwrap_or_else(|_| Utc::now())
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| String::from("session"))
+}
+
+fn read_sgpt(path: &Path, contents: &str) -> TokioResult<Vec<Imported>> {
+    let messages: Vec<RoleMessage> = serde_json::from_str(contents)?;
+    Ok(vec![Imported {
+        name: file_name(path),
+        created: modified(path),
+        model: None,
+        messages: messages.iter().filter_map(RoleMessage::convert).collect(),
+    }])
+}
+
+fn read_aichat(path: &Path, contents: &str) -> TokioResult<Vec<Imported>> {
+    let session: AichatSession = serde_yaml::from_str(contents)?;
+    let model = session.model.map(|model| match model.split_once(':') {
+        Some((_provider, model)) => model.to_string(),
+        None => model,
+    });
+    Ok(vec![Imported {
+        name: file_name(path),
+        created: modified(path),
+        model,
+        messages: session
+            .messages
+            .iter()
+            .filter_map(RoleMessage::convert)
+            .collect(),
+    }])
+}
+
+fn read_llm(contents: &str) -> TokioResult<Vec<Imported>> {
+    let responses: Vec<LlmResponse> = serde_json::from_str(contents)?;
+    let mut conversations: BTreeMap<String, Vec<LlmResponse>> = BTreeMap::new();
+    for response in responses {
+        let id = response.conversation_id.clone().unwrap_or_default();
+        conversations.entry(id).or_default().push(response);
+    }
+    let time = |response: &LlmResponse| {
+        response
+            .datetime_utc
+            .as_deref()
+            .and_then(|time| time.parse::<NaiveDateTime>().ok())
+            .map(|time| DateTime::from_naive_utc_and_offset(time, Utc))
+    };
+    Ok(conversations
+        .into_iter()
+        .map(|(id, mut responses)| {
+            responses.sort_by_key(time);
+            let mut messages = vec![];
+            if let Some(system) = responses.iter().find_map(|r| r.system.clone()) {
+                messages.push(string_to_chat_completion_system_message(system));
+            }
+            for response in &responses {
+                messages.extend(
+                    response
+                        .prompt
+                        .clone()
+                        .map(string_to_chat_completion_request_user_message),
+                );
+                messages.extend(
+                    response
+                        .response
+                        .clone()
+                        .map(string_to_chat_completion_assistant_message),
+                );
+            }
+            Imported {
+                name: id,
+                created: responses.first().and_then(time).unwrap_or_else(Utc::now),
+                model: responses.first().and_then(|r| r.model.clone()),
+                messages,
+            }
+        })
+        .collect())
+}
+
+/// Handles `ata2 sessions import`: saves the conversations in `path` as sessions, printing their
+/// IDs.
+pub fn import(format: Format, path: &Path) -> TokioResult<()> {
+    let contents = fs::read_to_string(path)?;
+    let imported = match format {
+        Format::Sgpt => read_sgpt(path, &contents)?,
+        Format::Llm => read_llm(&contents)?,
+        Format::Aichat => read_aichat(path, &contents)?,
+    };
+    for conversation in imported {
+        if conversation.messages.is_empty() {
+            continue;
+        }
+        let session = Session {
+            id: format!("{format}-{}", conversation.name),
+            title: None,
+            created: conversation.created,
+            updated: Utc::now(),
+            model: conversation.model,
+            messages: conversation.messages,
+            models: BTreeMap::new(),
+            truncated: BTreeSet::new(),
+            alternatives: vec![],
+            tags: BTreeSet::from([String::from("imported"), format.to_string()]),
+            checkpoints: vec![],
+        };
+        sessions::write(&session)?;
+        println!("{}", session.id);
+    }
+    Ok(())
+}
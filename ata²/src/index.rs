@@ -0,0 +1,390 @@
+//! Answering from local files (`[rag]`): `ata2 index <paths>` splits files into passages, asks for
+//! their embeddings and keeps them in an index file. With `/rag on`, the passages most like each
+//! prompt are looked up in it and sent along, as context.
+//!
+//! The index is a JSON file searched exhaustively, which is quick enough for a few thousand
+//! passages, such as a directory of notes, and needs nothing else to be installed.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::types::{CreateEmbeddingRequestArgs, CreateEmbeddingResponse};
+use async_openai::Client;
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{ApiConfig, Config};
+use crate::params;
+use crate::TokioResult;
+use crate::CONFIGURATION;
+use crate::FLAGS;
+
+/// Files larger than this are left out of the index, in bytes.
+const MAX_FILE_BYTES: u64 = 1_000_000;
+
+/// How many passages are sent per embeddings request.
+const BATCH: usize = 64;
+
+/// A passage of an indexed file.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Chunk {
+    /// The line it starts on, from 1.
+    line: usize,
+    text: String,
+    vector: Vec<f32>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct IndexedFile {
+    /// When the file was last changed, in seconds since the epoch, to index only changed files.
+    modified: u64,
+    chunks: Vec<Chunk>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct Index {
+    /// The model the vectors are from. Vectors of different models can't be compared.
+    model: String,
+    files: BTreeMap<PathBuf, IndexedFile>,
+}
+
+lazy_static! {
+    static ref ENABLED: AtomicBool = AtomicBool::new(CONFIGURATION.load().rag.enabled);
+    /// The index last read, and when its file was changed.
+    static ref LOADED: Mutex<Option<(PathBuf, SystemTime, Arc<Index>)>> = Mutex::new(None);
+    /// The context found for the last prompt, so that it isn't looked up again for each round of
+    /// tool calls or `/retry`.
+    static ref LAST: Mutex<Option<(String, String)>> = Mutex::new(None);
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn read(path: &Path) -> Result<Index, String> {
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map_err(|e| format!("Could not read the index {}: {e}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Index::default()),
+        Err(e) => Err(format!("Could not read the index {}: {e}", path.display())),
+    }
+}
+
+/// The index at `path`, read again only if it changed.
+fn load(path: &Path) -> Result<Arc<Index>, String> {
+    let changed = modified(path).unwrap_or(UNIX_EPOCH);
+    let mut loaded = LOADED.lock().unwrap();
+    if let Some((ref p, time, ref index)) = *loaded {
+        if p == path && time == changed {
+            return Ok(index.clone());
+        }
+    }
+    let index = Arc::new(read(path)?);
+    *loaded = Some((path.to_path_buf(), changed, index.clone()));
+    Ok(index)
+}
+
+/// The files under `paths`, leaving out hidden ones, e.g. `.git`.
+fn walk(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = vec![];
+    let mut pending: Vec<PathBuf> = paths.to_vec();
+    while let Some(path) = pending.pop() {
+        if path.is_dir() {
+            match fs::read_dir(&path) {
+                Ok(entries) => pending.extend(
+                    entries
+                        .filter_map(|entry| entry.ok())
+                        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+                        .map(|entry| entry.path()),
+                ),
+                Err(e) => warn!("Could not read {}: {e}", path.display()),
+            }
+        } else if path.is_file() {
+            files.push(path.canonicalize().unwrap_or(path));
+        } else {
+            warn!("{} is not a file or directory", path.display());
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Splits `text` into passages of at most about `max_chars` characters at blank lines, and
+/// longer paragraphs at line ends. Returns each passage with the line it starts on.
+fn chunks(text: &str, max_chars: usize) -> Vec<(usize, String)> {
+    let mut ret: Vec<(usize, String)> = vec![];
+    let mut current = String::new();
+    let mut start = 1;
+    for (i, line) in text.lines().enumerate() {
+        let len = current.chars().count();
+        let full = len + line.chars().count() > max_chars;
+        let paragraph_end = line.trim().is_empty() && len >= max_chars / 2;
+        if len > 0 && (full || paragraph_end) {
+            ret.push((start, std::mem::take(&mut current).trim_end().to_string()));
+        }
+        if current.is_empty() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            start = i + 1;
+        }
+        current.push_str(line);
+        current.push('\n');
+    }
+    if !current.trim().is_empty() {
+        ret.push((start, current.trim_end().to_string()));
+    }
+    ret
+}
+
+/// The embeddings of `texts`, in order.
+async fn embed(config: &Config, texts: Vec<String>) -> Result<Vec<Vec<f32>>, String> {
+    if FLAGS.dry_run {
+        return Err(String::from("Not asking for embeddings in a dry run"));
+    }
+    let request = CreateEmbeddingRequestArgs::default()
+        .model(&config.rag.embedding_model)
+        .input(texts)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let http = config.http_client().map_err(|e| e.to_string())?;
+    let response: Result<CreateEmbeddingResponse, _> = match config.embeddings_api_config()? {
+        ApiConfig::OpenAI(c) => {
+            Client::with_config(c)
+                .with_http_client(http)
+                .embeddings()
+                .create(request)
+                .await
+        }
+        ApiConfig::Azure(c) => {
+            Client::with_config(c)
+                .with_http_client(http)
+                .embeddings()
+                .create(request)
+                .await
+        }
+    };
+    let mut data = response
+        .map_err(|e| format!("Could not get embeddings: {e}"))?
+        .data;
+    data.sort_by_key(|embedding| embedding.index);
+    Ok(data
+        .into_iter()
+        .map(|embedding| embedding.embedding)
+        .collect())
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+/// `ata2 index <paths>`: indexes the files under `paths`, or those that changed since they were
+/// last indexed. Files that no longer exist are removed from the index.
+pub async fn build(paths: &[PathBuf]) -> TokioResult<()> {
+    let config = params::effective_config(&CONFIGURATION.load(), &Default::default())?;
+    let path = config.rag.file();
+    let mut index = read(&path)?;
+    if index.model != config.rag.embedding_model {
+        if !index.files.is_empty() {
+            warn!(
+                "The index was made with {}; indexing everything again with {}",
+                index.model, config.rag.embedding_model
+            );
+        }
+        index = Index {
+            model: config.rag.embedding_model.clone(),
+            ..Default::default()
+        };
+    }
+    index.files.retain(|file, _| file.exists());
+
+    let (mut indexed, mut unchanged) = (0, 0);
+    for file in walk(paths) {
+        let metadata = match fs::metadata(&file) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                warn!("Could not read {}: {e}", file.display());
+                continue;
+            }
+        };
+        let changed = seconds(metadata.modified()?);
+        if index.files.get(&file).map(|f| f.modified) == Some(changed) {
+            unchanged += 1;
+            continue;
+        }
+        if metadata.len() > MAX_FILE_BYTES {
+            debug!("Leaving out {}, which is too large", file.display());
+            continue;
+        }
+        let text = match fs::read_to_string(&file) {
+            Ok(text) => text,
+            // Not text.
+            Err(_) => continue,
+        };
+        let passages = chunks(&text, config.rag.chunk_chars.max(1) as usize);
+        let mut chunks = vec![];
+        for batch in passages.chunks(BATCH) {
+            let texts = batch.iter().map(|(_, text)| text.clone()).collect();
+            let vectors = embed(&config, texts).await?;
+            chunks.extend(
+                batch
+                    .iter()
+                    .zip(vectors)
+                    .map(|((line, text), vector)| Chunk {
+                        line: *line,
+                        text: text.clone(),
+                        vector,
+                    }),
+            );
+        }
+        eprintln!("{} ({} passages)", file.display(), chunks.len());
+        index.files.insert(
+            file,
+            IndexedFile {
+                modified: changed,
+                chunks,
+            },
+        );
+        indexed += 1;
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, serde_json::to_string(&index)?)?;
+    let passages: usize = index.files.values().map(|f| f.chunks.len()).sum();
+    eprintln!(
+        "Indexed {indexed} files ({unchanged} unchanged); {} files, {passages} passages in {}",
+        index.files.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// The passages of the index most like `prompt`, to send along with it, if `/rag` is on.
+pub async fn context(config: &Config, prompt: &str) -> Option<String> {
+    if !ENABLED.load(Ordering::Relaxed) || prompt.trim().is_empty() {
+        return None;
+    }
+    if let Some((ref last, ref context)) = *LAST.lock().unwrap() {
+        if last == prompt {
+            return Some(context.clone());
+        }
+    }
+    let index = match load(&config.rag.file()) {
+        Ok(index) if !index.files.is_empty() => index,
+        Ok(_) => {
+            warn!("The index is empty; add files to it with `ata2 index <paths>`");
+            return None;
+        }
+        Err(e) => {
+            warn!("{e}");
+            return None;
+        }
+    };
+    if index.model != config.rag.embedding_model {
+        warn!(
+            "The index was made with {}, not {}; run `ata2 index` again",
+            index.model, config.rag.embedding_model
+        );
+        return None;
+    }
+    let query = match embed(config, vec![prompt.to_string()]).await {
+        Ok(mut vectors) if !vectors.is_empty() => vectors.remove(0),
+        Ok(_) => return None,
+        Err(e) => {
+            warn!("{e}");
+            return None;
+        }
+    };
+    let mut scored: Vec<(f32, &Path, &Chunk)> = index
+        .files
+        .iter()
+        .flat_map(|(file, indexed)| {
+            indexed
+                .chunks
+                .iter()
+                .map(move |chunk| (cosine(&query, &chunk.vector), file.as_path(), chunk))
+        })
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let passages: Vec<String> = scored
+        .iter()
+        .take(config.rag.top_k as usize)
+        .map(|(_, file, chunk)| {
+            format!(
+                "From {}, line {}:\n\n{}",
+                file.display(),
+                chunk.line,
+                chunk.text
+            )
+        })
+        .collect();
+    if passages.is_empty() {
+        return None;
+    }
+    let context = format!(
+        "These passages of the user's files may help to answer the next message:\n\n{}",
+        passages.join("\n\n---\n\n")
+    );
+    *LAST.lock().unwrap() = Some((prompt.to_string(), context.clone()));
+    Some(context)
+}
+
+/// Takes up `config`, e.g. once it is reloaded.
+pub fn apply(config: &Config) {
+    ENABLED.store(config.rag.enabled, Ordering::Relaxed);
+    LAST.lock().unwrap().take();
+}
+
+/// `/rag [on|off]`.
+pub fn command(args: &str) -> Result<String, String> {
+    match args.trim() {
+        "" => {}
+        "on" => ENABLED.store(true, Ordering::Relaxed),
+        "off" => ENABLED.store(false, Ordering::Relaxed),
+        _ => return Err(String::from("Usage: /rag [on|off]")),
+    }
+    Ok(if ENABLED.load(Ordering::Relaxed) {
+        format!(
+            "Passages of the index ({}) are sent with each prompt",
+            CONFIGURATION.load().rag.file().display()
+        )
+    } else {
+        String::from("The index isn't used")
+    })
+}
@@ -0,0 +1,193 @@
+//! Limits on outgoing requests, for when many prompts are sent at once.
+//!
+//! At most `max_concurrent_requests` requests are streamed at the same time; the others wait for
+//! their turn. A request identical to one that is still being answered isn't sent again: it gets a
+//! copy of the other one's answer instead. Requests made in the background, e.g. for session
+//! titles, wait until no other is being answered.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::error::OpenAIError;
+use async_openai::types::{
+    ChatCompletionResponseStream, CreateChatCompletionRequest, CreateChatCompletionStreamResponse,
+};
+use futures_util::stream;
+use tokio::sync::{watch, Semaphore};
+use tokio_stream::StreamExt as _;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::hedge;
+use crate::Config;
+use crate::FLAGS;
+use crate::IS_RUNNING;
+
+type Item = Result<CreateChatCompletionStreamResponse, String>;
+
+/// A request being answered, and what has been received so far.
+struct InFlight {
+    items: Mutex<Vec<Item>>,
+    done: AtomicBool,
+    /// Bumped whenever `items` or `done` change.
+    progress: watch::Sender<usize>,
+}
+
+impl InFlight {
+    fn push(&self, item: Item) {
+        self.items.lock().unwrap().push(item);
+        self.progress.send_modify(|n| *n += 1);
+    }
+
+    fn finish(&self) {
+        self.done.store(true, Ordering::SeqCst);
+        self.progress.send_modify(|n| *n += 1);
+    }
+}
+
+lazy_static! {
+    /// In-flight requests, by their JSON.
+    static ref IN_FLIGHT: Mutex<HashMap<String, Arc<InFlight>>> = Mutex::new(HashMap::new());
+}
+
+lazy_static! {
+    /// Limits the requests answered at once to `max_concurrent_requests`, with the limit it was
+    /// made for. It is made again whenever a request is made with another limit, e.g. after
+    /// `/set max_concurrent_requests`.
+    static ref SEMAPHORE: Mutex<Option<(u64, Arc<Semaphore>)>> = Mutex::new(None);
+}
+
+/// Takes up `config`, e.g. once it is reloaded: requests from now on are limited by its
+/// `max_concurrent_requests`. Those already being answered finish as they are.
+pub fn apply(_config: &Config) {
+    SEMAPHORE.lock().unwrap().take();
+}
+
+/// Sends the request and shares its answer with identical requests made in the meantime.
+struct Leader {
+    key: String,
+    in_flight: Arc<InFlight>,
+}
+
+impl Drop for Leader {
+    fn drop(&mut self) {
+        IN_FLIGHT.lock().unwrap().remove(&self.key);
+        self.in_flight.finish();
+    }
+}
+
+/// Replays the answer to an identical request, as it arrives.
+fn follow(in_flight: Arc<InFlight>) -> ChatCompletionResponseStream {
+    let progress = in_flight.progress.subscribe();
+    Box::pin(stream::unfold(
+        (in_flight, progress, 0),
+        |(in_flight, mut progress, i)| async move {
+            loop {
+                let item = in_flight.items.lock().unwrap().get(i).cloned();
+                if let Some(item) = item {
+                    let item = item.map_err(OpenAIError::StreamError);
+                    return Some((item, (in_flight, progress, i + 1)));
+                }
+                if in_flight.done.load(Ordering::SeqCst) || progress.changed().await.is_err() {
+                    return None;
+                }
+            }
+        },
+    ))
+}
+
+/// How often background requests check whether they can be sent.
+const IDLE_POLL: Duration = Duration::from_millis(250);
+
+/// Waits until no request is being answered, so that background requests stay out of the way of
+/// the ones being waited for.
+pub async fn when_idle() {
+    while IS_RUNNING.load(Ordering::SeqCst) || !IN_FLIGHT.lock().unwrap().is_empty() {
+        tokio::time::sleep(IDLE_POLL).await;
+    }
+}
+
+/// Starts streaming the answer to `request` (see [`hedge::create_stream`]) once there is room for
+/// it, unless an identical request is already being answered.
+pub async fn create_stream(
+    config: &Config,
+    request: CreateChatCompletionRequest,
+) -> Result<(ChatCompletionResponseStream, String), OpenAIError> {
+    // Whatever asked, nothing is sent.
+    if FLAGS.dry_run {
+        return Err(OpenAIError::InvalidArgument(String::from(
+            "Not sending requests in a dry run",
+        )));
+    }
+    let key = serde_json::to_string(&request).unwrap_or_default();
+    let leader = {
+        let mut in_flight = IN_FLIGHT.lock().unwrap();
+        if let Some(other) = in_flight.get(&key) {
+            debug!("An identical request is in flight, sharing its answer");
+            return Ok((follow(other.clone()), request.model.clone()));
+        }
+        let leader = Leader {
+            key: key.clone(),
+            in_flight: Arc::new(InFlight {
+                items: Mutex::new(vec![]),
+                done: AtomicBool::new(false),
+                progress: watch::channel(0).0,
+            }),
+        };
+        in_flight.insert(key, leader.in_flight.clone());
+        leader
+    };
+
+    let semaphore = {
+        let mut semaphore = SEMAPHORE.lock().unwrap();
+        let limit = config.max_concurrent_requests;
+        // Requests already being answered keep their permits of an old one.
+        if semaphore.as_ref().map(|(made_for, _)| *made_for) != Some(limit) {
+            let made = match limit {
+                0 => Semaphore::new(Semaphore::MAX_PERMITS),
+                n => Semaphore::new(n as usize),
+            };
+            *semaphore = Some((limit, Arc::new(made)));
+        }
+        semaphore.as_ref().unwrap().1.clone()
+    };
+    let permit = semaphore
+        .acquire()
+        .await
+        .expect("the semaphore is never closed");
+
+    let (stream, model) = match hedge::create_stream(config, request).await {
+        Ok(started) => started,
+        Err(e) => {
+            leader.in_flight.push(Err(e.to_string()));
+            return Err(e);
+        }
+    };
+    // The permit is held, and the answer shared, until the stream is dropped.
+    let stream = stream.map(move |item| {
+        let _permit = &permit;
+        leader
+            .in_flight
+            .push(item.as_ref().cloned().map_err(ToString::to_string));
+        item
+    });
+    Ok((Box::pin(stream), model))
+}
@@ -20,27 +20,101 @@ extern crate lazy_static;
 #[macro_use]
 extern crate log;
 
+mod alts;
+mod apply;
 mod args;
-pub use crate::args::Ata2;
+mod ask;
+mod attach;
+mod audio;
+mod auth;
+mod autowrap;
+mod backend;
+mod batch;
+pub use crate::args::{
+    Ata2, Command, ConfigCommand, PricingCommand, ScriptCommand, SessionsCommand, SyncCommand,
+};
+mod capabilities;
+mod clipboard;
+mod commands;
+mod completions;
 mod config;
+mod configure;
+mod context;
+mod control;
+mod conversation;
+mod digest;
+mod duplicates;
+mod edit;
 pub use crate::config::Config;
+mod export;
+mod extract;
+mod forecast;
+mod git;
+mod hedge;
 mod help;
+mod helper;
+mod highlight;
+#[cfg(unix)]
+mod history_search;
+mod import;
+mod index;
+mod limits;
+mod memory;
+mod mock;
+mod models;
+mod nvim;
+mod output;
+mod pager;
+mod params;
+mod paste;
+mod pii;
+mod postprocess;
+mod pricing;
 mod prompt;
 use crate::prompt::load_conversation;
+mod ratelimit;
 mod readline;
+mod reasoning;
+mod reload;
+mod repetition;
+mod request_body;
+mod risk;
+mod script;
+mod search;
+mod serve;
+mod session_log;
+mod sessions;
+mod shared;
+mod spinner;
+mod sse;
 mod state;
+mod status;
+mod stream_decode;
+mod substitute;
+mod sync;
+mod theme;
+mod title;
+mod tls;
+mod tokens;
+mod tools;
+#[cfg(unix)]
+mod tui;
+mod undo;
+mod web;
+mod wrap;
+use crate::output::OutputSink as _;
 pub use crate::state::*;
 
-use ansi_colors::ColouredStr;
 use futures_util::future::FutureExt as _;
 use futures_util::task::Context;
 use futures_util::task::Poll;
 
 use std::error::Error;
 use std::fs::File;
+use std::io::Write as _;
 
-use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -51,27 +125,187 @@ pub async fn main() -> TokioResult<()> {
         std::process::exit(0);
     } else {
         init_logger();
+        #[cfg(windows)]
+        output::enable_ansi();
+    }
+    if FLAGS.dry_run && !FLAGS.honours_dry_run() {
+        return Err(
+            "--dry-run only works in the REPL, with `ata2 ask` or a prompt, and with --batch"
+                .into(),
+        );
+    }
+    if let Some(Command::Completions { shell }) = &FLAGS.command {
+        completions::print(*shell, &FLAGS.config);
+        return Ok(());
+    }
+    // Apart from `show`, these work without a configuration file, or with an invalid one.
+    if let Some(Command::Config { action }) = &FLAGS.command {
+        let location = &FLAGS.config;
+        let result = match action {
+            ConfigCommand::Show => {
+                print!("{}", CONFIGURATION.load().annotated_toml());
+                return Ok(());
+            }
+            ConfigCommand::Path => {
+                configure::path(location);
+                return Ok(());
+            }
+            ConfigCommand::Init {
+                profile,
+                model,
+                api_key,
+                force,
+            } => configure::init(
+                location,
+                profile.as_deref(),
+                model.as_deref(),
+                api_key.as_deref(),
+                *force,
+            ),
+            ConfigCommand::Edit => configure::edit(location),
+            ConfigCommand::Validate => configure::validate(location),
+        };
+        match result {
+            Ok(message) => eprintln!("{message}"),
+            Err(e) => {
+                error!("{e}");
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    if FLAGS.list_sessions {
+        sessions::list(&[]);
+        return Ok(());
     }
     if FLAGS.load.is_some() {
         load_conversation(FLAGS.load.as_ref().unwrap()).await?;
     }
+    if let Some(ref id) = FLAGS.resume {
+        sessions::resume_saved(id).await?;
+    }
+    // These don't need a valid configuration.
+    match &FLAGS.command {
+        Some(Command::Pricing { action }) => {
+            match action {
+                PricingCommand::Show => pricing::show(&CONFIGURATION.load().model),
+                PricingCommand::Update { source } => pricing::update(source.as_deref()).await?,
+            }
+            return Ok(());
+        }
+        // Asks the model, so it needs a valid configuration.
+        Some(Command::Sessions {
+            action: SessionsCommand::Replay { .. },
+        }) => {}
+        Some(Command::Sessions { action }) => {
+            match action {
+                SessionsCommand::Gc {
+                    archive_after,
+                    history_retention,
+                } => sessions::gc(&CONFIGURATION.load().ui, *archive_after, *history_retention)?,
+                SessionsCommand::List { tag } => sessions::list(tag),
+                SessionsCommand::Delete { id } => println!("{}", sessions::delete(id)?),
+                SessionsCommand::Tag { id, tags } => sessions::tag_saved(id, tags)?,
+                SessionsCommand::Import { format, path } => import::import(*format, path)?,
+                SessionsCommand::Replay { .. } => {
+                    return Err("`sessions replay` is run once the configuration is checked".into())
+                }
+            }
+            return Ok(());
+        }
+        Some(Command::Sync { action }) => {
+            let config = &CONFIGURATION.load_full().sync;
+            match action {
+                SyncCommand::Status => sync::status(config)?,
+                SyncCommand::Pull { force } => sync::pull(config, *force)?,
+                SyncCommand::Push { force } => sync::push(config, *force)?,
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
     let mut rl = readline::Readline::new();
-    let config = CONFIGURATION.clone();
-    config.validate().unwrap_or_else(|e| {
+    let config = CONFIGURATION.load_full();
+    let effective = params::effective_config(&config, &Default::default()).unwrap_or_else(|e| {
         error!("Config error!: {e}. Dying.");
         panic!()
     });
+    for warning in effective.warnings() {
+        warn!("{warning}");
+    }
+    sessions::restore_model(&config);
+
+    match &FLAGS.command {
+        Some(Command::NvimRpc) => return nvim::serve().await,
+        Some(Command::ServeSession { listen }) => return shared::serve(listen).await,
+        Some(Command::JoinSession { addr, name, token }) => {
+            shared::join(addr, name.clone(), token).await?
+        }
+        Some(Command::Sessions {
+            action: SessionsCommand::Replay { id, yes },
+        }) => return sessions::replay(id, *yes).await,
+        Some(Command::Script {
+            action: ScriptCommand::Play { path },
+        }) => return script::play(path).await,
+        Some(Command::Index { paths }) => return index::build(paths).await,
+        Some(Command::Pricing { .. })
+        | Some(Command::Sessions { .. })
+        | Some(Command::Config { .. })
+        | Some(Command::Sync { .. })
+        | Some(Command::Completions { .. })
+        | Some(Command::Repl)
+        | None => {}
+        Some(Command::Ask { prompt }) => {
+            if prompt.is_empty() && atty::is(atty::Stream::Stdin) {
+                return Err("Nothing to ask: give `ata2 ask` a prompt, or pipe one in".into());
+            }
+        }
+    }
+    if let Some(ref path) = FLAGS.batch {
+        return batch::run(path, FLAGS.batch_output.as_deref(), FLAGS.fresh, FLAGS.jobs).await;
+    }
+    if let Some(ref addr) = FLAGS.serve {
+        return serve::serve(Some(addr.as_str()).filter(|a| !a.is_empty())).await;
+    }
+    if let Some(ref addr) = FLAGS.connect {
+        serve::connect(Some(addr.as_str()).filter(|a| !a.is_empty())).await?;
+    }
 
-    let mut header = ColouredStr::new("Ask the Terminal Anything²\n\n");
-    header.bold();
+    let piped_prompt = if FLAGS.interactive_after_pipe
+        && (!atty::is(atty::Stream::Stdin) || !FLAGS.prompt().is_empty())
+    {
+        let mut piped = String::new();
+        if !atty::is(atty::Stream::Stdin) {
+            piped = digest::read().await?;
+            readline::reopen_tty_as_stdin()?;
+        }
+        Some(readline::with_question(piped))
+    } else {
+        None
+    };
 
-    if atty::is(atty::Stream::Stderr) {
-        eprint!("{}", header);
+    if atty::is(atty::Stream::Stderr) && !FLAGS.tui {
+        eprint!(
+            "{}\n\n",
+            theme::paint(theme::Role::Header, "Ask the Terminal Anything²")
+        );
     }
 
-    if !FLAGS.hide_config && !config.ui.hide_config && atty::is(atty::Stream::Stderr) {
+    if !FLAGS.hide_config && !config.ui.hide_config && atty::is(atty::Stream::Stderr) && !FLAGS.tui
+    {
         eprintln!("{config}");
     }
+    title::init(&config.ui);
+    reload::watch();
+    if let Err(e) = sessions::gc(&config.ui, None, None) {
+        warn!("Could not clean up old sessions and history: {e}");
+    }
+    if FLAGS.tui {
+        #[cfg(unix)]
+        return tui::run().await;
+        #[cfg(not(unix))]
+        return Err("--tui is only available on Unix".into());
+    }
     if atty::is(atty::Stream::Stdin) && config.ui.save_history {
         if rl.load_history().await.is_err() {
             warn!("No history file found. Creating a new one.");
@@ -84,11 +318,20 @@ pub async fn main() -> TokioResult<()> {
     }
     rl.enable_multiline().await;
     rl.enable_request_save().await;
+    rl.enable_copy().await;
+    rl.enable_edit().await;
+    rl.enable_history_search().await;
     // use tokio asynchronous message queue
     let (tx, mut rx): (tokio::sync::mpsc::Sender<Option<String>>, _) =
         tokio::sync::mpsc::channel(1);
+    if cfg!(windows) || config.ui.waits_for_answers() {
+        prompt::handle_ctrl_c(tx.clone());
+    }
 
-    let handle = tokio::spawn(async move {
+    // Set when --extract finds nothing, to exit with an error once shut down as usual.
+    let extract_failed = Arc::new(AtomicBool::new(false));
+    let extract_failed_ = extract_failed.clone();
+    let mut handle = tokio::spawn(async move {
         let n_pending_debug_log_notices = Arc::new(AtomicUsize::new(0));
         loop {
             let msg = Box::pin(rx.recv()).poll_unpin(&mut Context::from_waker(
@@ -96,7 +339,24 @@ pub async fn main() -> TokioResult<()> {
             ));
             match msg {
                 Poll::Ready(Some(Some(line))) => {
-                    let result = prompt::request(line.to_string(), 0).await;
+                    let result = match (shared::client(), serve::client(), &FLAGS.extract) {
+                        (Some(client), _, _) => client.send(line).await,
+                        (None, Some(server), _) => server.ask(line).await,
+                        (None, None, Some(extract)) => prompt::request_with(
+                            &mut output::NullSink,
+                            Some(line),
+                            Default::default(),
+                        )
+                        .await
+                        .map(|deltas| {
+                            let answer = prompt::response_text(deltas);
+                            match extract.apply(&answer) {
+                                Some(extracted) => output::StdoutSink.write(&extracted),
+                                None => extract_failed_.store(true, Ordering::Relaxed),
+                            }
+                        }),
+                        (None, None, None) => prompt::dispatch(line).await.map(drop),
+                    };
                     match result {
                         Ok(_) => {}
                         Err(e) => {
@@ -104,6 +364,7 @@ pub async fn main() -> TokioResult<()> {
                         }
                     }
                     n_pending_debug_log_notices.store(0, Ordering::SeqCst);
+                    status::handled();
                 }
                 Poll::Ready(Some(None)) => {
                     n_pending_debug_log_notices.store(0, Ordering::SeqCst);
@@ -152,17 +413,34 @@ pub async fn main() -> TokioResult<()> {
         }
     });
 
+    if let Some(piped) = piped_prompt.filter(|p| !p.trim().is_empty()) {
+        tx.send(Some(piped)).await?;
+    }
+    if let Some(ref path) = FLAGS.control_fifo {
+        if let Err(e) = control::listen(path, tx.clone()) {
+            error!("Could not create the control pipe {}: {e}", path.display());
+        }
+    }
     let readline_handle = rl.handle(tx).await;
 
     tokio::select! {
         _ = readline_handle => {
             info!("Readline died");
+            // Let the answer to the last line (e.g. everything piped in) finish printing.
+            let _ = handle.await;
         }
-        _ = handle => {
+        _ = &mut handle => {
             info!("API request loop died");
         }
     }
 
+    prompt::autosave().await;
+    if let Err(e) = sessions::save_current().await {
+        error!("Could not save session: {e}");
+    }
+    title::restore();
+    control::remove();
+
     if atty::is(atty::Stream::Stdin) && config.ui.save_history {
         rl.save_history().await?;
         info!(
@@ -172,12 +450,27 @@ pub async fn main() -> TokioResult<()> {
         );
     }
 
+    if extract_failed.load(Ordering::Relaxed) {
+        return Err("Nothing to extract from the answer".into());
+    }
     Ok(())
 }
 
 fn init_logger() {
     let env = env_logger::Env::default().default_filter_or("info");
+    let coloured = atty::is(atty::Stream::Stderr);
     env_logger::Builder::from_env(env)
-        .format_timestamp(None)
+        .format(move |buf, record| {
+            if spinner::is_drawn() {
+                write!(buf, "\r\x1b[K")?;
+            }
+            let level = record.level().to_string();
+            let level = match record.level() {
+                log::Level::Error if coloured => theme::paint(theme::Role::Error, &level),
+                log::Level::Warn if coloured => theme::paint(theme::Role::Warning, &level),
+                _ => level,
+            };
+            writeln!(buf, "[{level} {}] {}", record.target(), record.args())
+        })
         .init();
 }
@@ -0,0 +1,174 @@
+//! Facts remembered across sessions (`[memory]`).
+//!
+//! `/remember <text>` appends a fact to the memory file, one JSON object per line. When a new
+//! conversation starts, the facts are put before the system prompt, so that the model knows them
+//! from the first message on. `/memories` lists them and `/forget <id>` removes one.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write as _};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::config::MemoryConfig;
+use crate::CONFIGURATION;
+
+/// A remembered fact.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Fact {
+    id: u64,
+    text: String,
+    saved: DateTime<Local>,
+}
+
+lazy_static! {
+    /// The preamble of the current conversation, taken when it started, so that facts remembered
+    /// meanwhile don't change its system prompt.
+    static ref PREAMBLE: Mutex<Option<String>> = Mutex::new(None);
+}
+
+/// The facts in `path`, skipping lines that can't be read.
+fn load(path: &Path) -> io::Result<Vec<Fact>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(fact) => Some(fact),
+                Err(e) => {
+                    warn!("Skipping a fact in {}: {e}", path.display());
+                    None
+                }
+            })
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e),
+    }
+}
+
+fn line(fact: &Fact) -> String {
+    serde_json::to_string(fact).expect("facts are always serializable") + "\n"
+}
+
+fn enabled() -> Result<MemoryConfig, String> {
+    let config = CONFIGURATION.load().memory.clone();
+    if !config.enabled {
+        return Err(String::from("Set `enabled = true` in [memory] first"));
+    }
+    Ok(config)
+}
+
+/// Called when a new conversation starts: takes the facts to tell the model in it.
+pub fn start_conversation(config: &MemoryConfig) {
+    let facts = if config.enabled {
+        load(&config.file()).unwrap_or_else(|e| {
+            warn!("Could not read {}: {e}", config.file().display());
+            vec![]
+        })
+    } else {
+        vec![]
+    };
+    *PREAMBLE.lock().unwrap() = (!facts.is_empty()).then(|| {
+        let facts: Vec<_> = facts.iter().map(|f| format!("- {}", f.text)).collect();
+        format!(
+            "The user asked you to remember these facts in earlier conversations:\n{}",
+            facts.join("\n")
+        )
+    });
+}
+
+/// What to put before the system prompt of the current conversation, if anything.
+pub fn preamble() -> Option<String> {
+    PREAMBLE.lock().unwrap().clone()
+}
+
+/// `/remember <text>`
+pub fn remember(text: &str) -> Result<String, String> {
+    let config = enabled()?;
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(String::from("Usage: /remember <text>"));
+    }
+    let path = config.file();
+    let facts = load(&path).map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+    let fact = Fact {
+        id: facts.iter().map(|f| f.id).max().unwrap_or(0) + 1,
+        text: text.to_string(),
+        saved: Local::now(),
+    };
+    let append = || -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        file.write_all(line(&fact).as_bytes())
+    };
+    append().map_err(|e| format!("Could not write {}: {e}", path.display()))?;
+    Ok(format!(
+        "Remembered as fact {}, from the next conversation on",
+        fact.id
+    ))
+}
+
+/// `/memories`: lists the remembered facts.
+pub fn list() -> Result<String, String> {
+    let config = enabled()?;
+    let path = config.file();
+    let facts = load(&path).map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+    if facts.is_empty() {
+        return Ok(String::from("Nothing remembered yet; see /remember"));
+    }
+    let width = facts
+        .iter()
+        .map(|f| f.id.to_string().len())
+        .max()
+        .unwrap_or(0);
+    for fact in &facts {
+        eprintln!(
+            "{:>width$}  {}  {}",
+            fact.id,
+            fact.saved.format("%Y-%m-%d"),
+            fact.text
+        );
+    }
+    Ok(format!("{} facts in {}", facts.len(), path.display()))
+}
+
+/// `/forget <id>`
+pub fn forget(args: &str) -> Result<String, String> {
+    let config = enabled()?;
+    let id: u64 = args
+        .trim()
+        .parse()
+        .map_err(|_| String::from("Usage: /forget <id>, with the id shown by /memories"))?;
+    let path = config.file();
+    let mut facts = load(&path).map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+    let len = facts.len();
+    facts.retain(|f| f.id != id);
+    if facts.len() == len {
+        return Err(format!("There is no fact {id}; see /memories"));
+    }
+    let contents: String = facts.iter().map(line).collect();
+    fs::write(&path, contents).map_err(|e| format!("Could not write {}: {e}", path.display()))?;
+    Ok(format!("Forgot fact {id}"))
+}
@@ -0,0 +1,143 @@
+//! Recording answers (`--record <dir>`) and replaying them (`provider = "mock"`), for tests and
+//! offline demos.
+//!
+//! Requests are numbered from 1 in the order they are made. The answer to the nth is recorded as
+//! `<n>.sse`, the chunks streamed as server-sent events in OpenAI's shape, whichever API answered,
+//! next to `<n>.request.json`, what was sent (as `/dry` prints it). The mock provider answers the
+//! nth request with `<n>.sse` from `api_base`, ignoring what is asked.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::error::OpenAIError;
+use async_openai::types::{ChatCompletionResponseStream, CreateChatCompletionRequest};
+use futures_util::future::{BoxFuture, FutureExt as _};
+use futures_util::stream::{self, StreamExt as _};
+use serde_json::{json, Value};
+
+use std::fs::{self, File};
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::backend::Backend;
+use crate::request_body;
+use crate::sse;
+
+/// How many requests were answered by the mock provider, and recorded.
+static REPLAYED: AtomicUsize = AtomicUsize::new(0);
+static RECORDED: AtomicUsize = AtomicUsize::new(0);
+
+/// Replays the answers recorded in a directory.
+pub struct Mock {
+    pub dir: PathBuf,
+}
+
+impl Backend for Mock {
+    fn stream_chat(
+        &self,
+        _request: CreateChatCompletionRequest,
+    ) -> BoxFuture<'_, Result<ChatCompletionResponseStream, OpenAIError>> {
+        async move {
+            let n = REPLAYED.fetch_add(1, Ordering::SeqCst) + 1;
+            let path = self.dir.join(format!("{n}.sse"));
+            let recorded = fs::read_to_string(&path).map_err(|e| {
+                OpenAIError::InvalidArgument(format!(
+                    "No recorded answer to request {n} ({}): {e}",
+                    path.display()
+                ))
+            })?;
+            let chunks = sse::parse(&recorded)
+                .iter()
+                .map(|data| request_body::chunk(data))
+                .collect::<Vec<_>>();
+            let stream: ChatCompletionResponseStream = Box::pin(stream::iter(chunks));
+            Ok(stream)
+        }
+        .boxed()
+    }
+
+    fn models(&self) -> BoxFuture<'_, Result<Vec<String>, OpenAIError>> {
+        async move { Ok(vec![]) }.boxed()
+    }
+
+    fn payload(&self, request: &CreateChatCompletionRequest) -> Value {
+        json!({
+            "url": self.dir.display().to_string(),
+            "headers": {},
+            "body": serde_json::to_value(request).expect("requests are always serializable"),
+        })
+    }
+}
+
+/// Records the answers of another backend.
+pub struct Recording {
+    pub inner: Box<dyn Backend>,
+    pub dir: PathBuf,
+}
+
+/// Saves the request `request` makes as the `n`th, returning the file to record its answer in.
+fn start(dir: &Path, n: usize, request: &Value) -> io::Result<File> {
+    fs::create_dir_all(dir)?;
+    let json = serde_json::to_string_pretty(request).expect("JSON values are always serializable");
+    fs::write(dir.join(format!("{n}.request.json")), json + "\n")?;
+    File::create(dir.join(format!("{n}.sse")))
+}
+
+impl Backend for Recording {
+    fn stream_chat(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> BoxFuture<'_, Result<ChatCompletionResponseStream, OpenAIError>> {
+        async move {
+            let n = RECORDED.fetch_add(1, Ordering::SeqCst) + 1;
+            let payload = self.inner.payload(&request);
+            let stream = self.inner.stream_chat(request).await?;
+            let mut file = match start(&self.dir, n, &payload) {
+                Ok(file) => file,
+                Err(e) => {
+                    warn!(
+                        "Could not record request {n} in {}: {e}",
+                        self.dir.display()
+                    );
+                    return Ok(stream);
+                }
+            };
+            let stream = stream.map(move |item| {
+                if let Ok(ref chunk) = item {
+                    let data = serde_json::to_string(chunk).unwrap_or_default();
+                    if let Err(e) = write!(file, "data: {data}\n\n") {
+                        warn!("Could not record the answer: {e}");
+                    }
+                }
+                item
+            });
+            let stream: ChatCompletionResponseStream = Box::pin(stream);
+            Ok(stream)
+        }
+        .boxed()
+    }
+
+    fn models(&self) -> BoxFuture<'_, Result<Vec<String>, OpenAIError>> {
+        self.inner.models()
+    }
+
+    fn payload(&self, request: &CreateChatCompletionRequest) -> Value {
+        self.inner.payload(request)
+    }
+}
@@ -0,0 +1,72 @@
+//! `/models`: the models the API offers.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::error::OpenAIError;
+
+use std::sync::Mutex;
+
+use crate::backend;
+use crate::capabilities;
+use crate::params;
+use crate::prompt::{finish_prompt, print_error};
+use crate::Config;
+use crate::CONFIGURATION;
+
+lazy_static! {
+    /// The models last listed by the API, for completion.
+    static ref LISTED: Mutex<Vec<String>> = Mutex::new(vec![]);
+}
+
+/// The IDs of the models available with `config`, sorted.
+async fn list(config: &Config) -> Result<Vec<String>, OpenAIError> {
+    let mut ids = backend::primary(config)?.models().await?;
+    ids.sort();
+    *LISTED.lock().unwrap() = ids.clone();
+    Ok(ids)
+}
+
+/// The models known without asking the API: those listed by `/models` so far, those in the
+/// capability registry and the one configured. Sorted.
+pub fn known() -> Vec<String> {
+    let mut ids = LISTED.lock().unwrap().clone();
+    ids.extend(capabilities::models());
+    ids.push(CONFIGURATION.load().model.clone());
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+/// Handles `/models [filter]`: lists the models whose ID contains `filter`, marking the one in use.
+pub async fn command(filter: &str) {
+    let config = match params::effective_config(&CONFIGURATION.load(), &Default::default()) {
+        Ok(config) => config,
+        Err(e) => return print_error(&format!("Invalid parameters: {e}")),
+    };
+    let ids = match list(&config).await {
+        Ok(ids) => ids,
+        Err(e) => return print_error(&format!("Could not list the models: {e}")),
+    };
+    let filter = filter.trim();
+    for id in ids.iter().filter(|id| id.contains(filter)) {
+        let mark = if *id == config.model { '*' } else { ' ' };
+        eprintln!("{mark} {id}");
+    }
+    finish_prompt();
+}
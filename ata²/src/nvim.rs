@@ -0,0 +1,163 @@
+//! msgpack-RPC server mode for the Neovim plugin (`ata2 nvim-rpc`).
+//!
+//! Neovim starts ata² as a job (`jobstart(['ata2', 'nvim-rpc'], {'rpc': v:true})`) and talks to it
+//! over stdin/stdout. The following methods are exposed:
+//!
+//! * `ask(prompt)` returns the whole answer once it is complete.
+//! * `stream(prompt)` calls `require('ata2').on_delta(text)` in Neovim for every piece of the
+//!   answer as it arrives, then returns the whole answer.
+//! * `insert_at_cursor(prompt)` inserts the answer at the cursor as it arrives, then returns it.
+//!
+//! The conversation, configuration and so on are the same as in the REPL.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use rmpv::Value;
+use tokio::sync::mpsc;
+
+use std::io::{self, Write as _};
+
+use crate::ask;
+use crate::output::{NullSink, OutputSink};
+use crate::prompt;
+use crate::TokioResult;
+
+/// msgpack-RPC message types.
+const REQUEST: u64 = 0;
+const RESPONSE: u64 = 1;
+const NOTIFICATION: u64 = 2;
+
+/// Lua called for every delta by the `stream` method.
+const ON_DELTA_LUA: &str = "require('ata2').on_delta(...)";
+
+/// Writes a message to Neovim. stdout is the RPC channel, so nothing else may write to it.
+fn send(message: &Value) {
+    let mut stdout = io::stdout().lock();
+    if let Err(e) = rmpv::encode::write_value(&mut stdout, message) {
+        error!("Failed to write RPC message: {e}");
+    }
+    let _ = stdout.flush();
+}
+
+fn notify(method: &str, params: Vec<Value>) {
+    send(&Value::Array(vec![
+        NOTIFICATION.into(),
+        method.into(),
+        Value::Array(params),
+    ]));
+}
+
+/// Sends each delta to `require('ata2').on_delta`.
+struct LuaCallbackSink;
+
+impl OutputSink for LuaCallbackSink {
+    fn write(&mut self, text: &str) {
+        if !text.is_empty() {
+            notify(
+                "nvim_exec_lua",
+                vec![ON_DELTA_LUA.into(), Value::Array(vec![text.into()])],
+            );
+        }
+    }
+}
+
+/// Inserts each delta at the cursor with `nvim_put`.
+struct InsertAtCursorSink;
+
+impl OutputSink for InsertAtCursorSink {
+    fn write(&mut self, text: &str) {
+        if !text.is_empty() {
+            let lines = text.split('\n').map(Value::from).collect::<Vec<_>>();
+            notify(
+                "nvim_put",
+                vec![Value::Array(lines), "c".into(), true.into(), true.into()],
+            );
+        }
+    }
+}
+
+async fn call(method: &str, params: &[Value]) -> Result<Value, String> {
+    let prompt = params
+        .first()
+        .and_then(|p| p.as_str())
+        .ok_or_else(|| format!("{method}: expected a prompt string as the first parameter"))?
+        .to_string();
+    let result = match method {
+        "ask" => prompt::request_with(&mut NullSink, Some(prompt), Default::default()).await,
+        "stream" => {
+            prompt::request_with(&mut LuaCallbackSink, Some(prompt), Default::default()).await
+        }
+        "insert_at_cursor" => {
+            prompt::request_with(&mut InsertAtCursorSink, Some(prompt), Default::default()).await
+        }
+        _ => return Err(format!("Unknown method: {method}")),
+    };
+    let answer = prompt::response_text(result.map_err(|e| e.to_string())?);
+    Ok(answer.into())
+}
+
+/// Serves RPC requests on stdin/stdout until Neovim closes the channel.
+pub async fn serve() -> TokioResult<()> {
+    // Stdin is Neovim's, so nobody can answer questions; commands the model wants run are declined.
+    ask::set_unattended();
+    let (tx, mut rx) = mpsc::channel::<Value>(16);
+    // Reading msgpack is blocking, so it gets its own thread.
+    tokio::task::spawn_blocking(move || {
+        let mut stdin = io::stdin().lock();
+        while let Ok(message) = rmpv::decode::read_value(&mut stdin) {
+            if tx.blocking_send(message).is_err() {
+                break;
+            }
+        }
+        info!("Neovim closed the RPC channel");
+    });
+
+    while let Some(message) = rx.recv().await {
+        let message = match message {
+            Value::Array(message) => message,
+            other => {
+                warn!("Ignoring malformed RPC message: {other}");
+                continue;
+            }
+        };
+        match (message.get(0).and_then(Value::as_u64), message.as_slice()) {
+            (Some(REQUEST), [_, id, method, Value::Array(params)]) => {
+                let method = method.as_str().unwrap_or_default();
+                let (error, result) = match call(method, params).await {
+                    Ok(result) => (Value::Nil, result),
+                    Err(e) => (Value::from(e), Value::Nil),
+                };
+                send(&Value::Array(vec![
+                    RESPONSE.into(),
+                    id.clone(),
+                    error,
+                    result,
+                ]));
+            }
+            (Some(NOTIFICATION), [_, method, Value::Array(params)]) => {
+                let method = method.as_str().unwrap_or_default();
+                if let Err(e) = call(method, params).await {
+                    error!("{e}");
+                }
+            }
+            _ => warn!("Ignoring unexpected RPC message: {message:?}"),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,114 @@
+//! Where output goes.
+//!
+//! Only the model's content is written to stdout, and only through [`StdoutSink`]; everything else
+//! (the prompt and response banners, warnings, errors, statistics, the configuration…) goes to
+//! stderr. This holds in every mode, so ata²'s stdout can always be piped into another program.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use atty;
+
+use std::env;
+use std::io::Write as _;
+use std::io::{self, Stderr, Stdout};
+
+use crate::theme::{self, Role};
+
+lazy_static! {
+    static ref STDOUT: Stdout = io::stdout();
+    static ref STDERR: Stderr = io::stderr();
+}
+
+/// Receives the model's output as it is streamed in.
+pub trait OutputSink: Send {
+    fn write(&mut self, text: &str);
+
+    /// Called once the response is complete.
+    fn flush(&mut self) {}
+}
+
+/// Lets escape sequences colour the text in Windows' console host, which otherwise shows them as
+/// they are. Windows Terminal doesn't need this.
+#[cfg(windows)]
+pub fn enable_ansi() {
+    if let Err(code) = enable_ansi_support::enable_ansi_support() {
+        debug!("Could not enable ANSI escape sequences (error {code})");
+    }
+}
+
+/// The default sink, printing the model's output to stdout.
+pub struct StdoutSink;
+
+impl OutputSink for StdoutSink {
+    fn write(&mut self, text: &str) {
+        print!("{text}");
+        (&*STDOUT).flush().unwrap();
+    }
+}
+
+/// Discards the model's output, for when only the result of a request is wanted.
+pub struct NullSink;
+
+impl OutputSink for NullSink {
+    fn write(&mut self, _text: &str) {}
+}
+
+/// The width of the terminal stderr is on, in columns, or `$COLUMNS` if it can't be asked.
+pub fn terminal_width() -> usize {
+    #[cfg(unix)]
+    {
+        let mut size = unsafe { std::mem::zeroed::<libc::winsize>() };
+        if unsafe { libc::ioctl(libc::STDERR_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+            && size.ws_col > 0
+        {
+            return size.ws_col as usize;
+        }
+    }
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(80)
+}
+
+/// Writes decoration or status to stderr.
+pub fn eprint_and_flush(text: &str) {
+    eprint!("{text}");
+    (&*STDERR).flush().unwrap();
+}
+
+/// Like [`eprint_and_flush`], styled as a banner (`theme.banner`, bold by default) if stderr is a
+/// terminal.
+pub fn eprint_bold(msg: &str) {
+    if atty::is(atty::Stream::Stderr) {
+        // Styled line by line, so that the newlines around banners are left alone.
+        let styled: Vec<String> = msg
+            .split('\n')
+            .map(|line| {
+                if line.is_empty() {
+                    String::new()
+                } else {
+                    theme::paint(Role::Banner, line)
+                }
+            })
+            .collect();
+        eprint_and_flush(&styled.join("\n"));
+    } else {
+        eprint_and_flush(msg);
+    }
+}
@@ -0,0 +1,156 @@
+//! Reading long answers in a pager (`/page`, `ui.auto_page`).
+//!
+//! The answer is piped into `$PAGER`, run by the shell, or `less -R` if it isn't set, which keeps
+//! the terminal's scrollback as it was once the pager is quit. An answer is long if it takes up
+//! more rows than the terminal has, lines being wrapped at its width.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use unicode_width::UnicodeWidthStr as _;
+
+use std::env;
+use std::io::{self, Write as _};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::clipboard;
+use crate::output;
+use crate::prompt;
+use crate::CONFIGURATION;
+
+lazy_static! {
+    /// A long answer to be paged once the line editor is waiting for the next line.
+    static ref PENDING: Mutex<Option<String>> = Mutex::new(None);
+}
+
+/// Whether the pager is showing something. Ctrl-C is then the pager's, not ata²'s.
+static PAGING: AtomicBool = AtomicBool::new(false);
+
+/// Whether the pager is showing something.
+pub fn is_paging() -> bool {
+    PAGING.load(Ordering::Relaxed)
+}
+
+/// The height of the terminal stdout is on, in rows, or `$LINES` if it can't be asked.
+fn terminal_height() -> usize {
+    #[cfg(unix)]
+    {
+        let mut size = unsafe { std::mem::zeroed::<libc::winsize>() };
+        if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+            && size.ws_row > 0
+        {
+            return size.ws_row as usize;
+        }
+    }
+    env::var("LINES")
+        .ok()
+        .and_then(|lines| lines.parse().ok())
+        .unwrap_or(24)
+}
+
+/// How many rows `text` takes up on a terminal `columns` wide.
+fn rows(text: &str, columns: usize) -> usize {
+    text.lines()
+        .map(|line| line.width().max(1).div_ceil(columns.max(1)))
+        .sum()
+}
+
+/// The pager command, run by the shell as git does, so that e.g. `less -R '+/^#'` works.
+fn pager_command(pager: &str) -> Command {
+    #[cfg(unix)]
+    {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(pager);
+        command
+    }
+    #[cfg(not(unix))]
+    {
+        let mut words = pager.split_whitespace();
+        let mut command = Command::new(words.next().unwrap_or("more"));
+        command.args(words);
+        command
+    }
+}
+
+/// Shows `text` in the pager, returning once it is quit.
+pub fn page(text: &str) -> io::Result<()> {
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or_else(|| String::from("less -R"));
+    PAGING.store(true, Ordering::Relaxed);
+    let shown = show(&pager, text);
+    PAGING.store(false, Ordering::Relaxed);
+    shown
+}
+
+fn show(pager: &str, text: &str) -> io::Result<()> {
+    let mut child = pager_command(pager).stdin(Stdio::piped()).spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager may be quit before reading everything.
+        match stdin.write_all(text.as_bytes()) {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e),
+            _ => {}
+        }
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("{pager} exited with an error"),
+        ));
+    }
+    Ok(())
+}
+
+/// Whether `line` is `/page`, which is handled before it is sent, while the terminal is free.
+pub fn page_requested(line: &str) -> bool {
+    line.trim() == "/page"
+}
+
+/// `/page`: shows the last answer in the pager.
+pub fn page_last() -> Result<(), String> {
+    let answer = clipboard::last().ok_or_else(|| String::from("There is no answer to page yet"))?;
+    page(&answer).map_err(|e| format!("Could not page the answer: {e}"))
+}
+
+/// With `ui.auto_page`, notes the finished `answer` to be paged if it didn't fit on the screen.
+pub fn answered(answer: &str) {
+    if CONFIGURATION.load().ui.auto_page
+        && atty::is(atty::Stream::Stdout)
+        && atty::is(atty::Stream::Stdin)
+        && rows(answer, output::terminal_width()) >= terminal_height()
+    {
+        *PENDING.lock().unwrap() = Some(answer.to_string());
+    }
+}
+
+/// Shows the answer noted by [`answered`], if any, once the line editor has let go of the
+/// terminal.
+pub fn page_pending() {
+    let answer = match PENDING.lock().unwrap().take() {
+        Some(answer) => answer,
+        None => return,
+    };
+    if let Err(e) = page(&answer) {
+        warn!("Could not page the answer: {e}");
+    }
+    prompt::print_prompt();
+}
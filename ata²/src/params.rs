@@ -0,0 +1,241 @@
+//! Overriding request parameters without editing the configuration.
+//!
+//! Parameters can be overridden for a single prompt by starting it with `?key=value` tokens, e.g.
+//! `?temp=0.2 ?model=gpt-4o Write a haiku`, or for the rest of the session with `/set temp 0.2`.
+//! Any value of the configuration that isn't a table or a list can be overridden, by its dotted key
+//! (e.g. `/set ui.highlight_code false`); `/show` prints the values requests are made with.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::sync::Mutex;
+
+use crate::auth;
+use crate::theme;
+use crate::Config;
+use crate::CONFIGURATION;
+use crate::FLAGS;
+
+lazy_static! {
+    /// Overrides set with `/set`, applying to every request until unset.
+    pub static ref SESSION_OVERRIDES: Mutex<Overrides> = Mutex::new(Overrides::default());
+    /// The profile selected with `--profile` (or `-c <name>`), or `/profile`.
+    static ref PROFILE: Mutex<Option<String>> =
+        Mutex::new(FLAGS.profile.clone().or_else(|| FLAGS.config.profile()));
+}
+
+/// Parameters that can be overridden, by dotted key (e.g. `temperature` or `ui.highlight_code`),
+/// with their values as typed. Unset ones come from the configuration.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Overrides {
+    values: BTreeMap<String, String>,
+}
+
+impl Overrides {
+    /// The dotted key of a parameter, accepting some abbreviations.
+    fn canonical_key(key: &str) -> String {
+        match key {
+            "m" => String::from("model"),
+            "temp" | "t" => String::from("temperature"),
+            "max" => String::from("max_tokens"),
+            key => key.replace('-', "_"),
+        }
+    }
+
+    /// Overrides `key` with `value`, which has to fit the type of the configuration's field.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let key = Self::canonical_key(key);
+        let mut config = (**CONFIGURATION.load()).clone();
+        config.set_field(&key, value)?;
+        self.values.insert(key, value.to_string());
+        Ok(())
+    }
+
+    /// Overrides the model, which any name is valid for.
+    pub fn set_model(&mut self, model: &str) {
+        self.values.insert(String::from("model"), model.to_string());
+    }
+
+    pub fn unset(&mut self, key: &str) -> Result<(), String> {
+        let key = Self::canonical_key(key);
+        match self.values.remove(&key) {
+            Some(_) => Ok(()),
+            None => Err(format!("{key} isn't overridden")),
+        }
+    }
+
+    /// `config` with these overrides applied.
+    pub fn apply(&self, config: &Config) -> Config {
+        let mut config = config.clone();
+        if self.values.is_empty() {
+            return config;
+        }
+        let fields = self.values.iter().map(|(k, v)| (k.as_str(), v.as_str()));
+        if config.set_fields(fields).is_ok() {
+            for key in self.values.keys() {
+                config.sources.set_by_flag(key, "override");
+            }
+            return config;
+        }
+        // Only when a reloaded configuration no longer takes some of them: those are left out.
+        for (key, value) in &self.values {
+            match config.set_field(key, value) {
+                Ok(()) => config.sources.set_by_flag(key, "override"),
+                Err(e) => warn!("Ignoring the override {key}={value}: {e}"),
+            }
+        }
+        config
+    }
+
+    /// Whether the theme depends on `key`.
+    fn styles(key: &str) -> bool {
+        key.starts_with("theme.") || key == "ui.code_theme"
+    }
+}
+
+impl Display for Overrides {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        if self.values.is_empty() {
+            return write!(f, "(none)");
+        }
+        let fields: Vec<_> = self
+            .values
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        write!(f, "{}", fields.join(" "))
+    }
+}
+
+/// Splits leading `?key=value` tokens off `line`, returning the overrides and the rest of the
+/// prompt.
+pub fn parse_inline(line: &str) -> Result<(Overrides, String), String> {
+    let mut overrides = Overrides::default();
+    let mut rest = line.trim_start();
+    while let Some(token) = rest.strip_prefix('?') {
+        let end = token.find(char::is_whitespace).unwrap_or(token.len());
+        let (key, value) = match token[..end].split_once('=') {
+            Some((key, value)) if !key.is_empty() && !value.is_empty() => (key, value),
+            // Not an override, just a prompt starting with a question mark.
+            _ => break,
+        };
+        overrides.set(key, value)?;
+        rest = token[end..].trim_start();
+    }
+    Ok((overrides, rest.to_string()))
+}
+
+/// `config` with the selected profile applied.
+fn profile_config(config: &Config) -> Result<Config, String> {
+    match *PROFILE.lock().unwrap() {
+        Some(ref name) => config.with_profile(name),
+        None => Ok(config.clone()),
+    }
+}
+
+/// The configuration to make a request with: the configuration file, then the selected profile,
+/// then the session's overrides, then the request's.
+pub fn effective_config(config: &Config, request: &Overrides) -> Result<Config, String> {
+    let session = SESSION_OVERRIDES
+        .lock()
+        .unwrap()
+        .apply(&profile_config(config)?);
+    let mut config = request.apply(&session);
+    auth::apply(&mut config);
+    config.validate()?;
+    Ok(config)
+}
+
+/// Handles `/set [key value]`. Without arguments, prints the session's overrides.
+pub fn set_command(args: &str) -> Result<String, String> {
+    let mut overrides = SESSION_OVERRIDES.lock().unwrap();
+    match args.split_once(char::is_whitespace) {
+        Some((key, value)) => {
+            let mut changed = overrides.clone();
+            changed.set(key, value.trim())?;
+            // Don't accept values the API would reject.
+            let config = changed.apply(&profile_config(&CONFIGURATION.load())?);
+            config.validate()?;
+            for warning in config.warnings() {
+                warn!("{warning}");
+            }
+            *overrides = changed;
+            if Overrides::styles(&Overrides::canonical_key(key)) {
+                theme::apply(&config);
+            }
+            Ok(format!("Session overrides: {overrides}"))
+        }
+        None if args.is_empty() => Ok(format!("Session overrides: {overrides}")),
+        None => Err(String::from("Usage: /set <parameter> <value>")),
+    }
+}
+
+/// Handles `/unset key`.
+pub fn unset_command(args: &str) -> Result<String, String> {
+    let mut overrides = SESSION_OVERRIDES.lock().unwrap();
+    overrides.unset(args.trim())?;
+    if Overrides::styles(&Overrides::canonical_key(args.trim())) {
+        theme::apply(&overrides.apply(&profile_config(&CONFIGURATION.load())?));
+    }
+    Ok(format!("Session overrides: {overrides}"))
+}
+
+/// The configuration as the session's overrides leave it, e.g. for the `ui` settings the REPL reads
+/// as it goes. An invalid one falls back to the configuration file's.
+pub fn current_config() -> Config {
+    effective_config(&CONFIGURATION.load(), &Overrides::default())
+        .unwrap_or_else(|_| (**CONFIGURATION.load()).clone())
+}
+
+/// The model the next request goes to, unless it overrides it.
+pub fn current_model() -> String {
+    match effective_config(&CONFIGURATION.load(), &Overrides::default()) {
+        Ok(config) => config.model,
+        Err(_) => CONFIGURATION.load().model.clone(),
+    }
+}
+
+/// The selected profile, if any.
+pub fn current_profile() -> Option<String> {
+    PROFILE.lock().unwrap().clone()
+}
+
+/// Handles `/profile [name]`. Without arguments, prints the selected profile.
+pub fn profile_command(args: &str) -> Result<String, String> {
+    let names = CONFIGURATION.load().profile_names().join(", ");
+    let name = args.trim();
+    if name.is_empty() {
+        return Ok(match *PROFILE.lock().unwrap() {
+            Some(ref name) => format!("Profile: {name} (profiles: {names})"),
+            None => format!("No profile selected (profiles: {names})"),
+        });
+    }
+    if name == "-" {
+        *PROFILE.lock().unwrap() = None;
+        return Ok(String::from("Using the top-level settings"));
+    }
+    let config = CONFIGURATION.load().with_profile(name)?;
+    config.validate()?;
+    *PROFILE.lock().unwrap() = Some(name.to_string());
+    Ok(format!(
+        "Switched to profile {name} (model: {})",
+        config.model
+    ))
+}
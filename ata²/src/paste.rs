@@ -0,0 +1,70 @@
+//! Tidying up pasted terminal transcripts (`ui.clean_pastes`).
+//!
+//! A paste of several lines with shell prompts in it (`user@host:~/src$ make`, `% ls`) is taken to
+//! be a transcript: its colors and other escape sequences are removed, prompts are shortened to
+//! their `$`, `%` or `#`, and lines with only a prompt are dropped. A bare `#` is not taken for a
+//! prompt, as it is more often a comment or a Markdown heading.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use regex::Regex;
+
+lazy_static! {
+    /// CSI (colors, cursor movement) and OSC (titles, links) sequences, and other escapes.
+    static ref ESCAPES: Regex =
+        Regex::new(r"\x1b\[[0-?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\)|\x1b[@-_]").unwrap();
+    /// A prompt naming the user and host, e.g. `(venv) user@host:~/src$ `.
+    static ref FULL_PROMPT: Regex =
+        Regex::new(r"^(?:\([^)]*\) )?[\w.-]+@[\w.-]+(?:[: ][^\s$%#]*)? ?([$%#])(?: |$)").unwrap();
+    static ref BARE_PROMPT: Regex = Regex::new(r"^([$%])(?: |$)").unwrap();
+}
+
+/// If `line` starts with a prompt, its `$`, `%` or `#`, and what was typed after it.
+fn split_prompt(line: &str) -> Option<(&str, &str)> {
+    [&*FULL_PROMPT, &*BARE_PROMPT].into_iter().find_map(|re| {
+        let captures = re.captures(line)?;
+        let sigil = captures.get(1)?.as_str();
+        Some((sigil, &line[captures.get(0)?.end()..]))
+    })
+}
+
+/// `text`, tidied up if it looks like a terminal transcript.
+pub fn clean(text: String) -> String {
+    if !text.contains('\n') {
+        return text;
+    }
+    let plain = ESCAPES.replace_all(&text, "");
+    // Of a line redrawn with carriage returns (e.g. a progress bar), only the last version shows.
+    let lines: Vec<&str> = plain
+        .lines()
+        .map(|line| line.rsplit('\r').next().unwrap_or(line).trim_end())
+        .collect();
+    if !lines.iter().any(|line| split_prompt(line).is_some()) {
+        return text;
+    }
+    lines
+        .into_iter()
+        .filter_map(|line| match split_prompt(line) {
+            Some((_, "")) => None,
+            Some((sigil, command)) => Some(format!("{sigil} {command}")),
+            None => Some(line.to_string()),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
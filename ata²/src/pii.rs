@@ -0,0 +1,277 @@
+//! Filtering personal information out of outgoing prompts.
+//!
+//! Each class of information (see [`PiiClass`]) can be ignored, warned about, or masked, as set in
+//! the `[pii]` table of the configuration. Masked values are replaced by placeholders such as
+//! `[EMAIL_1]` before the prompt is sent, and the placeholders are replaced by the original values
+//! again when the answer is printed. The conversation itself keeps the placeholders, so the model
+//! never sees the originals.
+//!
+//! [`Redactor`] masks everything regardless of the configuration, for `/export --redact`.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use regex::{Captures, Regex};
+
+use std::sync::Mutex;
+
+use crate::config::{PiiAction, PiiConfig};
+use crate::output::OutputSink;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PiiClass {
+    Secret,
+    Email,
+    Ip,
+    Phone,
+    Name,
+}
+
+impl PiiClass {
+    /// In the order they are applied: secrets may contain anything, e-mail addresses contain things
+    /// that look like names, and IP addresses look like phone numbers.
+    pub const ALL: [PiiClass; 5] = [
+        PiiClass::Secret,
+        PiiClass::Email,
+        PiiClass::Ip,
+        PiiClass::Phone,
+        PiiClass::Name,
+    ];
+
+    fn placeholder_prefix(&self) -> &'static str {
+        match self {
+            PiiClass::Secret => "SECRET",
+            PiiClass::Email => "EMAIL",
+            PiiClass::Ip => "IP",
+            PiiClass::Phone => "PHONE",
+            PiiClass::Name => "NAME",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            PiiClass::Secret => "secret (API key, token or private key)",
+            PiiClass::Email => "e-mail address",
+            PiiClass::Ip => "IP address",
+            PiiClass::Phone => "phone number",
+            PiiClass::Name => "name",
+        }
+    }
+
+    pub fn regex(&self) -> &'static Regex {
+        match self {
+            PiiClass::Secret => &SECRET,
+            PiiClass::Email => &EMAIL,
+            PiiClass::Ip => &IP,
+            PiiClass::Phone => &PHONE,
+            PiiClass::Name => &NAME,
+        }
+    }
+
+    fn action(&self, config: &PiiConfig) -> PiiAction {
+        match self {
+            PiiClass::Secret => config.secret,
+            PiiClass::Email => config.email,
+            PiiClass::Ip => config.ip,
+            PiiClass::Phone => config.phone,
+            PiiClass::Name => config.name,
+        }
+    }
+}
+
+/// Capitalized words that commonly start sentences or are otherwise not names.
+const NOT_NAMES: &[&str] = &[
+    "A", "An", "And", "Are", "But", "Can", "Could", "Do", "Does", "For", "How", "I", "If", "In",
+    "Is", "It", "My", "Of", "On", "Please", "So", "The", "Then", "This", "To", "We", "What",
+    "When", "Where", "Which", "Who", "Why", "Will", "With", "Would", "You",
+];
+
+/// Placeholders are short; anything longer after a `[` can't be one.
+const MAX_PLACEHOLDER_LEN: usize = 16;
+
+lazy_static! {
+    static ref SECRET: Regex = Regex::new(concat!(
+        r"-----BEGIN [A-Z ]*PRIVATE KEY-----[\s\S]*?-----END [A-Z ]*PRIVATE KEY-----",
+        r"|\bsk-[A-Za-z0-9_-]{20,}",
+        r"|\bgh[pousr]_[A-Za-z0-9]{36,}",
+        r"|\bxox[abprs]-[A-Za-z0-9-]{10,}",
+        r"|\bAKIA[0-9A-Z]{16}\b",
+        r"|\bAIza[0-9A-Za-z_-]{35}",
+        r"|\bBearer\s+[A-Za-z0-9._~+/=-]{20,}",
+    ))
+    .unwrap();
+    static ref EMAIL: Regex = Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}").unwrap();
+    static ref IP: Regex = Regex::new(
+        r"\b(?:(?:25[0-5]|2[0-4]\d|1?\d?\d)\.){3}(?:25[0-5]|2[0-4]\d|1?\d?\d)\b|\b(?:[0-9A-Fa-f]{1,4}:){7}[0-9A-Fa-f]{1,4}\b"
+    )
+    .unwrap();
+    static ref PHONE: Regex =
+        Regex::new(r"(?:\+\d{1,3}[\s.-]?)?(?:\(\d{2,4}\)[\s.-]?)?\b\d{3,4}[\s.-]\d{3,4}(?:[\s.-]\d{2,4})?\b")
+            .unwrap();
+    static ref NAME: Regex = Regex::new(r"\b[A-Z][a-z]+(?:\s+[A-Z][a-z]+)+\b").unwrap();
+    static ref PLACEHOLDER: Regex =
+        Regex::new(r"\[(?:SECRET|EMAIL|IP|PHONE|NAME)_\d+\]").unwrap();
+    /// Placeholders handed out this session, and the values they stand for.
+    static ref PLACEHOLDERS: Mutex<Vec<(String, String)>> = Mutex::new(vec![]);
+}
+
+fn looks_like_name(candidate: &str) -> bool {
+    candidate
+        .split_whitespace()
+        .all(|word| !NOT_NAMES.contains(&word))
+}
+
+/// The placeholder for `value`, reusing the existing one if it was masked before.
+fn placeholder_for(
+    placeholders: &mut Vec<(String, String)>,
+    class: PiiClass,
+    value: &str,
+) -> String {
+    if let Some((placeholder, _)) = placeholders.iter().find(|(_, v)| v == value) {
+        return placeholder.clone();
+    }
+    let prefix = class.placeholder_prefix();
+    let n = placeholders
+        .iter()
+        .filter(|(p, _)| p[1..].starts_with(prefix))
+        .count()
+        + 1;
+    let placeholder = format!("[{prefix}_{n}]");
+    placeholders.push((placeholder.clone(), value.to_string()));
+    placeholder
+}
+
+/// Applies the configured actions to an outgoing prompt, returning what should be sent instead.
+pub fn filter(prompt: &str, config: &PiiConfig) -> String {
+    let mut ret = prompt.to_string();
+    for class in PiiClass::ALL {
+        let action = class.action(config);
+        if action == PiiAction::Off {
+            continue;
+        }
+        ret = class
+            .regex()
+            .replace_all(&ret, |caps: &Captures| {
+                let found = &caps[0];
+                if class == PiiClass::Name && !looks_like_name(found) {
+                    return found.to_string();
+                }
+                match action {
+                    PiiAction::Mask => {
+                        placeholder_for(&mut PLACEHOLDERS.lock().unwrap(), class, found)
+                    }
+                    _ => {
+                        warn!(
+                            "Your prompt seems to contain a {}: {found}",
+                            class.description()
+                        );
+                        found.to_string()
+                    }
+                }
+            })
+            .into_owned();
+    }
+    ret
+}
+
+/// Masks every kind of personal information and secret, whatever the configuration says, e.g. for
+/// sharing a conversation. The same value gets the same placeholder throughout.
+#[derive(Default)]
+pub struct Redactor {
+    placeholders: Vec<(String, String)>,
+}
+
+impl Redactor {
+    pub fn redact(&mut self, text: &str) -> String {
+        let mut ret = text.to_string();
+        for class in PiiClass::ALL {
+            ret = class
+                .regex()
+                .replace_all(&ret, |caps: &Captures| {
+                    let found = &caps[0];
+                    if class == PiiClass::Name && !looks_like_name(found) {
+                        return found.to_string();
+                    }
+                    placeholder_for(&mut self.placeholders, class, found)
+                })
+                .into_owned();
+        }
+        ret
+    }
+}
+
+/// Replaces placeholders in `text` with the values they stand for.
+pub fn restore(text: &str) -> String {
+    let placeholders = PLACEHOLDERS.lock().unwrap();
+    PLACEHOLDER
+        .replace_all(text, |caps: &Captures| {
+            placeholders
+                .iter()
+                .find(|(p, _)| p == &caps[0])
+                .map(|(_, v)| v.clone())
+                .unwrap_or_else(|| caps[0].to_string())
+        })
+        .into_owned()
+}
+
+/// Restores placeholders in the model's output before passing it on. Placeholders may be split
+/// across deltas, so a trailing `[…` is held back until it is complete.
+pub struct RestoringSink<'a> {
+    inner: &'a mut dyn OutputSink,
+    pending: String,
+}
+
+impl<'a> RestoringSink<'a> {
+    pub fn new(inner: &'a mut dyn OutputSink) -> Self {
+        Self {
+            inner,
+            pending: String::new(),
+        }
+    }
+}
+
+impl OutputSink for RestoringSink<'_> {
+    fn write(&mut self, text: &str) {
+        if PLACEHOLDERS.lock().unwrap().is_empty() {
+            self.inner.write(text);
+            return;
+        }
+        self.pending.push_str(text);
+        let ready_len = match self.pending.rfind('[') {
+            Some(i)
+                if !self.pending[i..].contains(']')
+                    && self.pending.len() - i <= MAX_PLACEHOLDER_LEN =>
+            {
+                i
+            }
+            _ => self.pending.len(),
+        };
+        let ready = self.pending.drain(..ready_len).collect::<String>();
+        if !ready.is_empty() {
+            self.inner.write(&restore(&ready));
+        }
+    }
+
+    fn flush(&mut self) {
+        let rest = std::mem::take(&mut self.pending);
+        if !rest.is_empty() {
+            self.inner.write(&restore(&rest));
+        }
+        self.inner.flush();
+    }
+}
@@ -0,0 +1,200 @@
+//! Changing answers once they are complete (`postprocess`), before they are printed and stored.
+//!
+//! `postprocess` is a list of steps, applied in order:
+//!
+//! * `strip_fences` removes the fences of code blocks, keeping the code.
+//! * `format_code` formats code blocks with the usual formatter of their language (`rustfmt`,
+//!   `shfmt`, `gofmt`), if it is installed.
+//! * `command:<command>` pipes the answer through a shell command, e.g. `command:fold -s -w 80`.
+//!
+//! A step that fails leaves the answer as it was. With any steps, answers are printed only once
+//! they are complete.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use serde::{Deserialize, Serialize};
+
+use std::fmt::{self, Display};
+use std::io::{self, Write as _};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+
+/// The formatter of each language, by the tags code blocks are marked with. Each reads the code on
+/// stdin and prints it formatted.
+const FORMATTERS: &[(&[&str], &str, &[&str])] = &[
+    (&["rust", "rs"], "rustfmt", &["--edition", "2021"]),
+    (&["sh", "bash", "shell", "zsh"], "shfmt", &[]),
+    (&["go", "golang"], "gofmt", &[]),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum PostProcessor {
+    StripFences,
+    FormatCode,
+    /// A shell command reading the answer on stdin and printing the new one.
+    Command(String),
+}
+
+impl FromStr for PostProcessor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strip_fences" => Ok(Self::StripFences),
+            "format_code" => Ok(Self::FormatCode),
+            _ => match s.strip_prefix("command:") {
+                Some(command) if !command.trim().is_empty() => {
+                    Ok(Self::Command(command.trim().to_string()))
+                }
+                _ => Err(format!(
+                    "`{s}` is not one of strip_fences, format_code or command:<command>"
+                )),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for PostProcessor {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Display for PostProcessor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Self::StripFences => write!(f, "strip_fences"),
+            Self::FormatCode => write!(f, "format_code"),
+            Self::Command(command) => write!(f, "command:{command}"),
+        }
+    }
+}
+
+impl From<PostProcessor> for String {
+    fn from(step: PostProcessor) -> String {
+        step.to_string()
+    }
+}
+
+/// If `line` is a code fence, the language tag after it.
+fn fence(line: &str) -> Option<&str> {
+    let tag = line.trim_start().strip_prefix("```")?;
+    Some(tag.trim())
+}
+
+/// Runs `program` with `input` on stdin, returning what it printed.
+fn pipe(program: &str, args: &[&str], input: &str) -> io::Result<String> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_string();
+    // Written from another thread, so that a program printing a lot before reading all of its
+    // input can't block.
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    match writer.join().unwrap() {
+        // The program exited without reading all of its input, which its status tells about.
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+        result => result?,
+    }
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("{program} failed ({}): {}", output.status, stderr.trim()),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn strip_fences(answer: &str) -> String {
+    answer
+        .split_inclusive('\n')
+        .filter(|line| fence(line).is_none())
+        .collect()
+}
+
+/// `code`, formatted if there is a formatter for `language`.
+fn format(language: &str, code: &str) -> String {
+    let formatter = FORMATTERS
+        .iter()
+        .find(|(tags, _, _)| tags.contains(&language.to_lowercase().as_str()));
+    match formatter {
+        Some((_, program, args)) => pipe(program, args, code).unwrap_or_else(|e| {
+            debug!("Not formatting the {language} code: {e}");
+            code.to_string()
+        }),
+        None => code.to_string(),
+    }
+}
+
+fn format_code(answer: &str) -> String {
+    let mut ret = String::with_capacity(answer.len());
+    // The language and code of the block being read.
+    let mut block: Option<(&str, String)> = None;
+    for line in answer.split_inclusive('\n') {
+        match (block.take(), fence(line)) {
+            (None, Some(language)) => {
+                ret.push_str(line);
+                block = Some((language, String::new()));
+            }
+            (Some((language, code)), Some(_)) => {
+                ret.push_str(&format(language, &code));
+                ret.push_str(line);
+            }
+            (Some((language, mut code)), None) => {
+                code.push_str(line);
+                block = Some((language, code));
+            }
+            (None, None) => ret.push_str(line),
+        }
+    }
+    // A block cut short is left as it is.
+    if let Some((_, code)) = block {
+        ret.push_str(&code);
+    }
+    ret
+}
+
+/// `answer`, changed by each step of `pipeline` in turn.
+pub fn apply(answer: &str, pipeline: &[PostProcessor]) -> String {
+    let mut answer = answer.to_string();
+    for step in pipeline {
+        answer = match step {
+            PostProcessor::StripFences => strip_fences(&answer),
+            PostProcessor::FormatCode => format_code(&answer),
+            PostProcessor::Command(command) => match pipe("sh", &["-c", command], &answer) {
+                Ok(output) => output,
+                Err(e) => {
+                    warn!("Post-processing step {step} failed: {e}");
+                    answer
+                }
+            },
+        };
+    }
+    answer
+}
@@ -0,0 +1,137 @@
+//! Per-model pricing, used to estimate what requests cost.
+//!
+//! A table is built into the binary (`pricing.json`). `ata2 pricing update` replaces it with the
+//! one maintained in the project repository, or with a local file, by writing it to
+//! `pricing.json` in the cache directory, out of what is synced. That file always takes precedence.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config;
+use crate::TokioResult;
+
+const BUILTIN_PRICING: &str = include_str!("pricing.json");
+
+/// Where `ata2 pricing update` fetches the maintained table from by default.
+pub const PRICING_URL: &str =
+    "https://raw.githubusercontent.com/ctrlcctrlv/ata2/main/ata%C2%B2/src/pricing.json";
+
+/// Prices in USD per million tokens.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Pricing {
+    /// When the table was last updated, as an ISO 8601 date.
+    pub updated: String,
+    pub models: BTreeMap<String, ModelPrice>,
+}
+
+/// The override written by `ata2 pricing update`.
+pub fn override_path() -> PathBuf {
+    config::get_cache_dir().join("pricing.json")
+}
+
+impl Pricing {
+    fn builtin() -> Self {
+        serde_json::from_str(BUILTIN_PRICING).expect("built-in pricing table is invalid")
+    }
+
+    /// The override file if there is a valid one, otherwise the built-in table.
+    pub fn load() -> Self {
+        let path = override_path();
+        match fs::read_to_string(&path) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(pricing) => return pricing,
+                Err(e) => warn!(
+                    "Ignoring invalid pricing table {}: {e}",
+                    path.to_string_lossy()
+                ),
+            },
+            Err(_) => {}
+        }
+        Self::builtin()
+    }
+
+    /// The price of `model`. Dated snapshots like `gpt-4o-2024-08-06` fall back to the longest
+    /// listed prefix, i.e. `gpt-4o`.
+    pub fn lookup(&self, model: &str) -> Option<ModelPrice> {
+        if let Some(price) = self.models.get(model) {
+            return Some(*price);
+        }
+        self.models
+            .iter()
+            .filter(|(name, _)| model.starts_with(name.as_str()))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, price)| *price)
+    }
+}
+
+/// Handles `ata2 pricing update [SOURCE]`. `source` is a URL or a local file.
+pub async fn update(source: Option<&str>) -> TokioResult<()> {
+    let source = source.unwrap_or(PRICING_URL);
+    let contents = if source.starts_with("http://") || source.starts_with("https://") {
+        reqwest::get(source)
+            .await?
+            .error_for_status()?
+            .text()
+            .await?
+    } else {
+        fs::read_to_string(source)?
+    };
+    let pricing: Pricing = serde_json::from_str(&contents)
+        .map_err(|e| format!("{source} is not a valid pricing table: {e}"))?;
+    let path = override_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, contents)?;
+    eprintln!(
+        "Updated pricing for {} models (as of {}) in {}",
+        pricing.models.len(),
+        pricing.updated,
+        path.to_string_lossy()
+    );
+    Ok(())
+}
+
+/// Handles `ata2 pricing show`, printing the table in effect.
+pub fn show(model: &str) {
+    let pricing = Pricing::load();
+    println!("Prices in USD per 1M tokens, as of {}:", pricing.updated);
+    println!("{:<24} {:>10} {:>10}", "model", "input", "output");
+    for (name, price) in &pricing.models {
+        println!("{name:<24} {:>10.2} {:>10.2}", price.input, price.output);
+    }
+    match pricing.lookup(model) {
+        Some(price) => println!(
+            "\nConfigured model {model}: {:.2} input, {:.2} output",
+            price.input, price.output
+        ),
+        None => println!("\nConfigured model {model} is not in the table."),
+    }
+}
@@ -18,161 +18,578 @@
 //!  See the License for the specific language governing permissions and
 //!  limitations under the License.
 
-use ansi_colors::ColouredStr;
-use async_openai::{
-    config::OpenAIConfig,
-    types::{
-        ChatCompletionRequestMessage, ChatCompletionResponseStreamMessage,
-        CreateChatCompletionRequestArgs, FinishReason,
-    },
-    Client,
+use async_openai::types::{
+    ChatCompletionRequestMessage, ChatCompletionResponseStreamMessage, CreateChatCompletionRequest,
+    CreateChatCompletionRequestArgs, FinishReason,
 };
 use atty;
 use log::debug;
 use tokio::sync::Mutex;
 use tokio_stream::StreamExt as _;
 
-use std::io::{self, Stderr, Stdout};
-use std::io::{Read as _, Write as _};
+use std::fs;
+use std::io::{self, Read as _};
+use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::alts;
+use crate::attach;
+use crate::audio;
+use crate::auth;
+use crate::autowrap;
+use crate::backend;
+use crate::capabilities;
+use crate::clipboard;
+use crate::commands::{looks_like_command, COMMANDS};
+use crate::conversation::ConversationManager;
+use crate::duplicates;
+use crate::highlight;
+use crate::index;
+use crate::limits;
+use crate::memory;
+use crate::output::{eprint_and_flush, eprint_bold, NullSink, OutputSink};
+use crate::pager;
+use crate::params::{self, Overrides};
+use crate::pii;
+use crate::postprocess;
 use crate::readline::{
-    string_to_chat_completion_assistant_message, string_to_chat_completion_request_user_message,
+    self, chat_completion_message_to_string, string_to_chat_completion_assistant_message,
+    string_to_chat_completion_request_user_message, string_to_chat_completion_system_message,
 };
+use crate::repetition;
+use crate::session_log;
+use crate::sessions::{self, Session};
+use crate::spinner;
+use crate::stream_decode;
+use crate::title;
+use crate::tokens;
+use crate::tools;
+use crate::web;
+use crate::Config;
 use crate::TokioResult;
 use crate::ABORT;
 use crate::CONFIGURATION;
+use crate::FLAGS;
 use crate::IS_RUNNING;
+use crate::STOP_ANSWER;
 
 lazy_static! {
-    static ref STDOUT: Stdout = io::stdout();
-    static ref STDERR: Stderr = io::stderr();
     pub static ref CONVERSATION: Mutex<Vec<ChatCompletionRequestMessage>> = Mutex::new(vec![]);
 }
 
+/// Saves `conversation` as JSON to `path`, or to `conversation-<unix time>.json` in the current
+/// directory, returning the path written to.
+pub fn save_conversation(
+    conversation: &[ChatCompletionRequestMessage],
+    path: Option<PathBuf>,
+) -> io::Result<PathBuf> {
+    let path = path.unwrap_or_else(|| {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        format!("conversation-{now}.json").into()
+    });
+    fs::write(&path, serde_json::to_string(conversation)?)?;
+    Ok(path)
+}
+
+/// Writes the conversation to the `--save` path, and to the data directory if
+/// `ui.autosave_conversations` is set. Called after each answer and on exit.
+pub async fn autosave() {
+    let conversation = CONVERSATION.lock().await.clone();
+    if conversation.is_empty() {
+        return;
+    }
+    let mut paths = vec![];
+    if let Some(ref path) = FLAGS.save {
+        paths.push(path.clone());
+    }
+    if CONFIGURATION.load().ui.autosave_conversations {
+        let dir = CONFIGURATION.load().paths.conversations();
+        if let Err(e) = fs::create_dir_all(&dir) {
+            warn!("Could not create {}: {e}", dir.display());
+        }
+        paths.push(dir.join(format!("{}.json", sessions::current_id())));
+    }
+    for path in paths {
+        if let Err(e) = save_conversation(&conversation, Some(path.clone())) {
+            warn!("Could not save the conversation to {}: {e}", path.display());
+        }
+    }
+}
+
 pub async fn load_conversation<P: AsRef<std::path::Path>>(path: P) -> TokioResult<()> {
     let mut file = std::fs::File::open(path)?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
     let lines = contents.split("\n").collect::<Vec<_>>();
     let mut conversation = CONVERSATION.lock().await;
-    let loaded_conversation = serde_json::from_str::<Vec<ChatCompletionRequestMessage>>(
-        &lines
-            .into_iter()
-            .filter(|o| !o.is_empty())
-            .collect::<Vec<_>>()
-            .join("\n"),
-    )?;
+    let contents = lines
+        .into_iter()
+        .filter(|o| !o.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    // Either a conversation saved with F2, or a saved session.
+    let loaded_conversation =
+        match serde_json::from_str::<Vec<ChatCompletionRequestMessage>>(&contents) {
+            Ok(messages) => messages,
+            Err(e) => match serde_json::from_str::<Session>(&contents) {
+                Ok(session) => {
+                    sessions::resume(&session);
+                    session.messages
+                }
+                Err(_) => return Err(e.into()),
+            },
+        };
     conversation.clear();
     conversation.extend(loaded_conversation);
     Ok(())
 }
 
-fn print_and_flush(text: &str) {
-    print!("{text}");
-    (&*STDOUT).flush().unwrap();
-}
-
-fn eprint_and_flush(text: &str) {
-    eprint!("{text}");
-    (&*STDERR).flush().unwrap();
-}
-
-fn eprint_bold(msg: &str) {
-    if atty::is(atty::Stream::Stderr) {
-        let mut bold = ColouredStr::new(msg);
-        bold.bold();
-        let bold = bold.to_string();
-        eprint_and_flush(&bold.as_str());
-    } else {
-        eprint_and_flush(msg);
-    }
+/// Joins the streamed deltas returned by [`request`] into the text of the answer.
+pub fn response_text(deltas: Vec<ChatCompletionResponseStreamMessage>) -> String {
+    deltas
+        .into_iter()
+        .filter_map(|choice| choice.delta.content)
+        .collect()
 }
 
 pub fn print_prompt() {
     if atty::is(atty::Stream::Stderr) {
-        eprint_bold("\nPrompt:\n");
+        if readline::vi_mode() {
+            eprint_bold("\nPrompt (vi):\n");
+        } else {
+            eprint_bold("\nPrompt:\n");
+        }
     }
 }
 
-fn print_response_prompt() {
+pub fn print_response_prompt() {
     if atty::is(atty::Stream::Stderr) {
         eprint_bold("\nResponse:\n");
     }
 }
 
-fn finish_prompt() {
+pub fn finish_prompt() {
     IS_RUNNING.store(false, Ordering::SeqCst);
     print_prompt();
 }
 
-fn print_error(msg: &str) {
+/// On Windows, Ctrl-C reaches the process as a console event, rather than through the line editor,
+/// whenever no line is being read, e.g. while a one-shot answer is printed; elsewhere, it does as a
+/// signal while answering with a prompt string (see [`crate::status`]). It is handled as the line
+/// editor's interruptions are: it stops the answer, and with `ui.double_ctrlc`, only exits the
+/// second time, by sending `None` through `tx` so that ata² shuts down as usual.
+pub fn handle_ctrl_c(tx: tokio::sync::mpsc::Sender<Option<String>>) {
+    use crate::HAD_FIRST_INTERRUPT;
+
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = tokio::signal::ctrl_c().await {
+                warn!("Could not handle Ctrl-C: {e}");
+                return;
+            }
+            if pager::is_paging() {
+                // e.g. to stop a search in less
+                continue;
+            }
+            if IS_RUNNING.load(Ordering::SeqCst) {
+                STOP_ANSWER.store(true, Ordering::Relaxed);
+            } else if params::current_config().ui.double_ctrlc
+                && !HAD_FIRST_INTERRUPT.swap(true, Ordering::Relaxed)
+            {
+                eprint!("\nPress Ctrl-C again to exit.");
+                print_prompt();
+            } else {
+                ABORT.store(true, Ordering::Relaxed);
+                let _ = tx.send(None).await;
+                return;
+            }
+        }
+    });
+}
+
+pub fn print_error(msg: &str) {
+    spinner::stop();
     error!("{msg}");
     finish_prompt()
 }
 
-fn store_and_do_nothing(print_buffer: &mut Vec<String>, text: &str) -> String {
-    print_buffer.push(text.to_string());
-    "".to_string()
+/// Puts `system_prompt`, after the remembered facts, at the head of the conversation, or updates it
+/// there if it changed. A conversation that was started without one (e.g. a loaded one) gets one.
+fn set_system_prompt(conversation: &mut Vec<ChatCompletionRequestMessage>, config: &Config) {
+    if conversation.is_empty() {
+        memory::start_conversation(&config.memory);
+    }
+    let system_prompt = match (memory::preamble(), config.system_prompt.clone()) {
+        (Some(preamble), Some(system_prompt)) => format!("{preamble}\n\n{system_prompt}"),
+        (Some(prompt), None) | (None, Some(prompt)) => prompt,
+        (None, None) => return,
+    };
+    match conversation.first_mut() {
+        None => conversation.push(string_to_chat_completion_system_message(system_prompt)),
+        Some(ChatCompletionRequestMessage::System(message)) => {
+            message.content = Some(system_prompt);
+        }
+        Some(_) => {
+            conversation.insert(0, string_to_chat_completion_system_message(system_prompt));
+            alts::shift();
+        }
+    }
 }
 
-fn join_and_clear(print_buffer: &mut Vec<String>, text: &str) -> String {
-    let from_buffer = print_buffer.join("");
-    print_buffer.clear();
-    let joined = format!("{from_buffer}{text}");
-    joined.replace("\\n", "\n")
+/// The instruction to answer in the language requested by `reply_language`, if any. `"auto"` means
+/// the language of `prompt`, if it can be detected reliably.
+pub fn reply_language_instruction(config: &Config, prompt: &str) -> Option<String> {
+    let language = match config.reply_language.as_deref()?.trim() {
+        "auto" => {
+            let info = whatlang::detect(prompt)?;
+            if !info.is_reliable() {
+                debug!("Could not reliably detect the language of the prompt");
+                return None;
+            }
+            info.lang().eng_name().to_string()
+        }
+        language => whatlang::Lang::from_code(language)
+            .map(|lang| lang.eng_name().to_string())
+            .unwrap_or_else(|| language.to_string()),
+    };
+    Some(format!(
+        "Answer in the following language, regardless of the language of any earlier messages: \
+         {language}."
+    ))
 }
 
-// Fixes cases where the model returns ["\", "n"] instead of ["\n"],
-// which is interpreted as a newline in the OpenAI playground.
-fn fix_newlines(print_buffer: &mut Vec<String>, text: &str) -> String {
-    let single_backslash = r#"\"#;
-    if text.ends_with(single_backslash) {
-        return store_and_do_nothing(print_buffer, text);
-    }
-    if !print_buffer.is_empty() {
-        return join_and_clear(print_buffer, text);
+/// Marks the final line of a prompt as the beginning of the answer, e.g.:
+///
+/// ```text
+/// Write a haiku about rust
+/// prefill: Iron
+/// ```
+pub const PREFILL_MARKER: &str = "prefill:";
+
+/// Splits a `prefill:` line off the end of the prompt, if there is one.
+fn split_prefill(line: &str) -> (String, Option<String>) {
+    let trimmed = line.trim_end();
+    let (prompt, last) = match trimmed.rsplit_once('\n') {
+        Some((prompt, last)) => (prompt, last),
+        None => ("", trimmed),
+    };
+    match last.trim_start().strip_prefix(PREFILL_MARKER) {
+        Some(prefill) if !prompt.trim().is_empty() => (
+            prompt.to_string(),
+            Some(prefill.strip_prefix(' ').unwrap_or(prefill).to_string()),
+        ),
+        _ => (line.to_string(), None),
     }
-    text.to_string()
 }
 
-fn post_process(print_buffer: &mut Vec<String>, text: &str) -> String {
-    fix_newlines(print_buffer, text)
+/// Entry point for every line read by the REPL: runs slash commands, and sends anything else to
+/// the model.
+pub async fn dispatch(line: String) -> TokioResult<Vec<ChatCompletionResponseStreamMessage>> {
+    dispatch_to(&mut *highlight::terminal_sink(), line).await
 }
 
-pub async fn request(
-    prompt: String,
-    _count: i64,
+/// Like [`dispatch`], but writes the model's answers to `sink`.
+pub async fn dispatch_to(
+    sink: &mut dyn OutputSink,
+    line: String,
 ) -> TokioResult<Vec<ChatCompletionResponseStreamMessage>> {
-    let mut print_buffer: Vec<String> = Vec::new();
-    let config = &*CONFIGURATION.to_owned();
-    let oconfig: OpenAIConfig = config.into();
-    let openai = Client::with_config(oconfig);
-    let completions = openai.chat();
-    let messages = {
-        CONVERSATION
-            .lock()
-            .await
-            .push(string_to_chat_completion_request_user_message(
-                prompt.clone(),
-            ));
+    let trimmed = line.trim();
+    let (command, args) = trimmed
+        .split_once(char::is_whitespace)
+        .unwrap_or((trimmed, ""));
+    if let Some(command) = COMMANDS.get(command) {
+        return command.run(args.trim()).await;
+    }
+    if looks_like_command(command) {
+        print_error(&format!("Unknown command {command}. See /help."));
+        return Ok(vec![]);
+    }
+    let (prompt, options) = match prepare(&line) {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            print_error(&e);
+            return Ok(vec![]);
+        }
+    };
+    if duplicates::hold_back(&prompt).await {
+        return Ok(vec![]);
+    }
+    request_with(sink, Some(prompt), options).await
+}
+
+/// The prompt `line` stands for, with its inline parameters (`?key=value`) and prefill taken out,
+/// and code wrapped and files included as configured.
+fn prepare(line: &str) -> Result<(String, RequestOptions), String> {
+    let (overrides, line) = params::parse_inline(line)?;
+    let (prompt, prefill) = split_prefill(&line);
+    let configuration = CONFIGURATION.load_full();
+    let ui = &configuration.ui;
+    let prompt = if ui.auto_wrap_code {
+        autowrap::wrap(&prompt, &ui.auto_wrap_question).unwrap_or(prompt)
+    } else {
+        prompt
+    };
+    let prompt = attach::expand(&prompt, configuration.attach_max_bytes)
+        .map_err(|e| format!("Could not include a file: {e}"))?;
+    let options = RequestOptions {
+        prefill,
+        overrides,
+        ..Default::default()
+    };
+    Ok((prompt, options))
+}
+
+/// `/dry [prompt]`: prints the request `prompt` would make, or without one, the request for the
+/// conversation as it is, without sending it.
+pub async fn dry_run(args: &str) -> TokioResult<Vec<ChatCompletionResponseStreamMessage>> {
+    let (prompt, mut options) = if args.is_empty() {
+        (None, RequestOptions::default())
+    } else {
+        match prepare(args) {
+            Ok((prompt, options)) => (Some(prompt), options),
+            Err(e) => {
+                print_error(&e);
+                return Ok(vec![]);
+            }
+        }
+    };
+    options.dry_run = true;
+    request_with(&mut NullSink, prompt, options).await
+}
+
+/// Prints the request that sending `request` would make, for `--dry-run` and `/dry`.
+fn print_payload(config: &Config, request: &CreateChatCompletionRequest) -> TokioResult<()> {
+    let payload = backend::primary(config)?.payload(request);
+    println!("{}", serde_json::to_string_pretty(&payload)?);
+    let tokens: usize = request
+        .messages
+        .iter()
+        .map(|message| tokens::count_message(&request.model, message))
+        .sum();
+    info!("Dry run, nothing was sent. The messages take about {tokens} tokens.");
+    finish_prompt();
+    Ok(())
+}
+
+/// Re-sends the conversation with the last assistant message as a prefill, so that an answer
+/// cut short (e.g. by `max_tokens`) is resumed exactly where it stopped.
+pub async fn continue_last() -> TokioResult<Vec<ChatCompletionResponseStreamMessage>> {
+    let partial = {
+        let mut conversation = CONVERSATION.lock().await;
+        match conversation.pop() {
+            Some(ChatCompletionRequestMessage::Assistant(msg)) => msg,
+            other => {
+                conversation.extend(other);
+                drop(conversation);
+                print_error("Nothing to continue: the last message is not from the assistant.");
+                return Ok(vec![]);
+            }
+        }
+    };
+    let prefill = partial.content.clone().unwrap_or_else(String::new);
+    let result = request(None, Some(prefill)).await;
+    if !matches!(result, Ok(ref r) if !r.is_empty()) {
+        // Don't lose the partial answer if continuing it failed.
         CONVERSATION
             .lock()
             .await
-            .clone()
-            .into_iter()
-            .collect::<Vec<_>>()
+            .push(ChatCompletionRequestMessage::Assistant(partial));
+    }
+    result
+}
+
+/// How a single request differs from the defaults.
+#[derive(Clone, Debug, Default)]
+pub struct RequestOptions {
+    /// Sent as the start of the assistant's answer, which the model then continues. The stored
+    /// assistant message includes the prefill.
+    pub prefill: Option<String>,
+    /// Parameters overridden for this request only (`?key=value`), on top of the session's.
+    pub overrides: Overrides,
+    /// Print the request instead of sending it (`/dry`), as `--dry-run` does for every request.
+    pub dry_run: bool,
+    /// Ask in this conversation rather than the REPL's, e.g. for batch prompts asked at once.
+    pub conversation: Option<Arc<Detached>>,
+    /// Leave out what is done with answers the user reads: copying, paging and reading them
+    /// aloud, logging them in `ui.session_log_dir`, and autosaving.
+    pub quiet: bool,
+}
+
+/// A conversation apart from the REPL's, which isn't saved.
+#[derive(Debug, Default)]
+pub struct Detached {
+    pub messages: Mutex<Vec<ChatCompletionRequestMessage>>,
+    /// The model that gave the last answer.
+    pub model: std::sync::Mutex<Option<String>>,
+}
+
+/// Sends the conversation to the API, with `prompt` appended as a new user message if given.
+pub async fn request(
+    prompt: Option<String>,
+    prefill: Option<String>,
+) -> TokioResult<Vec<ChatCompletionResponseStreamMessage>> {
+    let options = RequestOptions {
+        prefill,
+        ..Default::default()
+    };
+    request_with(&mut *highlight::terminal_sink(), prompt, options).await
+}
+
+/// How many times in a row the model may call tools before answering.
+const MAX_TOOL_ROUNDS: usize = 10;
+
+/// Like [`request`], but writes the model's output to `sink`.
+pub async fn request_with(
+    sink: &mut dyn OutputSink,
+    prompt: Option<String>,
+    options: RequestOptions,
+) -> TokioResult<Vec<ChatCompletionResponseStreamMessage>> {
+    let mut prompt = prompt;
+    let mut options = options;
+    for _ in 0..MAX_TOOL_ROUNDS {
+        let mut used_tools = false;
+        let result = request_once(sink, prompt.take(), options.clone(), &mut used_tools).await?;
+        if !used_tools {
+            web::print_sources();
+            return Ok(result);
+        }
+        // The results of the tools are in the conversation now; the model answers with them.
+        options.prefill = None;
+    }
+    print_error(&format!(
+        "The model called tools {MAX_TOOL_ROUNDS} times in a row, stopping"
+    ));
+    Ok(vec![])
+}
+
+/// A single request of [`request_with`]. If the model calls tools, they are run and
+/// `used_tools` is set.
+async fn request_once(
+    sink: &mut dyn OutputSink,
+    prompt: Option<String>,
+    options: RequestOptions,
+    used_tools: &mut bool,
+) -> TokioResult<Vec<ChatCompletionResponseStreamMessage>> {
+    let mut decoder = stream_decode::Decoder::default();
+    let config = &match params::effective_config(&CONFIGURATION.load(), &options.overrides) {
+        Ok(config) => config,
+        Err(e) => {
+            print_error(&format!("Invalid parameters: {e}"));
+            return Ok(vec![]);
+        }
+    };
+    let prefill = options.prefill;
+    let conversation = match options.conversation {
+        Some(ref detached) => &detached.messages,
+        None => &*CONVERSATION,
+    };
+    // Sent again if the key is rejected and replaced.
+    let mut original_prompt = prompt.clone();
+    let prompt = prompt.map(|prompt| pii::filter(&prompt, &config.pii));
+    let mut sink = pii::RestoringSink::new(sink);
+    let pushed_prompt = prompt.is_some();
+    let (mut messages, last_prompt) = {
+        let mut conversation = conversation.lock().await;
+        set_system_prompt(&mut conversation, config);
+        if let Some(prompt) = prompt {
+            conversation.push(string_to_chat_completion_request_user_message(prompt));
+        }
+        let mut messages = conversation.clone().into_iter().collect::<Vec<_>>();
+        let last_prompt = messages
+            .iter()
+            .rev()
+            .find(|m| matches!(m, ChatCompletionRequestMessage::User(_)))
+            .map(chat_completion_message_to_string)
+            .unwrap_or_else(String::new);
+        if let Some(instruction) = reply_language_instruction(config, &last_prompt) {
+            messages.push(string_to_chat_completion_system_message(instruction));
+        }
+        if let Some(ref prefill) = prefill {
+            messages.push(string_to_chat_completion_assistant_message(prefill.clone()));
+        }
+        (messages, last_prompt)
+    };
+    let dry_run = options.dry_run || FLAGS.dry_run;
+    let context = if dry_run {
+        None
+    } else {
+        index::context(config, &last_prompt).await
+    };
+    if let Some(context) = context {
+        // Just before the prompt, and only for this request.
+        let at = messages
+            .iter()
+            .rposition(|m| matches!(m, ChatCompletionRequestMessage::User(_)))
+            .unwrap_or(messages.len());
+        messages.insert(at, string_to_chat_completion_system_message(context));
+    }
+    let fitted = ConversationManager::new(config)
+        .dry_run(dry_run)
+        .fit(messages)
+        .await;
+    let messages = match fitted {
+        Ok(messages) => messages,
+        Err(e) => {
+            if pushed_prompt {
+                conversation.lock().await.pop();
+            }
+            print_error(&e);
+            return Ok(vec![]);
+        }
     };
     let mut request: CreateChatCompletionRequestArgs = config.into();
-    let mut stream = completions
-        .create_stream(request.messages(messages).build()?)
-        .await?;
+    let _title = title::busy();
+    let tools = tools::definitions(&config.tools);
+    if !tools.is_empty() && capabilities::lacks_tools(&config.model) {
+        debug!("{} doesn't take tools, leaving them out", config.model);
+    } else if !tools.is_empty() {
+        request.tools(tools);
+    }
+    let request = request.messages(messages).build()?;
+    if dry_run {
+        // The prompt wasn't asked.
+        if pushed_prompt {
+            conversation.lock().await.pop();
+        }
+        print_payload(config, &request)?;
+        return Ok(vec![]);
+    }
+    // Set from here on, e.g. by `abort` on the control pipe while the request is being made, it
+    // stops the answer before its first words.
+    STOP_ANSWER.store(false, Ordering::Relaxed);
+    let _spinner = spinner::start(&config.model);
+    let (mut stream, model) = match limits::create_stream(config, request).await {
+        Ok(started) => started,
+        Err(e) => {
+            if auth::rejected(&e) && auth::offer(&e, original_prompt) {
+                if pushed_prompt {
+                    conversation.lock().await.pop();
+                }
+                return Ok(vec![]);
+            }
+            return Err(e.into());
+        }
+    };
+    if let Some(prompt) = original_prompt.as_ref().filter(|_| !options.quiet) {
+        session_log::prompt(&config.ui, &model, prompt);
+    }
     IS_RUNNING.store(true, Ordering::SeqCst);
 
     let got_first_success: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    let mut rejected = false;
+    // Whether the answer was cut short with Ctrl-C (or the control pipe).
+    let mut interrupted = false;
     let mut ret = vec![];
+    let mut tool_calls = tools::Calls::default();
+    let mut repetition_guard = repetition::Guard::new(&config.generation);
+    // Answers to be post-processed are printed once complete.
+    let buffered = !config.postprocess.is_empty();
 
     'abort: while !ABORT.load(Ordering::Relaxed) {
         while let Some(c) = stream.next().await {
@@ -182,25 +599,44 @@ pub async fn request(
                     ret.push(completion.clone());
                     if !got_first_success.load(Ordering::SeqCst) {
                         got_first_success.store(true, Ordering::SeqCst);
+                        spinner::stop();
+                        auth::answered();
                         print_response_prompt();
+                        if let Some(prefill) = prefill.as_ref().filter(|_| !buffered) {
+                            sink.write(prefill);
+                        }
                     }
                     for choice in &completion.choices {
-                        if ABORT.load(Ordering::Relaxed) {
+                        if ABORT.load(Ordering::Relaxed) || STOP_ANSWER.load(Ordering::Relaxed) {
+                            interrupted = true;
                             break 'abort;
                         }
                         match choice.delta.content {
                             Some(ref text) => {
-                                let newline_fixed = post_process(&mut print_buffer, &text);
-                                print_and_flush(&newline_fixed);
+                                let decoded = decoder.push(text);
+                                if !buffered {
+                                    sink.write(&decoded);
+                                }
+                                if repetition_guard.push(text) {
+                                    IS_RUNNING.store(false, Ordering::SeqCst);
+                                    break 'abort;
+                                }
                             }
                             None => {}
                         }
+                        for chunk in choice.delta.tool_calls.iter().flatten() {
+                            tool_calls.push(chunk);
+                        }
                         match choice.finish_reason {
                             Some(FinishReason::Stop) => {
                                 debug!("Got stop from API, returning to REPL");
                                 IS_RUNNING.store(false, Ordering::SeqCst);
                                 break 'abort;
                             }
+                            Some(FinishReason::ToolCalls) => {
+                                debug!("Got tool calls from API");
+                                break 'abort;
+                            }
                             Some(reason) => {
                                 let msg = format!("OpenAI API error: {reason:?}");
                                 print_error(&msg);
@@ -211,25 +647,58 @@ pub async fn request(
                     }
                 }
                 Err(e) => {
+                    if !got_first_success.load(Ordering::SeqCst)
+                        && auth::rejected(&e)
+                        && auth::offer(&e, original_prompt.take())
+                    {
+                        rejected = true;
+                        break 'abort;
+                    }
                     let msg = format!("OpenAI API error: {e}");
                     print_error(&msg);
+                    // e.g. the stream stalled: what came so far is kept, to be continued.
+                    interrupted = got_first_success.load(Ordering::SeqCst);
                     break 'abort;
                 }
             }
         }
         debug!("Got end of stream, returning to REPL");
-        IS_RUNNING.store(false, Ordering::SeqCst);
         break 'abort;
     }
+    IS_RUNNING.store(false, Ordering::SeqCst);
+    let processed = if buffered && got_first_success.load(Ordering::SeqCst) {
+        let answer: String = prefill
+            .iter()
+            .cloned()
+            .chain(
+                ret.iter()
+                    .flat_map(|completion| completion.choices.iter())
+                    .filter_map(|choice| choice.delta.content.clone()),
+            )
+            .collect();
+        let processed = postprocess::apply(&answer, &config.postprocess);
+        sink.write(&processed);
+        Some(processed)
+    } else {
+        sink.write(&decoder.finish());
+        None
+    };
+    sink.flush();
     eprint_and_flush("\n");
 
+    if rejected {
+        if pushed_prompt {
+            conversation.lock().await.pop();
+        }
+        return Ok(vec![]);
+    }
     if !got_first_success.load(Ordering::SeqCst) {
         let msg = format!("Empty prompt, aborting.");
         print_error(&msg);
         return Ok(vec![]);
     }
 
-    let result = ret
+    let mut result = ret
         .drain(..)
         .map(|o| Arc::new(o.choices.clone().into_iter().collect::<Vec<_>>()))
         .collect::<Vec<_>>()
@@ -245,14 +714,54 @@ pub async fn request(
 
     let complete_message = result.iter().map(|o| o.delta.clone()).collect::<Vec<_>>();
 
-    let assistant_msg = string_to_chat_completion_assistant_message(
-        complete_message
+    let assistant_msg = string_to_chat_completion_assistant_message(match processed {
+        Some(ref processed) => processed.clone(),
+        None => prefill
             .into_iter()
-            .map(|o| o.content.unwrap_or_else(String::new))
+            .chain(
+                complete_message
+                    .into_iter()
+                    .map(|o| o.content.unwrap_or_else(String::new)),
+            )
             .collect::<Vec<_>>()
             .join(""),
-    );
-    (*CONVERSATION).lock().await.push(assistant_msg);
+    });
+    // Callers see the answer as post-processed.
+    if let Some(processed) = processed {
+        for (i, message) in result.iter_mut().enumerate() {
+            message.delta.content = (i == 0).then(|| processed.clone());
+        }
+    }
+    let answer = chat_completion_message_to_string(&assistant_msg);
+    if !tool_calls.is_empty() && !interrupted {
+        // Still running: Ctrl-C declines the commands and stops.
+        IS_RUNNING.store(true, Ordering::SeqCst);
+        let messages = tool_calls.run(answer, &config.tools).await;
+        conversation.lock().await.extend(messages);
+        IS_RUNNING.store(false, Ordering::SeqCst);
+        *used_tools = !STOP_ANSWER.load(Ordering::Relaxed);
+        return Ok(result);
+    }
+    if let Some(ref detached) = options.conversation {
+        detached.messages.lock().await.push(assistant_msg);
+        *detached.model.lock().unwrap() = Some(model.clone());
+    } else {
+        let mut conversation = conversation.lock().await;
+        conversation.push(assistant_msg);
+        sessions::record_model(conversation.len() - 1, &model);
+        sessions::record_truncated(conversation.len() - 1, interrupted);
+        sessions::generate_title(config, &conversation);
+    }
+    if interrupted {
+        info!("Kept the answer so far; /continue resumes it");
+    }
+    if !options.quiet {
+        autosave().await;
+        clipboard::answered(&answer);
+        pager::answered(&answer);
+        session_log::answer(&config.ui, &model, &pii::restore(&answer), interrupted);
+        audio::read_aloud(config, &answer);
+    }
 
     IS_RUNNING.store(false, Ordering::SeqCst);
     finish_prompt();
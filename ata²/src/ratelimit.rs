@@ -0,0 +1,161 @@
+//! Client-side rate limits (`[rate_limit]`): requests wait for their turn rather than being
+//! refused by the provider.
+//!
+//! Each provider has a bucket of requests and one of tokens, refilled continuously up to the limit
+//! per minute. They are shared by everything asking the provider at the same time: prompts,
+//! batches, summaries, hedging and tool calls.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::types::CreateChatCompletionRequest;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimitConfig;
+use crate::tokens;
+
+/// Waits shorter than this aren't worth telling about.
+const NOTICEABLE_WAIT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Provider {
+    Primary,
+    Fallback,
+}
+
+struct Bucket {
+    per_minute: f64,
+    available: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(per_minute: u64) -> Self {
+        Self {
+            per_minute: per_minute as f64,
+            available: per_minute as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let refilled = now.duration_since(self.updated).as_secs_f64() * self.per_minute / 60.0;
+        self.available = (self.available + refilled).min(self.per_minute);
+        self.updated = now;
+    }
+
+    /// How long until `amount` is available. More than fits in the bucket is available once it is
+    /// full.
+    fn wait_for(&self, amount: f64) -> Duration {
+        let missing = amount.min(self.per_minute) - self.available;
+        if self.per_minute == 0.0 || missing <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(missing * 60.0 / self.per_minute)
+    }
+
+    fn take(&mut self, amount: f64) {
+        self.available -= amount.min(self.per_minute);
+    }
+}
+
+struct Limiter {
+    config: RateLimitConfig,
+    requests: Bucket,
+    tokens: Bucket,
+}
+
+impl Limiter {
+    fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            config: config.clone(),
+            requests: Bucket::new(config.requests_per_minute),
+            tokens: Bucket::new(config.tokens_per_minute),
+        }
+    }
+
+    /// Takes a request of `tokens`, or returns how long until it could be.
+    fn try_take(&mut self, tokens: f64) -> Option<Duration> {
+        let now = Instant::now();
+        self.requests.refill(now);
+        self.tokens.refill(now);
+        let wait = self
+            .requests
+            .wait_for(1.0)
+            .max(self.tokens.wait_for(tokens));
+        if !wait.is_zero() {
+            return Some(wait);
+        }
+        self.requests.take(1.0);
+        self.tokens.take(tokens);
+        None
+    }
+}
+
+lazy_static! {
+    static ref LIMITERS: Mutex<HashMap<Provider, Limiter>> = Mutex::new(HashMap::new());
+}
+
+/// The tokens `request` counts for: its messages, and as many as it may be answered with.
+pub fn estimate(request: &CreateChatCompletionRequest) -> usize {
+    let prompt: usize = request
+        .messages
+        .iter()
+        .map(|message| tokens::count_message(&request.model, message))
+        .sum();
+    prompt + request.max_tokens.unwrap_or(0) as usize
+}
+
+/// Waits until `provider` may be sent a request of `tokens` under `config`, and counts it.
+pub async fn acquire(provider: Provider, config: &RateLimitConfig, tokens: usize) {
+    if !config.is_configured() {
+        return;
+    }
+    let mut told = false;
+    loop {
+        let wait = {
+            let mut limiters = LIMITERS.lock().unwrap();
+            let limiter = limiters
+                .entry(provider)
+                .or_insert_with(|| Limiter::new(config));
+            if limiter.config != *config {
+                *limiter = Limiter::new(config);
+            }
+            limiter.try_take(tokens as f64)
+        };
+        let wait = match wait {
+            Some(wait) => wait,
+            None => return,
+        };
+        if !told && wait >= NOTICEABLE_WAIT {
+            let name = match provider {
+                Provider::Primary => "primary",
+                Provider::Fallback => "fallback",
+            };
+            info!(
+                "Waiting {:.1}s for the rate limit of the {name} provider",
+                wait.as_secs_f64()
+            );
+            told = true;
+        }
+        tokio::time::sleep(wait).await;
+    }
+}
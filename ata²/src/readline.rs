@@ -22,29 +22,50 @@
 
 use async_openai::types::{
     ChatCompletionRequestAssistantMessage, ChatCompletionRequestMessage,
+    ChatCompletionRequestMessageContentPart, ChatCompletionRequestSystemMessage,
     ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent, Role,
 };
 use futures_util::lock::Mutex;
 use rustyline::error::ReadlineError;
 use rustyline::{
-    Cmd, ConditionalEventHandler, Editor, EventContext, EventHandler, KeyCode, KeyEvent, Modifiers,
-    RepeatCount,
+    Behavior, Cmd, ConditionalEventHandler, Editor, Event, EventContext, EventHandler, KeyCode,
+    KeyEvent, Modifiers, RepeatCount,
 };
 use std::future::IntoFuture;
-use std::io::Read as _;
-use std::io::Write as _;
-use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc::Sender;
 use tokio::task::JoinHandle;
 
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
+use crate::ask;
+use crate::attach;
+use crate::audio;
+use crate::auth;
+use crate::clipboard;
+use crate::commands;
+use crate::config::EditMode;
+use crate::digest;
+use crate::edit;
+use crate::helper::AtaHelper;
+#[cfg(unix)]
+use crate::history_search;
+use crate::pager;
+use crate::params;
+use crate::paste;
 use crate::prompt::{self, CONVERSATION};
+use crate::sessions;
+use crate::spinner;
+use crate::status;
+use crate::substitute;
+use crate::undo;
 use crate::TokioResult;
 use crate::ABORT;
 use crate::CONFIGURATION as config;
+use crate::FLAGS;
 use crate::HAD_FIRST_INTERRUPT;
+use crate::IS_RUNNING;
+use crate::STOP_ANSWER;
 
 pub fn string_to_chat_completion_request_user_message(
     string: String,
@@ -64,13 +85,229 @@ pub fn string_to_chat_completion_assistant_message(string: String) -> ChatComple
     })
 }
 
+pub fn string_to_chat_completion_system_message(string: String) -> ChatCompletionRequestMessage {
+    ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+        role: Role::System,
+        content: Some(string),
+        ..Default::default()
+    })
+}
+
+/// The text of a message, regardless of its role. Non-text content (e.g. images) is skipped.
+pub fn chat_completion_message_to_string(message: &ChatCompletionRequestMessage) -> String {
+    match message {
+        ChatCompletionRequestMessage::System(m) => m.content.clone(),
+        ChatCompletionRequestMessage::User(m) => match &m.content {
+            Some(ChatCompletionRequestUserMessageContent::Text(text)) => Some(text.clone()),
+            Some(ChatCompletionRequestUserMessageContent::Array(parts)) => Some(
+                parts
+                    .iter()
+                    .filter_map(|part| match part {
+                        ChatCompletionRequestMessageContentPart::Text(t) => Some(t.text.clone()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+            None => None,
+        },
+        ChatCompletionRequestMessage::Assistant(m) => m.content.clone(),
+        ChatCompletionRequestMessage::Tool(m) => m.content.clone(),
+        ChatCompletionRequestMessage::Function(m) => m.content.clone(),
+    }
+    .unwrap_or_else(String::new)
+}
+
+/// The role of a message, as named by the API.
+pub fn chat_completion_message_role(message: &ChatCompletionRequestMessage) -> &'static str {
+    match message {
+        ChatCompletionRequestMessage::System(_) => "system",
+        ChatCompletionRequestMessage::User(_) => "user",
+        ChatCompletionRequestMessage::Assistant(_) => "assistant",
+        ChatCompletionRequestMessage::Tool(_) => "tool",
+        ChatCompletionRequestMessage::Function(_) => "function",
+    }
+}
+
+/// The prompt given on the command line, if any, followed by what was `piped` to stdin as a code
+/// block.
+pub fn with_question(piped: String) -> String {
+    let question = FLAGS.prompt().join(" ");
+    match (question.trim(), piped.trim()) {
+        ("", _) => piped,
+        (question, "") => question.to_string(),
+        (question, _) => format!("{question}\n\n{}", attach::fenced(&piped, "")),
+    }
+}
+
+/// Makes the terminal the process's stdin, e.g. after piped input has been consumed, so that the
+/// REPL can continue interactively.
+#[cfg(unix)]
+pub fn reopen_tty_as_stdin() -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd as _;
+    let tty = std::fs::File::open("/dev/tty")?;
+    if unsafe { libc::dup2(tty.as_raw_fd(), libc::STDIN_FILENO) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn reopen_tty_as_stdin() -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "reopening the terminal is only supported on Unix",
+    ))
+}
+
 pub struct Readline {
-    pub rl: Arc<Mutex<Editor<()>>>,
+    pub rl: Arc<Mutex<Editor<AtaHelper>>>,
+}
+
+/// Whether prompts are edited with vi's keys.
+pub fn vi_mode() -> bool {
+    FLAGS.vi || config.load().ui.edit_mode == EditMode::Vi
+}
+
+/// A line editor that draws on the terminal rather than stdout, so that stdout only ever gets the
+/// model's output, even when it is redirected.
+pub fn editor() -> Editor<AtaHelper> {
+    let behavior = if atty::is(atty::Stream::Stdin) {
+        Behavior::PreferTerm
+    } else {
+        Behavior::Stdio
+    };
+    let edit_mode = if vi_mode() {
+        rustyline::EditMode::Vi
+    } else {
+        rustyline::EditMode::Emacs
+    };
+    let mut editor = Editor::with_config(
+        rustyline::Config::builder()
+            .behavior(behavior)
+            .edit_mode(edit_mode)
+            .build(),
+    )
+    .unwrap();
+    editor.set_helper(Some(AtaHelper::default()));
+    // Ctrl-Z ends the input in Windows' console, as Ctrl-D does elsewhere.
+    #[cfg(windows)]
+    editor.bind_sequence(
+        KeyEvent(KeyCode::Char('z'), Modifiers::CTRL),
+        Cmd::EndOfFile,
+    );
+    editor
+}
+
+/// What a key can be bound to in `[keybindings]`.
+#[derive(Clone, Copy, Debug)]
+pub enum Action {
+    AcceptLine,
+    Newline,
+    ClearScreen,
+    AbortRequest,
+    EditInEditor,
+}
+
+impl Action {
+    fn handler(self) -> EventHandler {
+        match self {
+            Action::AcceptLine => Cmd::AcceptLine.into(),
+            Action::Newline => Cmd::Newline.into(),
+            Action::ClearScreen => Cmd::ClearScreen.into(),
+            Action::AbortRequest => EventHandler::Conditional(Box::new(AbortRequestHandler)),
+            Action::EditInEditor => EventHandler::Conditional(Box::new(EditHandler)),
+        }
+    }
+}
+
+/// The key `key` names, e.g. `ctrl-e`, `alt-enter` or `f5`.
+fn parse_key(key: &str) -> Result<KeyEvent, String> {
+    let mut rest = key;
+    let mut modifiers = Modifiers::NONE;
+    while let Some((modifier, after)) = rest.split_once('-').filter(|(_, a)| !a.is_empty()) {
+        modifiers |= match modifier.to_lowercase().as_str() {
+            "ctrl" | "c" => Modifiers::CTRL,
+            "alt" | "meta" | "m" => Modifiers::ALT,
+            "shift" | "s" => Modifiers::SHIFT,
+            _ => return Err(format!("Unknown modifier {modifier} in {key}")),
+        };
+        rest = after;
+    }
+    let code = match rest.to_lowercase().as_str() {
+        "enter" | "return" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "esc" | "escape" => KeyCode::Esc,
+        "backspace" => KeyCode::Backspace,
+        "delete" | "del" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "space" => KeyCode::Char(' '),
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        name if name.len() > 1 && name.starts_with('f') && name[1..].parse::<u8>().is_ok() => {
+            KeyCode::F(name[1..].parse().unwrap())
+        }
+        _ => {
+            let mut chars = rest.chars();
+            match (chars.next(), chars.next()) {
+                // rustyline reads control characters as upper case letters.
+                (Some(c), None) if modifiers.contains(Modifiers::CTRL) => {
+                    KeyCode::Char(c.to_ascii_uppercase())
+                }
+                (Some(c), None) => KeyCode::Char(c),
+                _ => return Err(format!("Unknown key {rest} in {key}")),
+            }
+        }
+    };
+    Ok(KeyEvent(code, modifiers))
+}
+
+/// The keys `keys` names, pressed one after the other, e.g. `ctrl-x ctrl-e`.
+pub fn parse_keys(keys: &str) -> Result<Event, String> {
+    let keys = keys
+        .split_whitespace()
+        .map(parse_key)
+        .collect::<Result<Vec<_>, _>>()?;
+    if keys.is_empty() {
+        return Err(String::from("A key binding is empty"));
+    }
+    Ok(Event::KeySeq(keys))
+}
+
+/// Binds `event` to `handler`, unless it is bound in `[keybindings]`, which take precedence.
+fn bind_default(
+    rl: &mut Editor<AtaHelper>,
+    event: impl Into<Event>,
+    handler: impl Into<EventHandler>,
+) {
+    let event = event.into();
+    let configured = config
+        .load()
+        .keybindings
+        .actions()
+        .into_iter()
+        .flat_map(|(_, keys)| keys)
+        .any(|keys| parse_keys(keys).map_or(false, |keys| keys == event));
+    if !configured {
+        rl.bind_sequence(event, handler);
+    }
 }
 
 impl Readline {
     pub fn new() -> Self {
-        let rl = Editor::<()>::new().unwrap();
+        let mut rl = editor();
+        let configuration = config.load();
+        for (action, keys) in configuration.keybindings.actions() {
+            for keys in keys.iter().filter_map(|keys| parse_keys(keys).ok()) {
+                rl.bind_sequence(keys, action.handler());
+            }
+        }
         Self {
             rl: Arc::new(Mutex::new(rl)),
         }
@@ -90,59 +327,363 @@ impl ConditionalEventHandler for RequestSaveHandler {
     ) -> Option<Cmd> {
         let convo = CONVERSATION.lock().into_future();
         let convo = convo.now_or_never().unwrap();
-        let convo = convo.clone();
-        let convo_json = serde_json::to_string(&convo).unwrap();
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        // as unix secs
-        let filename = format!("conversation-{}.json", now);
-        let _ = std::fs::remove_file(&filename);
-        let convo_file = std::fs::File::create(&filename).unwrap();
-        let mut convo_file = std::io::BufWriter::new(convo_file);
-        convo_file.write_all(convo_json.as_bytes()).unwrap();
-        info!("Saved conversation to {filename}");
+        match prompt::save_conversation(&convo, None) {
+            Ok(path) => info!("Saved conversation to {}", path.display()),
+            Err(e) => error!("Could not save the conversation: {e}"),
+        }
         Some(Cmd::Noop)
     }
 }
 
+/// Enter in multiline mode: inserts a newline, unless a question is waiting for an answer.
+struct MultilineEnterHandler;
+impl ConditionalEventHandler for MultilineEnterHandler {
+    fn handle(
+        &self,
+        _event: &rustyline::Event,
+        _n: RepeatCount,
+        _positive: bool,
+        _: &EventContext,
+    ) -> Option<Cmd> {
+        if ask::is_pending() {
+            Some(Cmd::AcceptLine)
+        } else {
+            Some(Cmd::Newline)
+        }
+    }
+}
+
+struct CopyAnswerHandler;
+impl ConditionalEventHandler for CopyAnswerHandler {
+    fn handle(
+        &self,
+        _event: &rustyline::Event,
+        _n: RepeatCount,
+        _positive: bool,
+        _: &EventContext,
+    ) -> Option<Cmd> {
+        match clipboard::copy_preferred() {
+            Ok(msg) => info!("{msg}"),
+            Err(e) => warn!("{e}"),
+        }
+        Some(Cmd::Noop)
+    }
+}
+
+/// Ctrl-X Ctrl-E: accepts the line, to be edited in `$EDITOR`.
+struct EditHandler;
+impl ConditionalEventHandler for EditHandler {
+    fn handle(
+        &self,
+        _event: &rustyline::Event,
+        _n: RepeatCount,
+        _positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        edit::request(ctx.line());
+        Some(Cmd::AcceptLine)
+    }
+}
+
+/// Ctrl-R: accepts the line, to be the start of a fuzzy search of the history.
+#[cfg(unix)]
+struct HistorySearchHandler;
+#[cfg(unix)]
+impl ConditionalEventHandler for HistorySearchHandler {
+    fn handle(
+        &self,
+        _event: &rustyline::Event,
+        _n: RepeatCount,
+        _positive: bool,
+        _: &EventContext,
+    ) -> Option<Cmd> {
+        history_search::request();
+        Some(Cmd::AcceptLine)
+    }
+}
+
+/// `abort-request`: stops the answer being printed, like Ctrl-C. Otherwise, the key does what it
+/// would have.
+struct AbortRequestHandler;
+impl ConditionalEventHandler for AbortRequestHandler {
+    fn handle(
+        &self,
+        _event: &rustyline::Event,
+        _n: RepeatCount,
+        _positive: bool,
+        _: &EventContext,
+    ) -> Option<Cmd> {
+        if !IS_RUNNING.load(Ordering::SeqCst) {
+            return None;
+        }
+        STOP_ANSWER.store(true, Ordering::Relaxed);
+        ask::answer("");
+        Some(Cmd::Noop)
+    }
+}
+
+/// Keys typed while an answer is awaited before the prompt string is shown, kept for the next line
+/// rather than echoed into the answer, until dropped.
+#[cfg(unix)]
+struct TypeAhead {
+    original: libc::termios,
+}
+
+#[cfg(unix)]
+impl TypeAhead {
+    fn hold() -> Option<Self> {
+        if !atty::is(atty::Stream::Stdin) {
+            return None;
+        }
+        let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } < 0 {
+            return None;
+        }
+        let original = termios;
+        // Ctrl-C still interrupts: ISIG is left on.
+        termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) } < 0 {
+            return None;
+        }
+        Some(Self { original })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for TypeAhead {
+    fn drop(&mut self) {
+        // Without flushing, so that what was typed is read by the line editor.
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+    }
+}
+
+/// If `line` ends with `<<TERMINATOR` (e.g. `<<EOF`), returns the text before it and the
+/// terminator.
+fn heredoc_start(line: &str) -> Option<(&str, &str)> {
+    let (text, terminator) = line.trim_end().rsplit_once("<<")?;
+    let is_word = !terminator.is_empty()
+        && terminator
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    (is_word && (text.is_empty() || text.ends_with(char::is_whitespace)))
+        .then_some((text.trim_end(), terminator))
+}
+
+/// Reads lines literally until one is `terminator`, whatever `multiline_insertions` is, and returns
+/// them after `text`. Returns `None` if interrupted.
+fn read_heredoc(rl: &mut Editor<AtaHelper>, text: &str, terminator: &str) -> Option<String> {
+    let mut block = text.to_string();
+    let enter = KeyEvent(KeyCode::Enter, Modifiers::NONE);
+    let previous = rl.bind_sequence(enter, Cmd::AcceptLine);
+    let ret = loop {
+        spinner::editing(true);
+        let line = rl.readline("");
+        spinner::editing(false);
+        match line {
+            Ok(line) if line.trim_end() == terminator => break Some(block),
+            Ok(line) => {
+                if !block.is_empty() {
+                    block.push('\n');
+                }
+                block.push_str(&line);
+            }
+            Err(ReadlineError::Eof) => break Some(block),
+            Err(_) => break None,
+        }
+    };
+    match previous {
+        Some(previous) => rl.bind_sequence(enter, previous),
+        None => rl.unbind_sequence(enter),
+    };
+    ret
+}
+
 impl Readline {
     pub async fn handle(&mut self, tx: Sender<Option<String>>) -> JoinHandle<TokioResult<()>> {
         let rl = self.rl.clone();
         let readline_handle: JoinHandle<TokioResult<()>> = tokio::spawn(async move {
             // If stdin is not a tty, we want to read once to the end of it and then exit.
             let mut already_read = false;
-            let mut stdin = std::io::stdin();
+            // What the next prompt starts with, after `/paste` or `/speak`.
+            let mut initial = String::new();
+            let mut dictate_next = FLAGS.dictate;
+            // Whether the last line sent is being handled, and the prompt string has to wait.
+            let mut handling = false;
             prompt::print_prompt();
             while !ABORT.load(Ordering::Relaxed) {
+                if handling {
+                    #[cfg(unix)]
+                    let type_ahead = TypeAhead::hold();
+                    handling = !status::wait_until_handled().await;
+                    #[cfg(unix)]
+                    drop(type_ahead);
+                    // Ctrl-C while waiting, to exit.
+                    if ABORT.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    if !handling {
+                        pager::page_pending();
+                    }
+                }
+                if atty::is(atty::Stream::Stdin) {
+                    auth::troubleshoot(&tx).await;
+                }
                 // lock Readlien
                 let mut rl = rl.lock().await;
-                // Using an empty prompt text because otherwise the user would
+                // Using an empty prompt text (unless ui.prompt or ui.auto_page is set, in which
+                // case the answer is waited for) because otherwise the user would
                 // "see" that the prompt is ready again during response printing.
                 // Also, the current readline is cleared in some cases by rustyline,
                 // so being on a newline is the only way to avoid that.
-                let readline = if atty::is(atty::Stream::Stdin) {
-                    rl.readline("")
+                if atty::is(atty::Stream::Stdin) && std::mem::take(&mut dictate_next) {
+                    match audio::dictate().await {
+                        Ok(text) => initial = text,
+                        Err(e) => error!("{e}"),
+                    }
+                }
+                // A prompt given on the command line is asked once, like a piped one.
+                let one_shot = !atty::is(atty::Stream::Stdin)
+                    || (!FLAGS.prompt().is_empty() && !FLAGS.interactive_after_pipe);
+                let readline = if !one_shot {
+                    let start = std::mem::take(&mut initial);
+                    let prompt_string = if handling || config.load().ui.prompt.is_empty() {
+                        String::new()
+                    } else {
+                        status::render().await
+                    };
+                    spinner::editing(true);
+                    let line = rl.readline_with_initial(&prompt_string, (&start, ""));
+                    spinner::editing(false);
+                    let readline = match line {
+                        Ok(line) if clipboard::paste_requested(&line).is_some() => {
+                            let typed = clipboard::paste_requested(&line).unwrap_or_default();
+                            match clipboard::paste() {
+                                Ok(text) if typed.is_empty() => initial = text,
+                                Ok(text) => initial = format!("{typed} {text}"),
+                                Err(e) => error!("Could not paste: {e}"),
+                            }
+                            continue;
+                        }
+                        #[cfg(unix)]
+                        Ok(line) if history_search::requested() => {
+                            let history: Vec<String> = rl.history().iter().cloned().collect();
+                            match history_search::pick(&history, &line) {
+                                Ok(Some(entry)) => initial = entry,
+                                Ok(None) => initial = line,
+                                Err(e) => {
+                                    error!("Could not search the history: {e}");
+                                    initial = line;
+                                }
+                            }
+                            continue;
+                        }
+                        Ok(line) if pager::page_requested(&line) => {
+                            if let Err(e) = pager::page_last() {
+                                error!("{e}");
+                            }
+                            prompt::print_prompt();
+                            continue;
+                        }
+                        Ok(line) if audio::speak_requested(&line) => {
+                            match audio::dictate().await {
+                                Ok(text) => initial = text,
+                                Err(e) => error!("{e}"),
+                            }
+                            continue;
+                        }
+                        Ok(line) if undo::edit_last_requested(&line) => {
+                            match undo::edit_last().await {
+                                Ok(Some(text)) => {
+                                    eprintln!("{text}");
+                                    Ok(text)
+                                }
+                                Ok(None) => continue,
+                                Err(e) => {
+                                    error!("{e}");
+                                    continue;
+                                }
+                            }
+                        }
+                        Ok(line) => match (edit::requested(&line), heredoc_start(&line)) {
+                            (Some(initial), _) => match edit::compose(&initial) {
+                                Ok(text) if !text.trim().is_empty() => {
+                                    eprintln!("{text}");
+                                    Ok(text)
+                                }
+                                Ok(_) => continue,
+                                Err(e) => {
+                                    error!("Could not edit the prompt: {e}");
+                                    continue;
+                                }
+                            },
+                            (None, Some((text, terminator))) => {
+                                match read_heredoc(&mut rl, text, terminator) {
+                                    Some(block) => Ok(block),
+                                    None => continue,
+                                }
+                            }
+                            (None, None) => Ok(line),
+                        },
+                        Err(e) => Err(e),
+                    };
+                    if params::current_config().ui.clean_pastes {
+                        readline.map(paste::clean)
+                    } else {
+                        readline
+                    }
                 } else if !already_read {
-                    let mut buf = String::with_capacity(1024);
-                    stdin.read_to_string(&mut buf)?;
+                    let buf = if atty::is(atty::Stream::Stdin) {
+                        String::new()
+                    } else {
+                        digest::read().await?
+                    };
                     already_read = true;
-                    Ok(buf)
+                    Ok(with_question(buf))
                 } else {
-                    Err(ReadlineError::Eof)?
+                    Err(ReadlineError::Eof)
+                };
+                // Piped input may end with a line of Ctrl-Z, too.
+                #[cfg(windows)]
+                let readline = match readline {
+                    Ok(line) if line.trim_end() == "\u{1a}" => Err(ReadlineError::Eof),
+                    readline => readline,
                 };
                 match readline {
+                    Ok(line) if ask::answer(&line) => continue,
                     Ok(line) => {
                         if line.is_empty() {
                             continue;
                         }
                         rl.add_history_entry(line.as_str());
+                        sessions::touch_history_entry(&config.load().ui, &line);
+                        let first_word = line.split_whitespace().next().unwrap_or("");
+                        let line = if !params::current_config().ui.expand_env_vars
+                            || commands::looks_like_command(first_word)
+                        {
+                            line
+                        } else if atty::is(atty::Stream::Stdin) {
+                            substitute::expand(&line, substitute::confirm)
+                        } else {
+                            substitute::expand(&line, substitute::refuse)
+                        };
                         tx.send(Some(line)).await?;
+                        handling = config.load().ui.waits_for_answers();
                         HAD_FIRST_INTERRUPT.store(false, Ordering::Relaxed);
+                        dictate_next = FLAGS.dictate;
+                    }
+                    Err(ReadlineError::Interrupted) if IS_RUNNING.load(Ordering::SeqCst) => {
+                        // Stops the answer being printed, rather than ata², declining whatever was
+                        // asked meanwhile.
+                        STOP_ANSWER.store(true, Ordering::Relaxed);
+                        ask::answer("");
+                        continue;
                     }
                     Err(ReadlineError::Interrupted) => {
-                        if config.ui.double_ctrlc && !HAD_FIRST_INTERRUPT.load(Ordering::Relaxed) {
+                        if params::current_config().ui.double_ctrlc
+                            && !HAD_FIRST_INTERRUPT.load(Ordering::Relaxed)
+                        {
                             HAD_FIRST_INTERRUPT.store(true, Ordering::Relaxed);
                             eprint!("\nPress Ctrl-C again to exit.");
                             prompt::print_prompt();
@@ -158,6 +699,9 @@ impl Readline {
                         tx.send(None).await?;
                         break;
                     }
+                    // Resizing the console interrupts reading the line, rather than ending it.
+                    #[cfg(windows)]
+                    Err(ReadlineError::WindowResized) => continue,
                     Err(err) => {
                         eprintln!("{err:?}");
                         tx.send(None).await?;
@@ -172,14 +716,15 @@ impl Readline {
 
     pub async fn enable_multiline(&mut self) {
         let mut rl = self.rl.lock().await;
-        if config.ui.multiline_insertions {
+        if config.load().ui.multiline_insertions {
             if atty::is(atty::Stream::Stdin) {
                 // Cmd::Newline inserts a newline, Cmd::AcceptLine accepts the line
-                rl.bind_sequence(KeyEvent(KeyCode::Enter, Modifiers::NONE), Cmd::Newline);
-                rl.bind_sequence(
-                    KeyEvent(KeyCode::Char('d'), Modifiers::CTRL),
-                    Cmd::AcceptLine,
+                bind_default(
+                    &mut rl,
+                    KeyEvent(KeyCode::Enter, Modifiers::NONE),
+                    EventHandler::Conditional(Box::new(MultilineEnterHandler)),
                 );
+                bind_default(&mut rl, KeyEvent::ctrl('D'), Cmd::AcceptLine);
             }
         }
     }
@@ -187,22 +732,57 @@ impl Readline {
     pub async fn enable_request_save(&mut self) {
         let mut rl = self.rl.lock().await;
         if atty::is(atty::Stream::Stdin) {
-            rl.bind_sequence(
+            bind_default(
+                &mut rl,
                 KeyEvent(KeyCode::F(2), Modifiers::NONE),
                 EventHandler::Conditional(Box::new(RequestSaveHandler)),
             );
         }
     }
 
+    pub async fn enable_copy(&mut self) {
+        let mut rl = self.rl.lock().await;
+        if atty::is(atty::Stream::Stdin) {
+            bind_default(
+                &mut rl,
+                KeyEvent(KeyCode::F(3), Modifiers::NONE),
+                EventHandler::Conditional(Box::new(CopyAnswerHandler)),
+            );
+        }
+    }
+
+    pub async fn enable_edit(&mut self) {
+        let mut rl = self.rl.lock().await;
+        if atty::is(atty::Stream::Stdin) {
+            bind_default(
+                &mut rl,
+                Event::KeySeq(vec![KeyEvent::ctrl('X'), KeyEvent::ctrl('E')]),
+                EventHandler::Conditional(Box::new(EditHandler)),
+            );
+        }
+    }
+
+    /// Replaces rustyline's Ctrl-R with [`history_search`].
+    pub async fn enable_history_search(&mut self) {
+        #[cfg(unix)]
+        if atty::is(atty::Stream::Stdin) {
+            bind_default(
+                &mut *self.rl.lock().await,
+                KeyEvent::ctrl('R'),
+                EventHandler::Conditional(Box::new(HistorySearchHandler)),
+            );
+        }
+    }
+
     pub async fn save_history(&mut self) -> TokioResult<()> {
         let mut rl = self.rl.lock().await;
-        rl.save_history(&config.ui.history_file)?;
+        rl.save_history(&config.load().ui.history_file)?;
         Ok(())
     }
 
     pub async fn load_history(&mut self) -> TokioResult<()> {
         let mut rl = self.rl.lock().await;
-        rl.load_history(&config.ui.history_file)?;
+        rl.load_history(&config.load().ui.history_file)?;
         Ok(())
     }
 
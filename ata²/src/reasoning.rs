@@ -0,0 +1,80 @@
+//! Showing what reasoning models think before answering (`ui.show_reasoning`, `/reasoning`).
+//!
+//! Some APIs send the reasoning, or a summary of it, along with the answer: as `reasoning_content`
+//! or `reasoning` in OpenAI compatible chunks (e.g. DeepSeek's or OpenRouter's), or as thinking
+//! blocks (Anthropic's). It is printed dimmed before the answer, on stderr so that it isn't mistaken
+//! for the answer when stdout is redirected. OpenAI's own reasoning models don't send theirs.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::output::eprint_and_flush;
+use crate::spinner;
+use crate::theme::{self, Role};
+use crate::Config;
+use crate::CONFIGURATION;
+
+lazy_static! {
+    static ref SHOW: AtomicBool = AtomicBool::new(CONFIGURATION.load().ui.show_reasoning);
+}
+
+/// Takes up `config`, e.g. once it is reloaded.
+pub fn apply(config: &Config) {
+    SHOW.store(config.ui.show_reasoning, Ordering::Relaxed);
+}
+
+/// Whether reasoning was shown since the answer last started.
+static SHOWN: AtomicBool = AtomicBool::new(false);
+
+/// Prints a piece of reasoning, if it is to be shown.
+pub fn show(text: &str) {
+    if text.is_empty() || !SHOW.load(Ordering::Relaxed) {
+        return;
+    }
+    SHOWN.store(true, Ordering::Relaxed);
+    spinner::stop();
+    if atty::is(atty::Stream::Stderr) {
+        eprint_and_flush(&theme::paint(Role::Muted, text));
+    } else {
+        eprint_and_flush(text);
+    }
+}
+
+/// Separates the reasoning shown, if any, from the answer that follows.
+pub fn end() {
+    if SHOWN.swap(false, Ordering::Relaxed) {
+        eprint_and_flush("\n\n");
+    }
+}
+
+/// `/reasoning [on|off]`.
+pub fn command(args: &str) -> Result<String, String> {
+    match args.trim() {
+        "" => {}
+        "on" => SHOW.store(true, Ordering::Relaxed),
+        "off" => SHOW.store(false, Ordering::Relaxed),
+        _ => return Err(String::from("Usage: /reasoning [on|off]")),
+    }
+    Ok(if SHOW.load(Ordering::Relaxed) {
+        String::from("Reasoning is shown when the model sends it")
+    } else {
+        String::from("Reasoning isn't shown")
+    })
+}
@@ -0,0 +1,87 @@
+//! Taking edits to the configuration file into account without restarting: `/reload`, and
+//! `ui.watch_config` to reload whenever the file changes.
+//!
+//! The file is read again and, if valid, replaces the configuration for the requests that follow.
+//! An invalid file is reported and the configuration in use is kept. What is only read at startup,
+//! such as the history file, still needs a restart.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::audio;
+use crate::capabilities;
+use crate::index;
+use crate::limits;
+use crate::params;
+use crate::reasoning;
+use crate::state;
+use crate::theme;
+use crate::CONFIGURATION;
+use crate::FLAGS;
+
+/// How often the file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// `/reload`: reads the configuration file again.
+pub fn reload() -> Result<String, String> {
+    let filename = FLAGS.config.location();
+    let config = state::read_configuration(&filename)?;
+    let effective = params::effective_config(&config, &Default::default())
+        .map_err(|e| format!("Keeping the configuration in use: {e}"))?;
+    CONFIGURATION.store(Arc::new(config));
+    // What was set up from the configuration when ata² started.
+    theme::apply(&effective);
+    audio::apply(&effective);
+    reasoning::apply(&effective);
+    index::apply(&effective);
+    limits::apply(&effective);
+    capabilities::reload();
+    Ok(format!("Reloaded {}", filename.display()))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Reloads the configuration whenever its file changes, if `ui.watch_config` is set.
+pub fn watch() {
+    if !CONFIGURATION.load().ui.watch_config {
+        return;
+    }
+    let filename = FLAGS.config.location();
+    tokio::spawn(async move {
+        let mut last = modified(&filename);
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let now = modified(&filename);
+            if now == last {
+                continue;
+            }
+            last = now;
+            match reload() {
+                Ok(msg) => info!("{msg}"),
+                Err(e) => warn!("{e}"),
+            }
+        }
+    });
+}
@@ -0,0 +1,105 @@
+//! Noticing answers stuck in a loop (`generation.repetition_guard`), as local models sometimes
+//! get, repeating the same passage until `max_tokens` runs out.
+//!
+//! The end of the answer is checked for a passage repeated `generation.repetition_threshold`
+//! times in a row. Short loops must be at least [`MIN_LOOP_LEN`] long in all, so that e.g. a
+//! horizontal rule isn't taken for one.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use crate::config::{GenerationConfig, RepetitionGuard};
+
+/// The shortest stretch of repeated text taken for a loop, in bytes.
+const MIN_LOOP_LEN: usize = 400;
+
+/// The longest passage looked for, in bytes.
+const MAX_PERIOD: usize = 2000;
+
+/// How much the answer grows between checks, in bytes.
+const CHECK_EVERY: usize = 32;
+
+/// Whether `text` ends with a passage repeated `times` times in a row.
+fn ends_in_loop(text: &[u8], times: usize) -> bool {
+    (1..=MAX_PERIOD).any(|period| {
+        let len = (period * times).max(MIN_LOOP_LEN);
+        if len > text.len() {
+            return false;
+        }
+        let tail = &text[text.len() - len..];
+        tail[period..] == tail[..len - period]
+    })
+}
+
+/// Watches an answer as it streams in.
+pub struct Guard {
+    action: RepetitionGuard,
+    times: usize,
+    /// The end of the answer, at least as much of it as a loop could take up.
+    text: Vec<u8>,
+    /// How much of the answer has been seen, and how much of it had been when last checked.
+    seen: usize,
+    checked: usize,
+    warned: bool,
+}
+
+impl Guard {
+    pub fn new(config: &GenerationConfig) -> Self {
+        Self {
+            action: config.repetition_guard,
+            times: config.repetition_threshold as usize,
+            text: vec![],
+            seen: 0,
+            checked: 0,
+            warned: false,
+        }
+    }
+
+    /// Notes the next piece of the answer. Returns whether to stop it.
+    pub fn push(&mut self, text: &str) -> bool {
+        if self.action == RepetitionGuard::Off || self.warned {
+            return false;
+        }
+        self.text.extend_from_slice(text.as_bytes());
+        self.seen += text.len();
+        let keep = (MAX_PERIOD * self.times).max(MIN_LOOP_LEN);
+        // Trimmed now and then rather than at every piece, not to move the tail each time.
+        if self.text.len() > 2 * keep {
+            self.text.drain(..self.text.len() - keep);
+        }
+        if self.seen < self.checked + CHECK_EVERY {
+            return false;
+        }
+        self.checked = self.seen;
+        if !ends_in_loop(&self.text, self.times) {
+            return false;
+        }
+        match self.action {
+            RepetitionGuard::Off => false,
+            RepetitionGuard::Warn => {
+                self.warned = true;
+                warn!("The answer seems to be repeating itself; generation.repetition_guard = \"stop\" would stop it");
+                false
+            }
+            RepetitionGuard::Stop => {
+                warn!("Stopped the answer, which was repeating itself");
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Changing the JSON body of chat requests (`[request]`), for endpoints with nonstandard
+//! parameters, e.g. `min_p` or `repetition_penalty` on local servers, and for reasoning models,
+//! which take `max_completion_tokens` instead of `max_tokens`.
+//!
+//! `async_openai` can only send the parameters it knows about, so such requests are sent here
+//! instead, and their server-sent events decoded by hand, along with the reasoning some APIs send.
+//! The decoding is shared with the backends `async_openai` doesn't speak to at all (see
+//! [`crate::backend`]).
+//!
+//! Requests with `stream = false` are sent here too, and their answer is made into a stream of one
+//! chunk, so that it is handled like any other.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::config::Config as ClientConfig;
+use async_openai::error::OpenAIError;
+use async_openai::types::{
+    ChatCompletionResponseStream, CreateChatCompletionRequest, CreateChatCompletionStreamResponse,
+};
+use futures_util::stream::{self, StreamExt as _};
+use serde_json::Value;
+
+use crate::capabilities;
+use crate::config::RequestConfig;
+use crate::reasoning;
+use crate::sse;
+
+/// Merges `extra_body` into the JSON `body` of a request and removes `drop_params`.
+pub fn customize(body: &mut Value, config: &RequestConfig) {
+    if let Value::Object(ref mut object) = body {
+        for (key, value) in &config.extra_body {
+            object.insert(key.clone(), value.clone());
+        }
+        for param in &config.drop_params {
+            object.remove(param);
+        }
+    }
+}
+
+/// `request` as JSON, with `extra_body` merged in and `drop_params` removed.
+pub fn body(request: &CreateChatCompletionRequest, config: &RequestConfig) -> Value {
+    let mut body = serde_json::to_value(request).expect("requests are always serializable");
+    if capabilities::is_reasoning(&request.model) {
+        if let Value::Object(ref mut object) = body {
+            if let Some(max_tokens) = object.remove("max_tokens") {
+                object.insert(String::from("max_completion_tokens"), max_tokens);
+            }
+        }
+    }
+    customize(&mut body, config);
+    body
+}
+
+/// Decodes a chunk, showing the reasoning it holds, which `async_openai` has no field for.
+pub fn chunk(data: &str) -> Result<CreateChatCompletionStreamResponse, OpenAIError> {
+    let chunk: Value = serde_json::from_str(data).map_err(OpenAIError::JSONDeserialize)?;
+    let delta = &chunk["choices"][0]["delta"];
+    for field in ["reasoning_content", "reasoning"] {
+        if let Some(reasoning) = delta[field].as_str() {
+            reasoning::show(reasoning);
+        }
+    }
+    if delta["content"]
+        .as_str()
+        .map_or(false, |content| !content.is_empty())
+    {
+        reasoning::end();
+    }
+    serde_json::from_value(chunk).map_err(OpenAIError::JSONDeserialize)
+}
+
+/// Decodes the server-sent events of a chat completion stream.
+fn decode(response: reqwest::Response) -> ChatCompletionResponseStream {
+    Box::pin(sse::events(response).map(|data| chunk(&data?)))
+}
+
+/// Whether `request` has to be sent here rather than by `async_openai`.
+pub fn is_needed(request: &CreateChatCompletionRequest, config: &RequestConfig) -> bool {
+    !config.extra_body.is_empty()
+        || !config.drop_params.is_empty()
+        || capabilities::is_reasoning(&request.model)
+}
+
+/// The chunk holding all of the non-streamed `response`: each message becomes a delta.
+fn as_chunk(mut response: Value) -> Result<CreateChatCompletionStreamResponse, OpenAIError> {
+    response["object"] = Value::from("chat.completion.chunk");
+    for choice in response["choices"].as_array_mut().into_iter().flatten() {
+        let mut message = choice["message"].take();
+        for field in ["reasoning_content", "reasoning"] {
+            if let Some(reasoning) = message[field].as_str() {
+                reasoning::show(reasoning);
+                reasoning::end();
+            }
+        }
+        // Tool calls in chunks are numbered.
+        for (i, call) in message["tool_calls"]
+            .as_array_mut()
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            call["index"] = Value::from(i);
+        }
+        choice["delta"] = message;
+    }
+    serde_json::from_value(response).map_err(OpenAIError::JSONDeserialize)
+}
+
+/// Sends `request` without streaming, as configured with `stream = false`, returning the whole
+/// answer as a stream of one chunk.
+pub async fn create<C: ClientConfig>(
+    http: &reqwest::Client,
+    client_config: &C,
+    request: CreateChatCompletionRequest,
+    config: &RequestConfig,
+) -> Result<ChatCompletionResponseStream, OpenAIError> {
+    let response = http
+        .post(client_config.url("/chat/completions"))
+        .query(&client_config.query())
+        .headers(client_config.headers())
+        .json(&body(&request, config))
+        .send()
+        .await?;
+    let response: Value = sse::check(response).await?.json().await?;
+    Ok(Box::pin(stream::once(async move { as_chunk(response) })))
+}
+
+/// Like [`async_openai::Chat::create_stream`], but with the body changed as configured.
+pub async fn create_stream<C: ClientConfig>(
+    http: &reqwest::Client,
+    client_config: &C,
+    request: CreateChatCompletionRequest,
+    config: &RequestConfig,
+) -> Result<ChatCompletionResponseStream, OpenAIError> {
+    let response = http
+        .post(client_config.url("/chat/completions"))
+        .query(&client_config.query())
+        .headers(client_config.headers())
+        .json(&body(&request, config))
+        .send()
+        .await?;
+    Ok(decode(sse::check(response).await?))
+}
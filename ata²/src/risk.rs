@@ -0,0 +1,143 @@
+//! How dangerous a command suggested by the model looks, before it is run.
+//!
+//! Commands are matched against patterns of known harm: deleting recursively, writing to disks,
+//! fork bombs, running scripts straight from the internet, …. The riskier the command, the more
+//! it takes to confirm it: high-risk ones have to be typed back.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use regex::Regex;
+
+use crate::ask;
+use crate::output::eprint_bold;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Risk {
+    Low,
+    Medium,
+    High,
+}
+
+lazy_static! {
+    static ref PATTERNS: Vec<(Risk, Regex, &'static str)> = [
+        (
+            Risk::High,
+            // Recursive and forced, in one option or two, short or long, in any order.
+            r"\brm(\s+-\S+)*\s+(-\w*([rR]\w*f|f\w*[rR])\w*|(-\w*[rR]\w*|--recursive)(\s+-\S+)*\s+(-\w*f\w*|--force)|(-\w*f\w*|--force)(\s+-\S+)*\s+(-\w*[rR]\w*|--recursive))",
+            "deletes files recursively without asking",
+        ),
+        (
+            Risk::High,
+            r"\bdd\b.*\bof=",
+            "writes raw data to a file or device"
+        ),
+        (Risk::High, r"\bmkfs(\.\w+)?\b", "formats a file system"),
+        (
+            Risk::High,
+            r">\s*/dev/(sd|hd|nvme|disk|mmcblk)",
+            "overwrites a disk"
+        ),
+        (
+            Risk::High,
+            r":\s*\(\s*\)\s*\{.*:\s*\|\s*:.*\}",
+            "is a fork bomb"
+        ),
+        (
+            Risk::High,
+            r"\b(curl|wget|fetch)\b[^|]*\|\s*(sudo\s+)?(ba|z|da|k|fi)?sh\b",
+            "runs a script straight from the internet",
+        ),
+        (
+            Risk::High,
+            r"\bchmod\s+(-R\s+)?0?777\s+/(\s|$)",
+            "opens up the whole system"
+        ),
+        (
+            Risk::Medium,
+            r"\bsudo\b|\bdoas\b|\bsu\b",
+            "runs as another user"
+        ),
+        (
+            Risk::Medium,
+            r"\brm\b|\bshred\b|\bunlink\b",
+            "deletes files"
+        ),
+        (Risk::Medium, r"\bfind\b.*\s-delete\b", "deletes files"),
+        (
+            Risk::Medium,
+            r"\bgit\s+push\b.*\s(-f|--force)\b",
+            "rewrites remote history"
+        ),
+        (
+            Risk::Medium,
+            r"\bgit\s+(reset\s+--hard|clean\s+-\w*f|checkout\s+--\s)",
+            "throws away changes",
+        ),
+        (
+            Risk::Medium,
+            r"\bch(own|mod)\s+-R\b",
+            "changes permissions recursively"
+        ),
+        (
+            Risk::Medium,
+            r"\b(shutdown|reboot|halt|poweroff|kill(all)?)\b",
+            "stops programs or the system",
+        ),
+        (
+            Risk::Medium,
+            r"\b(mv|cp)\b.*\s/(etc|usr|bin|boot)\b",
+            "changes system files"
+        ),
+    ]
+    .into_iter()
+    .map(|(risk, pattern, why)| (risk, Regex::new(pattern).unwrap(), why))
+    .collect();
+}
+
+/// How dangerous `command` looks, and why.
+pub fn assess(command: &str) -> (Risk, Vec<&'static str>) {
+    let matches: Vec<_> = PATTERNS
+        .iter()
+        .filter(|(_, pattern, _)| pattern.is_match(command))
+        .collect();
+    let risk = matches.iter().map(|(risk, _, _)| *risk).max();
+    let mut reasons: Vec<_> = matches.iter().map(|(_, _, why)| *why).collect();
+    reasons.dedup();
+    (risk.unwrap_or(Risk::Low), reasons)
+}
+
+/// Asks whether to run `command`, as insistently as its risk calls for.
+pub async fn confirm(command: &str) -> bool {
+    let (risk, reasons) = assess(command);
+    for reason in &reasons {
+        eprint_bold(&format!("Careful: this command {reason}.\n"));
+    }
+    if risk < Risk::High {
+        return ask::confirm("Run it?").await;
+    }
+    let normalized = |s: &str| s.split_whitespace().collect::<Vec<_>>().join(" ");
+    match ask::ask("Type the command back to run it, or anything else not to: ").await {
+        Some(typed) if normalized(&typed) == normalized(command) => true,
+        Some(_) => {
+            warn!("That isn't the command, not running it");
+            false
+        }
+        None => false,
+    }
+}
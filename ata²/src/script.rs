@@ -0,0 +1,221 @@
+//! `ata2 script play`: scripted conversations with checks on the answers, for testing prompts
+//! against the live model from the terminal.
+//!
+//! A scenario is a YAML file:
+//!
+//! ```yaml
+//! model: gpt-4            # optional, like temperature
+//! steps:
+//!   - prompt: What is the capital of France? Answer with one word.
+//!     expect:
+//!       contains: [Paris]
+//!       not_contains: [Lyon]
+//!       matches: ['^\w+\.?$']
+//!     capture:
+//!       city: '(\w+)'     # the first capture group, or the whole match
+//!   - wait: 1.5           # seconds
+//!     prompt: How many people live in ${city}?
+//! ```
+//!
+//! The steps are one conversation. `${name}` in prompts and in `contains` and `not_contains` is
+//! replaced by a captured value. The scenario fails if any check does.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use regex::Regex;
+use serde::Deserialize;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::highlight;
+use crate::output::eprint_bold;
+use crate::params::Overrides;
+use crate::prompt::{self, RequestOptions};
+use crate::TokioResult;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Scenario {
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    temperature: Option<f64>,
+    steps: Vec<Step>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Step {
+    #[serde(default)]
+    prompt: Option<String>,
+    /// Seconds to wait before the prompt.
+    #[serde(default)]
+    wait: Option<f64>,
+    #[serde(default)]
+    expect: Expect,
+    /// Regexes, by the name of the variable to capture into.
+    #[serde(default)]
+    capture: BTreeMap<String, String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Expect {
+    #[serde(default)]
+    contains: Vec<String>,
+    #[serde(default)]
+    not_contains: Vec<String>,
+    /// Regexes.
+    #[serde(default)]
+    matches: Vec<String>,
+}
+
+/// `text` with each `${name}` replaced by the value of variable `name`.
+fn substitute(text: &str, variables: &BTreeMap<String, String>) -> String {
+    variables
+        .iter()
+        .fold(text.to_string(), |text, (name, value)| {
+            text.replace(&format!("${{{name}}}"), value)
+        })
+}
+
+impl Scenario {
+    /// Fails on invalid regexes, before anything is asked.
+    fn validate(&self) -> Result<(), String> {
+        for (i, step) in self.steps.iter().enumerate() {
+            let patterns = step.expect.matches.iter().chain(step.capture.values());
+            for pattern in patterns {
+                Regex::new(pattern).map_err(|e| format!("Step {}: {e}", i + 1))?;
+            }
+            if step.prompt.is_none() && !(step.capture.is_empty() && step.expect.is_empty()) {
+                return Err(format!("Step {} has checks, but no prompt", i + 1));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Expect {
+    fn is_empty(&self) -> bool {
+        self.contains.is_empty() && self.not_contains.is_empty() && self.matches.is_empty()
+    }
+
+    fn len(&self) -> usize {
+        self.contains.len() + self.not_contains.len() + self.matches.len()
+    }
+
+    /// What is wrong with `answer`.
+    fn failures(&self, answer: &str, variables: &BTreeMap<String, String>) -> Vec<String> {
+        let mut failures = vec![];
+        for text in &self.contains {
+            let text = substitute(text, variables);
+            if !answer.contains(&text) {
+                failures.push(format!("the answer doesn't contain `{text}`"));
+            }
+        }
+        for text in &self.not_contains {
+            let text = substitute(text, variables);
+            if answer.contains(&text) {
+                failures.push(format!("the answer contains `{text}`"));
+            }
+        }
+        for pattern in &self.matches {
+            if !Regex::new(pattern).unwrap().is_match(answer) {
+                failures.push(format!("the answer doesn't match `{pattern}`"));
+            }
+        }
+        failures
+    }
+}
+
+/// The first capture group of `pattern` in `answer`, or the whole match if it has no groups.
+fn capture(pattern: &str, answer: &str) -> Option<String> {
+    let captures = Regex::new(pattern).unwrap().captures(answer)?;
+    let matched = captures.get(1).or_else(|| captures.get(0))?;
+    Some(matched.as_str().to_string())
+}
+
+/// `ata2 script play`: plays the scenario at `path`. Fails if any check does.
+pub async fn play(path: &Path) -> TokioResult<()> {
+    let scenario: Scenario = serde_yaml::from_str(&fs::read_to_string(path)?)?;
+    scenario.validate()?;
+    let mut overrides = Overrides::default();
+    if let Some(ref model) = scenario.model {
+        overrides.set_model(model);
+    }
+    if let Some(temperature) = scenario.temperature {
+        overrides.set("temperature", &temperature.to_string())?;
+    }
+    let options = RequestOptions {
+        overrides,
+        ..Default::default()
+    };
+    let mut variables = BTreeMap::new();
+    let (mut checks, mut failures) = (0, 0);
+    for (i, step) in scenario.steps.iter().enumerate() {
+        let n = i + 1;
+        if let Some(seconds) = step.wait {
+            tokio::time::sleep(Duration::from_secs_f64(seconds)).await;
+        }
+        let prompt = match step.prompt {
+            Some(ref prompt) => substitute(prompt, &variables),
+            None => continue,
+        };
+        eprint_bold(&format!("\nStep {n}:\n"));
+        eprintln!("{prompt}");
+        let deltas = prompt::request_with(
+            &mut *highlight::terminal_sink(),
+            Some(prompt),
+            options.clone(),
+        )
+        .await?;
+        let answer = prompt::response_text(deltas);
+        if answer.is_empty() {
+            error!("Step {n}: there is no answer");
+            failures += 1;
+            continue;
+        }
+        checks += step.expect.len();
+        for failure in step.expect.failures(&answer, &variables) {
+            error!("Step {n}: {failure}");
+            failures += 1;
+        }
+        for (name, pattern) in &step.capture {
+            checks += 1;
+            match capture(pattern, &answer) {
+                Some(value) => {
+                    info!("Step {n}: captured {name} = {value}");
+                    variables.insert(name.clone(), value);
+                }
+                None => {
+                    error!("Step {n}: nothing to capture as {name} with `{pattern}`");
+                    failures += 1;
+                }
+            }
+        }
+    }
+    if failures > 0 {
+        return Err(format!("{failures} of {checks} checks failed").into());
+    }
+    info!("All {checks} checks passed");
+    Ok(())
+}
@@ -0,0 +1,371 @@
+//! `/search`: finding text in the prompt history and saved sessions.
+//!
+//! Saved sessions are indexed with SQLite's full-text search in the background as they are saved,
+//! in `paths.search_index`. Before each search, sessions that changed otherwise (e.g. imported or
+//! synced ones) are indexed too, and deleted ones left out.
+//! `/search text` finds the messages with all of the words of `text`; `/search /regex/` matches a
+//! regular expression instead, reading the sessions themselves. Either way, the matching history
+//! entries are listed too, and one of the sessions found can then be opened.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use regex::Regex;
+use rusqlite::{params, Connection};
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::ask;
+use crate::config::PathsConfig;
+use crate::output::eprint_bold;
+use crate::readline::{chat_completion_message_role, chat_completion_message_to_string};
+use crate::sessions::{self, Session};
+use crate::theme::{self, Role};
+use crate::CONFIGURATION;
+
+/// Words shown of a message around the match.
+const SNIPPET_WORDS: i64 = 12;
+/// Characters shown of a message on either side of a regular expression's match.
+const CONTEXT_CHARS: usize = 60;
+/// The version of the index's tables, in `PRAGMA user_version`. An index of another version is
+/// rebuilt.
+const SCHEMA_VERSION: i64 = 1;
+/// The most messages and history entries listed.
+const MAX_MESSAGES: usize = 20;
+const MAX_HISTORY: usize = 10;
+
+/// A message that matched.
+struct Hit {
+    session: String,
+    role: String,
+    snippet: String,
+}
+
+lazy_static! {
+    /// Held while writing to the index, so that sessions saved in quick succession are indexed one
+    /// after the other.
+    static ref WRITING: Mutex<()> = Mutex::new(());
+}
+
+fn open_index(paths: &PathsConfig) -> rusqlite::Result<Connection> {
+    let path = paths.search_index();
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let db = Connection::open(path)?;
+    db.busy_timeout(Duration::from_secs(5))?;
+    let version: i64 = db.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version != SCHEMA_VERSION {
+        db.execute_batch(&format!(
+            "DROP TABLE IF EXISTS messages; \
+            DROP TABLE IF EXISTS indexed; \
+            CREATE VIRTUAL TABLE messages \
+            USING fts5(session UNINDEXED, position UNINDEXED, role UNINDEXED, text); \
+            CREATE TABLE indexed (session TEXT PRIMARY KEY, count INTEGER, last INTEGER); \
+            PRAGMA user_version = {SCHEMA_VERSION};"
+        ))?;
+    }
+    Ok(db)
+}
+
+/// Tells messages apart, to notice when the last one indexed has changed.
+fn fingerprint(message: &str) -> i64 {
+    let mut hasher = DefaultHasher::new();
+    message.hash(&mut hasher);
+    hasher.finish() as i64
+}
+
+/// Brings what is indexed of `session` up to date: its new messages are added, or if messages
+/// already indexed have changed (e.g. after `/undo`), all of them are indexed again.
+fn index_into(db: &Connection, session: &Session) -> rusqlite::Result<()> {
+    let texts: Vec<String> = session
+        .messages
+        .iter()
+        .map(chat_completion_message_to_string)
+        .collect();
+    let indexed: Option<(i64, i64)> = db
+        .query_row(
+            "SELECT count, last FROM indexed WHERE session = ?1",
+            params![session.id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .ok();
+    let unchanged = |count: usize, last: i64| {
+        count <= texts.len() && (count == 0 || fingerprint(&texts[count - 1]) == last)
+    };
+    let from = match indexed {
+        Some((count, last)) if count as usize == texts.len() && unchanged(count as usize, last) => {
+            return Ok(())
+        }
+        Some((count, last)) if unchanged(count as usize, last) => count as usize,
+        Some(_) => {
+            db.execute(
+                "DELETE FROM messages WHERE session = ?1",
+                params![session.id],
+            )?;
+            0
+        }
+        None => 0,
+    };
+
+    let mut insert =
+        db.prepare("INSERT INTO messages (session, position, role, text) VALUES (?1, ?2, ?3, ?4)")?;
+    for (i, (message, text)) in session.messages.iter().zip(&texts).enumerate().skip(from) {
+        insert.execute(params![
+            session.id,
+            i as i64,
+            chat_completion_message_role(message),
+            text,
+        ])?;
+    }
+    db.execute(
+        "INSERT OR REPLACE INTO indexed (session, count, last) VALUES (?1, ?2, ?3)",
+        params![
+            session.id,
+            texts.len() as i64,
+            texts.last().map_or(0, |text| fingerprint(text)),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Indexes the new messages of `session` as it is saved, in the background.
+pub fn index(session: &Session) {
+    let session = session.clone();
+    let paths = CONFIGURATION.load().paths.clone();
+    thread::spawn(move || {
+        let _writing = WRITING.lock().unwrap();
+        let result = open_index(&paths).and_then(|mut db| {
+            let transaction = db.transaction()?;
+            index_into(&transaction, &session)?;
+            transaction.commit()
+        });
+        if let Err(e) = result {
+            warn!("Could not index session {} for /search: {e}", session.id);
+        }
+    });
+}
+
+/// Removes `id` from the index, as the session is deleted.
+pub fn forget(id: &str) {
+    let _writing = WRITING.lock().unwrap();
+    let result = open_index(&CONFIGURATION.load().paths).and_then(|db| {
+        db.execute("DELETE FROM messages WHERE session = ?1", params![id])?;
+        db.execute("DELETE FROM indexed WHERE session = ?1", params![id])
+    });
+    if let Err(e) = result {
+        warn!("Could not remove session {id} from the /search index: {e}");
+    }
+}
+
+/// Brings the index up to date with `saved`, the saved sessions: sessions saved by other means
+/// than this process (imported, synced, or saved while it was being indexed) are indexed, and
+/// those no longer saved are removed.
+fn catch_up(db: &mut Connection, saved: &[Session]) -> rusqlite::Result<()> {
+    let _writing = WRITING.lock().unwrap();
+    let transaction = db.transaction()?;
+    for session in saved {
+        index_into(&transaction, session)?;
+    }
+    let ids: HashSet<&str> = saved.iter().map(|session| session.id.as_str()).collect();
+    let gone: Vec<String> = transaction
+        .prepare("SELECT session FROM indexed")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .filter_map(Result::ok)
+        .filter(|id| !ids.contains(id.as_str()))
+        .collect();
+    for id in gone {
+        transaction.execute("DELETE FROM messages WHERE session = ?1", params![id])?;
+        transaction.execute("DELETE FROM indexed WHERE session = ?1", params![id])?;
+    }
+    transaction.commit()
+}
+
+/// What goes around the matches in snippets: the theme's emphasis, on a terminal.
+fn markers() -> (String, String) {
+    if atty::is(atty::Stream::Stderr) {
+        theme::markers(Role::Emphasis)
+    } else {
+        (String::new(), String::new())
+    }
+}
+
+/// The messages of `saved` with all of the words of `text`, best matches first.
+fn search_index(text: &str, saved: &[Session]) -> rusqlite::Result<Vec<Hit>> {
+    let mut db = open_index(&CONFIGURATION.load().paths)?;
+    catch_up(&mut db, saved)?;
+    // Each word as a string, so that none is read as FTS syntax.
+    let query = text
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let (start, end) = markers();
+    let mut select = db.prepare(
+        "SELECT session, role, snippet(messages, 3, ?2, ?3, '…', ?4) FROM messages \
+        WHERE messages MATCH ?1 ORDER BY rank LIMIT ?5",
+    )?;
+    let hits = select
+        .query_map(
+            params![query, start, end, SNIPPET_WORDS, MAX_MESSAGES as i64],
+            |row| {
+                Ok(Hit {
+                    session: row.get(0)?,
+                    role: row.get(1)?,
+                    snippet: row.get(2)?,
+                })
+            },
+        )?
+        .collect();
+    hits
+}
+
+/// `text` around the first match of `regex`, which is highlighted, if there is one.
+fn context(text: &str, regex: &Regex, (start, end): &(String, String)) -> Option<String> {
+    let found = regex.find(text)?;
+    let before: String = text[..found.start()]
+        .chars()
+        .rev()
+        .take(CONTEXT_CHARS)
+        .collect();
+    let before: String = before.chars().rev().collect();
+    let after: String = text[found.end()..].chars().take(CONTEXT_CHARS).collect();
+    Some(format!("…{before}{start}{}{end}{after}…", found.as_str()))
+}
+
+/// The messages of saved sessions matching `regex`, most recent sessions first.
+fn search_sessions(regex: &Regex, sessions: &[Session]) -> Vec<Hit> {
+    let markers = markers();
+    sessions
+        .iter()
+        .rev()
+        .flat_map(|session| {
+            session.messages.iter().filter_map(|message| {
+                let text = chat_completion_message_to_string(message);
+                Some(Hit {
+                    session: session.id.clone(),
+                    role: chat_completion_message_role(message).to_string(),
+                    snippet: context(&text, regex, &markers)?,
+                })
+            })
+        })
+        .take(MAX_MESSAGES)
+        .collect()
+}
+
+/// The most recent history entries for which `matches` holds.
+fn search_history(matches: impl Fn(&str) -> bool) -> Vec<String> {
+    let history = fs::read_to_string(&CONFIGURATION.load().ui.history_file).unwrap_or_default();
+    let mut found: Vec<String> = vec![];
+    for line in history.lines().rev() {
+        // rustyline's header
+        if line == "#V2" || !matches(line) || found.iter().any(|entry| entry == line) {
+            continue;
+        }
+        found.push(line.to_string());
+        if found.len() == MAX_HISTORY {
+            break;
+        }
+    }
+    found
+}
+
+/// Puts a snippet on one line.
+fn one_line(snippet: &str) -> String {
+    snippet.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Handles `/search text` and `/search /regex/`, offering to open a session found.
+pub async fn command(args: &str) -> Result<String, String> {
+    let args = args.trim();
+    if args.is_empty() {
+        return Err(String::from("Usage: /search <text> or /search /<regex>/"));
+    }
+    let saved = sessions::load_saved();
+    let (hits, history) = match args
+        .strip_prefix('/')
+        .and_then(|rest| rest.strip_suffix('/'))
+    {
+        Some(pattern) => {
+            let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+            (
+                search_sessions(&regex, &saved),
+                search_history(|line| regex.is_match(line)),
+            )
+        }
+        None => {
+            let hits = search_index(args, &saved).map_err(|e| format!("Could not search: {e}"))?;
+            let words: Vec<String> = args.split_whitespace().map(str::to_lowercase).collect();
+            let history = search_history(|line| {
+                let line = line.to_lowercase();
+                words.iter().all(|word| line.contains(word))
+            });
+            (hits, history)
+        }
+    };
+
+    if !history.is_empty() {
+        eprint_bold("History\n");
+        for entry in &history {
+            eprintln!("  {entry}");
+        }
+    }
+    // Numbered by session, in the order they were first found.
+    let mut found: Vec<&str> = vec![];
+    for hit in &hits {
+        if !found.contains(&hit.session.as_str()) {
+            found.push(&hit.session);
+            let title = saved
+                .iter()
+                .find(|session| session.id == hit.session)
+                .map(sessions::title_of)
+                .unwrap_or_default();
+            eprint_bold(&format!("{}. {} {title}\n", found.len(), hit.session));
+        }
+        eprintln!("  {}: {}", hit.role, one_line(&hit.snippet));
+    }
+    if found.is_empty() {
+        return match history.len() {
+            0 => Err(format!("Nothing matches {args}")),
+            n => Ok(format!("{n} history entries match, but no saved session")),
+        };
+    }
+
+    let choice = ask::ask(&format!(
+        "Open which session? [1–{}, Enter for none] ",
+        found.len()
+    ))
+    .await;
+    match choice.as_deref().map(str::trim) {
+        None | Some("") => Ok(format!("{} sessions match", found.len())),
+        Some(choice) => {
+            let id = choice
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| found.get(n.wrapping_sub(1)))
+                .ok_or_else(|| format!("There is no session {choice} in the results"))?;
+            sessions::switch(id).await
+        }
+    }
+}
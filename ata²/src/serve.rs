@@ -0,0 +1,542 @@
+//! `ata2 --serve`: keeps one process, with its conversation and configuration, running for other
+//! tools to ask through, e.g. editors or tmux popups.
+//!
+//! It listens on a Unix socket, by default `ata2.sock` in the state directory, or on a loopback TCP
+//! address such as `127.0.0.1:7878`. The protocol is newline-delimited JSON. Each request has an
+//! `id`, copied to every reply to it, and a `method`:
+//!
+//! * `{"id": 1, "method": "prompt", "text": "…"}` asks the model, as if typed in the REPL; `model`
+//!   may be given too. The answer is streamed back as `{"id": 1, "type": "delta", "text": "…"}`
+//!   replies, followed by `{"id": 1, "type": "done", "answer": "…"}`.
+//! * `{"id": 2, "method": "history"}` replies `{"id": 2, "type": "history", "messages": […]}`.
+//! * `{"id": 3, "method": "clear"}` starts a new conversation and replies `done`.
+//! * `{"id": 0, "method": "auth", "token": "…"}` must be the first request over TCP, with the token
+//!   written to `ata2.token` in the state directory when the server started. It replies `done`.
+//!
+//! A request that fails is replied to with `{"id": …, "type": "error", "message": "…"}`. Requests
+//! are handled one at a time, in the order they arrive, as they share the conversation. The socket
+//! is only accessible to its owner, and so is the token; connections that start like HTTP, e.g.
+//! from a web page, are closed. Nobody is asked anything: what needs confirming, e.g. a command the
+//! model wants to run, is declined.
+//!
+//! `ata2 --connect` is a REPL asking through a server, rather than the API itself (see [`Client`]).
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{
+    AsyncBufReadExt as _, AsyncRead, AsyncWrite, AsyncWriteExt as _, BufReader, Lines,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Mutex};
+
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use async_openai::types::ChatCompletionRequestMessage;
+
+use crate::alts;
+use crate::ask;
+use crate::commands::looks_like_command;
+use crate::config;
+use crate::highlight;
+use crate::output::{eprint_and_flush, eprint_bold, OutputSink};
+use crate::prompt::{self, print_error, RequestOptions, CONVERSATION};
+use crate::readline::chat_completion_message_to_string;
+use crate::sessions;
+use crate::TokioResult;
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+enum Request {
+    Prompt {
+        text: String,
+        #[serde(default)]
+        model: Option<String>,
+    },
+    History,
+    Clear,
+    Auth {
+        token: String,
+    },
+}
+
+#[derive(Deserialize, Serialize)]
+struct Envelope {
+    #[serde(default)]
+    id: Value,
+    #[serde(flatten)]
+    request: Request,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+    Delta {
+        text: String,
+    },
+    Done {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        answer: Option<String>,
+    },
+    History {
+        messages: Vec<ChatCompletionRequestMessage>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Serialize)]
+struct Tagged<'a> {
+    id: &'a Value,
+    #[serde(flatten)]
+    reply: Reply,
+}
+
+/// A reply, as a client reads it.
+#[derive(Deserialize)]
+struct Incoming {
+    #[serde(default)]
+    id: Value,
+    #[serde(flatten)]
+    reply: Reply,
+}
+
+/// Where the replies to a client's requests go.
+#[derive(Clone)]
+struct Replies(mpsc::UnboundedSender<String>);
+
+impl Replies {
+    fn send(&self, id: &Value, reply: Reply) {
+        let mut line =
+            serde_json::to_string(&Tagged { id, reply }).expect("replies are always serializable");
+        line.push('\n');
+        // The client may be gone; its request is finished anyway.
+        let _ = self.0.send(line);
+    }
+}
+
+/// Streams the answer to a request to its client.
+struct DeltaSink<'a> {
+    replies: &'a Replies,
+    id: &'a Value,
+}
+
+impl OutputSink for DeltaSink<'_> {
+    fn write(&mut self, text: &str) {
+        if !text.is_empty() {
+            self.replies.send(
+                self.id,
+                Reply::Delta {
+                    text: text.to_string(),
+                },
+            );
+        }
+    }
+}
+
+/// The socket `--serve` listens on when not given one.
+pub fn default_socket() -> PathBuf {
+    config::get_state_dir().join("ata2.sock")
+}
+
+/// Where the token TCP clients authenticate with is written.
+fn token_file() -> PathBuf {
+    config::get_state_dir().join("ata2.token")
+}
+
+/// A new random token, written to `path` where only the user can read it.
+pub(crate) fn new_token(path: &Path) -> TokioResult<String> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).map_err(|e| format!("Could not make a token: {e}"))?;
+    let token: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    std::io::Write::write_all(&mut options.open(path)?, token.as_bytes())?;
+    Ok(token)
+}
+
+/// Whether `given` is `token`, compared in the same time whichever byte differs.
+pub(crate) fn is_token(token: &str, given: &str) -> bool {
+    token.len() == given.len()
+        && token
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |differences, (a, b)| differences | (a ^ b))
+            == 0
+}
+
+/// Whether `line` is the request line of an HTTP request, e.g. `POST / HTTP/1.1`, as a web page
+/// would send.
+pub(crate) fn looks_like_http(line: &str) -> bool {
+    line.split_whitespace()
+        .nth(2)
+        .map_or(false, |version| version.starts_with("HTTP/"))
+}
+
+async fn handle(request: Request, id: &Value, replies: &Replies) -> Result<Reply, String> {
+    match request {
+        Request::Prompt { text, model } => {
+            let mut options = RequestOptions::default();
+            if let Some(ref model) = model {
+                options.overrides.set_model(model);
+            }
+            let mut sink = DeltaSink { replies, id };
+            let deltas = prompt::request_with(&mut sink, Some(text), options)
+                .await
+                .map_err(|e| e.to_string())?;
+            let answer = prompt::response_text(deltas);
+            if answer.is_empty() {
+                return Err(String::from("There is no answer; see the server's log"));
+            }
+            Ok(Reply::Done {
+                answer: Some(answer),
+            })
+        }
+        Request::History => Ok(Reply::History {
+            messages: CONVERSATION.lock().await.clone(),
+        }),
+        Request::Clear => {
+            CONVERSATION.lock().await.clear();
+            alts::truncate(0);
+            sessions::truncate(0);
+            Ok(Reply::Done { answer: None })
+        }
+        // Checked as the request is read.
+        Request::Auth { .. } => Ok(Reply::Done { answer: None }),
+    }
+}
+
+/// Reads a client's requests and queues them, while its replies are written back. If there is a
+/// `token`, the first request must authenticate with it.
+async fn serve_client<S>(
+    stream: S,
+    queue: mpsc::Sender<(Envelope, Replies)>,
+    token: Option<Arc<String>>,
+) -> TokioResult<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    let replies = Replies(tx);
+    let forward = tokio::spawn(async move {
+        while let Some(line) = rx.recv().await {
+            if writer.write_all(line.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+    let mut lines = BufReader::new(reader).lines();
+    let mut first = true;
+    let mut authenticated = token.is_none();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if std::mem::take(&mut first) && looks_like_http(&line) {
+            debug!("Closing a connection speaking HTTP");
+            break;
+        }
+        match serde_json::from_str::<Envelope>(&line) {
+            Ok(Envelope {
+                id,
+                request: Request::Auth { token: ref given },
+            }) => {
+                if token
+                    .as_deref()
+                    .map_or(true, |token| is_token(token, given))
+                {
+                    authenticated = true;
+                    replies.send(&id, Reply::Done { answer: None });
+                } else {
+                    let message = String::from("Wrong token");
+                    replies.send(&id, Reply::Error { message });
+                    break;
+                }
+            }
+            Ok(envelope) if !authenticated => {
+                let message = format!(
+                    "The first request must be {{\"method\": \"auth\", \"token\": …}}, with the \
+                     token in {}",
+                    token_file().display()
+                );
+                replies.send(&envelope.id, Reply::Error { message });
+                break;
+            }
+            Ok(envelope) => {
+                if queue.send((envelope, replies.clone())).await.is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                // Answer with the id, if there is one to find.
+                let id = serde_json::from_str::<Value>(&line)
+                    .map(|v| v["id"].clone())
+                    .unwrap_or_default();
+                replies.send(
+                    &id,
+                    Reply::Error {
+                        message: format!("Invalid request: {e}"),
+                    },
+                );
+            }
+        }
+    }
+    // Let the replies to requests still queued be written.
+    drop(replies);
+    let _ = forward.await;
+    Ok(())
+}
+
+/// Serves requests on `addr`, a Unix socket path or a TCP address, until interrupted.
+pub async fn serve(addr: Option<&str>) -> TokioResult<()> {
+    // Nobody is at a REPL to answer questions, and one left waiting would hold up every client.
+    ask::set_unattended();
+    let (queue, mut requests) = mpsc::channel::<(Envelope, Replies)>(64);
+    tokio::spawn(async move {
+        while let Some((envelope, replies)) = requests.recv().await {
+            let reply = handle(envelope.request, &envelope.id, &replies)
+                .await
+                .unwrap_or_else(|message| Reply::Error { message });
+            replies.send(&envelope.id, reply);
+        }
+    });
+
+    if let Some(addr) = addr.and_then(|a| a.parse::<SocketAddr>().ok()) {
+        if !addr.ip().is_loopback() {
+            return Err(format!(
+                "Not serving on {addr}, which is not a loopback address: anyone reaching it could \
+                 use ata²"
+            )
+            .into());
+        }
+        let listener = TcpListener::bind(addr).await?;
+        let token = Arc::new(new_token(&token_file())?);
+        info!(
+            "Serving on {addr}, with the token in {}",
+            token_file().display()
+        );
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            debug!("Connection from {peer}");
+            tokio::spawn(serve_client(stream, queue.clone(), Some(token.clone())));
+        }
+    }
+    serve_unix(
+        addr.map(PathBuf::from).unwrap_or_else(default_socket),
+        queue,
+    )
+    .await
+}
+
+#[cfg(unix)]
+async fn serve_unix(path: PathBuf, queue: mpsc::Sender<(Envelope, Replies)>) -> TokioResult<()> {
+    use std::os::unix::fs::PermissionsExt as _;
+    use std::os::unix::net::UnixStream;
+    use tokio::net::UnixListener;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            return Err(format!("ata² is already serving on {}", path.display()).into());
+        }
+        // Left behind by a server that didn't exit cleanly.
+        fs::remove_file(&path)?;
+    }
+    let listener = UnixListener::bind(&path)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    info!("Serving on {}", path.display());
+    loop {
+        let (stream, _) = listener.accept().await?;
+        debug!("Connection on {}", path.display());
+        tokio::spawn(serve_client(stream, queue.clone(), None));
+    }
+}
+
+#[cfg(not(unix))]
+async fn serve_unix(path: PathBuf, _queue: mpsc::Sender<(Envelope, Replies)>) -> TokioResult<()> {
+    Err(format!(
+        "Unix sockets are not available here; serve on a TCP address such as 127.0.0.1:7878, not {}",
+        path.display()
+    )
+    .into())
+}
+
+type Reader = Lines<BufReader<Box<dyn AsyncRead + Send + Unpin>>>;
+type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// Connection to a server, used by the REPL instead of making requests itself.
+pub struct Client {
+    connection: Mutex<(Reader, Writer)>,
+    next_id: AtomicU64,
+}
+
+static CLIENT: OnceCell<Client> = OnceCell::new();
+
+/// The server the REPL is connected to, if any.
+pub fn client() -> Option<&'static Client> {
+    CLIENT.get()
+}
+
+impl Client {
+    /// Sends `request`, passing the deltas of the answer to `on_delta`, and returns the last reply.
+    async fn request(
+        &self,
+        request: Request,
+        mut on_delta: impl FnMut(String),
+    ) -> TokioResult<Reply> {
+        let id = Value::from(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let mut line = serde_json::to_string(&Envelope {
+            id: id.clone(),
+            request,
+        })?;
+        line.push('\n');
+        let mut connection = self.connection.lock().await;
+        let (lines, writer) = &mut *connection;
+        writer.write_all(line.as_bytes()).await?;
+        while let Some(line) = lines.next_line().await? {
+            let incoming: Incoming = serde_json::from_str(&line)?;
+            match incoming.reply {
+                _ if incoming.id != id => {}
+                Reply::Delta { text } => on_delta(text),
+                reply => return Ok(reply),
+            }
+        }
+        Err("The server closed the connection".into())
+    }
+
+    /// Asks the server to answer `line`, a prompt, `/clear` or `/history`.
+    pub async fn ask(&self, line: String) -> TokioResult<()> {
+        let trimmed = line.trim();
+        let request = match trimmed {
+            "/clear" => Request::Clear,
+            "/history" => Request::History,
+            _ if looks_like_command(trimmed.split_whitespace().next().unwrap_or("")) => {
+                print_error("Only prompts, /clear and /history are sent to the server");
+                return Ok(());
+            }
+            _ => Request::Prompt {
+                text: line,
+                model: None,
+            },
+        };
+        let mut sink = highlight::terminal_sink();
+        let mut answering = false;
+        let reply = self
+            .request(request, |text| {
+                if !std::mem::replace(&mut answering, true) {
+                    prompt::print_response_prompt();
+                }
+                sink.write(&text);
+            })
+            .await;
+        sink.flush();
+        match reply? {
+            Reply::Error { message } => {
+                print_error(&message);
+                return Ok(());
+            }
+            Reply::History { messages } => {
+                for message in &messages {
+                    eprint_bold(&format!("\n{}:\n", role(message)));
+                    eprintln!("{}", chat_completion_message_to_string(message));
+                }
+            }
+            Reply::Done { answer: None } => info!("The server started a new conversation"),
+            _ => eprint_and_flush("\n"),
+        }
+        prompt::finish_prompt();
+        Ok(())
+    }
+}
+
+fn role(message: &ChatCompletionRequestMessage) -> &'static str {
+    match message {
+        ChatCompletionRequestMessage::System(_) => "System",
+        ChatCompletionRequestMessage::User(_) => "Prompt",
+        ChatCompletionRequestMessage::Assistant(_) => "Response",
+        _ => "Tool",
+    }
+}
+
+/// Connects to the server at `addr`, as [`serve`] takes it. Afterwards, [`client`] returns the
+/// connection.
+pub async fn connect(addr: Option<&str>) -> TokioResult<()> {
+    let (reader, writer): (Box<dyn AsyncRead + Send + Unpin>, Writer) =
+        match addr.and_then(|a| a.parse::<SocketAddr>().ok()) {
+            Some(addr) => {
+                let (reader, writer) = tokio::io::split(TcpStream::connect(addr).await?);
+                (Box::new(reader), Box::new(writer))
+            }
+            None => connect_unix(addr.map(PathBuf::from).unwrap_or_else(default_socket)).await?,
+        };
+    let client = Client {
+        connection: Mutex::new((BufReader::new(reader).lines(), writer)),
+        next_id: AtomicU64::new(1),
+    };
+    if addr.map_or(false, |a| a.parse::<SocketAddr>().is_ok()) {
+        let path = token_file();
+        let token = fs::read_to_string(&path).map_err(|e| {
+            format!(
+                "Could not read the server's token from {}: {e}",
+                path.display()
+            )
+        })?;
+        let token = token.trim().to_string();
+        if let Reply::Error { message } = client.request(Request::Auth { token }, drop).await? {
+            return Err(message.into());
+        }
+    }
+    CLIENT
+        .set(client)
+        .map_err(|_| "already connected to a server")?;
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn connect_unix(path: PathBuf) -> TokioResult<(Box<dyn AsyncRead + Send + Unpin>, Writer)> {
+    let stream = tokio::net::UnixStream::connect(&path)
+        .await
+        .map_err(|e| format!("Could not connect to {}: {e}", path.display()))?;
+    let (reader, writer) = tokio::io::split(stream);
+    Ok((Box::new(reader), Box::new(writer)))
+}
+
+#[cfg(not(unix))]
+async fn connect_unix(path: PathBuf) -> TokioResult<(Box<dyn AsyncRead + Send + Unpin>, Writer)> {
+    Err(format!(
+        "Unix sockets are not available here; connect to a TCP address such as 127.0.0.1:7878, not {}",
+        path.display()
+    )
+    .into())
+}
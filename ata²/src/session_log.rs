@@ -0,0 +1,78 @@
+//! A plain-text log of every prompt and answer (`ui.session_log_dir`), to grep through.
+//!
+//! Each day has its own file, e.g. `2024-05-01.log`, to which entries are appended with the time,
+//! the session and the model. The log is kept apart from saved sessions: `/undo`, `/clear` and so
+//! on don't change it.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use chrono::Local;
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write as _};
+use std::path::Path;
+
+use crate::config::UiConfig;
+use crate::sessions;
+
+fn append(dir: &Path, heading: &str, text: &str) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let now = Local::now();
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(format!("{}.log", now.format("%Y-%m-%d"))))?;
+    // In one write, so that entries from several ata² at once aren't mixed up.
+    file.write_all(
+        format!(
+            "[{}] [{}] {heading}:\n{}\n\n",
+            now.format("%Y-%m-%d %H:%M:%S"),
+            sessions::current_id(),
+            text.trim_end()
+        )
+        .as_bytes(),
+    )
+}
+
+fn log(ui: &UiConfig, heading: &str, text: &str) {
+    let dir = match ui.session_log_dir {
+        Some(ref dir) => dir,
+        None => return,
+    };
+    if let Err(e) = append(dir, heading, text) {
+        warn!(
+            "Could not write to the session log in {}: {e}",
+            dir.display()
+        );
+    }
+}
+
+/// Logs `prompt`, as sent to `model`.
+pub fn prompt(ui: &UiConfig, model: &str, prompt: &str) {
+    log(ui, &format!("You, to {model}"), prompt);
+}
+
+/// Logs the `answer` of `model`, noting if it was stopped before the end.
+pub fn answer(ui: &UiConfig, model: &str, answer: &str, interrupted: bool) {
+    if interrupted {
+        log(ui, &format!("{model} (stopped)"), answer);
+    } else {
+        log(ui, model, answer);
+    }
+}
@@ -0,0 +1,729 @@
+//! Saved sessions, and cleaning up old sessions and history (`ata2 sessions gc`).
+//!
+//! Every conversation is saved as `<id>.json` in the `sessions` directory of the data directory
+//! when the REPL exits. Sessions older than `ui.archive_sessions_after_days` are gzipped into
+//! `sessions/archive`. History entries older than `ui.history_retention_days` are removed from the
+//! history file; as rustyline doesn't record when entries were added, that is tracked in
+//! `<history file>.times`.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::types::ChatCompletionRequestMessage;
+use chrono::{DateTime, Local, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rustyline::history::History;
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::alts::{self, Alternatives};
+use crate::backend;
+use crate::config::UiConfig;
+use crate::conversation;
+use crate::forecast::Forecast;
+use crate::highlight;
+use crate::limits;
+use crate::output::eprint_bold;
+use crate::params::{self, SESSION_OVERRIDES};
+use crate::prompt::{self, CONVERSATION};
+use crate::readline::{chat_completion_message_role, chat_completion_message_to_string};
+use crate::search;
+use crate::Config;
+use crate::TokioResult;
+use crate::CONFIGURATION;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Longest title made up from a session's first prompt.
+const TITLE_LEN: usize = 60;
+/// What `ui.generate_titles` asks for, given the first exchange.
+const TITLE_INSTRUCTION: &str = "Write a title of at most five words for this conversation. \
+    Reply with the title only, without quotes or a full stop.";
+/// The most tokens a generated title may take, and of the conversation it is generated from.
+const TITLE_TOKENS: u16 = 24;
+const TITLE_SOURCE_CHARS: usize = 4000;
+
+/// What is known about this process's session besides the conversation itself.
+struct Current {
+    id: String,
+    title: Option<String>,
+    created: DateTime<Utc>,
+    model: Option<String>,
+    models: BTreeMap<usize, String>,
+    truncated: BTreeSet<usize>,
+    tags: BTreeSet<String>,
+    checkpoints: Vec<Checkpoint>,
+}
+
+lazy_static! {
+    static ref CURRENT: Mutex<Current> = Mutex::new(Current {
+        id: Local::now().format("%Y%m%d-%H%M%S").to_string(),
+        title: None,
+        created: Utc::now(),
+        model: None,
+        models: BTreeMap::new(),
+        truncated: BTreeSet::new(),
+        tags: BTreeSet::new(),
+        checkpoints: vec![],
+    });
+}
+
+/// The conversation as it was at some point, saved with `/checkpoint`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Checkpoint {
+    pub name: String,
+    pub taken: DateTime<Utc>,
+    pub messages: Vec<ChatCompletionRequestMessage>,
+    pub models: BTreeMap<usize, String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Session {
+    pub id: String,
+    /// Made up from the first prompt when the session is first saved.
+    #[serde(default)]
+    pub title: Option<String>,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    /// The model the session was created with.
+    #[serde(default)]
+    pub model: Option<String>,
+    pub messages: Vec<ChatCompletionRequestMessage>,
+    /// The model that produced each assistant message, by index in `messages`.
+    #[serde(default)]
+    pub models: BTreeMap<usize, String>,
+    /// The indices in `messages` of answers that were interrupted, which `/continue` can resume.
+    #[serde(default)]
+    pub truncated: BTreeSet<usize>,
+    /// Answers regenerated with `/retry`.
+    #[serde(default)]
+    pub alternatives: Vec<Alternatives>,
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    #[serde(default)]
+    pub checkpoints: Vec<Checkpoint>,
+}
+
+pub fn sessions_dir() -> PathBuf {
+    CONFIGURATION.load().paths.sessions()
+}
+
+fn archive_dir() -> PathBuf {
+    sessions_dir().join("archive")
+}
+
+/// Saves the current conversation, if there is one, as this process's session.
+pub async fn save_current() -> TokioResult<()> {
+    let messages = CONVERSATION.lock().await.clone();
+    if messages.is_empty() {
+        return Ok(());
+    }
+    let session = {
+        let mut current = CURRENT.lock().unwrap();
+        if current.title.is_none() {
+            current.title = auto_title(&messages);
+        }
+        Session {
+            id: current.id.clone(),
+            title: current.title.clone(),
+            created: current.created,
+            updated: Utc::now(),
+            model: current.model.clone(),
+            messages,
+            models: current.models.clone(),
+            truncated: current.truncated.clone(),
+            alternatives: alts::all(),
+            tags: current.tags.clone(),
+            checkpoints: current.checkpoints.clone(),
+        }
+    };
+    let path = write(&session)?;
+    debug!("Saved session to {}", path.to_string_lossy());
+    Ok(())
+}
+
+/// Saves `session` in the sessions directory, under its ID.
+pub fn write(session: &Session) -> TokioResult<PathBuf> {
+    let dir = sessions_dir();
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.json", session.id));
+    fs::write(&path, serde_json::to_string(session)?)?;
+    search::index(session);
+    Ok(path)
+}
+
+/// The ID of this process's session.
+pub fn current_id() -> String {
+    CURRENT.lock().unwrap().id.clone()
+}
+
+/// Records that the message at `index` in the conversation was produced by `model`.
+pub fn record_model(index: usize, model: &str) {
+    let mut current = CURRENT.lock().unwrap();
+    current.models.insert(index, model.to_string());
+    current.model.get_or_insert_with(|| model.to_string());
+}
+
+/// The model that produced the message at `index` in the conversation, if known.
+pub fn model_at(index: usize) -> Option<String> {
+    CURRENT.lock().unwrap().models.get(&index).cloned()
+}
+
+/// Forgets the models of messages from index `len` on, e.g. when the conversation is cleared.
+pub fn truncate(len: usize) {
+    let mut current = CURRENT.lock().unwrap();
+    current.models.retain(|&i, _| i < len);
+    current.truncated.retain(|&i| i < len);
+}
+
+/// Records whether the answer at `index` in the conversation was interrupted.
+pub fn record_truncated(index: usize, truncated: bool) {
+    let mut current = CURRENT.lock().unwrap();
+    if truncated {
+        current.truncated.insert(index);
+    } else {
+        current.truncated.remove(&index);
+    }
+}
+
+/// Continues `session` instead of starting a new one: it will be saved under its own ID.
+pub fn resume(session: &Session) {
+    let mut current = CURRENT.lock().unwrap();
+    current.id = session.id.clone();
+    current.title = session.title.clone();
+    current.created = session.created;
+    current.model = session.model.clone();
+    current.models = session.models.clone();
+    current.truncated = session.truncated.clone();
+    current.tags = session.tags.clone();
+    current.checkpoints = session.checkpoints.clone();
+    alts::restore(session.alternatives.clone());
+}
+
+/// A title for a conversation: the start of its first prompt's first line.
+fn auto_title(messages: &[ChatCompletionRequestMessage]) -> Option<String> {
+    let prompt = messages
+        .iter()
+        .find(|m| matches!(m, ChatCompletionRequestMessage::User(_)))
+        .map(chat_completion_message_to_string)?;
+    let line = prompt.lines().find(|line| !line.trim().is_empty())?;
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let mut title = String::new();
+    for word in words {
+        if title.chars().count() + word.chars().count() + 1 > TITLE_LEN {
+            if title.is_empty() {
+                title = word.chars().take(TITLE_LEN).collect();
+            }
+            title.push('…');
+            break;
+        }
+        if !title.is_empty() {
+            title.push(' ');
+        }
+        title.push_str(word);
+    }
+    Some(title)
+}
+
+/// The title of this process's session, if it has one yet.
+pub fn current_title() -> Option<String> {
+    CURRENT.lock().unwrap().title.clone()
+}
+
+/// After the first answer, with `ui.generate_titles`, asks the model (or `ui.title_model`) for a
+/// title for the session. The request is sent in the background, once no other is being answered.
+pub fn generate_title(config: &Config, messages: &[ChatCompletionRequestMessage]) {
+    let answers = messages
+        .iter()
+        .filter(|m| matches!(m, ChatCompletionRequestMessage::Assistant(_)))
+        .count();
+    if !config.ui.generate_titles || answers != 1 {
+        return;
+    }
+    let mut config = config.clone();
+    if let Some(ref model) = config.ui.title_model {
+        config.model = model.clone();
+    }
+    let exchange = messages
+        .iter()
+        .filter(|m| !matches!(m, ChatCompletionRequestMessage::System(_)))
+        .map(|m| {
+            format!(
+                "{}: {}",
+                chat_completion_message_role(m),
+                chat_completion_message_to_string(m)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+        .chars()
+        .take(TITLE_SOURCE_CHARS)
+        .collect();
+    let id = current_id();
+    tokio::spawn(async move {
+        limits::when_idle().await;
+        match conversation::complete(&config, TITLE_INSTRUCTION, exchange, TITLE_TOKENS).await {
+            Ok(title) => {
+                let title = title.trim().trim_matches(|c| c == '"' || c == '.').trim();
+                let mut current = CURRENT.lock().unwrap();
+                // Unless another session was switched to meanwhile.
+                if current.id == id && !title.is_empty() {
+                    debug!("Generated the title {title}");
+                    current.title = Some(title.to_string());
+                }
+            }
+            Err(e) => debug!("Could not generate a title: {e}"),
+        }
+    });
+}
+
+pub fn title_of(session: &Session) -> String {
+    session
+        .title
+        .clone()
+        .or_else(|| auto_title(&session.messages))
+        .unwrap_or_default()
+}
+
+/// The saved session `id`, or the only one whose ID starts with `id`.
+fn find(id: &str) -> Result<Session, String> {
+    let mut matching: Vec<Session> = load_saved()
+        .into_iter()
+        .filter(|session| session.id.starts_with(id))
+        .collect();
+    if let Some(exact) = matching.iter().position(|session| session.id == id) {
+        return Ok(matching.swap_remove(exact));
+    }
+    match matching.len() {
+        0 => Err(format!("There is no saved session {id}")),
+        1 => Ok(matching.remove(0)),
+        n => Err(format!("{n} sessions start with {id}")),
+    }
+}
+
+/// Continues the saved session `session`, conversation included.
+async fn open(session: Session) {
+    resume(&session);
+    let mut conversation = CONVERSATION.lock().await;
+    conversation.clear();
+    conversation.extend(session.messages);
+}
+
+/// `--resume ID`: continues the saved session `id` (see [`find`]).
+pub async fn resume_saved(id: &str) -> Result<(), String> {
+    open(find(id)?).await;
+    Ok(())
+}
+
+/// `/resume [ID]`: saves the current session and continues session `id` instead, by default the
+/// last one updated.
+pub async fn switch(id: &str) -> Result<String, String> {
+    let current_id = current_id();
+    let session = if id.is_empty() {
+        load_saved()
+            .into_iter()
+            .filter(|session| session.id != current_id)
+            .max_by_key(|session| session.updated)
+            .ok_or_else(|| String::from("There is no other saved session"))?
+    } else {
+        find(id)?
+    };
+    if session.id == current_id {
+        return Err(format!("Session {current_id} is the current one"));
+    }
+    save_current()
+        .await
+        .map_err(|e| format!("Could not save the current session: {e}"))?;
+    let msg = format!(
+        "Resumed session {} ({}), {} messages",
+        session.id,
+        title_of(&session),
+        session.messages.len()
+    );
+    open(session).await;
+    Ok(msg)
+}
+
+/// `ata2 sessions delete` and `/sessions delete`: deletes the saved session `id` (see [`find`]).
+pub fn delete(id: &str) -> Result<String, String> {
+    let session = find(id)?;
+    if session.id == current_id() {
+        return Err(format!("Session {} is the current one", session.id));
+    }
+    let path = sessions_dir().join(format!("{}.json", session.id));
+    fs::remove_file(&path).map_err(|e| format!("Could not delete {}: {e}", path.display()))?;
+    search::forget(&session.id);
+    Ok(format!(
+        "Deleted session {} ({})",
+        session.id,
+        title_of(&session)
+    ))
+}
+
+/// `/checkpoint [name]`: saves the conversation as it is, replacing any checkpoint of the same
+/// name. Unnamed checkpoints are numbered.
+pub async fn checkpoint(name: &str) -> String {
+    let messages = CONVERSATION.lock().await.clone();
+    let mut current = CURRENT.lock().unwrap();
+    let taken = |name: &str| current.checkpoints.iter().any(|c| c.name == name);
+    let name = match name {
+        "" => (1..)
+            .map(|n: usize| n.to_string())
+            .find(|n| !taken(n))
+            .unwrap(),
+        name => name.to_string(),
+    };
+    current.checkpoints.retain(|c| c.name != name);
+    let len = messages.len();
+    let models = current.models.clone();
+    current.checkpoints.push(Checkpoint {
+        name: name.clone(),
+        taken: Utc::now(),
+        messages,
+        models,
+    });
+    format!("Checkpoint {name}: {len} messages. /rollback {name} comes back to it.")
+}
+
+/// `/rollback [name]`: brings the conversation back to checkpoint `name`, or to the last one.
+/// The checkpoint is kept, to come back to again.
+pub async fn rollback(name: &str) -> Result<String, String> {
+    let checkpoint = {
+        let current = CURRENT.lock().unwrap();
+        let found = match name {
+            "" => current.checkpoints.last(),
+            name => current.checkpoints.iter().find(|c| c.name == name),
+        };
+        match found {
+            Some(checkpoint) => checkpoint.clone(),
+            None if current.checkpoints.is_empty() => {
+                return Err(String::from("There are no checkpoints; see /checkpoint"))
+            }
+            None => {
+                let names: Vec<_> = current
+                    .checkpoints
+                    .iter()
+                    .map(|c| c.name.as_str())
+                    .collect();
+                return Err(format!(
+                    "No checkpoint named {name}. Checkpoints: {}",
+                    names.join(", ")
+                ));
+            }
+        }
+    };
+    let len = checkpoint.messages.len();
+    {
+        let mut conversation = CONVERSATION.lock().await;
+        conversation.clear();
+        conversation.extend(checkpoint.messages);
+    }
+    alts::truncate(len);
+    CURRENT.lock().unwrap().models = checkpoint.models;
+    Ok(format!(
+        "Rolled back to checkpoint {} ({len} messages)",
+        checkpoint.name
+    ))
+}
+
+/// Applies `changes`, e.g. `rust,work,-old`, to `tags`: each tag is added, or removed if it
+/// starts with `-`.
+fn edit_tags(tags: &mut BTreeSet<String>, changes: &str) {
+    for change in changes
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|c| !c.is_empty())
+    {
+        match change.strip_prefix('-') {
+            Some(tag) => tags.remove(tag),
+            None => tags.insert(change.to_string()),
+        };
+    }
+}
+
+fn fmt_tags(tags: &BTreeSet<String>) -> String {
+    tags.iter().cloned().collect::<Vec<_>>().join(",")
+}
+
+/// `/tag [tags]`: changes the tags of the current session (see [`edit_tags`]), returning them.
+pub fn tag_current(changes: &str) -> String {
+    let mut current = CURRENT.lock().unwrap();
+    edit_tags(&mut current.tags, changes);
+    if current.tags.is_empty() {
+        String::from("This session has no tags")
+    } else {
+        format!("Tags: {}", fmt_tags(&current.tags))
+    }
+}
+
+/// `ata2 sessions tag`: changes the tags of the saved session `id`.
+pub fn tag_saved(id: &str, changes: &str) -> TokioResult<()> {
+    let path = sessions_dir().join(format!("{id}.json"));
+    let mut session: Session = serde_json::from_str(&fs::read_to_string(&path)?)?;
+    edit_tags(&mut session.tags, changes);
+    fs::write(&path, serde_json::to_string(&session)?)?;
+    println!("{}", fmt_tags(&session.tags));
+    Ok(())
+}
+
+/// `ata2 sessions replay`: asks the prompts of session `id` again, in order, in a new
+/// conversation, once the estimated cost is confirmed.
+pub async fn replay(id: &str, yes: bool) -> TokioResult<()> {
+    let path = sessions_dir().join(format!("{id}.json"));
+    let session: Session = serde_json::from_str(&fs::read_to_string(&path)?)?;
+    let config = params::effective_config(&CONFIGURATION.load(), &Default::default())?;
+    if !Forecast::replay(&config, &session.messages).confirm(&config, yes) {
+        return Ok(());
+    }
+    for message in &session.messages {
+        if !matches!(message, ChatCompletionRequestMessage::User(_)) {
+            continue;
+        }
+        let prompt = chat_completion_message_to_string(message);
+        eprint_bold("\nPrompt:\n");
+        eprintln!("{prompt}");
+        prompt::request_with(
+            &mut *highlight::terminal_sink(),
+            Some(prompt),
+            Default::default(),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// The saved sessions that have all of `tags`, oldest first.
+fn sorted(tags: &[String]) -> Vec<Session> {
+    let mut sessions = load_saved();
+    sessions.retain(|session| tags.iter().all(|tag| session.tags.contains(tag)));
+    sessions.sort_by_key(|session| session.updated);
+    sessions
+}
+
+/// One line of a list of sessions: ID, last update, model, tags and title.
+fn fmt_line(session: &Session) -> String {
+    format!(
+        "{}\t{}\t{}\t{}\t{}",
+        session.id,
+        session
+            .updated
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M"),
+        session.model.as_deref().unwrap_or("-"),
+        fmt_tags(&session.tags),
+        title_of(session),
+    )
+}
+
+/// `ata2 sessions list` and `--list-sessions`: prints the saved sessions that have all of `tags`,
+/// oldest first.
+pub fn list(tags: &[String]) {
+    for session in sorted(tags) {
+        println!("{}", fmt_line(&session));
+    }
+}
+
+/// `/sessions`: lists the saved sessions, marking the current one.
+pub fn print_list() {
+    let current_id = current_id();
+    let sessions = sorted(&[]);
+    if sessions.is_empty() {
+        info!("There are no saved sessions yet");
+    }
+    for session in sessions {
+        let mark = if session.id == current_id { '*' } else { ' ' };
+        eprintln!("{mark} {}", fmt_line(&session));
+    }
+}
+
+/// When resuming a session created with another model than the configured one, keeps using that
+/// model, unless it no longer exists. That is checked in the background, so as not to hold up the
+/// prompt.
+pub fn restore_model(config: &Config) {
+    let model = match CURRENT.lock().unwrap().model.clone() {
+        Some(model) if model != config.model => model,
+        _ => return,
+    };
+    info!("Using {model}, the model this session was created with");
+    SESSION_OVERRIDES.lock().unwrap().set_model(&model);
+    let config = config.clone();
+    tokio::spawn(async move {
+        let listed = match backend::primary(&config) {
+            Ok(backend) => backend.models().await,
+            Err(e) => Err(e),
+        };
+        match listed {
+            Ok(ids) if ids.contains(&model) => {}
+            Ok(_) => {
+                // Unless another one was chosen meanwhile.
+                if params::current_model() == model {
+                    SESSION_OVERRIDES.lock().unwrap().unset("model").ok();
+                }
+                warn!(
+                    "This session was created with {model}, which is no longer available; using {}",
+                    config.model
+                );
+            }
+            Err(e) => warn!("Could not check whether {model} is still available: {e}"),
+        }
+    });
+}
+
+fn is_older_than(path: &Path, age: Duration) -> io::Result<bool> {
+    let modified = fs::metadata(path)?.modified()?;
+    Ok(SystemTime::now()
+        .duration_since(modified)
+        .map(|elapsed| elapsed > age)
+        .unwrap_or(false))
+}
+
+/// The saved sessions that aren't archived. Unreadable ones are skipped.
+pub fn load_saved() -> Vec<Session> {
+    let entries = match fs::read_dir(sessions_dir()) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map(|e| e == "json").unwrap_or(false))
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .filter_map(|contents| serde_json::from_str(&contents).ok())
+        .collect()
+}
+
+/// Gzips `path` into the archive directory and removes it.
+fn archive(path: &Path) -> io::Result<()> {
+    let dir = archive_dir();
+    fs::create_dir_all(&dir)?;
+    let name = path.file_name().unwrap().to_string_lossy();
+    let target = dir.join(format!("{name}.gz"));
+    let mut encoder = GzEncoder::new(File::create(&target)?, Compression::default());
+    io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(path)
+}
+
+/// Archives sessions older than `days` days, returning how many were archived.
+pub fn archive_old_sessions(days: u64) -> io::Result<usize> {
+    let entries = match fs::read_dir(sessions_dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut archived = 0;
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().map(|e| e == "json").unwrap_or(false)
+            && is_older_than(&path, DAY * days as u32)?
+        {
+            archive(&path)?;
+            archived += 1;
+        }
+    }
+    Ok(archived)
+}
+
+/// The file holding when each history entry was added.
+pub fn history_times_path(history_file: &Path) -> PathBuf {
+    let mut path = history_file.as_os_str().to_owned();
+    path.push(".times");
+    path.into()
+}
+
+fn load_history_times(history_file: &Path) -> HashMap<String, i64> {
+    fs::read_to_string(history_times_path(history_file))
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn save_history_times(history_file: &Path, times: &HashMap<String, i64>) -> io::Result<()> {
+    fs::write(
+        history_times_path(history_file),
+        serde_json::to_string(times).map_err(io::Error::from)?,
+    )
+}
+
+/// Records that `entry` was just added to the history, if history entries expire.
+pub fn touch_history_entry(ui: &UiConfig, entry: &str) {
+    if ui.history_retention_days == 0 {
+        return;
+    }
+    let mut times = load_history_times(&ui.history_file);
+    times.insert(entry.to_string(), Utc::now().timestamp());
+    if let Err(e) = save_history_times(&ui.history_file, &times) {
+        warn!("Could not record history timestamp: {e}");
+    }
+}
+
+/// Removes history entries older than `days` days, returning how many were removed. Entries
+/// without a recorded time (from before expiry was enabled) are considered new.
+pub fn purge_old_history(history_file: &Path, days: u64) -> TokioResult<usize> {
+    if !history_file.exists() {
+        return Ok(0);
+    }
+    let mut history = History::new();
+    history.load(history_file)?;
+    let mut times = load_history_times(history_file);
+    let now = Utc::now().timestamp();
+    let cutoff = now - (days * DAY.as_secs()) as i64;
+    let entries = history.iter().cloned().collect::<Vec<_>>();
+    let kept = entries
+        .into_iter()
+        .filter(|entry| *times.entry(entry.clone()).or_insert(now) >= cutoff)
+        .collect::<Vec<_>>();
+    let purged = history.len() - kept.len();
+    history.clear();
+    for entry in &kept {
+        history.add(entry.as_str());
+    }
+    history.save(history_file)?;
+    times.retain(|entry, _| kept.contains(entry));
+    save_history_times(history_file, &times)?;
+    Ok(purged)
+}
+
+/// Applies the configured archiving and history retention. `archive_days` and `history_days`
+/// override the configuration.
+pub fn gc(ui: &UiConfig, archive_days: Option<u64>, history_days: Option<u64>) -> TokioResult<()> {
+    let archive_days = archive_days.unwrap_or(ui.archive_sessions_after_days);
+    if archive_days > 0 {
+        let archived = archive_old_sessions(archive_days)?;
+        if archived > 0 {
+            info!(
+                "Archived {archived} sessions older than {archive_days} days to {}",
+                archive_dir().to_string_lossy()
+            );
+        }
+    }
+    let history_days = history_days.unwrap_or(ui.history_retention_days);
+    if history_days > 0 {
+        let purged = purge_old_history(&ui.history_file, history_days)?;
+        if purged > 0 {
+            info!("Removed {purged} history entries older than {history_days} days");
+        }
+    }
+    Ok(())
+}
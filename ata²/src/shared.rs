@@ -0,0 +1,303 @@
+//! Collaborative shared sessions (experimental).
+//!
+//! `ata2 serve-session --listen 0.0.0.0:7777` hosts a conversation, and `ata2 join-session
+//! host:7777 --token …` attaches a REPL to it. Everyone sees each other's prompts and the
+//! responses as they stream in. Prompts are answered one at a time, in the order they arrive, so
+//! participants take turns. Only the host needs an API key; requests are made with the host's
+//! configuration, and what would need the host's confirmation, e.g. a command the model wants to
+//! run, is declined.
+//!
+//! The protocol is newline-delimited JSON [`Event`]s over TCP. Participants must give the token the
+//! host prints, also written to `session.token` in its state directory, in their first event.
+//! There is no encryption, so only listen on networks you trust (or tunnel through SSH).
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::types::ChatCompletionRequestMessage;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc};
+
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::ask;
+use crate::config;
+use crate::output::{eprint_bold, OutputSink, StdoutSink};
+use crate::prompt::{self, CONVERSATION};
+use crate::serve;
+use crate::TokioResult;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// Sent by a client right after connecting, with the host's token.
+    Hello {
+        name: String,
+        #[serde(default)]
+        token: String,
+    },
+    /// The conversation so far, sent to a client when it joins.
+    History {
+        messages: Vec<ChatCompletionRequestMessage>,
+    },
+    Joined {
+        name: String,
+    },
+    Left {
+        name: String,
+    },
+    /// A participant's prompt. Sent by clients, and broadcast by the host when it is its turn.
+    Prompt {
+        from: String,
+        text: String,
+    },
+    /// Part of the response to the current prompt.
+    Delta {
+        text: String,
+    },
+    /// The response to the current prompt is complete.
+    Done,
+    Error {
+        message: String,
+    },
+}
+
+impl Event {
+    fn to_line(&self) -> String {
+        let mut line = serde_json::to_string(self).expect("events are always serializable");
+        line.push('\n');
+        line
+    }
+}
+
+/// Where the token participants join with is written.
+fn token_file() -> PathBuf {
+    config::get_state_dir().join("session.token")
+}
+
+/// Broadcasts the response to every participant.
+struct BroadcastSink(broadcast::Sender<Event>);
+
+impl OutputSink for BroadcastSink {
+    fn write(&mut self, text: &str) {
+        let _ = self.0.send(Event::Delta {
+            text: text.to_string(),
+        });
+    }
+}
+
+/// Hosts a shared session on `listen` until interrupted.
+pub async fn serve(listen: &str) -> TokioResult<()> {
+    // The host's REPL isn't running to answer questions, and one left waiting would hold up
+    // everyone's turn.
+    ask::set_unattended();
+    let listener = TcpListener::bind(listen).await?;
+    let token = Arc::new(serve::new_token(&token_file())?);
+    info!("Hosting shared session on {listen}");
+    eprintln!("Participants join with: ata2 join-session <this host>:<port> --token {token}");
+    let (events, _) = broadcast::channel::<Event>(1024);
+    let (prompts, mut queue) = mpsc::channel::<(String, String)>(64);
+
+    let turn_events = events.clone();
+    tokio::spawn(async move {
+        while let Some((from, text)) = queue.recv().await {
+            info!("{from} asks: {text}");
+            let _ = turn_events.send(Event::Prompt {
+                from,
+                text: text.clone(),
+            });
+            let mut sink = BroadcastSink(turn_events.clone());
+            if let Err(e) = prompt::request_with(&mut sink, Some(text), Default::default()).await {
+                let _ = turn_events.send(Event::Error {
+                    message: e.to_string(),
+                });
+            }
+            let _ = turn_events.send(Event::Done);
+        }
+    });
+
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        info!("Connection from {addr}");
+        let events = events.clone();
+        let prompts = prompts.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_participant(stream, events, prompts, &token).await {
+                warn!("Participant {addr} disconnected: {e}");
+            }
+        });
+    }
+}
+
+async fn serve_participant(
+    stream: TcpStream,
+    events: broadcast::Sender<Event>,
+    prompts: mpsc::Sender<(String, String)>,
+    token: &str,
+) -> TokioResult<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let name = match lines.next_line().await? {
+        Some(line) if serve::looks_like_http(&line) => return Err("spoke HTTP".into()),
+        Some(line) => match serde_json::from_str::<Event>(&line)? {
+            Event::Hello { name, token: given } if serve::is_token(token, &given) => name,
+            Event::Hello { .. } => {
+                let refusal = Event::Error {
+                    message: String::from("Wrong token; ask the host for theirs"),
+                };
+                writer.write_all(refusal.to_line().as_bytes()).await?;
+                return Err("gave the wrong token".into());
+            }
+            _ => return Err("expected a hello".into()),
+        },
+        None => return Ok(()),
+    };
+
+    let mut subscription = events.subscribe();
+    let history = Event::History {
+        messages: CONVERSATION.lock().await.clone(),
+    };
+    writer.write_all(history.to_line().as_bytes()).await?;
+    let _ = events.send(Event::Joined { name: name.clone() });
+
+    let forward = tokio::spawn(async move {
+        loop {
+            match subscription.recv().await {
+                Ok(event) => {
+                    if writer.write_all(event.to_line().as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("Participant fell behind by {n} events");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
+    while let Some(line) = lines.next_line().await? {
+        match serde_json::from_str::<Event>(&line) {
+            Ok(Event::Prompt { text, .. }) => prompts.send((name.clone(), text)).await?,
+            Ok(other) => debug!("Ignoring {other:?} from {name}"),
+            Err(e) => warn!("Malformed event from {name}: {e}"),
+        }
+    }
+    forward.abort();
+    let _ = events.send(Event::Left { name });
+    Ok(())
+}
+
+/// Connection to a shared session, used by the REPL instead of making requests itself.
+pub struct Client {
+    name: String,
+    outgoing: mpsc::Sender<String>,
+}
+
+static CLIENT: OnceCell<Client> = OnceCell::new();
+
+/// The shared session the REPL has joined, if any.
+pub fn client() -> Option<&'static Client> {
+    CLIENT.get()
+}
+
+impl Client {
+    /// Asks the host to answer `text`. The answer arrives as events, like everyone else's.
+    pub async fn send(&self, text: String) -> TokioResult<()> {
+        let event = Event::Prompt {
+            from: self.name.clone(),
+            text,
+        };
+        self.outgoing.send(event.to_line()).await?;
+        Ok(())
+    }
+}
+
+fn print_event(event: Event, own_name: &str) {
+    match event {
+        Event::History { messages } => {
+            eprintln!("Joined a conversation with {} messages.", messages.len());
+        }
+        Event::Joined { name } => eprint_bold(&format!("\n{name} joined.\n")),
+        Event::Left { name } => eprint_bold(&format!("\n{name} left.\n")),
+        Event::Prompt { from, text } => {
+            if from != own_name {
+                eprint_bold(&format!("\n{from}:\n"));
+                eprintln!("{text}");
+            }
+            eprint_bold("\nResponse:\n");
+        }
+        Event::Delta { text } => {
+            StdoutSink.write(&text);
+        }
+        Event::Done => {
+            eprintln!();
+            prompt::print_prompt();
+        }
+        Event::Error { message } => error!("{message}"),
+        Event::Hello { .. } => {}
+    }
+}
+
+/// Connects to the shared session at `addr` with the host's `token`. Afterwards, [`client`]
+/// returns the connection.
+pub async fn join(addr: &str, name: Option<String>, token: &str) -> TokioResult<()> {
+    let name = name
+        .or_else(|| env::var("USER").ok())
+        .unwrap_or_else(|| "anonymous".to_string());
+    let stream = TcpStream::connect(addr).await?;
+    let (reader, mut writer) = stream.into_split();
+    let (outgoing, mut queue) = mpsc::channel::<String>(16);
+
+    let hello = Event::Hello {
+        name: name.clone(),
+        token: token.to_string(),
+    };
+    writer.write_all(hello.to_line().as_bytes()).await?;
+    tokio::spawn(async move {
+        while let Some(line) = queue.recv().await {
+            if writer.write_all(line.as_bytes()).await.is_err() {
+                error!("Lost connection to the shared session");
+                break;
+            }
+        }
+    });
+
+    let own_name = name.clone();
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            match serde_json::from_str::<Event>(&line) {
+                Ok(event) => print_event(event, &own_name),
+                Err(e) => warn!("Malformed event from host: {e}"),
+            }
+        }
+        error!("The host closed the shared session");
+    });
+
+    CLIENT
+        .set(Client { name, outgoing })
+        .map_err(|_| "already joined a shared session")?;
+    Ok(())
+}
@@ -0,0 +1,116 @@
+//! A spinner on stderr while waiting for an answer to start, with the time taken so far, so that a
+//! slow model doesn't look like ata² hanging. It is cleared as soon as anything else is printed:
+//! the answer, its reasoning, or an error.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::output::eprint_and_flush;
+
+const FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+const INTERVAL: Duration = Duration::from_millis(100);
+
+/// The spinner running, if any.
+struct Spinning {
+    id: u64,
+    /// Whether a frame is on the screen, to be cleared.
+    drawn: bool,
+}
+
+lazy_static! {
+    static ref SPINNING: Mutex<Option<Spinning>> = Mutex::new(None);
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+/// Whether the line editor is reading a line (e.g. the next prompt, typed ahead), which frames
+/// would be drawn over.
+static EDITING: AtomicBool = AtomicBool::new(false);
+
+/// Stops the spinner when dropped.
+pub struct Spinner;
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        stop();
+    }
+}
+
+/// Shows the spinner while waiting for `model`, if stderr is a terminal, until [`stop`] is called
+/// or the returned guard is dropped.
+pub fn start(model: &str) -> Spinner {
+    if !atty::is(atty::Stream::Stderr) {
+        return Spinner;
+    }
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    *SPINNING.lock().unwrap() = Some(Spinning { id, drawn: false });
+    let model = model.to_string();
+    let started = Instant::now();
+    tokio::spawn(async move {
+        for frame in FRAMES.iter().cycle() {
+            tokio::time::sleep(INTERVAL).await;
+            // Drawn with the lock held, so that a frame can't follow the clearing.
+            let mut spinning = SPINNING.lock().unwrap();
+            match spinning.as_mut() {
+                Some(_) if EDITING.load(Ordering::Relaxed) => continue,
+                Some(spinning) if spinning.id == id => spinning.drawn = true,
+                _ => break,
+            }
+            let elapsed = started.elapsed().as_secs_f64();
+            eprint_and_flush(&format!(
+                "\r\x1b[K{frame} Waiting for {model}… {elapsed:.1}s"
+            ));
+        }
+    });
+    Spinner
+}
+
+/// Stops the spinner, clearing it.
+pub fn stop() {
+    if let Some(spinning) = SPINNING.lock().unwrap().take() {
+        if spinning.drawn {
+            eprint_and_flush("\r\x1b[K");
+        }
+    }
+}
+
+/// Tells the spinner whether the line editor is reading a line. While it is, no frames are drawn,
+/// and one showing is cleared first.
+pub fn editing(reading: bool) {
+    let mut spinning = SPINNING.lock().unwrap();
+    EDITING.store(reading, Ordering::Relaxed);
+    if let Some(spinning) = spinning
+        .as_mut()
+        .filter(|spinning| reading && spinning.drawn)
+    {
+        eprint_and_flush("\r\x1b[K");
+        spinning.drawn = false;
+    }
+}
+
+/// Whether the spinner is showing, so that what is logged meanwhile starts on a clear line.
+pub fn is_drawn() -> bool {
+    SPINNING
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map_or(false, |spinning| spinning.drawn)
+}
@@ -0,0 +1,110 @@
+//! Server-sent events, as chat answers are streamed in.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::error::{ApiError, OpenAIError};
+use futures_util::stream::{self, BoxStream, StreamExt as _};
+use serde::Deserialize;
+
+/// The error body of a failed request. Anthropic's has the same shape.
+#[derive(Deserialize)]
+struct WrappedError {
+    error: ApiError,
+}
+
+/// Splits the first complete event off `buffer`, returning its `data`.
+fn next_event(buffer: &mut Vec<u8>) -> Option<String> {
+    let end = buffer.windows(2).position(|w| w == b"\n\n")?;
+    let event: Vec<u8> = buffer.drain(..end + 2).collect();
+    let event = String::from_utf8_lossy(&event);
+    let data = event
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.strip_prefix(' ').unwrap_or(data))
+        .collect::<Vec<_>>()
+        .join("\n");
+    Some(data)
+}
+
+/// The `data` of each server-sent event in `text`, e.g. a recorded stream, up to `[DONE]`.
+pub fn parse(text: &str) -> Vec<String> {
+    let mut buffer: Vec<u8> = text.bytes().filter(|&b| b != b'\r').collect();
+    // The last event may not be followed by a blank line.
+    buffer.extend_from_slice(b"\n\n");
+    let mut events = vec![];
+    while let Some(data) = next_event(&mut buffer) {
+        match data.as_str() {
+            "" => continue,
+            "[DONE]" => break,
+            _ => events.push(data),
+        }
+    }
+    events
+}
+
+type Chunks = BoxStream<'static, reqwest::Result<Vec<u8>>>;
+
+/// The `data` of each server-sent event of `response`, up to `[DONE]`.
+pub fn events(response: reqwest::Response) -> BoxStream<'static, Result<String, OpenAIError>> {
+    let chunks = response
+        .bytes_stream()
+        .map(|chunk| chunk.map(|bytes| bytes.to_vec()));
+    split(chunks.boxed())
+}
+
+fn split(chunks: Chunks) -> BoxStream<'static, Result<String, OpenAIError>> {
+    Box::pin(stream::unfold(
+        (chunks, vec![], false),
+        |(mut chunks, mut buffer, done)| async move {
+            if done {
+                return None;
+            }
+            loop {
+                if let Some(data) = next_event(&mut buffer) {
+                    match data.as_str() {
+                        "" => continue,
+                        "[DONE]" => return None,
+                        _ => return Some((Ok(data), (chunks, buffer, false))),
+                    }
+                }
+                match chunks.next().await {
+                    // Line endings may be `\r\n`.
+                    Some(Ok(chunk)) => buffer.extend(chunk.iter().filter(|&&b| b != b'\r')),
+                    Some(Err(e)) => {
+                        return Some((Err(OpenAIError::Reqwest(e)), (chunks, buffer, true)))
+                    }
+                    None => return None,
+                }
+            }
+        },
+    ))
+}
+
+/// Returns `response` if it succeeded, or the error it holds.
+pub async fn check(response: reqwest::Response) -> Result<reqwest::Response, OpenAIError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let text = response.text().await?;
+    Err(match serde_json::from_str::<WrappedError>(&text) {
+        Ok(wrapped) => OpenAIError::ApiError(wrapped.error),
+        Err(_) => OpenAIError::StreamError(format!("{status}: {text}")),
+    })
+}
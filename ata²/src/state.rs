@@ -18,15 +18,17 @@
 //!  See the License for the specific language governing permissions and
 //!  limitations under the License.
 
+use arc_swap::ArcSwap;
 use clap::Parser as _;
 
 use crate::args::Ata2;
-use crate::config::{self, Config};
+use crate::config::{self, Config, Sources};
+use crate::configure;
 use crate::help;
+use crate::theme;
 
 use std::fs;
-use std::fs::File;
-use std::io::Read as _;
+use std::path::Path;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
@@ -34,7 +36,8 @@ use std::sync::Arc;
 lazy_static! {
     pub static ref FLAGS: Ata2 = Ata2::parse();
     pub static ref EXIT: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
-    pub static ref CONFIGURATION: Arc<Config> = {
+    /// The configuration, which `/reload` replaces.
+    pub static ref CONFIGURATION: ArcSwap<Config> = {
         if FLAGS.print_shortcuts {
             help::commands();
             EXIT.store(true, Ordering::Relaxed);
@@ -58,19 +61,35 @@ lazy_static! {
                     ),
                 );
             } else {
-                help::missing_toml();
+                help::missing_toml(&filename);
             }
         }
-        let mut contents = String::new();
-        File::open(filename)
-            .unwrap()
-            .read_to_string(&mut contents)
-            .expect("Could not read configuration file");
-
-        let config_ = Arc::new(Config::from(&contents));
-        config_
+        let config_ = read_configuration(&filename).unwrap_or_else(|e| {
+            error!("{e}");
+            std::process::exit(1);
+        });
+        config::migrate_runtime_files(&config_);
+        theme::apply(&config_);
+        ArcSwap::from_pointee(config_)
     };
     pub static ref ABORT: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    /// Set to stop printing the current answer, without exiting.
+    pub static ref STOP_ANSWER: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
     pub static ref IS_RUNNING: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
     pub static ref HAD_FIRST_INTERRUPT: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
 }
+
+/// Reads the configuration file at `filename`, with the flags that change it applied.
+pub fn read_configuration(filename: &Path) -> Result<Config, String> {
+    let contents = fs::read_to_string(filename)
+        .map_err(|e| format!("Could not read {}: {e}", filename.display()))?;
+    let mut config: Config = contents
+        .parse()
+        .map_err(|e| configure::parse_error(filename, &contents, &e))?;
+    config.sources = Sources::new(filename, &contents);
+    if let Some(ref system) = FLAGS.system {
+        config.system_prompt = Some(system.clone());
+        config.sources.set_by_flag("system_prompt", "--system");
+    }
+    Ok(config)
+}
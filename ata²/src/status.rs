@@ -0,0 +1,222 @@
+//! The prompt string (`ui.prompt`), e.g. `"{model}|{tokens_used} ❯ "`, with segments showing the
+//! state of the conversation in colour.
+//!
+//! With a prompt string, the next prompt is only read once the last has been handled, so that it
+//! shows e.g. the tokens the answer took. Ctrl-C while answering is then caught as a signal.
+//!
+//! Segments: `{model}`, `{profile}`, `{title}` (the session's), `{tokens_used}` (sent and received
+//! so far in the conversation, counted locally) and `{cost}` (their estimated cost in USD). Those
+//! that are unknown, e.g. the profile if none is selected, are left out.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::types::ChatCompletionRequestMessage;
+use tokio::sync::Notify;
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::ask;
+use crate::forecast::Forecast;
+use crate::params;
+use crate::prompt::CONVERSATION;
+use crate::readline::chat_completion_message_to_string;
+use crate::sessions;
+use crate::theme::{self, Role};
+use crate::tokens;
+use crate::ABORT;
+use crate::CONFIGURATION;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Segment {
+    Model,
+    Profile,
+    Title,
+    TokensUsed,
+    Cost,
+}
+
+impl Segment {
+    fn role(self) -> Role {
+        match self {
+            Segment::Model => Role::Model,
+            Segment::Profile => Role::Profile,
+            Segment::Title => Role::Title,
+            Segment::TokensUsed | Segment::Cost => Role::Usage,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Piece {
+    Text(String),
+    Segment(Segment),
+}
+
+lazy_static! {
+    /// The prompt string last rendered, and the same in colour.
+    static ref RENDERED: Mutex<(String, String)> = Mutex::new(Default::default());
+    static ref HANDLED: Notify = Notify::new();
+    /// The tokens of the conversation's messages, by model and message, so that rendering the
+    /// prompt string doesn't count them all again after each answer.
+    static ref COUNTED: Mutex<(String, HashMap<u64, usize>)> = Mutex::new(Default::default());
+}
+
+/// The pieces of `template`, or why it is invalid.
+fn parse(template: &str) -> Result<Vec<Piece>, String> {
+    let mut pieces = vec![];
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        if open > 0 {
+            pieces.push(Piece::Text(rest[..open].to_string()));
+        }
+        let close = rest[open..]
+            .find('}')
+            .ok_or_else(|| format!("Unclosed {{ in prompt string {template}"))?;
+        let segment = match &rest[open + 1..open + close] {
+            "model" => Segment::Model,
+            "profile" => Segment::Profile,
+            "title" => Segment::Title,
+            "tokens_used" => Segment::TokensUsed,
+            "cost" => Segment::Cost,
+            name => return Err(format!("Unknown segment {{{name}}} in prompt string")),
+        };
+        pieces.push(Piece::Segment(segment));
+        rest = &rest[open + close + 1..];
+    }
+    if !rest.is_empty() {
+        pieces.push(Piece::Text(rest.to_string()));
+    }
+    Ok(pieces)
+}
+
+/// Whether `template` is a valid `ui.prompt`.
+pub fn validate(template: &str) -> Result<(), String> {
+    parse(template).map(drop)
+}
+
+/// The tokens used by the conversation so far, and their cost.
+pub async fn forecast() -> Option<Forecast> {
+    let config = params::effective_config(&CONFIGURATION.load(), &Default::default()).ok()?;
+    let conversation = CONVERSATION.lock().await;
+    let mut counted = COUNTED.lock().unwrap();
+    if counted.0 != config.model {
+        *counted = (config.model.clone(), HashMap::new());
+    }
+    // Messages are told apart by their role and text.
+    let key = |message: &ChatCompletionRequestMessage| {
+        let mut hasher = DefaultHasher::new();
+        std::mem::discriminant(message).hash(&mut hasher);
+        chat_completion_message_to_string(message).hash(&mut hasher);
+        hasher.finish()
+    };
+    let forecast = Forecast::replay_with(&config, &conversation, |message| {
+        *counted
+            .1
+            .entry(key(message))
+            .or_insert_with(|| tokens::count_message(&config.model, message))
+    });
+    // Messages no longer in the conversation, e.g. after /clear, are forgotten.
+    let keys: HashSet<u64> = conversation.iter().map(key).collect();
+    counted.1.retain(|k, _| keys.contains(k));
+    Some(forecast)
+}
+
+/// `cost` in dollars, with more digits if it is under a cent.
+pub fn dollars(cost: f64) -> String {
+    if cost < 0.01 {
+        format!("${cost:.4}")
+    } else {
+        format!("${cost:.2}")
+    }
+}
+
+/// The prompt string, without colour. The line editor is given this, to measure, and colours it
+/// with [`coloured`].
+pub async fn render() -> String {
+    let template = CONFIGURATION.load().ui.prompt.clone();
+    let pieces = parse(&template).unwrap_or_default();
+    let uses = |segment| {
+        pieces
+            .iter()
+            .any(|piece| matches!(piece, Piece::Segment(s) if *s == segment))
+    };
+    let forecast = if uses(Segment::TokensUsed) || uses(Segment::Cost) {
+        forecast().await
+    } else {
+        None
+    };
+
+    let (mut plain, mut coloured) = (String::new(), String::new());
+    for piece in pieces {
+        let segment = match piece {
+            Piece::Text(text) => {
+                plain.push_str(&text);
+                coloured.push_str(&theme::paint(Role::Banner, &text));
+                continue;
+            }
+            Piece::Segment(segment) => segment,
+        };
+        let value = match segment {
+            Segment::Model => Some(params::current_model()),
+            Segment::Profile => params::current_profile(),
+            Segment::Title => sessions::current_title(),
+            Segment::TokensUsed => forecast
+                .as_ref()
+                .map(|f| (f.input_tokens + f.output_tokens).to_string()),
+            Segment::Cost => forecast.as_ref().and_then(|f| f.cost).map(dollars),
+        };
+        if let Some(value) = value {
+            plain.push_str(&value);
+            coloured.push_str(&theme::paint(segment.role(), &value));
+        }
+    }
+    *RENDERED.lock().unwrap() = (plain.clone(), coloured);
+    plain
+}
+
+/// `prompt` in colour, if it is the prompt string last rendered.
+pub fn coloured(prompt: &str) -> Option<String> {
+    let rendered = RENDERED.lock().unwrap();
+    (!prompt.is_empty() && rendered.0 == prompt).then(|| rendered.1.clone())
+}
+
+/// Tells the line editor that the last line sent has been handled, e.g. answered.
+pub fn handled() {
+    HANDLED.notify_one();
+}
+
+/// Waits for the last line sent to be handled. Returns false if a question has to be answered
+/// first, or if ata² is exiting.
+pub async fn wait_until_handled() -> bool {
+    loop {
+        tokio::select! {
+            _ = HANDLED.notified() => return true,
+            _ = tokio::time::sleep(Duration::from_millis(50)) => {
+                if ask::is_pending() || ABORT.load(Ordering::Relaxed) {
+                    return false;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Decoding answers as they stream in, a delta at a time.
+//!
+//! Some models send a newline as two deltas, `\` and `n`, which the OpenAI playground shows as a
+//! line break. [`Decoder`] does the same, but only for a backslash sent on its own that isn't
+//! itself escaped; every other backslash, e.g. `\n` in code, is left as it is. A backslash held
+//! back in case an `n` follows is printed once the stream ends.
+//!
+//! [`Utf8`] decodes text read in pieces that may split a character.
+//!
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use std::mem;
+
+/// Text decoded from bytes that may end in the middle of a character.
+#[derive(Debug, Default)]
+pub struct Utf8 {
+    /// The start of a character split off the bytes pushed so far.
+    partial: Vec<u8>,
+}
+
+impl Utf8 {
+    /// The text of `bytes`, after whatever was left over from before. A character cut off at the
+    /// end is kept until the rest of it comes; invalid bytes are replaced with `�`.
+    pub fn push(&mut self, bytes: &[u8]) -> String {
+        self.partial.extend_from_slice(bytes);
+        let mut text = String::new();
+        let mut rest: &[u8] = &self.partial;
+        // Invalid bytes may come before a character cut off at the end.
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    rest = &[];
+                    break;
+                }
+                Err(e) => {
+                    let (valid, after) = rest.split_at(e.valid_up_to());
+                    text.push_str(&String::from_utf8_lossy(valid));
+                    rest = after;
+                    match e.error_len() {
+                        Some(invalid) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            rest = &rest[invalid..];
+                        }
+                        None => break,
+                    }
+                }
+            }
+        }
+        let complete = self.partial.len() - rest.len();
+        self.partial.drain(..complete);
+        text
+    }
+
+    /// What is left over once there is nothing more to push.
+    pub fn finish(&mut self) -> String {
+        String::from_utf8_lossy(&mem::take(&mut self.partial)).into_owned()
+    }
+}
+
+/// The text of an answer, from its deltas.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    /// Whether a lone backslash was held back.
+    held: bool,
+    /// How many backslashes the text given back so far ends with.
+    backslashes: usize,
+}
+
+impl Decoder {
+    /// The text to show for `delta`, which may be nothing until the next delta comes.
+    pub fn push(&mut self, delta: &str) -> String {
+        let mut text = String::new();
+        let mut delta = delta;
+        if delta.is_empty() {
+            return text;
+        }
+        if self.held {
+            self.held = false;
+            match delta.strip_prefix('n') {
+                Some(rest) => {
+                    text.push('\n');
+                    self.backslashes = 0;
+                    delta = rest;
+                }
+                None => {
+                    text.push('\\');
+                    self.backslashes += 1;
+                }
+            }
+        } else if delta == "\\" && self.backslashes % 2 == 0 {
+            self.held = true;
+            return text;
+        }
+        text.push_str(delta);
+        let run = delta.len() - delta.trim_end_matches('\\').len();
+        self.backslashes = if run == delta.len() {
+            self.backslashes + run
+        } else {
+            run
+        };
+        text
+    }
+
+    /// What is left to show once the stream has ended.
+    pub fn finish(&mut self) -> String {
+        self.backslashes = 0;
+        if mem::take(&mut self.held) {
+            String::from("\\")
+        } else {
+            String::new()
+        }
+    }
+}
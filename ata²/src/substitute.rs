@@ -0,0 +1,148 @@
+//! Shell-style substitution in prompts (`ui.expand_env_vars`), e.g. `review the diff for $TICKET`.
+//!
+//! `$VAR` and `${VAR}` are replaced by the environment variable, and `$(command)` by the output of
+//! the command, which is only run once confirmed at the terminal. Anything that can't be replaced
+//! (an unset variable, `$5`, a refused or failing command) is left as it was, and `\$` is a
+//! literal `$`. Commands like `/set` are not expanded.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use std::env;
+use std::io;
+use std::process::{Command, Stdio};
+
+use crate::output::eprint_and_flush;
+
+/// Where the command in `text`, which follows `$(`, ends: the index of the matching `)`.
+fn command_end(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The length of the variable name `text` starts with, if it does.
+fn name_len(text: &str) -> Option<usize> {
+    if !text.starts_with(|c: char| c == '_' || c.is_ascii_alphabetic()) {
+        return None;
+    }
+    Some(
+        text.find(|c: char| c != '_' && !c.is_ascii_alphanumeric())
+            .unwrap_or(text.len()),
+    )
+}
+
+/// The output of `command`, run by `sh`, without its trailing newlines.
+fn run(command: &str) -> Result<String, String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!("it failed ({})", output.status));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end_matches('\n')
+        .to_string())
+}
+
+/// What replaces the `$` followed by `text`, if anything, and how much of `text` it replaces.
+fn substitution(text: &str, confirm: &mut impl FnMut(&str) -> bool) -> (Option<String>, usize) {
+    if let Some(inner) = text.strip_prefix('(') {
+        let end = match command_end(inner) {
+            Some(end) => end,
+            None => return (None, 0),
+        };
+        let command = &inner[..end];
+        if !confirm(command) {
+            return (None, end + 2);
+        }
+        return match run(command) {
+            Ok(output) => (Some(output), end + 2),
+            Err(e) => {
+                warn!("Could not substitute $({command}): {e}");
+                (None, end + 2)
+            }
+        };
+    }
+    if let Some(inner) = text.strip_prefix('{') {
+        return match name_len(inner) {
+            Some(len) if inner[len..].starts_with('}') => (env::var(&inner[..len]).ok(), len + 2),
+            _ => (None, 0),
+        };
+    }
+    match name_len(text) {
+        Some(len) => (env::var(&text[..len]).ok(), len),
+        None => (None, 0),
+    }
+}
+
+/// `prompt` with its variables and commands substituted. Commands are only run if `confirm`
+/// allows.
+pub fn expand(prompt: &str, mut confirm: impl FnMut(&str) -> bool) -> String {
+    let mut expanded = String::with_capacity(prompt.len());
+    let mut rest = prompt;
+    while let Some(i) = rest.find(&['\\', '$'][..]) {
+        expanded.push_str(&rest[..i]);
+        let after = &rest[i + 1..];
+        if rest[i..].starts_with('\\') {
+            match after.strip_prefix('$') {
+                Some(after) => {
+                    expanded.push('$');
+                    rest = after;
+                }
+                None => {
+                    expanded.push('\\');
+                    rest = after;
+                }
+            }
+            continue;
+        }
+        let (replacement, len) = substitution(after, &mut confirm);
+        match replacement {
+            Some(replacement) => expanded.push_str(&replacement),
+            None => expanded.push_str(&rest[i..i + 1 + len]),
+        }
+        rest = &after[len..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Asks at the terminal whether to run `command`.
+pub fn confirm(command: &str) -> bool {
+    eprint_and_flush(&format!("Run `{command}` for the prompt? [y/N] "));
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y")
+}
+
+/// For when there is no terminal to ask at: commands are not run.
+pub fn refuse(command: &str) -> bool {
+    warn!("Not running $({command}) without a terminal to confirm it");
+    false
+}
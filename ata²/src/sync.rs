@@ -0,0 +1,461 @@
+//! `ata2 sync`: keeping the configuration directory the same across machines, through a git
+//! repository or an rsync target (`[sync]`).
+//!
+//! A copy of the remote is kept in the data directory, along with the files as of the last sync,
+//! so that changes can be told apart: a file changed only here is pushed, one changed only on the
+//! remote is pulled, and one changed on both sides is a conflict, left alone unless forced.
+//!
+//! Secrets never leave the machine: hidden files, keys and certificates aren't synced, and lines
+//! like `api_key = "…"` are dropped from TOML files on the way out and put back on the way in.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use regex::Regex;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::{self, SyncConfig, SyncMethod};
+use crate::CONFIGURATION;
+
+lazy_static! {
+    /// Keys of TOML files holding secrets, e.g. `api_key` or `proxy_password`.
+    static ref SECRET_LINE: Regex =
+        Regex::new(r#"(?i)^\s*"?[a-z0-9_-]*(api_key|token|password|secret)"?\s*="#).unwrap();
+    static ref TABLE_HEADER: Regex = Regex::new(r"^\s*\[").unwrap();
+}
+
+/// Extensions of files holding keys or certificates.
+const SECRET_EXTENSIONS: &[&str] = &["key", "pem", "p12", "pfx"];
+
+fn sync_dir() -> PathBuf {
+    CONFIGURATION.load().paths.sync()
+}
+
+/// The copy of the remote.
+fn remote_dir() -> PathBuf {
+    sync_dir().join("remote")
+}
+
+/// The files as of the last sync.
+fn base_dir() -> PathBuf {
+    sync_dir().join("base")
+}
+
+/// Whether `path`, relative to the configuration directory, is left out of syncing.
+fn excluded(path: &Path, config: &SyncConfig) -> bool {
+    let hidden = path
+        .components()
+        .any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+    let secret = path.extension().map_or(false, |e| {
+        SECRET_EXTENSIONS.contains(&&*e.to_string_lossy())
+    });
+    let full = config::get_config_dir::<2>().join(path);
+    let configuration = CONFIGURATION.load();
+    let history = full == configuration.ui.history_file;
+    // Where the state directory is inside the configuration directory (e.g. on macOS), the copies
+    // kept for syncing, the backups and the search index are under it too: they are this
+    // machine's, and syncing the sync directory would copy it into itself.
+    let state = full.starts_with(configuration.paths.sync())
+        || full.starts_with(configuration.paths.backups())
+        || full == configuration.paths.search_index();
+    hidden
+        || secret
+        || history
+        || state
+        || config.exclude.iter().any(|prefix| path.starts_with(prefix))
+}
+
+/// The files under `root`, relative to it.
+fn files(root: &Path, config: &SyncConfig) -> BTreeSet<PathBuf> {
+    fn walk(root: &Path, dir: &Path, config: &SyncConfig, found: &mut BTreeSet<PathBuf>) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let relative = path.strip_prefix(root).unwrap().to_path_buf();
+            if excluded(&relative, config) {
+                continue;
+            }
+            if path.is_dir() {
+                walk(root, &path, config, found);
+            } else {
+                found.insert(relative);
+            }
+        }
+    }
+    let mut found = BTreeSet::new();
+    walk(root, root, config, &mut found);
+    found
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().map_or(false, |e| e == "toml")
+}
+
+/// `text` without the lines setting secrets.
+fn strip_secrets(text: &str) -> String {
+    text.split_inclusive('\n')
+        .filter(|line| !SECRET_LINE.is_match(line))
+        .collect()
+}
+
+/// `incoming` with the lines setting secrets in `local` put back, in the same tables.
+fn restore_secrets(incoming: &str, local: &str) -> String {
+    let mut secrets: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut table = String::new();
+    for line in local.split_inclusive('\n') {
+        if TABLE_HEADER.is_match(line) {
+            table = line.trim().to_string();
+        } else if SECRET_LINE.is_match(line) {
+            let line = line.trim_end_matches('\n').to_string() + "\n";
+            secrets.entry(table.clone()).or_default().push(line);
+        }
+    }
+
+    // Keys outside of any table have to come before the first one.
+    let mut out: String = secrets.remove("").unwrap_or_default().concat();
+    for line in incoming.split_inclusive('\n') {
+        out.push_str(line);
+        if TABLE_HEADER.is_match(line) {
+            if !line.ends_with('\n') {
+                out.push('\n');
+            }
+            if let Some(lines) = secrets.remove(line.trim()) {
+                out.extend(lines);
+            }
+        }
+    }
+    // Tables the remote doesn't have any more.
+    for (table, lines) in secrets {
+        if !out.is_empty() && !out.ends_with('\n') {
+            out.push('\n');
+        }
+        out.push_str(&format!("\n{table}\n"));
+        out.extend(lines);
+    }
+    out
+}
+
+/// The contents of `path` in the configuration directory, as it would be synced.
+fn local_version(path: &Path) -> Option<Vec<u8>> {
+    let contents = fs::read(config::get_config_dir::<2>().join(path)).ok()?;
+    if !is_toml(path) {
+        return Some(contents);
+    }
+    match String::from_utf8(contents) {
+        Ok(text) => Some(strip_secrets(&text).into_bytes()),
+        Err(e) => Some(e.into_bytes()),
+    }
+}
+
+/// Writes `contents` to `path`, or removes it if `None`.
+fn write_or_remove(path: &Path, contents: Option<&[u8]>) -> io::Result<()> {
+    match contents {
+        Some(contents) => {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, contents)
+        }
+        None => match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        },
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Change {
+    Same,
+    Local,
+    Remote,
+    Conflict,
+}
+
+/// A file as it is here, on the remote and as of the last sync.
+struct Entry {
+    path: PathBuf,
+    local: Option<Vec<u8>>,
+    remote: Option<Vec<u8>>,
+    change: Change,
+}
+
+fn entries(config: &SyncConfig) -> Vec<Entry> {
+    let mut paths = files(&config::get_config_dir::<2>(), config);
+    paths.extend(files(&remote_dir(), config));
+    paths.extend(files(&base_dir(), config));
+    paths
+        .into_iter()
+        .map(|path| {
+            let local = local_version(&path);
+            let remote = fs::read(remote_dir().join(&path)).ok();
+            let base = fs::read(base_dir().join(&path)).ok();
+            let change = if local == remote {
+                Change::Same
+            } else if remote == base {
+                Change::Local
+            } else if local == base {
+                Change::Remote
+            } else {
+                Change::Conflict
+            };
+            Entry {
+                path,
+                local,
+                remote,
+                change,
+            }
+        })
+        .collect()
+}
+
+/// Runs `command`, returning its output.
+fn run(command: &mut Command) -> Result<String, String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .output()
+        .map_err(|e| format!("Could not run {program}: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{program} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn remote(config: &SyncConfig) -> Result<&str, String> {
+    config
+        .remote
+        .as_deref()
+        .ok_or_else(|| String::from("Set `remote` in [sync] first"))
+}
+
+/// Brings the copy of the remote up to date.
+fn fetch(config: &SyncConfig) -> Result<(), String> {
+    let remote = remote(config)?;
+    let dir = remote_dir();
+    fs::create_dir_all(sync_dir()).map_err(|e| e.to_string())?;
+    match config.method {
+        SyncMethod::Git if dir.join(".git").exists() => {
+            run(Command::new("git")
+                .arg("-C")
+                .arg(&dir)
+                .args(["remote", "set-url", "origin", remote]))?;
+            // An empty repository has nothing to pull yet.
+            let heads = run(Command::new("git").arg("-C").arg(&dir).args([
+                "ls-remote",
+                "--heads",
+                "origin",
+            ]))?;
+            if !heads.trim().is_empty() {
+                run(Command::new("git").arg("-C").arg(&dir).args([
+                    "pull",
+                    "--ff-only",
+                    "origin",
+                    "HEAD",
+                ]))?;
+            }
+        }
+        SyncMethod::Git => {
+            run(Command::new("git").args(["clone", remote]).arg(&dir))?;
+        }
+        SyncMethod::Rsync => {
+            fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+            let fetched = run(Command::new("rsync")
+                .args(["-a", "--delete", "--exclude=.git"])
+                .arg(format!("{}/", remote.trim_end_matches('/')))
+                .arg(&dir));
+            match fetched {
+                // The target is only created by the first push.
+                Err(e) if !base_dir().exists() => {
+                    warn!("{e}; assuming nothing was pushed to {remote} yet")
+                }
+                fetched => {
+                    fetched?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sends the copy of the remote to the remote.
+fn publish(config: &SyncConfig) -> Result<(), String> {
+    let remote = remote(config)?;
+    let dir = remote_dir();
+    match config.method {
+        SyncMethod::Git => {
+            let git = || {
+                let mut command = Command::new("git");
+                command.arg("-C").arg(&dir);
+                command
+            };
+            run(git().args(["add", "-A"]))?;
+            if run(git().args(["diff", "--cached", "--quiet"])).is_err() {
+                run(git().args(["commit", "-q", "-m", "Sync ata² configuration"]))?;
+            }
+            run(git().args(["push", "-q", "origin", "HEAD"]))?;
+        }
+        SyncMethod::Rsync => {
+            let mut dir = dir.into_os_string();
+            dir.push("/");
+            run(Command::new("rsync")
+                .args(["-a", "--delete", "--exclude=.git"])
+                .arg(dir)
+                .arg(remote))?;
+        }
+    }
+    Ok(())
+}
+
+fn record_base(entry: &Entry) -> Result<(), String> {
+    let path = base_dir().join(&entry.path);
+    write_or_remove(&path, entry.remote.as_deref())
+        .map_err(|e| format!("Could not write {}: {e}", path.display()))
+}
+
+fn conflict_error(conflicts: &[&Entry], hint: &str) -> String {
+    let paths: Vec<_> = conflicts
+        .iter()
+        .map(|entry| format!("  {}", entry.path.display()))
+        .collect();
+    format!(
+        "Changed both here and on the remote since the last sync:\n{}\n{hint}",
+        paths.join("\n")
+    )
+}
+
+/// `ata2 sync status`: what changed here and on the remote since the last sync.
+pub fn status(config: &SyncConfig) -> Result<(), String> {
+    fetch(config)?;
+    let mut changed = false;
+    for entry in entries(config) {
+        let what = match entry.change {
+            Change::Same => continue,
+            Change::Local => "changed here",
+            Change::Remote => "changed on the remote",
+            Change::Conflict => "conflict",
+        };
+        changed = true;
+        println!("{:<22}{}", what, entry.path.display());
+    }
+    if !changed {
+        println!("In sync with {}", remote(config)?);
+    }
+    Ok(())
+}
+
+/// `ata2 sync pull`: takes the changes made on the remote. Files changed on both sides are left
+/// alone, unless `force`d.
+pub fn pull(config: &SyncConfig, force: bool) -> Result<(), String> {
+    fetch(config)?;
+    let config_dir = config::get_config_dir::<2>();
+    let entries = entries(config);
+    for entry in &entries {
+        match entry.change {
+            Change::Remote => {}
+            Change::Conflict if force => {}
+            Change::Same => {
+                record_base(entry)?;
+                continue;
+            }
+            Change::Local | Change::Conflict => continue,
+        }
+        let path = config_dir.join(&entry.path);
+        let contents = match (&entry.remote, is_toml(&entry.path)) {
+            (Some(remote), true) => {
+                let local = fs::read_to_string(&path).unwrap_or_default();
+                Some(restore_secrets(&String::from_utf8_lossy(remote), &local).into_bytes())
+            }
+            (remote, _) => remote.clone(),
+        };
+        write_or_remove(&path, contents.as_deref())
+            .map_err(|e| format!("Could not write {}: {e}", path.display()))?;
+        record_base(entry)?;
+        println!(
+            "{} {}",
+            if contents.is_some() {
+                "Updated"
+            } else {
+                "Removed"
+            },
+            entry.path.display()
+        );
+    }
+
+    let conflicts: Vec<_> = entries
+        .iter()
+        .filter(|entry| entry.change == Change::Conflict && !force)
+        .collect();
+    if !conflicts.is_empty() {
+        return Err(conflict_error(
+            &conflicts,
+            &format!(
+                "The remote's versions are in {}. Merge them by hand and `ata2 sync push --force`, \
+                 or `ata2 sync pull --force` to take them as they are.",
+                remote_dir().display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// `ata2 sync push`: sends the changes made here. Refused if the remote has changes not pulled
+/// yet, unless `force`d.
+pub fn push(config: &SyncConfig, force: bool) -> Result<(), String> {
+    fetch(config)?;
+    let mut entries = entries(config);
+    let conflicts: Vec<_> = entries
+        .iter()
+        .filter(|entry| matches!(entry.change, Change::Remote | Change::Conflict))
+        .collect();
+    if !conflicts.is_empty() && !force {
+        return Err(conflict_error(
+            &conflicts,
+            "`ata2 sync pull` first, or `ata2 sync push --force` to replace the remote's versions.",
+        ));
+    }
+
+    for entry in &mut entries {
+        if entry.change != Change::Same {
+            let path = remote_dir().join(&entry.path);
+            write_or_remove(&path, entry.local.as_deref())
+                .map_err(|e| format!("Could not write {}: {e}", path.display()))?;
+            entry.remote = entry.local.clone();
+            println!(
+                "{} {}",
+                if entry.local.is_some() {
+                    "Sent"
+                } else {
+                    "Removed"
+                },
+                entry.path.display()
+            );
+        }
+    }
+    publish(config)?;
+    entries.iter().try_for_each(record_base)
+}
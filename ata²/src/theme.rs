@@ -0,0 +1,274 @@
+//! The colours of ata²'s own output, `[theme]`: the header, banners such as "Prompt:", errors and
+//! warnings, redacted values and code blocks.
+//!
+//! A preset (`dark`, `light` or `none`) gives each its style, and the table's other settings
+//! change them, e.g. `error = "bold magenta"`. Setting `NO_COLOR` selects `none`, whatever the
+//! configuration says.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use std::env;
+use std::sync::RwLock;
+
+use crate::config::{ThemeConfig, ThemePreset};
+use crate::highlight;
+use crate::Config;
+
+const RESET: &str = "\x1b[0m";
+
+/// What is being printed.
+#[derive(Clone, Copy, Debug)]
+pub enum Role {
+    /// "Ask the Terminal Anything²", and "Configuration:".
+    Header,
+    /// "Prompt:", "Response:" and the like.
+    Banner,
+    Error,
+    Warning,
+    /// `[redacted]`, in place of API keys.
+    Redacted,
+    /// Lines added and removed in diffs.
+    Added,
+    Removed,
+    /// The segments of the prompt string.
+    Model,
+    Profile,
+    Title,
+    Usage,
+    /// What was searched for, and the entry selected.
+    Emphasis,
+    /// Hints and reasoning.
+    Muted,
+}
+
+/// The styles in effect, as escape sequences.
+#[derive(Debug, Default)]
+struct Theme {
+    header: String,
+    banner: String,
+    error: String,
+    warning: String,
+    redacted: String,
+    added: String,
+    removed: String,
+    model: String,
+    profile: String,
+    title: String,
+    usage: String,
+    emphasis: String,
+    muted: String,
+    /// The syntax highlighting theme of code blocks, if they are highlighted.
+    code: Option<String>,
+}
+
+lazy_static! {
+    /// Set once the configuration is read; until then, the `dark` preset's, so that errors in the
+    /// configuration are coloured too.
+    static ref THEME: RwLock<Theme> =
+        RwLock::new(resolve(&ThemeConfig::default(), "base16-ocean.dark").unwrap_or_default());
+}
+
+/// Whether `NO_COLOR` asks for no colours.
+fn no_color() -> bool {
+    env::var_os("NO_COLOR").map_or(false, |value| !value.is_empty())
+}
+
+/// The escape sequence of `style`, e.g. `"bold red"`, or why it is invalid. `"none"` or nothing
+/// means no style.
+pub fn parse_style(style: &str) -> Result<String, String> {
+    let mut codes = vec![];
+    for word in style.split_whitespace() {
+        let code = match word.to_lowercase().as_str() {
+            "none" => continue,
+            "bold" => 1,
+            "dim" => 2,
+            "italic" => 3,
+            "underline" => 4,
+            "black" => 30,
+            "red" => 31,
+            "green" => 32,
+            "yellow" => 33,
+            "blue" => 34,
+            "magenta" => 35,
+            "cyan" => 36,
+            "white" => 37,
+            "bright-black" | "gray" | "grey" => 90,
+            "bright-red" => 91,
+            "bright-green" => 92,
+            "bright-yellow" => 93,
+            "bright-blue" => 94,
+            "bright-magenta" => 95,
+            "bright-cyan" => 96,
+            "bright-white" => 97,
+            _ => return Err(format!("Unknown style {word} in {style}")),
+        };
+        codes.push(code.to_string());
+    }
+    if codes.is_empty() {
+        return Ok(String::new());
+    }
+    Ok(format!("\x1b[{}m", codes.join(";")))
+}
+
+/// The styles of `preset`: header, banner, error, warning, redacted, added, removed, the prompt
+/// string's model, profile, title and usage, emphasis and muted.
+fn preset_styles(preset: ThemePreset) -> [&'static str; 13] {
+    match preset {
+        ThemePreset::Dark => [
+            "bold",
+            "bold",
+            "bold red",
+            "bold yellow",
+            "red",
+            "green",
+            "red",
+            "cyan",
+            "magenta",
+            "yellow",
+            "green",
+            "bold",
+            "dim",
+        ],
+        ThemePreset::Light => [
+            "bold",
+            "bold blue",
+            "bold red",
+            "bold magenta",
+            "red",
+            "green",
+            "red",
+            "blue",
+            "magenta",
+            "bright-black",
+            "green",
+            "bold",
+            "dim",
+        ],
+        ThemePreset::None => [""; 13],
+    }
+}
+
+fn resolve(config: &ThemeConfig, code_theme: &str) -> Result<Theme, String> {
+    let preset = if no_color() {
+        ThemePreset::None
+    } else {
+        config.preset
+    };
+    let [header, banner, error, warning, redacted, added, removed, model, profile, title, usage, emphasis, muted] =
+        preset_styles(preset);
+    // With `none` (or NO_COLOR), the table's styles don't apply either.
+    let style = |set: &Option<String>, default: &str| match (preset, set) {
+        (ThemePreset::None, _) | (_, None) => parse_style(default),
+        (_, Some(set)) => parse_style(set),
+    };
+    let code = match preset {
+        ThemePreset::None => None,
+        ThemePreset::Light => Some(
+            config
+                .code
+                .clone()
+                .unwrap_or_else(|| String::from("InspiredGitHub")),
+        ),
+        ThemePreset::Dark => Some(
+            config
+                .code
+                .clone()
+                .unwrap_or_else(|| code_theme.to_string()),
+        ),
+    };
+    if let Some(code) = code.as_ref().filter(|code| !highlight::has_theme(code)) {
+        return Err(format!("Unknown code theme {code}"));
+    }
+    Ok(Theme {
+        header: style(&config.header, header)?,
+        banner: style(&config.banner, banner)?,
+        error: style(&config.error, error)?,
+        warning: style(&config.warning, warning)?,
+        redacted: style(&config.redacted, redacted)?,
+        added: style(&None, added)?,
+        removed: style(&None, removed)?,
+        model: style(&config.model, model)?,
+        profile: style(&config.profile, profile)?,
+        title: style(&config.title, title)?,
+        usage: style(&config.usage, usage)?,
+        emphasis: style(&config.emphasis, emphasis)?,
+        muted: style(&config.muted, muted)?,
+        code,
+    })
+}
+
+/// Whether `[theme]` is valid.
+pub fn validate(config: &Config) -> Result<(), String> {
+    resolve(&config.theme, &config.ui.code_theme)
+        .map(drop)
+        .map_err(|e| format!("In [theme]: {e}"))
+}
+
+/// Uses the theme of `config`, e.g. once it is read or reloaded.
+pub fn apply(config: &Config) {
+    match resolve(&config.theme, &config.ui.code_theme) {
+        Ok(theme) => *THEME.write().unwrap() = theme,
+        Err(e) => warn!("Keeping the theme in use: {e}"),
+    }
+}
+
+/// `text` styled as `role`.
+pub fn paint(role: Role, text: &str) -> String {
+    let theme = THEME.read().unwrap();
+    let style = match role {
+        Role::Header => &theme.header,
+        Role::Banner => &theme.banner,
+        Role::Error => &theme.error,
+        Role::Warning => &theme.warning,
+        Role::Redacted => &theme.redacted,
+        Role::Added => &theme.added,
+        Role::Removed => &theme.removed,
+        Role::Model => &theme.model,
+        Role::Profile => &theme.profile,
+        Role::Title => &theme.title,
+        Role::Usage => &theme.usage,
+        Role::Emphasis => &theme.emphasis,
+        Role::Muted => &theme.muted,
+    };
+    if style.is_empty() {
+        text.to_string()
+    } else {
+        format!("{style}{text}{RESET}")
+    }
+}
+
+/// What goes before and after text styled as `role`, for text styled by others (e.g. SQLite's
+/// `snippet()`): nothing if the role has no style.
+pub fn markers(role: Role) -> (String, String) {
+    let painted = paint(role, "");
+    match painted.strip_suffix(RESET) {
+        Some(style) if !style.is_empty() => (style.to_string(), RESET.to_string()),
+        _ => (String::new(), String::new()),
+    }
+}
+
+/// Whether anything is coloured at all: not with the `none` preset or `NO_COLOR`.
+pub fn is_coloured() -> bool {
+    THEME.read().unwrap().code.is_some()
+}
+
+/// The syntax highlighting theme of code blocks, if they are highlighted.
+pub fn code_theme() -> Option<String> {
+    THEME.read().unwrap().code.clone()
+}
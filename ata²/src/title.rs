@@ -0,0 +1,77 @@
+//! The terminal window's title (`ui.set_terminal_title`).
+//!
+//! While ata² runs, the title shows the session and the model, or that an answer is being
+//! generated. The previous title is saved on the terminal's title stack and restored on exit.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config::UiConfig;
+use crate::output::eprint_and_flush;
+use crate::params;
+use crate::sessions;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// OSC 2: set the window title.
+fn set(title: &str) {
+    if ENABLED.load(Ordering::Relaxed) {
+        eprint_and_flush(&format!("\x1b]2;{title}\x07"));
+    }
+}
+
+/// Saves the current title and shows ours, if enabled and stderr is a terminal.
+pub fn init(ui: &UiConfig) {
+    if !ui.set_terminal_title || !atty::is(atty::Stream::Stderr) {
+        return;
+    }
+    ENABLED.store(true, Ordering::Relaxed);
+    // XTWINOPS: push the window title on the stack.
+    eprint_and_flush("\x1b[22;2t");
+    idle();
+}
+
+/// Shows the session and the model.
+pub fn idle() {
+    let model = params::current_model();
+    set(&format!("ata²: {} — {model}", sessions::current_id()));
+}
+
+/// Shows that an answer is being generated, until the returned guard is dropped.
+pub fn busy() -> Busy {
+    set("ata²: generating…");
+    Busy
+}
+
+pub struct Busy;
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        idle();
+    }
+}
+
+/// Puts back the title the terminal had before [`init`].
+pub fn restore() {
+    if ENABLED.swap(false, Ordering::Relaxed) {
+        // XTWINOPS: pop the window title from the stack.
+        eprint_and_flush("\x1b[23;2t");
+    }
+}
@@ -0,0 +1,103 @@
+//! Connecting to providers over TLS (`[tls]` and `[fallback.tls]`): trusting an internal CA,
+//! presenting a client certificate, or not verifying certificates at all. The connect timeout of
+//! `[network]` is set here too.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use reqwest::{Certificate, Client, Identity};
+
+use std::fs;
+use std::sync::Mutex;
+
+use crate::config::{NetworkConfig, TlsConfig};
+
+lazy_static! {
+    /// The clients built so far, so that their connections are reused.
+    static ref CLIENTS: Mutex<Vec<(TlsConfig, NetworkConfig, Client)>> = Mutex::new(vec![]);
+}
+
+fn read(setting: &str, path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("{setting} {path}: {e}"))
+}
+
+/// The certificates in the PEM file `pem`, which may hold several.
+fn certificates(path: &str, pem: &[u8]) -> Result<Vec<Certificate>, String> {
+    const END: &str = "-----END CERTIFICATE-----";
+    let pem = String::from_utf8_lossy(pem);
+    let mut certificates = vec![];
+    let mut rest = pem.as_ref();
+    while let Some(start) = rest.find("-----BEGIN CERTIFICATE-----") {
+        let end = rest[start..]
+            .find(END)
+            .map(|end| start + end + END.len())
+            .ok_or_else(|| format!("ca_bundle {path}: a certificate is cut short"))?;
+        let certificate = Certificate::from_pem(rest[start..end].as_bytes())
+            .map_err(|e| format!("ca_bundle {path}: {e}"))?;
+        certificates.push(certificate);
+        rest = &rest[end..];
+    }
+    if certificates.is_empty() {
+        return Err(format!("ca_bundle {path} holds no PEM certificates"));
+    }
+    Ok(certificates)
+}
+
+fn build(tls: &TlsConfig, network: &NetworkConfig) -> Result<Client, String> {
+    let mut builder = Client::builder();
+    if let Some(timeout) = network.connect_timeout() {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(path) = &tls.ca_bundle {
+        for certificate in certificates(path, &read("ca_bundle", path)?)? {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    match (&tls.client_cert, &tls.client_key) {
+        (Some(cert_path), key_path) => {
+            let cert = read("client_cert", cert_path)?;
+            let key = match key_path {
+                Some(key_path) => read("client_key", key_path)?,
+                None => cert.clone(),
+            };
+            let identity = Identity::from_pkcs8_pem(&cert, &key)
+                .map_err(|e| format!("client_cert {cert_path}: {e}"))?;
+            builder = builder.identity(identity);
+        }
+        (None, Some(_)) => return Err(String::from("client_key is set, but client_cert isn't")),
+        (None, None) => {}
+    }
+    if tls.insecure_skip_verify {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// The HTTP client to reach a provider with, as configured by `tls` and `network`.
+pub fn http_client(tls: &TlsConfig, network: &NetworkConfig) -> Result<Client, String> {
+    let mut clients = CLIENTS.lock().unwrap();
+    if let Some((_, _, client)) = clients.iter().find(|(t, n, _)| t == tls && n == network) {
+        return Ok(client.clone());
+    }
+    let client = build(tls, network)?;
+    if tls.insecure_skip_verify {
+        warn!("Not verifying the certificates of the provider (insecure_skip_verify)");
+    }
+    clients.push((tls.clone(), network.clone(), client.clone()));
+    Ok(client)
+}
@@ -0,0 +1,77 @@
+//! Counting tokens, with the model's tokenizer.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::types::ChatCompletionRequestMessage;
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+use tiktoken_rs::{cl100k_base_singleton, o200k_base_singleton};
+
+use crate::capabilities;
+use crate::readline::chat_completion_message_to_string;
+use crate::Config;
+
+/// Tokens taken by the framing of each message (role, separators).
+const PER_MESSAGE: usize = 4;
+/// Kept for the answer with `max_tokens = 0`, when the model's maximum is unknown.
+const DEFAULT_ANSWER_TOKENS: usize = 4096;
+
+/// The number of tokens in `text`, for `model`. Models with an unknown tokenizer (e.g. other
+/// providers') are counted as if they used OpenAI's `cl100k_base`.
+pub fn count(model: &str, text: &str) -> usize {
+    let bpe = match get_tokenizer(model) {
+        Some(Tokenizer::O200kBase) => o200k_base_singleton(),
+        _ => cl100k_base_singleton(),
+    };
+    let bpe = bpe.lock();
+    bpe.encode_with_special_tokens(text).len()
+}
+
+/// The number of tokens a message with the content `text` takes in a request.
+pub fn count_as_message(model: &str, text: &str) -> usize {
+    PER_MESSAGE + count(model, text)
+}
+
+/// The number of tokens `message` takes in a request.
+pub fn count_message(model: &str, message: &ChatCompletionRequestMessage) -> usize {
+    count_as_message(model, &chat_completion_message_to_string(message))
+}
+
+/// How many tokens the model takes in, prompt and answer together: `context_window`, or what is
+/// known of the model. The window of an unknown model is taken to be unlimited (`usize::MAX`), and
+/// left for the API to enforce.
+pub fn context_window(config: &Config) -> usize {
+    match config.context_window {
+        0 => capabilities::lookup(&config.model)
+            .and_then(|c| c.context_window)
+            .map_or(usize::MAX, |n| n as usize),
+        n => n as usize,
+    }
+}
+
+/// How many tokens to keep for the answer: `max_tokens`, or with `max_tokens = 0`, as many as the
+/// model may answer with, up to half of the context window.
+pub fn answer_tokens(config: &Config) -> usize {
+    match config.max_tokens {
+        n if n > 0 => n as usize,
+        _ => capabilities::lookup(&config.model)
+            .and_then(|c| c.max_output_tokens)
+            .map_or(DEFAULT_ANSWER_TOKENS, |n| n as usize)
+            .min(context_window(config) / 2),
+    }
+}
@@ -0,0 +1,270 @@
+//! Tools the model may call (`[tools]`): `run_shell_command` (`[tools.shell]`) and `web_search`
+//! (`[tools.web]`, see [`crate::web`]).
+//!
+//! When the model calls tools, its calls and their results are added to the conversation, and it
+//! is asked again, so that it can answer with the results.
+//!
+//! A command the model asks to run is refused if `deny` or `allow` say so, and otherwise shown and
+//! only run once confirmed. It runs with `sh -c` in the current directory, without stdin and with a
+//! time limit. `allow` and `deny` are a convenience, not a sandbox: what a command does can't be
+//! told from how it starts, so read it before saying yes.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::types::{
+    ChatCompletionMessageToolCall, ChatCompletionMessageToolCallChunk,
+    ChatCompletionRequestAssistantMessage, ChatCompletionRequestMessage,
+    ChatCompletionRequestToolMessage, ChatCompletionTool, ChatCompletionToolType, FunctionCall,
+    Role,
+};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::process::Command;
+
+use std::process::Stdio;
+use std::time::Duration;
+
+use crate::config::{ShellToolConfig, ToolsConfig};
+use crate::output::eprint_bold;
+use crate::risk;
+use crate::web;
+
+const SHELL_TOOL: &str = "run_shell_command";
+
+/// What separates the commands of a pipeline or list.
+const SEPARATORS: &[&str] = &["&&", "||", ";", "|", "&", "\n", "$(", "`", "("];
+
+/// The tools to offer the model.
+pub fn definitions(config: &ToolsConfig) -> Vec<ChatCompletionTool> {
+    let mut tools = vec![];
+    if config.shell.enabled {
+        let tool = json!({
+            "type": "function",
+            "function": {
+                "name": SHELL_TOOL,
+                "description": "Run a shell command on the user's computer, in the current \
+                    directory, and get its exit status and output. The user is asked to confirm \
+                    each command first, and may refuse.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "command": {
+                            "type": "string",
+                            "description": "The command, as typed at a POSIX shell.",
+                        },
+                    },
+                    "required": ["command"],
+                },
+            },
+        });
+        tools.push(serde_json::from_value(tool).expect("the tool definition is valid"));
+    }
+    if config.web.enabled {
+        let tool = json!({
+            "type": "function",
+            "function": {
+                "name": web::TOOL,
+                "description": "Search the web, and get the text of the pages found, numbered. \
+                    Cite them by number, e.g. [1], where the answer relies on them.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "query": {
+                            "type": "string",
+                            "description": "What to search for, as typed in a search engine.",
+                        },
+                    },
+                    "required": ["query"],
+                },
+            },
+        });
+        tools.push(serde_json::from_value(tool).expect("the tool definition is valid"));
+    }
+    tools
+}
+
+/// The tool calls of an answer, put together as they stream in.
+#[derive(Default)]
+pub struct Calls {
+    calls: Vec<ChatCompletionMessageToolCall>,
+}
+
+impl Calls {
+    pub fn push(&mut self, chunk: &ChatCompletionMessageToolCallChunk) {
+        let index = chunk.index as usize;
+        while self.calls.len() <= index {
+            self.calls.push(ChatCompletionMessageToolCall {
+                id: String::new(),
+                r#type: ChatCompletionToolType::Function,
+                function: FunctionCall {
+                    name: String::new(),
+                    arguments: String::new(),
+                },
+            });
+        }
+        let call = &mut self.calls[index];
+        if let Some(ref id) = chunk.id {
+            call.id.push_str(id);
+        }
+        if let Some(ref function) = chunk.function {
+            if let Some(ref name) = function.name {
+                call.function.name.push_str(name);
+            }
+            if let Some(ref arguments) = function.arguments {
+                call.function.arguments.push_str(arguments);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// Runs the calls, returning the messages to add to the conversation: the answer with the
+    /// calls, and the result of each.
+    pub async fn run(
+        self,
+        text: String,
+        config: &ToolsConfig,
+    ) -> Vec<ChatCompletionRequestMessage> {
+        let mut messages = vec![ChatCompletionRequestMessage::Assistant(
+            ChatCompletionRequestAssistantMessage {
+                role: Role::Assistant,
+                content: Some(text).filter(|text| !text.is_empty()),
+                tool_calls: Some(self.calls.clone()),
+                ..Default::default()
+            },
+        )];
+        for call in self.calls {
+            let result = match call.function.name.as_str() {
+                SHELL_TOOL => run_shell(&call.function.arguments, &config.shell).await,
+                web::TOOL => web::run_tool(&call.function.arguments, &config.web).await,
+                name => format!("There is no tool called {name}."),
+            };
+            messages.push(ChatCompletionRequestMessage::Tool(
+                ChatCompletionRequestToolMessage {
+                    role: Role::Tool,
+                    content: Some(result),
+                    tool_call_id: call.id,
+                },
+            ));
+        }
+        messages
+    }
+}
+
+#[derive(Deserialize)]
+struct ShellArguments {
+    command: String,
+}
+
+/// The commands of the pipelines and lists in `command`.
+fn parts(command: &str) -> Vec<&str> {
+    let mut parts = vec![command];
+    for separator in SEPARATORS {
+        parts = parts
+            .into_iter()
+            .flat_map(|part| part.split(separator))
+            .collect();
+    }
+    parts
+        .into_iter()
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+/// Whether `part` is the command `prefix`, or starts with it followed by arguments.
+fn starts_with(part: &str, prefix: &str) -> bool {
+    part == prefix
+        || part
+            .strip_prefix(prefix)
+            .map_or(false, |rest| rest.starts_with(char::is_whitespace))
+}
+
+/// Why `command` may not be run, if it may not.
+fn refusal(command: &str, config: &ShellToolConfig) -> Option<String> {
+    for part in parts(command) {
+        if let Some(denied) = config.deny.iter().find(|deny| starts_with(part, deny)) {
+            return Some(format!(
+                "`{denied}` commands are denied by the configuration."
+            ));
+        }
+        if !config.allow.is_empty() && !config.allow.iter().any(|allow| starts_with(part, allow)) {
+            return Some(format!(
+                "`{part}` is not among the commands allowed by the configuration: {}.",
+                config.allow.join(", ")
+            ));
+        }
+    }
+    None
+}
+
+/// At most `max_bytes` of `text`, noting what was left out.
+pub fn truncated(text: &str, max_bytes: usize) -> String {
+    if text.len() <= max_bytes {
+        return text.to_string();
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}\n[{} more bytes]", &text[..end], text.len() - end)
+}
+
+/// Runs the command in `arguments` if allowed and confirmed, returning what to tell the model.
+async fn run_shell(arguments: &str, config: &ShellToolConfig) -> String {
+    let command = match serde_json::from_str::<ShellArguments>(arguments) {
+        Ok(arguments) => arguments.command,
+        Err(e) => return format!("Invalid arguments: {e}"),
+    };
+    eprint_bold("\nThe model asks to run:\n");
+    eprintln!("$ {command}");
+    if let Some(refusal) = refusal(&command, config) {
+        warn!("Refused: {refusal}");
+        return format!("Refused: {refusal}");
+    }
+    if !risk::confirm(&command).await {
+        return String::from("The user declined to run the command.");
+    }
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = match tokio::time::timeout(Duration::from_secs(config.timeout_secs), child).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => return format!("Could not run the command: {e}"),
+        Err(_) => {
+            warn!("Killed the command after {}s", config.timeout_secs);
+            return format!("The command was killed after {}s.", config.timeout_secs);
+        }
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    eprint!("{stdout}{stderr}");
+    let max_bytes = config.max_output_bytes as usize;
+    format!(
+        "{}\nstdout:\n{}\nstderr:\n{}",
+        output.status,
+        truncated(&stdout, max_bytes),
+        truncated(&stderr, max_bytes),
+    )
+}
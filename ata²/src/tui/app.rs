@@ -0,0 +1,243 @@
+//! The state of the full-screen interface, and what keys and the mouse do to it.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseEventKind};
+
+use std::time::Instant;
+
+/// Lines scrolled by a turn of the mouse wheel.
+const WHEEL_LINES: usize = 3;
+
+/// Who a part of the conversation is from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Speaker {
+    User,
+    Assistant,
+    /// Printed by ata², e.g. log messages and the output of commands.
+    Output,
+}
+
+pub struct Entry {
+    pub speaker: Speaker,
+    pub text: String,
+}
+
+/// What the main loop has to do after a key.
+pub enum Action {
+    None,
+    Send(String),
+    /// Stop the answer being streamed.
+    Stop,
+}
+
+#[derive(Default)]
+pub struct App {
+    pub entries: Vec<Entry>,
+    /// Whether the last entry is the answer being streamed.
+    answering: bool,
+    /// Where the entries for the line being handled start.
+    handling: usize,
+    pub input: String,
+    /// In characters.
+    pub cursor: usize,
+    /// The lines sent, for Up and Down.
+    sent: Vec<String>,
+    recalled: Option<usize>,
+    /// Lines scrolled up from the bottom; 0 follows the answer.
+    pub scroll: usize,
+    /// The height of the conversation pane, which PageUp and PageDown scroll by.
+    pub page: usize,
+    /// When the answer being waited for was asked.
+    pub busy: Option<Instant>,
+    pub status: String,
+    /// Shown in the status bar until the next key.
+    pub notice: Option<String>,
+    pub quit: bool,
+}
+
+impl App {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Shows `entries` in place of the conversation.
+    pub fn show(&mut self, entries: Vec<(Speaker, String)>) {
+        self.entries = entries
+            .into_iter()
+            .map(|(speaker, text)| Entry { speaker, text })
+            .collect();
+        self.answering = false;
+    }
+
+    /// Shows the conversation again if it changed other than by the line just handled, keeping
+    /// what was printed while handling it.
+    pub fn sync(&mut self, entries: Vec<(Speaker, String)>) {
+        let shown = self
+            .entries
+            .iter()
+            .filter(|entry| entry.speaker != Speaker::Output)
+            .count();
+        if shown != entries.len() {
+            let printed = self
+                .entries
+                .drain(self.handling.min(self.entries.len())..)
+                .filter(|entry| entry.speaker == Speaker::Output)
+                .collect::<Vec<_>>();
+            self.show(entries);
+            self.entries.extend(printed);
+        }
+        self.answering = false;
+    }
+
+    /// Adds `text` from `speaker`. What is printed is added to what was printed just before.
+    pub fn push(&mut self, speaker: Speaker, text: &str) {
+        match self.entries.last_mut() {
+            Some(last) if last.speaker == Speaker::Output && speaker == Speaker::Output => {
+                last.text.push_str(text)
+            }
+            _ => self.entries.push(Entry {
+                speaker,
+                text: text.to_string(),
+            }),
+        }
+        self.answering = false;
+    }
+
+    /// Adds a piece of the answer being streamed.
+    pub fn answer(&mut self, text: &str) {
+        match self.entries.last_mut() {
+            Some(last) if self.answering => last.text.push_str(text),
+            _ => {
+                self.entries.push(Entry {
+                    speaker: Speaker::Assistant,
+                    text: text.to_string(),
+                });
+                self.answering = true;
+            }
+        }
+    }
+
+    /// Shows the `line` sent, and follows the answer to it.
+    pub fn submit(&mut self, line: &str) {
+        self.handling = self.entries.len();
+        self.push(Speaker::User, line);
+        self.scroll = 0;
+    }
+
+    fn byte_at(&self, cursor: usize) -> usize {
+        self.input
+            .char_indices()
+            .nth(cursor)
+            .map_or(self.input.len(), |(i, _)| i)
+    }
+
+    fn insert(&mut self, c: char) {
+        let at = self.byte_at(self.cursor);
+        self.input.insert(at, c);
+        self.cursor += 1;
+    }
+
+    fn recall(&mut self, recalled: Option<usize>) {
+        self.recalled = recalled;
+        self.input = recalled.map_or_else(String::new, |i| self.sent[i].clone());
+        self.cursor = self.input.chars().count();
+    }
+
+    pub fn handle(&mut self, event: Event) -> Action {
+        match event {
+            Event::Key(key) if key.kind != KeyEventKind::Release => {
+                self.notice = None;
+                self.key(key)
+            }
+            Event::Mouse(mouse) => {
+                match mouse.kind {
+                    MouseEventKind::ScrollUp => self.scroll += WHEEL_LINES,
+                    MouseEventKind::ScrollDown => {
+                        self.scroll = self.scroll.saturating_sub(WHEEL_LINES)
+                    }
+                    _ => {}
+                }
+                Action::None
+            }
+            _ => Action::None,
+        }
+    }
+
+    fn key(&mut self, key: KeyEvent) -> Action {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        match key.code {
+            KeyCode::Char('c') if ctrl => {
+                if self.busy.is_some() {
+                    return Action::Stop;
+                }
+                if self.input.is_empty() {
+                    self.quit = true;
+                } else {
+                    self.recall(None);
+                }
+            }
+            KeyCode::Char('d') if ctrl && self.input.is_empty() => self.quit = true,
+            KeyCode::Char('u') if ctrl => self.recall(None),
+            KeyCode::Esc if self.busy.is_some() => return Action::Stop,
+            KeyCode::Enter if alt || key.modifiers.contains(KeyModifiers::SHIFT) => {
+                self.insert('\n')
+            }
+            KeyCode::Enter if !self.input.trim().is_empty() => {
+                let line = std::mem::take(&mut self.input);
+                self.cursor = 0;
+                self.recalled = None;
+                if self.sent.last() != Some(&line) {
+                    self.sent.push(line.clone());
+                }
+                return Action::Send(line);
+            }
+            KeyCode::Char(c) if !ctrl => self.insert(c),
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                let at = self.byte_at(self.cursor);
+                self.input.remove(at);
+            }
+            KeyCode::Delete if self.cursor < self.input.chars().count() => {
+                let at = self.byte_at(self.cursor);
+                self.input.remove(at);
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.input.chars().count()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.input.chars().count(),
+            KeyCode::Up if !self.sent.is_empty() => {
+                let i = self.recalled.unwrap_or(self.sent.len());
+                self.recall(Some(i.saturating_sub(1)));
+            }
+            KeyCode::Down if self.recalled.is_some() => {
+                let next = self
+                    .recalled
+                    .map(|i| i + 1)
+                    .filter(|&i| i < self.sent.len());
+                self.recall(next);
+            }
+            KeyCode::PageUp => self.scroll += self.page.max(1),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(self.page.max(1)),
+            _ => {}
+        }
+        Action::None
+    }
+}
@@ -0,0 +1,96 @@
+//! Catching what is printed on stdout and stderr while the full-screen interface is shown, which
+//! would otherwise be drawn over it.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use tokio::sync::mpsc::UnboundedSender;
+
+use std::fs::File;
+use std::io::{self, Read as _, Write as _};
+use std::os::unix::io::{FromRawFd as _, RawFd};
+use std::thread;
+
+use super::Event;
+use crate::stream_decode::Utf8;
+
+/// Where stdout and stderr were, restored when dropped.
+pub struct Capture {
+    stdout: RawFd,
+    stderr: RawFd,
+}
+
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+impl Capture {
+    /// Redirects stdout and stderr into a pipe, whose text is sent as [`Event::Output`]. Returns
+    /// the terminal stdout was on, to draw on.
+    pub fn start(events: UnboundedSender<Event>) -> io::Result<(Self, File)> {
+        io::stdout().flush()?;
+        io::stderr().flush()?;
+        let mut pipe = [0; 2];
+        check(unsafe { libc::pipe(pipe.as_mut_ptr()) })?;
+        let [read, write] = pipe;
+        let capture = Capture {
+            stdout: check(unsafe { libc::dup(libc::STDOUT_FILENO) })?,
+            stderr: check(unsafe { libc::dup(libc::STDERR_FILENO) })?,
+        };
+        let terminal = unsafe { File::from_raw_fd(check(libc::dup(capture.stdout))?) };
+        check(unsafe { libc::dup2(write, libc::STDOUT_FILENO) })?;
+        check(unsafe { libc::dup2(write, libc::STDERR_FILENO) })?;
+        unsafe { libc::close(write) };
+
+        let mut output = unsafe { File::from_raw_fd(read) };
+        // Ends once stdout and stderr are restored, which closes the pipe. Text is sent as it is
+        // read rather than by line, so that questions waiting for an answer are shown.
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            // A character may be split between reads.
+            let mut utf8 = Utf8::default();
+            while let Ok(n @ 1..) = output.read(&mut buf) {
+                let text = utf8.push(&buf[..n]);
+                if !text.is_empty() && events.send(Event::Output(text)).is_err() {
+                    return;
+                }
+            }
+            let rest = utf8.finish();
+            if !rest.is_empty() {
+                let _ = events.send(Event::Output(rest));
+            }
+        });
+        Ok((capture, terminal))
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        unsafe {
+            libc::dup2(self.stdout, libc::STDOUT_FILENO);
+            libc::dup2(self.stderr, libc::STDERR_FILENO);
+            libc::close(self.stdout);
+            libc::close(self.stderr);
+        }
+    }
+}
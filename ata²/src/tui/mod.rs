@@ -0,0 +1,232 @@
+//! The full-screen interface (`--tui`): the conversation in a pane that scrolls, with the keys or
+//! the mouse wheel, an input box under it, and a status bar with the model and what the
+//! conversation has cost so far.
+//!
+//! Prompts and commands go through the same pipeline as in the REPL. While the interface is shown,
+//! whatever would have been printed on the terminal (log messages, the output of commands) is
+//! caught and shown in the pane, dimmed.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+mod app;
+mod capture;
+mod view;
+
+use async_openai::types::ChatCompletionRequestMessage;
+use crossterm::event::{DisableMouseCapture, EnableMouseCapture, EventStream};
+use crossterm::execute;
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use futures_util::StreamExt as _;
+use ratatui::backend::CrosstermBackend;
+use ratatui::Terminal;
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use std::fs::File;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use self::app::{Action, App, Speaker};
+use self::capture::Capture;
+use crate::ask;
+use crate::commands;
+use crate::output::OutputSink;
+use crate::params;
+use crate::prompt::{self, CONVERSATION};
+use crate::readline::chat_completion_message_to_string;
+use crate::sessions;
+use crate::status;
+use crate::substitute;
+use crate::title;
+use crate::TokioResult;
+use crate::IS_RUNNING;
+use crate::STOP_ANSWER;
+
+/// What happens while the interface waits for keys.
+pub enum Event {
+    /// A piece of the answer being streamed.
+    Delta(String),
+    /// Text printed on stdout or stderr.
+    Output(String),
+    /// The line sent was handled, successfully or with the given error.
+    Done(Option<String>),
+}
+
+/// Sends the answer to the interface as it is streamed in.
+struct ChannelSink(UnboundedSender<Event>);
+
+impl OutputSink for ChannelSink {
+    fn write(&mut self, text: &str) {
+        if !text.is_empty() {
+            let _ = self.0.send(Event::Delta(text.to_string()));
+        }
+    }
+}
+
+/// The terminal in raw mode, showing the alternate screen, until dropped.
+struct Screen(Terminal<CrosstermBackend<File>>);
+
+impl Screen {
+    fn open(mut tty: File) -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(tty, EnterAlternateScreen, EnableMouseCapture)?;
+        Ok(Screen(Terminal::new(CrosstermBackend::new(tty))?))
+    }
+}
+
+impl Deref for Screen {
+    type Target = Terminal<CrosstermBackend<File>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for Screen {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(
+            self.0.backend_mut(),
+            LeaveAlternateScreen,
+            DisableMouseCapture
+        );
+        let _ = self.0.show_cursor();
+    }
+}
+
+/// The prompts and answers of `messages`, as shown in the pane.
+fn entries(messages: &[ChatCompletionRequestMessage]) -> Vec<(Speaker, String)> {
+    messages
+        .iter()
+        .filter_map(|message| {
+            let speaker = match message {
+                ChatCompletionRequestMessage::User(_) => Speaker::User,
+                ChatCompletionRequestMessage::Assistant(_) => Speaker::Assistant,
+                _ => return None,
+            };
+            Some((speaker, chat_completion_message_to_string(message)))
+        })
+        .collect()
+}
+
+/// The model, profile and session's title, and the tokens and cost of the conversation so far.
+async fn status_line() -> String {
+    let mut parts = vec![params::current_model()];
+    parts.extend(params::current_profile());
+    parts.extend(sessions::current_title());
+    if let Some(forecast) = status::forecast().await {
+        parts.push(format!(
+            "{} tokens",
+            forecast.input_tokens + forecast.output_tokens
+        ));
+        parts.extend(forecast.cost.map(status::dollars));
+    }
+    parts.join(" · ")
+}
+
+/// Sends `line` through the REPL's pipeline, telling the interface once it is handled.
+fn send(line: String, events: &UnboundedSender<Event>) {
+    let line = if !params::current_config().ui.expand_env_vars
+        || commands::looks_like_command(line.split_whitespace().next().unwrap_or(""))
+    {
+        line
+    } else {
+        // There is no prompt to confirm commands at.
+        substitute::expand(&line, substitute::refuse)
+    };
+    let events = events.clone();
+    tokio::spawn(async move {
+        let mut sink = ChannelSink(events.clone());
+        let result = prompt::dispatch_to(&mut sink, line).await;
+        let _ = events.send(Event::Done(result.err().map(|e| e.to_string())));
+    });
+}
+
+/// Runs the full-screen interface until it is quit.
+pub async fn run() -> TokioResult<()> {
+    if !atty::is(atty::Stream::Stdin) || !atty::is(atty::Stream::Stdout) {
+        return Err("--tui needs a terminal".into());
+    }
+    let (events, mut received) = mpsc::unbounded_channel();
+    let mut app = App::new();
+    app.show(entries(&CONVERSATION.lock().await));
+    app.status = status_line().await;
+
+    let (capture, tty) = Capture::start(events.clone())?;
+    let mut screen = Screen::open(tty)?;
+    let mut keys = EventStream::new();
+    // Redraws the time waited for an answer.
+    let mut tick = tokio::time::interval(Duration::from_millis(100));
+    while !app.quit {
+        screen.draw(|frame| view::draw(frame, &mut app))?;
+        tokio::select! {
+            Some(Ok(event)) = keys.next() => match app.handle(event) {
+                Action::None => {}
+                Action::Send(line) if ask::answer(&line) => {
+                    app.push(Speaker::Output, &format!("{line}\n"))
+                }
+                Action::Send(line) if app.busy.is_some() => {
+                    app.notice = Some(String::from("Still answering: Esc stops the answer"));
+                    app.input = line;
+                    app.cursor = app.input.chars().count();
+                }
+                Action::Send(line) => {
+                    app.submit(&line);
+                    app.busy = Some(Instant::now());
+                    send(line, &events);
+                }
+                Action::Stop => {
+                    if IS_RUNNING.load(Ordering::SeqCst) {
+                        STOP_ANSWER.store(true, Ordering::Relaxed);
+                    }
+                }
+            },
+            Some(event) = received.recv() => match event {
+                Event::Delta(text) => app.answer(&text),
+                Event::Output(text) => app.push(Speaker::Output, &text),
+                Event::Done(error) => {
+                    if let Some(e) = error {
+                        app.push(Speaker::Output, &format!("Failed to request: {e}\n"));
+                    }
+                    app.busy = None;
+                    // Commands such as /clear or /undo change the conversation.
+                    app.sync(entries(&CONVERSATION.lock().await));
+                    app.status = status_line().await;
+                }
+            },
+            _ = tick.tick(), if app.busy.is_some() => {}
+        }
+    }
+    drop(screen);
+    drop(capture);
+
+    prompt::autosave().await;
+    if let Err(e) = sessions::save_current().await {
+        error!("Could not save session: {e}");
+    }
+    title::restore();
+    Ok(())
+}
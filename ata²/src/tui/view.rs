@@ -0,0 +1,247 @@
+//! Drawing the full-screen interface.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::Frame;
+use unicode_width::{UnicodeWidthChar as _, UnicodeWidthStr as _};
+
+use std::mem;
+
+use super::app::{App, Speaker};
+use crate::ask;
+use crate::theme;
+
+/// The most lines the input box grows to.
+const MAX_INPUT_LINES: usize = 8;
+
+/// `style`, unless nothing is to be coloured (`theme.preset = "none"` or `NO_COLOR`).
+fn styled(style: Style) -> Style {
+    if theme::is_coloured() {
+        style
+    } else {
+        Style::default()
+    }
+}
+
+/// `text` without escape sequences (e.g. colours) and carriage returns.
+fn plain(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI sequences end with a letter.
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if c.is_ascii_alphabetic() {
+                            break;
+                        }
+                    }
+                }
+                // OSC sequences (e.g. the window title) end with BEL or ST.
+                Some(']') => {
+                    for c in chars.by_ref() {
+                        if c == '\x07' || c == '\\' {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            '\r' => {}
+            c => plain.push(c),
+        }
+    }
+    plain
+}
+
+/// `text` broken into lines at most `width` columns wide, between words where possible.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = vec![];
+    for source in text.replace('\t', "    ").split('\n') {
+        let (mut line, mut column) = (String::new(), 0);
+        for word in source.split_inclusive(' ') {
+            if column > 0 && column + word.trim_end().width() > width {
+                lines.push(mem::take(&mut line));
+                column = 0;
+            }
+            for c in word.chars() {
+                let c_width = c.width().unwrap_or(0);
+                // Words longer than a line are broken where it ends.
+                if column > 0 && column + c_width > width && c != ' ' {
+                    lines.push(mem::take(&mut line));
+                    column = 0;
+                }
+                line.push(c);
+                column += c_width;
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// `input` broken into lines `width` columns wide, and where the cursor after `before` is.
+fn wrap_input(input: &str, before: &str, width: usize) -> (Vec<String>, (usize, usize)) {
+    let hard = |text: &str| {
+        let mut lines = vec![String::new()];
+        let mut column = 0;
+        for c in text.chars() {
+            let c_width = c.width().unwrap_or(0);
+            if c == '\n' || column + c_width > width.max(1) {
+                lines.push(String::new());
+                column = 0;
+            }
+            if c != '\n' {
+                lines.last_mut().unwrap().push(c);
+                column += c_width;
+            }
+        }
+        (lines, column)
+    };
+    let (before, mut column) = hard(before);
+    let mut row = before.len() - 1;
+    let (mut lines, _) = hard(input);
+    if column >= width {
+        row += 1;
+        column = 0;
+    }
+    if row == lines.len() {
+        lines.push(String::new());
+    }
+    (lines, (column, row))
+}
+
+/// The conversation as lines `width` columns wide.
+fn conversation(app: &App, width: usize) -> Vec<Line<'static>> {
+    let mut lines = vec![];
+    for entry in &app.entries {
+        let (heading, style) = match entry.speaker {
+            Speaker::User => (Some("You"), styled(Style::default().fg(Color::Cyan))),
+            Speaker::Assistant => (Some("ata²"), styled(Style::default().fg(Color::Green))),
+            Speaker::Output => (None, styled(Style::default().add_modifier(Modifier::DIM))),
+        };
+        let text = match entry.speaker {
+            Speaker::Output => plain(&entry.text).trim_matches('\n').to_string(),
+            _ => entry.text.clone(),
+        };
+        if text.is_empty() && heading.is_none() {
+            continue;
+        }
+        if let Some(heading) = heading {
+            lines.push(Line::from(Span::styled(
+                heading,
+                style.add_modifier(Modifier::BOLD),
+            )));
+        }
+        let body = match entry.speaker {
+            Speaker::Assistant => Style::default(),
+            _ => style,
+        };
+        lines.extend(
+            wrap(&text, width)
+                .into_iter()
+                .map(|line| Line::from(Span::styled(line, body))),
+        );
+        lines.push(Line::default());
+    }
+    lines
+}
+
+/// The status bar: `app.status` on the left, and what is going on or the keys on the right.
+fn status_bar(app: &App, width: usize) -> Line<'static> {
+    let right = if let Some(notice) = &app.notice {
+        notice.clone()
+    } else if ask::is_pending() {
+        String::from("Answer the question above")
+    } else if let Some(asked) = app.busy {
+        format!(
+            "Answering… {:.1}s (Esc stops)",
+            asked.elapsed().as_secs_f64()
+        )
+    } else if app.scroll > 0 {
+        format!("↑ {} lines (PageDown goes back)", app.scroll)
+    } else {
+        String::from("Enter sends · Alt-Enter new line · Ctrl-C quits")
+    };
+    let left = format!(" {}", app.status);
+    let gap = width.saturating_sub(left.width() + right.width() + 1);
+    Line::from(Span::styled(
+        format!("{left}{}{right} ", " ".repeat(gap)),
+        styled(Style::default().add_modifier(Modifier::REVERSED)),
+    ))
+}
+
+pub fn draw(frame: &mut Frame, app: &mut App) {
+    let area = frame.size();
+    let inner_width = area.width.saturating_sub(2) as usize;
+    let before = app.input.chars().take(app.cursor).collect::<String>();
+    let (input, (column, row)) = wrap_input(&app.input, &before, inner_width);
+    let visible = input.len().min(MAX_INPUT_LINES);
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(1),
+            Constraint::Length(visible as u16 + 2),
+            Constraint::Length(1),
+        ])
+        .split(area);
+
+    let pane = chunks[0];
+    let lines = conversation(app, pane.width as usize);
+    let height = pane.height as usize;
+    app.page = height;
+    let last_top = lines.len().saturating_sub(height);
+    app.scroll = app.scroll.min(last_top);
+    let top = last_top - app.scroll;
+    let shown = lines[top..lines.len().min(top + height)].to_vec();
+    frame.render_widget(Paragraph::new(shown), pane);
+
+    // The lines around the cursor.
+    let first = (row + 1).saturating_sub(visible);
+    let title = if ask::is_pending() {
+        " Answer "
+    } else {
+        " Prompt "
+    };
+    frame.render_widget(
+        Paragraph::new(
+            input[first..(first + visible).min(input.len())]
+                .iter()
+                .map(|line| Line::from(line.clone()))
+                .collect::<Vec<_>>(),
+        )
+        .block(Block::default().borders(Borders::ALL).title(title)),
+        chunks[1],
+    );
+    frame.set_cursor(
+        chunks[1].x + 1 + column as u16,
+        chunks[1].y + 1 + (row - first) as u16,
+    );
+
+    frame.render_widget(
+        Paragraph::new(status_bar(app, chunks[2].width as usize)),
+        chunks[2],
+    );
+}
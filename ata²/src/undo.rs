@@ -0,0 +1,111 @@
+//! Taking messages back out of the conversation: `/undo`, `/pop [n]` and `/edit-last`.
+//!
+//! The system prompt at the head of the conversation is never removed. Alternatives and the models
+//! recorded for removed messages are forgotten with them.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use async_openai::types::ChatCompletionRequestMessage;
+
+use crate::alts;
+use crate::edit;
+use crate::prompt::CONVERSATION;
+use crate::readline::chat_completion_message_to_string;
+use crate::sessions;
+
+/// The index of the first message that may be removed: the system prompt stays.
+fn head(conversation: &[ChatCompletionRequestMessage]) -> usize {
+    match conversation.first() {
+        Some(ChatCompletionRequestMessage::System(_)) => 1,
+        _ => 0,
+    }
+}
+
+/// Shortens the conversation to `len` messages.
+async fn truncate(len: usize) {
+    CONVERSATION.lock().await.truncate(len);
+    alts::truncate(len);
+    sessions::truncate(len);
+}
+
+/// The index of the last user message, if it may be removed.
+async fn last_prompt() -> Option<usize> {
+    let conversation = CONVERSATION.lock().await;
+    conversation
+        .iter()
+        .rposition(|m| matches!(m, ChatCompletionRequestMessage::User(_)))
+        .filter(|&i| i >= head(&conversation))
+}
+
+/// `/undo`: removes the last prompt and everything after it, i.e. its answer.
+pub async fn undo() -> Result<String, String> {
+    let index = last_prompt()
+        .await
+        .ok_or_else(|| String::from("There is no prompt to undo."))?;
+    let removed = CONVERSATION.lock().await.len() - index;
+    truncate(index).await;
+    Ok(format!("Removed the last exchange ({removed} messages)"))
+}
+
+/// `/pop [n]`: removes the last `n` messages, by default one.
+pub async fn pop(args: &str) -> Result<String, String> {
+    let n = match args {
+        "" => 1,
+        n => n
+            .parse::<usize>()
+            .ok()
+            .filter(|&n| n > 0)
+            .ok_or_else(|| String::from("Usage: /pop [n]"))?,
+    };
+    let (len, head) = {
+        let conversation = CONVERSATION.lock().await;
+        (conversation.len(), head(&conversation))
+    };
+    let removable = len - head;
+    if removable == 0 {
+        return Err(String::from("There are no messages to remove."));
+    }
+    let n = n.min(removable);
+    truncate(len - n).await;
+    Ok(match n {
+        1 => String::from("Removed the last message"),
+        n => format!("Removed the last {n} messages"),
+    })
+}
+
+/// Whether the accepted `line` is `/edit-last`, which needs the terminal and so is handled before
+/// commands are.
+pub fn edit_last_requested(line: &str) -> bool {
+    line.trim() == "/edit-last"
+}
+
+/// `/edit-last`: opens the last prompt in the editor. If it is saved with some text, the prompt and
+/// its answer are removed and the text returned, to be sent instead.
+pub async fn edit_last() -> Result<Option<String>, String> {
+    let index = last_prompt()
+        .await
+        .ok_or_else(|| String::from("There is no prompt to edit."))?;
+    let last = chat_completion_message_to_string(&CONVERSATION.lock().await[index]);
+    let text = edit::compose(&last).map_err(|e| format!("Could not edit the prompt: {e}"))?;
+    if text.trim().is_empty() {
+        return Ok(None);
+    }
+    truncate(index).await;
+    Ok(Some(text))
+}
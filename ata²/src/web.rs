@@ -0,0 +1,286 @@
+//! Searching the web (`[tools.web]`), with `/web <query>` or by the model through the
+//! `web_search` tool.
+//!
+//! The pages found are fetched and their main text extracted, roughly as reader views do: scripts,
+//! navigation, headers and footers are left out, and only the `<article>` or `<main>` element is
+//! kept if there is one. The pages are numbered for the model to cite, and listed after the answer.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use futures_util::future;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::config::{SearchApi, WebToolConfig};
+use crate::output::eprint_bold;
+use crate::tools::truncated;
+
+pub const TOOL: &str = "web_search";
+
+/// Elements whose content is never the text of a page.
+const DROPPED: &[&str] = &[
+    "script", "style", "noscript", "nav", "header", "footer", "aside", "form", "svg", "template",
+];
+
+lazy_static! {
+    static ref DROP: Vec<Regex> = DROPPED
+        .iter()
+        .map(|tag| Regex::new(&format!(r"(?is)<{tag}\b.*?</{tag}\s*>")).unwrap())
+        .collect();
+    static ref COMMENT: Regex = Regex::new(r"(?s)<!--.*?-->").unwrap();
+    static ref MAIN: Regex =
+        Regex::new(r"(?is)<(?:article|main)\b[^>]*>(.*)</(?:article|main)\s*>").unwrap();
+    static ref TITLE: Regex = Regex::new(r"(?is)<title[^>]*>(.*?)</title\s*>").unwrap();
+    static ref BLOCK: Regex = Regex::new(
+        r"(?i)</?(?:p|div|br|li|h[1-6]|tr|pre|blockquote|section|article|table|ul|ol|dt|dd)\b[^>]*>"
+    )
+    .unwrap();
+    static ref TAG: Regex = Regex::new(r"(?s)<[^>]*>").unwrap();
+    static ref ENTITY: Regex = Regex::new(r"&(#x[0-9a-fA-F]+|#[0-9]+|[a-z]+);").unwrap();
+    /// The pages given to the model since the last answer, to list after it.
+    static ref SOURCES: Mutex<Vec<Source>> = Mutex::new(vec![]);
+}
+
+/// A page found by a search.
+#[derive(Clone, Debug)]
+struct Source {
+    title: String,
+    url: String,
+    snippet: String,
+}
+
+#[derive(Deserialize)]
+struct ToolArguments {
+    query: String,
+}
+
+fn client(config: &WebToolConfig) -> Result<reqwest::Client, String> {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .user_agent(concat!("ata2/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+/// The pages found for `query`, best first.
+async fn search(
+    http: &reqwest::Client,
+    config: &WebToolConfig,
+    query: &str,
+) -> Result<Vec<Source>, String> {
+    let count = config.max_results.to_string();
+    let request = match config.api {
+        SearchApi::Searxng => {
+            let url = config
+                .url
+                .as_deref()
+                .ok_or_else(|| String::from("Set url in [tools.web] to a SearXNG instance"))?;
+            http.get(format!("{}/search", url.trim_end_matches('/')))
+                .query(&[("q", query), ("format", "json")])
+        }
+        SearchApi::Brave => {
+            let api_key = config.api_key.as_deref().ok_or_else(|| {
+                String::from("Set api_key in [tools.web] for the Brave Search API")
+            })?;
+            http.get("https://api.search.brave.com/res/v1/web/search")
+                .query(&[("q", query), ("count", &count)])
+                .header("X-Subscription-Token", api_key)
+                .header("Accept", "application/json")
+        }
+    };
+    let response = request
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Could not search: {e}"))?;
+    let body: Value = response
+        .json()
+        .await
+        .map_err(|e| format!("Could not read the search results: {e}"))?;
+    let (results, snippet) = match config.api {
+        SearchApi::Searxng => (&body["results"], "content"),
+        SearchApi::Brave => (&body["web"]["results"], "description"),
+    };
+    let text = |result: &Value, key: &str| {
+        TAG.replace_all(result[key].as_str().unwrap_or(""), "")
+            .to_string()
+    };
+    Ok(results
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter(|result| result["url"].is_string())
+        .take(config.max_results as usize)
+        .map(|result| Source {
+            title: text(result, "title"),
+            url: text(result, "url"),
+            snippet: text(result, snippet),
+        })
+        .collect())
+}
+
+fn decode_entities(text: &str) -> String {
+    ENTITY
+        .replace_all(text, |captures: &regex::Captures| {
+            let entity = &captures[1];
+            let decoded = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ => entity
+                    .strip_prefix("#x")
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .or_else(|| entity.strip_prefix('#').and_then(|n| n.parse().ok()))
+                    .and_then(char::from_u32),
+            };
+            decoded.map_or_else(|| captures[0].to_string(), String::from)
+        })
+        .to_string()
+}
+
+/// The title and main text of an HTML page.
+fn extract(html: &str) -> (Option<String>, String) {
+    let title = TITLE
+        .captures(html)
+        .map(|c| decode_entities(c[1].trim()))
+        .filter(|title| !title.is_empty());
+    let mut html = COMMENT.replace_all(html, "").to_string();
+    for drop in DROP.iter() {
+        html = drop.replace_all(&html, "").to_string();
+    }
+    if let Some(main) = MAIN.captures(&html).map(|c| c[1].to_string()) {
+        html = main;
+    }
+    let text = BLOCK.replace_all(&html, "\n");
+    let text = decode_entities(&TAG.replace_all(&text, ""));
+    let lines: Vec<String> = text
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect();
+    (title, lines.join("\n"))
+}
+
+/// The text of the page at `url`, at most `max_page_bytes` of it.
+async fn fetch(
+    http: &reqwest::Client,
+    config: &WebToolConfig,
+    url: &str,
+) -> Result<String, String> {
+    let response = http
+        .get(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| e.to_string())?;
+    let is_html = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|t| t.to_str().ok())
+        .map_or(true, |t| t.contains("html"));
+    let body = response.text().await.map_err(|e| e.to_string())?;
+    let text = if is_html { extract(&body).1 } else { body };
+    Ok(truncated(&text, config.max_page_bytes as usize))
+}
+
+/// Searches for `query` and reads the pages found, returning them numbered for the model to cite.
+/// The numbers go on from those of earlier searches since the last answer.
+pub async fn gather(query: &str, config: &WebToolConfig) -> Result<String, String> {
+    if !config.enabled {
+        return Err(String::from(
+            "Searching the web is off; set enabled in [tools.web] to allow it",
+        ));
+    }
+    let query = query.trim();
+    if query.is_empty() {
+        return Err(String::from("Usage: /web <query>"));
+    }
+    eprint_bold(&format!("\nSearching the web for: {query}\n"));
+    let http = client(config)?;
+    let sources = search(&http, config, query).await?;
+    if sources.is_empty() {
+        return Err(format!("Nothing was found for {query}"));
+    }
+    let pages = future::join_all(sources.iter().map(|s| fetch(&http, config, &s.url))).await;
+
+    let mut all = SOURCES.lock().unwrap();
+    let mut context = format!("Results of a web search for: {query}\n");
+    for (source, page) in sources.into_iter().zip(pages) {
+        let n = all.len() + 1;
+        let text = match page {
+            Ok(text) if !text.trim().is_empty() => text,
+            Ok(_) => source.snippet.clone(),
+            Err(e) => {
+                warn!("Could not read {}: {e}", source.url);
+                source.snippet.clone()
+            }
+        };
+        context.push_str(&format!(
+            "\n[{n}] {} <{}>\n{text}\n",
+            source.title, source.url
+        ));
+        all.push(source);
+    }
+    Ok(context)
+}
+
+/// `/web <query>`: the prompt to send, asking for an answer from the pages found.
+pub async fn prompt(query: &str, config: &WebToolConfig) -> Result<String, String> {
+    let context = gather(query, config).await?;
+    Ok(format!(
+        "{context}\nUsing these results, citing them by number, e.g. [1], answer: {}",
+        query.trim()
+    ))
+}
+
+/// Runs the `web_search` tool, returning what to tell the model.
+pub async fn run_tool(arguments: &str, config: &WebToolConfig) -> String {
+    let query = match serde_json::from_str::<ToolArguments>(arguments) {
+        Ok(arguments) => arguments.query,
+        Err(e) => return format!("Invalid arguments: {e}"),
+    };
+    gather(&query, config)
+        .await
+        .unwrap_or_else(|e| format!("The search failed: {e}"))
+}
+
+/// Lists the pages given to the model for the answer just printed, if any.
+pub fn print_sources() {
+    let sources = std::mem::take(&mut *SOURCES.lock().unwrap());
+    if sources.is_empty() {
+        return;
+    }
+    eprint_bold("\nSources:\n");
+    for (i, source) in sources.iter().enumerate() {
+        let title = if source.title.is_empty() {
+            &source.url
+        } else {
+            &source.title
+        };
+        eprintln!("[{}] {title} — {}", i + 1, source.url);
+    }
+}
@@ -0,0 +1,201 @@
+//! Wrapping answers between words as they are streamed (`ui.wrap`), rather than leaving the
+//! terminal to break them wherever the line ends.
+//!
+//! A word is held back until it is complete, then put on the next line if it doesn't fit on this
+//! one, indented under the text of a list item. Code blocks and tables are printed as they are,
+//! and words longer than a line are left whole. With `auto`, the terminal's width is followed as
+//! it is resized.
+//!
+//! # ata²
+//!
+//!	 © 2023    Fredrick R. Brennan <copypaste@kittens.ph>
+//!	 © 2023    Rik Huijzer <t.h.huijzer@rug.nl>
+//!	 © 2023–   ATA Project Authors
+//!
+//!  Licensed under the Apache License, Version 2.0 (the "License");
+//!  you may _not_ use this file except in compliance with the License.
+//!  You may obtain a copy of the License at
+//!
+//!      http://www.apache.org/licenses/LICENSE-2.0
+//!
+//!  Unless required by applicable law or agreed to in writing, software
+//!  distributed under the License is distributed on an "AS IS" BASIS,
+//!  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//!  See the License for the specific language governing permissions and
+//!  limitations under the License.
+
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(unix)]
+use std::sync::Once;
+
+use unicode_width::UnicodeWidthStr as _;
+
+use crate::output::{self, OutputSink};
+
+/// The terminal's width, once known; 0 before.
+static WIDTH: AtomicUsize = AtomicUsize::new(0);
+
+/// The terminal's width, updated on SIGWINCH where there is one.
+fn terminal_width() -> usize {
+    #[cfg(unix)]
+    {
+        static WATCH: Once = Once::new();
+        WATCH.call_once(|| {
+            use tokio::signal::unix::{signal, SignalKind};
+            if let Ok(mut resized) = signal(SignalKind::window_change()) {
+                tokio::spawn(async move {
+                    while resized.recv().await.is_some() {
+                        WIDTH.store(output::terminal_width(), Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+        match WIDTH.load(Ordering::Relaxed) {
+            0 => {
+                let width = output::terminal_width();
+                WIDTH.store(width, Ordering::Relaxed);
+                width
+            }
+            width => width,
+        }
+    }
+    #[cfg(not(unix))]
+    output::terminal_width()
+}
+
+pub struct WrappingSink {
+    inner: Box<dyn OutputSink>,
+    /// Columns, or none to follow the terminal's width.
+    columns: Option<usize>,
+    /// The current line as received, to tell fences and tables.
+    line: String,
+    /// How many columns of the current line have been printed.
+    column: usize,
+    /// Whitespace and the word after it, not printed yet.
+    spaces: String,
+    word: String,
+    in_code: bool,
+    /// Whether the current line is printed as it is, being part of a table.
+    verbatim: bool,
+}
+
+impl WrappingSink {
+    pub fn new(inner: Box<dyn OutputSink>, columns: Option<usize>) -> Self {
+        Self {
+            inner,
+            columns,
+            line: String::new(),
+            column: 0,
+            spaces: String::new(),
+            word: String::new(),
+            in_code: false,
+            verbatim: false,
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.columns.unwrap_or_else(terminal_width)
+    }
+
+    /// Prints the word held back, on the next line if it doesn't fit on this one.
+    fn finish_word(&mut self, out: &mut String) {
+        if self.word.is_empty() {
+            return;
+        }
+        let spaces = self.spaces.width();
+        let word = self.word.width();
+        let width = self.width();
+        if self.column > 0 && self.column + spaces + word > width {
+            let indent = hanging_indent(&self.line).min(width / 2);
+            out.push('\n');
+            out.push_str(&" ".repeat(indent));
+            self.column = indent;
+            self.spaces.clear();
+        }
+        self.column += self.spaces.width() + word;
+        out.push_str(&mem::take(&mut self.spaces));
+        out.push_str(&mem::take(&mut self.word));
+    }
+
+    fn finish_line(&mut self) {
+        let fence = ["```", "~~~"]
+            .iter()
+            .any(|fence| self.line.trim_start().starts_with(fence));
+        if fence {
+            self.in_code = !self.in_code;
+        }
+        self.line.clear();
+        self.column = 0;
+        self.verbatim = false;
+    }
+}
+
+/// How far the lines a list item is wrapped onto are indented: to where its text starts.
+fn hanging_indent(line: &str) -> usize {
+    let text = line.trim_start();
+    let leading = line[..line.len() - text.len()].width();
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let marker = if ["- ", "* ", "+ "]
+        .iter()
+        .any(|marker| text.starts_with(marker))
+    {
+        2
+    } else if digits > 0
+        && [". ", ") "]
+            .iter()
+            .any(|end| text[digits..].starts_with(end))
+    {
+        digits + 2
+    } else {
+        0
+    };
+    leading + marker
+}
+
+impl OutputSink for WrappingSink {
+    fn write(&mut self, text: &str) {
+        let mut out = String::new();
+        for c in text.chars() {
+            if !self.in_code && !self.verbatim && c == '|' && self.line.trim().is_empty() {
+                self.verbatim = true;
+                out.push_str(&mem::take(&mut self.spaces));
+            }
+            self.line.push(c);
+            if self.in_code || self.verbatim {
+                out.push(c);
+                if c == '\n' {
+                    self.finish_line();
+                }
+                continue;
+            }
+            match c {
+                '\n' => {
+                    self.finish_word(&mut out);
+                    // e.g. a Markdown line break
+                    out.push_str(&mem::take(&mut self.spaces));
+                    out.push('\n');
+                    self.finish_line();
+                }
+                c if c.is_whitespace() => {
+                    self.finish_word(&mut out);
+                    self.spaces.push(c);
+                }
+                c => self.word.push(c),
+            }
+        }
+        if !out.is_empty() {
+            self.inner.write(&out);
+        }
+    }
+
+    fn flush(&mut self) {
+        let mut out = String::new();
+        self.finish_word(&mut out);
+        out.push_str(&mem::take(&mut self.spaces));
+        self.inner.write(&out);
+        self.finish_line();
+        self.in_code = false;
+        self.inner.flush();
+    }
+}
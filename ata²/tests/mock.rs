@@ -0,0 +1,291 @@
+//! Whole requests, from the prompt read to the answer printed, against `provider = "mock"`, which
+//! replays answers recorded in a directory rather than asking an API.
+
+use pretty_assertions::assert_eq;
+use tempfile::TempDir;
+
+use std::fs;
+use std::io::Write as _;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+/// Records an answer streamed in `pieces` as the `n`th in `dir`.
+fn record(dir: &Path, n: usize, pieces: &[&str]) {
+    let mut sse = String::new();
+    for (i, piece) in pieces.iter().enumerate() {
+        let delta = if i == 0 {
+            serde_json::json!({"role": "assistant", "content": piece})
+        } else {
+            serde_json::json!({ "content": piece })
+        };
+        let chunk = serde_json::json!({
+            "id": format!("chatcmpl-{n}"),
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": "gpt-3.5-turbo",
+            "choices": [{"index": 0, "delta": delta, "finish_reason": null}],
+        });
+        sse.push_str(&format!("data: {chunk}\n\n"));
+    }
+    sse.push_str("data: [DONE]\n\n");
+    fs::write(dir.join(format!("{n}.sse")), sse).unwrap();
+}
+
+/// Runs ata² with `stdin` piped in, in a home directory of its own, replaying the answers in
+/// `answers`. `config` is added to the configuration file.
+fn run(answers: &Path, config: &str, args: &[&str], stdin: &str) -> Output {
+    let home = TempDir::new().unwrap();
+    let path = home.path().join("ata2.toml");
+    let config = format!(
+        "provider = \"mock\"\napi_base = {:?}\nmodel = \"gpt-3.5-turbo\"\n{config}",
+        answers.display().to_string()
+    );
+    fs::write(&path, config).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ata2"))
+        .arg("--config")
+        .arg(&path)
+        .args(args)
+        .env("HOME", home.path())
+        .env("XDG_CONFIG_HOME", home.path().join("config"))
+        .env("XDG_DATA_HOME", home.path().join("data"))
+        .env("XDG_STATE_HOME", home.path().join("state"))
+        .env_remove("RUST_LOG")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn answers_are_replayed_to_stdout() {
+    let answers = TempDir::new().unwrap();
+    record(answers.path(), 1, &["Hello", ", ", "world!"]);
+    let output = run(answers.path(), "", &[], "Say hello\n");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), "Hello, world!");
+    assert!(!stderr(&output).contains("Hello, world!"));
+}
+
+#[test]
+fn missing_answers_are_errors() {
+    let answers = TempDir::new().unwrap();
+    let output = run(answers.path(), "", &[], "Say hello\n");
+    assert_eq!(stdout(&output), "");
+    assert!(stderr(&output).contains("No recorded answer to request 1"));
+}
+
+#[test]
+fn answers_are_post_processed() {
+    let answers = TempDir::new().unwrap();
+    record(answers.path(), 1, &["```sh\n", "echo hi\n", "```\n"]);
+    let output = run(
+        answers.path(),
+        "postprocess = [\"strip_fences\"]\n",
+        &[],
+        "A command to say hi\n",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), "echo hi");
+}
+
+#[test]
+fn only_the_extracted_part_is_printed() {
+    let answers = TempDir::new().unwrap();
+    record(
+        answers.path(),
+        1,
+        &["Run this:\n\n", "```sh\nls -l\n```\n", "It lists files."],
+    );
+    let output = run(answers.path(), "", &["--extract", "code"], "List files\n");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), "ls -l");
+}
+
+#[test]
+fn scripted_conversations_are_answered_in_order() {
+    let answers = TempDir::new().unwrap();
+    record(answers.path(), 1, &["Paris"]);
+    record(answers.path(), 2, &["About 2 million"]);
+    let script = answers.path().join("capital.yaml");
+    fs::write(
+        &script,
+        "steps:
+  - prompt: What is the capital of France?
+    expect:
+      contains: [Paris]
+    capture:
+      city: '(\\w+)'
+  - prompt: How many people live in ${city}?
+    expect:
+      contains: [million]
+",
+    )
+    .unwrap();
+    let output = run(
+        answers.path(),
+        "",
+        &["script", "play", script.to_str().unwrap()],
+        "",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[test]
+fn dry_runs_print_the_request() {
+    let answers = TempDir::new().unwrap();
+    let output = run(answers.path(), "", &["--dry-run"], "Say hello\n");
+    assert!(output.status.success(), "{}", stderr(&output));
+    let request: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    let messages = request["body"]["messages"].as_array().unwrap();
+    assert_eq!(messages.last().unwrap()["content"], "Say hello");
+}
+
+#[test]
+fn recorded_answers_replay_the_same() {
+    let answers = TempDir::new().unwrap();
+    record(answers.path(), 1, &["Hello", ", ", "world!"]);
+    let recorded = TempDir::new().unwrap();
+    let record_dir = recorded.path().to_str().unwrap();
+    let live = run(answers.path(), "", &["--record", record_dir], "Say hello\n");
+    assert!(live.status.success(), "{}", stderr(&live));
+    assert!(recorded.path().join("1.request.json").exists());
+
+    let replayed = run(recorded.path(), "", &[], "Say hello\n");
+    assert!(replayed.status.success(), "{}", stderr(&replayed));
+    assert_eq!(stdout(&replayed), stdout(&live));
+}
+
+/// Serves a conversation in which the model asks to run `command`, then answers "Not run.".
+/// Returns the last reply to the prompt, and what ata² printed on stderr.
+#[cfg(unix)]
+fn serve_command(command: &str) -> (serde_json::Value, String) {
+    use std::io::{BufRead as _, BufReader};
+    use std::os::unix::net::UnixStream;
+    use std::time::{Duration, Instant};
+
+    let answers = TempDir::new().unwrap();
+    let call = serde_json::json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "gpt-3.5-turbo",
+        "choices": [{"index": 0, "finish_reason": "tool_calls", "delta": {
+            "role": "assistant",
+            "tool_calls": [{"index": 0, "id": "call_1", "type": "function", "function": {
+                "name": "run_shell_command",
+                "arguments": serde_json::json!({ "command": command }).to_string(),
+            }}],
+        }}],
+    });
+    fs::write(
+        answers.path().join("1.sse"),
+        format!("data: {call}\n\ndata: [DONE]\n\n"),
+    )
+    .unwrap();
+    record(answers.path(), 2, &["Not run."]);
+
+    let home = TempDir::new().unwrap();
+    let path = home.path().join("ata2.toml");
+    fs::write(
+        &path,
+        format!(
+            "provider = \"mock\"\napi_base = {:?}\nmodel = \"gpt-3.5-turbo\"\n\
+             [tools.shell]\nenabled = true\n",
+            answers.path().display().to_string()
+        ),
+    )
+    .unwrap();
+    let socket = home.path().join("ata2.sock");
+    let mut server = Command::new(env!("CARGO_BIN_EXE_ata2"))
+        .arg("--config")
+        .arg(&path)
+        .arg("--serve")
+        .arg(&socket)
+        .env("HOME", home.path())
+        .env("XDG_CONFIG_HOME", home.path().join("config"))
+        .env("XDG_DATA_HOME", home.path().join("data"))
+        .env("XDG_STATE_HOME", home.path().join("state"))
+        .env_remove("RUST_LOG")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let started = Instant::now();
+    let mut connection = loop {
+        match UnixStream::connect(&socket) {
+            Ok(connection) => break connection,
+            Err(_) if started.elapsed() < Duration::from_secs(30) => {
+                std::thread::sleep(Duration::from_millis(50))
+            }
+            Err(e) => panic!("ata2 --serve didn't start: {e}"),
+        }
+    };
+    connection
+        .set_read_timeout(Some(Duration::from_secs(30)))
+        .unwrap();
+    connection
+        .write_all(b"{\"id\": 1, \"method\": \"prompt\", \"text\": \"Run it\"}\n")
+        .unwrap();
+    let mut replies = BufReader::new(connection.try_clone().unwrap()).lines();
+    let last = loop {
+        let reply: serde_json::Value =
+            serde_json::from_str(&replies.next().unwrap().unwrap()).unwrap();
+        if reply["type"] != "delta" {
+            break reply;
+        }
+    };
+    server.kill().unwrap();
+    let output = server.wait_with_output().unwrap();
+    (last, stderr(&output))
+}
+
+#[cfg(unix)]
+#[test]
+fn served_commands_needing_confirmation_are_declined() {
+    let dir = TempDir::new().unwrap();
+    let ran = dir.path().join("ran");
+    let (last, _) = serve_command(&format!("touch {}", ran.display()));
+    assert_eq!(last["type"], "done", "{last}");
+    assert_eq!(last["answer"], "Not run.");
+    assert!(!ran.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn forced_recursive_removal_is_dangerous_however_spelled() {
+    for options in [
+        "-rf",
+        "-r -f",
+        "--force --recursive",
+        "--force -r",
+        "-R --force",
+    ] {
+        let dir = TempDir::new().unwrap();
+        let doomed = dir.path().join("doomed");
+        fs::create_dir(&doomed).unwrap();
+        let (last, stderr) = serve_command(&format!("rm {options} {}", doomed.display()));
+        assert_eq!(last["type"], "done", "{last}");
+        assert!(
+            stderr.contains("deletes files recursively without asking"),
+            "rm {options}: {stderr}"
+        );
+        assert!(doomed.exists());
+    }
+}
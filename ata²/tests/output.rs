@@ -0,0 +1,96 @@
+//! ata² only ever writes the model's output to stdout; decoration, warnings, errors and so on go
+//! to stderr, so that its stdout can be piped into other programs. None of these tests talk to the
+//! API.
+
+use pretty_assertions::assert_eq;
+use tempfile::TempDir;
+
+use std::fs;
+use std::io::Write as _;
+use std::process::{Command, Output, Stdio};
+
+const CONFIG: &str = r#"api_key = "sk-test"
+model = "gpt-3.5-turbo"
+max_tokens = 16
+temperature = 0.5
+"#;
+
+/// Runs ata² with `stdin` piped in, in a home directory of its own.
+fn run(args: &[&str], stdin: &str) -> Output {
+    let home = TempDir::new().unwrap();
+    let config = home.path().join("ata2.toml");
+    fs::write(&config, CONFIG).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ata2"))
+        .arg("--config")
+        .arg(&config)
+        .args(args)
+        .env("HOME", home.path())
+        .env("XDG_CONFIG_HOME", home.path().join("config"))
+        .env("XDG_DATA_HOME", home.path().join("data"))
+        .env_remove("RUST_LOG")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn commands_write_only_to_stderr() {
+    let output = run(&[], "/set temp 0.3\n");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "");
+    assert!(stderr(&output).contains("temperature=0.3"));
+}
+
+#[test]
+fn command_errors_write_only_to_stderr() {
+    for command in [
+        "/alts\n",
+        "/continue\n",
+        "/retry\n",
+        "/set nonsense 1\n",
+        "/nonsense\n",
+    ] {
+        let output = run(&[], command);
+        assert_eq!(stdout(&output), "", "{command}");
+        assert!(!stderr(&output).is_empty(), "{command}");
+    }
+}
+
+#[test]
+fn rejected_prompts_write_only_to_stderr() {
+    let output = run(&[], "?temp=9 Hello\n");
+    assert_eq!(stdout(&output), "");
+    assert!(stderr(&output).contains("Invalid parameters"));
+}
+
+#[test]
+fn requested_output_goes_to_stdout() {
+    let output = run(&["--print-shortcuts"], "");
+    assert!(stdout(&output).contains("Keyboard shortcuts:"));
+    assert!(!stderr(&output).contains("Keyboard shortcuts:"));
+
+    let output = run(&["pricing", "show"], "");
+    assert!(stdout(&output).contains("Prices in USD"));
+    assert!(!stderr(&output).contains("Prices in USD"));
+
+    let output = run(&["config", "show"], "");
+    assert!(stdout(&output).contains("temperature = 0.5 # "));
+    assert!(stdout(&output).contains("top_p = 1.0 # default"));
+}
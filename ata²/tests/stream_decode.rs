@@ -0,0 +1,94 @@
+//! Answers are shown as the model meant them however their deltas happen to be split, and a
+//! backslash is only ever made into a newline when the model sent it on its own before an `n`.
+
+#[path = "../src/stream_decode.rs"]
+mod stream_decode;
+
+use stream_decode::{Decoder, Utf8};
+
+/// The text shown for an answer streamed in `deltas`.
+fn decode(deltas: &[&str]) -> String {
+    let mut decoder = Decoder::default();
+    let mut text: String = deltas.iter().map(|delta| decoder.push(delta)).collect();
+    text.push_str(&decoder.finish());
+    text
+}
+
+#[test]
+fn text_is_unchanged() {
+    assert_eq!(decode(&["Hello", ", ", "world!\n"]), "Hello, world!\n");
+}
+
+#[test]
+fn newlines_split_into_a_backslash_and_an_n_are_joined() {
+    assert_eq!(decode(&["one", "\\", "n", "two"]), "one\ntwo");
+    assert_eq!(decode(&["one", "\\", "ntwo"]), "one\ntwo");
+}
+
+#[test]
+fn escapes_within_a_delta_are_kept() {
+    assert_eq!(decode(&["printf(\"a\\n\");"]), "printf(\"a\\n\");");
+}
+
+#[test]
+fn escapes_after_a_joined_newline_are_kept() {
+    assert_eq!(
+        decode(&["\\", "n", "printf(\"a\\n\");"]),
+        "\nprintf(\"a\\n\");"
+    );
+}
+
+#[test]
+fn backslashes_ending_a_delta_are_kept() {
+    assert_eq!(decode(&["C:\\", "new"]), "C:\\new");
+}
+
+#[test]
+fn escaped_backslashes_are_kept() {
+    assert_eq!(decode(&["a\\", "\\", "n"]), "a\\\\n");
+    assert_eq!(decode(&["\\", "\\", "n"]), "\\\\n");
+    assert_eq!(decode(&["\\\\", "\\", "n"]), "\\\\\n");
+}
+
+#[test]
+fn backslashes_before_other_text_are_kept() {
+    assert_eq!(decode(&["\\", "d+"]), "\\d+");
+}
+
+#[test]
+fn empty_deltas_change_nothing() {
+    assert_eq!(decode(&["\\", "", "n"]), "\n");
+}
+
+#[test]
+fn a_trailing_backslash_is_shown_at_the_end() {
+    let mut decoder = Decoder::default();
+    assert_eq!(decoder.push("a"), "a");
+    assert_eq!(decoder.push("\\"), "");
+    assert_eq!(decoder.finish(), "\\");
+}
+
+#[test]
+fn characters_split_between_pushes_are_joined() {
+    let bytes = "né".as_bytes();
+    let mut utf8 = Utf8::default();
+    assert_eq!(utf8.push(&bytes[..2]), "n");
+    assert_eq!(utf8.push(&bytes[2..]), "é");
+    assert_eq!(utf8.finish(), "");
+}
+
+#[test]
+fn invalid_bytes_are_replaced() {
+    let mut utf8 = Utf8::default();
+    assert_eq!(utf8.push(b"a\xffb"), "a\u{fffd}b");
+    assert_eq!(utf8.push(b"\xe2\x82"), "");
+    assert_eq!(utf8.finish(), "\u{fffd}");
+}
+
+#[test]
+fn a_character_split_after_invalid_bytes_is_joined() {
+    let mut utf8 = Utf8::default();
+    assert_eq!(utf8.push(b"\xff\xe2\x82"), "\u{fffd}");
+    assert_eq!(utf8.push(b"\xac"), "€");
+    assert_eq!(utf8.finish(), "");
+}